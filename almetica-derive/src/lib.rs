@@ -0,0 +1,170 @@
+/// Derive macros for the network packets of almetica.
+///
+/// The generated code uses absolute paths into the almetica crate, so the macros can only be used
+/// inside of it.
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{parse_macro_input, DeriveInput, Error, Ident, Result, Token};
+
+/// Valid targets of the event that carries a packet.
+const TARGETS: &[&str] = &["Global", "Local", "Connection"];
+
+/// Implements the `Packet` trait and adds a round trip property test for the packet.
+///
+/// Client packets are carried by the `Request` event and server packets by the `Response` event
+/// with the name of the packet, e.g. `CChat` by `RequestChat`. A derive macro only sees the item
+/// it is attached to, so the event is declared in the `assemble_event!` invocation in
+/// `ecs/event.rs`. The derive checks that it's declared there with the packet, so a packet that
+/// is missing from the list doesn't compile.
+///
+/// ```ignore
+/// #[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+/// #[cfg_attr(test, derive(Arbitrary))]
+/// #[packet(opcode = C_CHAT, target = Local)]
+/// pub struct CChat {
+///     pub message: String,
+///     pub channel: u32,
+/// }
+/// ```
+#[proc_macro_derive(Packet, attributes(packet))]
+pub fn derive_packet(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand_packet(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand_packet(input: &DeriveInput) -> Result<TokenStream2> {
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "packets can't have generic parameters",
+        ));
+    }
+
+    let attributes = PacketAttributes::from_input(input)?;
+    let name = &input.ident;
+    let opcode = &attributes.opcode;
+    let target = &attributes.target;
+    let event = event_name(name)?;
+    let test_name = format_ident!("test_{}_round_trip", opcode.to_string().to_lowercase());
+
+    Ok(quote! {
+        impl crate::protocol::packet::Packet for #name {
+            const OPCODE: crate::protocol::opcode::Opcode = crate::protocol::opcode::Opcode::#opcode;
+            const TARGET: crate::ecs::event::EventTarget = crate::ecs::event::EventTarget::#target;
+        }
+
+        // Fails to compile if the event of the packet is not declared in `assemble_event!`.
+        const _: () = {
+            #[allow(dead_code)]
+            fn packet(event: crate::ecs::event::Event) -> Option<#name> {
+                match event {
+                    crate::ecs::event::Event::#event { packet, .. } => Some(packet),
+                    _ => None,
+                }
+            }
+        };
+
+        #[cfg(test)]
+        proptest::proptest! {
+            #[test]
+            fn #test_name(packet in proptest::arbitrary::any::<#name>()) {
+                let data = crate::protocol::serde::to_vec(&packet).unwrap();
+                let decoded = crate::protocol::serde::from_vec::<#name>(data).unwrap();
+                proptest::prop_assert_eq!(packet, decoded);
+            }
+        }
+    })
+}
+
+/// Returns the name of the event that carries the packet.
+fn event_name(name: &Ident) -> Result<Ident> {
+    let name_string = name.to_string();
+    if let Some(rest) = name_string.strip_prefix('C') {
+        Ok(format_ident!("Request{}", rest))
+    } else if let Some(rest) = name_string.strip_prefix('S') {
+        Ok(format_ident!("Response{}", rest))
+    } else {
+        Err(Error::new_spanned(
+            name,
+            "packet names have to start with C (client) or S (server)",
+        ))
+    }
+}
+
+/// The values of the `#[packet(opcode = ..., target = ...)]` attribute.
+struct PacketAttributes {
+    opcode: Ident,
+    target: Ident,
+}
+
+impl PacketAttributes {
+    fn from_input(input: &DeriveInput) -> Result<PacketAttributes> {
+        let mut opcode = None;
+        let mut target = None;
+
+        for attr in input
+            .attrs
+            .iter()
+            .filter(|attr| attr.path.is_ident("packet"))
+        {
+            let values =
+                attr.parse_args_with(Punctuated::<KeyValue, Token![,]>::parse_terminated)?;
+            for value in values {
+                let slot = if value.key == "opcode" {
+                    &mut opcode
+                } else if value.key == "target" {
+                    if !TARGETS.iter().any(|target| value.value == target) {
+                        return Err(Error::new_spanned(
+                            &value.value,
+                            format!("target has to be one of: {}", TARGETS.join(", ")),
+                        ));
+                    }
+                    &mut target
+                } else {
+                    return Err(Error::new_spanned(
+                        &value.key,
+                        "unknown packet attribute, expected `opcode` or `target`",
+                    ));
+                };
+
+                if slot.replace(value.value).is_some() {
+                    return Err(Error::new_spanned(
+                        &value.key,
+                        "packet attribute is defined more than once",
+                    ));
+                }
+            }
+        }
+
+        match (opcode, target) {
+            (Some(opcode), Some(target)) => Ok(PacketAttributes { opcode, target }),
+            _ => Err(Error::new_spanned(
+                &input.ident,
+                "packets need a #[packet(opcode = ..., target = ...)] attribute",
+            )),
+        }
+    }
+}
+
+/// A `key = value` pair inside the packet attribute.
+struct KeyValue {
+    key: Ident,
+    value: Ident,
+}
+
+impl Parse for KeyValue {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let key = input.parse()?;
+        input.parse::<Token![=]>()?;
+        let value = input.parse()?;
+        Ok(KeyValue { key, value })
+    }
+}
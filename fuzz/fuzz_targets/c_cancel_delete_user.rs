@@ -0,0 +1,8 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use almetica::protocol::fuzz::run_target;
+
+fuzz_target!(|data: &[u8]| {
+    run_target("c_cancel_delete_user", data);
+});
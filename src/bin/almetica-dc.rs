@@ -0,0 +1,234 @@
+#![warn(clippy::all)]
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::process;
+
+use anyhow::{bail, Context};
+use clap::Clap;
+use serde_json::{json, Map, Number};
+use tracing::{error, info};
+use tracing_subscriber::filter::EnvFilter;
+use tracing_subscriber::fmt::Layer;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::registry::Registry;
+
+use almetica::config::read_configuration;
+use almetica::datacenter::{Architecture, DataCenter, Element, Value};
+use almetica::dataloader::{load_datacenter_key, read_datacenter_key};
+use almetica::Result;
+
+/// Name of the root element that contains the elements selected with `--element`.
+const XML_ELEMENTS_ROOT: &str = "Elements";
+
+#[derive(Clap)]
+#[clap(version = "0.0.1", author = "Almetica <almetica@protonmail.com>")]
+struct Opts {
+    #[clap(short = "c", long = "config", default_value = "config.yaml")]
+    config: PathBuf,
+
+    /// Key file with the key and IV of the datacenter. Defaults to the key.yaml in the data path.
+    #[clap(short = "k", long = "key")]
+    key: Option<PathBuf>,
+
+    /// Architecture of the client (x86 or x64).
+    #[clap(short = "a", long = "arch", default_value = "x64")]
+    architecture: String,
+
+    /// Output format (xml or json).
+    #[clap(short = "f", long = "format", default_value = "xml")]
+    format: String,
+
+    /// Only export the elements with the given name (and their children). The XML output wraps
+    /// them in an <Elements> root element.
+    #[clap(short = "e", long = "element")]
+    elements: Vec<String>,
+
+    /// Output file. Defaults to stdout.
+    #[clap(short = "o", long = "output")]
+    output: Option<PathBuf>,
+
+    /// The DataCenter_Final_*.dat file of the client.
+    #[clap(name = "FILE", parse(from_os_str))]
+    file: PathBuf,
+}
+
+fn main() {
+    init_logging();
+
+    if let Err(e) = run() {
+        error!("Error while executing program: {:?}", e);
+        process::exit(1);
+    }
+}
+
+fn init_logging() {
+    let fmt_layer = Layer::default().with_target(false).with_writer(io::stderr);
+    let filter_layer = EnvFilter::from_default_env();
+    let subscriber = Registry::default().with(filter_layer).with(fmt_layer);
+    tracing::subscriber::set_global_default(subscriber).unwrap();
+}
+
+/// Decrypts and parses the datacenter file and exports the selected elements.
+fn run() -> Result<()> {
+    let opts: Opts = Opts::parse();
+
+    let architecture = match opts.architecture.as_str() {
+        "x86" => Architecture::X86,
+        "x64" => Architecture::X64,
+        arch => bail!("Unknown architecture {}", arch),
+    };
+
+    let (key, iv) = match &opts.key {
+        Some(path) => {
+            let mut file =
+                File::open(path).context(format!("Can't open key file {}", path.display()))?;
+            read_datacenter_key(&mut file)?
+        }
+        None => {
+            let config = read_configuration(&opts.config).context(format!(
+                "Can't read configuration file {}",
+                &opts.config.display(),
+            ))?;
+            load_datacenter_key(&config.data.path).context(format!(
+                "Can't read key file in {}",
+                config.data.path.display()
+            ))?
+        }
+    };
+
+    info!("Reading datacenter file {}", opts.file.display());
+    let data = fs::read(&opts.file)?;
+    let datacenter =
+        DataCenter::read(&key, &iv, data, architecture).context("Can't read datacenter file")?;
+
+    let elements = if opts.elements.is_empty() {
+        vec![datacenter.root()]
+    } else {
+        let mut found = Vec::new();
+        find_elements(datacenter.root(), &opts.elements, &mut found);
+        found
+    };
+    info!("Exporting {} elements", elements.len());
+
+    let output: Box<dyn Write> = match &opts.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+    let mut output = BufWriter::new(output);
+
+    match opts.format.as_str() {
+        "xml" => {
+            if opts.elements.is_empty() {
+                write_xml(&mut output, &elements[0], 0)?;
+            } else {
+                // A XML document has only one root element, so the found elements are wrapped.
+                writeln!(output, "<{}>", XML_ELEMENTS_ROOT)?;
+                for element in elements.iter() {
+                    write_xml(&mut output, element, 1)?;
+                }
+                writeln!(output, "</{}>", XML_ELEMENTS_ROOT)?;
+            }
+        }
+        "json" => {
+            let elements: Vec<serde_json::Value> = elements.iter().map(to_json).collect();
+            serde_json::to_writer_pretty(&mut output, &elements)?;
+            writeln!(output)?;
+        }
+        format => bail!("Unknown format {}", format),
+    }
+    output.flush()?;
+
+    info!("Finished exporting the datacenter");
+    Ok(())
+}
+
+/// Collects all elements with one of the given names. Children of found elements are not searched.
+fn find_elements<'a>(element: Element<'a>, names: &[String], found: &mut Vec<Element<'a>>) {
+    if names.iter().any(|name| name == element.name()) {
+        found.push(element);
+        return;
+    }
+    for child in element.children() {
+        find_elements(child, names, found);
+    }
+}
+
+fn write_xml<W: Write>(output: &mut W, element: &Element, depth: usize) -> Result<()> {
+    let indent = "  ".repeat(depth);
+    write!(output, "{}<{}", indent, element.name())?;
+    for (name, value) in element.attributes() {
+        write!(output, " {}=\"{}\"", name, escape_xml(&value.to_string()))?;
+    }
+
+    let children = element.children();
+    match (element.value(), children.is_empty()) {
+        (None, true) => writeln!(output, "/>")?,
+        (Some(value), true) => writeln!(output, ">{}</{}>", escape_xml(value), element.name())?,
+        (value, false) => {
+            writeln!(output, ">")?;
+            if let Some(value) = value {
+                writeln!(output, "{}  {}", indent, escape_xml(value))?;
+            }
+            for child in children.iter() {
+                write_xml(output, child, depth + 1)?;
+            }
+            writeln!(output, "{}</{}>", indent, element.name())?;
+        }
+    }
+    Ok(())
+}
+
+/// Escapes the value for XML text and attributes. Characters that are not allowed in XML 1.0,
+/// like most control characters, can't be written even as character references, so they are
+/// written as Rust style unicode escapes, e.g. `\u{b}`.
+fn escape_xml(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            // Parsers normalize these whitespace characters in attributes.
+            '\t' | '\n' | '\r' => escaped.push_str(&format!("&#x{:x};", c as u32)),
+            '\u{0}'..='\u{1f}' | '\u{fffe}' | '\u{ffff}' => {
+                escaped.push_str(&c.escape_unicode().to_string())
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn to_json(element: &Element) -> serde_json::Value {
+    let attributes: Map<String, serde_json::Value> = element
+        .attributes()
+        .into_iter()
+        .map(|(name, value)| {
+            let value = match value {
+                Value::Int(v) => json!(v),
+                Value::Float(v) => Number::from_f64(v as f64)
+                    .map(serde_json::Value::Number)
+                    .unwrap_or(serde_json::Value::Null),
+                Value::Bool(v) => json!(v),
+                Value::String(v) => json!(v),
+            };
+            (name.to_string(), value)
+        })
+        .collect();
+
+    let mut object = json!({
+        "name": element.name(),
+        "attributes": attributes,
+    });
+    if let Some(value) = element.value() {
+        object["value"] = json!(value);
+    }
+    let children: Vec<serde_json::Value> = element.children().iter().map(to_json).collect();
+    if !children.is_empty() {
+        object["children"] = json!(children);
+    }
+    object
+}
@@ -0,0 +1,463 @@
+#![warn(clippy::all)]
+
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, ensure, Context};
+use async_std::net::TcpStream;
+use async_std::prelude::*;
+use async_std::task;
+use chrono::Utc;
+use clap::Clap;
+use sqlx::PgPool;
+use tracing::{debug, error, info, info_span, warn};
+use tracing_futures::Instrument;
+use tracing_subscriber::filter::EnvFilter;
+use tracing_subscriber::fmt::Layer;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::registry::Registry;
+
+use almetica::config::{read_configuration, Configuration};
+use almetica::crypt::password_hash;
+use almetica::metrics::MetricsSnapshot;
+use almetica::model::entity::Account;
+use almetica::model::repository::account;
+use almetica::model::{PasswordHashAlgorithm, Region};
+use almetica::protocol::client::GameClient;
+use almetica::protocol::packet::{
+    CCheckVersion, CCheckVersionEntry, CGetUserList, CLoginArbiter, SCheckVersion, SGetUserList,
+    SLoginArbiter,
+};
+use almetica::protocol::version::ProtocolVersion;
+use almetica::webserver::response::AuthResponse;
+use almetica::Result;
+
+#[derive(Clap)]
+#[clap(version = "0.0.1", author = "Almetica <almetica@protonmail.com>")]
+struct Opts {
+    #[clap(short = "c", long = "config", default_value = "config.yaml")]
+    config: PathBuf,
+
+    /// Host of the server. Defaults to the configured hostname.
+    #[clap(short = "H", long = "host")]
+    host: Option<String>,
+
+    /// Client version the simulated clients use. Defaults to the first configured version.
+    #[clap(short = "p", long = "protocol")]
+    protocol: Option<String>,
+
+    /// Number of simulated clients.
+    #[clap(short = "n", long = "clients", default_value = "100")]
+    clients: usize,
+
+    /// Seconds every client stays connected.
+    #[clap(short = "d", long = "duration", default_value = "60")]
+    duration: u64,
+
+    /// Milliseconds between the start of two clients.
+    #[clap(short = "s", long = "spawn-interval", default_value = "10")]
+    spawn_interval: u64,
+
+    /// Milliseconds between the user list requests of a client.
+    #[clap(short = "i", long = "interval", default_value = "1000")]
+    interval: u64,
+
+    /// Prefix of the account names. Client N uses the account "<prefix>-N".
+    #[clap(long = "account-prefix", default_value = "loadtest")]
+    account_prefix: String,
+
+    /// Password of the accounts.
+    #[clap(long = "password", default_value = "loadtest")]
+    password: String,
+
+    /// Creates the accounts of the clients in the database if they don't exist.
+    #[clap(long = "create-accounts")]
+    create_accounts: bool,
+}
+
+/// Settings that are shared by all simulated clients.
+struct Settings {
+    web_addr: String,
+    game_addr: String,
+    version: Arc<ProtocolVersion>,
+    account_prefix: String,
+    password: String,
+    deadline: Instant,
+    interval: Duration,
+}
+
+/// Latencies and failures of all simulated clients.
+#[derive(Default)]
+struct Stats {
+    auth: Mutex<Vec<Duration>>,
+    login: Mutex<Vec<Duration>>,
+    user_list: Mutex<Vec<Duration>>,
+    failed_clients: AtomicUsize,
+}
+
+#[async_std::main]
+async fn main() {
+    init_logging();
+
+    if let Err(e) = run().await {
+        error!("Error while executing program: {:?}", e);
+        process::exit(1);
+    }
+}
+
+fn init_logging() {
+    let fmt_layer = Layer::default().with_target(false);
+    let filter_layer = EnvFilter::from_default_env();
+    let subscriber = Registry::default().with(filter_layer).with(fmt_layer);
+    tracing::subscriber::set_global_default(subscriber).unwrap();
+}
+
+/// Spawns the simulated clients and reports the latencies and the metrics of the server once all
+/// clients are finished.
+async fn run() -> Result<()> {
+    let opts: Opts = Opts::parse();
+    let config = read_configuration(&opts.config).context(format!(
+        "Can't read configuration file {}",
+        &opts.config.display(),
+    ))?;
+
+    let version = Arc::new(ProtocolVersion::load(
+        &config.data,
+        opts.protocol.as_deref(),
+    )?);
+    info!("Using client version {}", version.name);
+
+    if opts.create_accounts {
+        create_accounts(&config, &opts).await?;
+    }
+
+    let host = opts
+        .host
+        .clone()
+        .unwrap_or_else(|| config.server.hostname.clone());
+    let web_addr = format!("{}:{}", host, config.server.web_port);
+    let game_addr = format!("{}:{}", host, config.server.game_port);
+    // The server only serves its metrics on the loopback interface.
+    let metrics_addr = match config.server.metrics_port {
+        Some(port) => format!("127.0.0.1:{}", port),
+        None => bail!("The metrics port of the server is not configured"),
+    };
+
+    let metrics_before = fetch_metrics(&metrics_addr)
+        .await
+        .context("Can't fetch the metrics of the server")?;
+
+    let spawn_interval = Duration::from_millis(opts.spawn_interval);
+    let ramp_up = spawn_interval * opts.clients as u32;
+    let settings = Arc::new(Settings {
+        web_addr,
+        game_addr,
+        version,
+        account_prefix: opts.account_prefix.clone(),
+        password: opts.password.clone(),
+        deadline: Instant::now() + ramp_up + Duration::from_secs(opts.duration),
+        interval: Duration::from_millis(opts.interval),
+    });
+    let stats = Arc::new(Stats::default());
+
+    info!("Spawning {} clients", opts.clients);
+    let start = Instant::now();
+    let mut handles = Vec::with_capacity(opts.clients);
+    for id in 0..opts.clients {
+        let settings = settings.clone();
+        let stats = stats.clone();
+        handles.push(task::spawn(
+            async move {
+                if let Err(e) = simulate_client(id, &settings, &stats).await {
+                    warn!("Client failed: {:?}", e);
+                    stats.failed_clients.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            .instrument(info_span!("client", id)),
+        ));
+        task::sleep(spawn_interval).await;
+    }
+    for handle in handles {
+        handle.await;
+    }
+    let elapsed = start.elapsed();
+
+    let metrics = fetch_metrics(&metrics_addr)
+        .await
+        .context("Can't fetch the metrics of the server")?
+        .since(&metrics_before);
+
+    print_report(&opts, elapsed, &stats, &metrics);
+    Ok(())
+}
+
+/// Creates the accounts of all clients that don't exist yet.
+async fn create_accounts(config: &Configuration, opts: &Opts) -> Result<()> {
+    let pool = PgPool::new(&format!(
+        "postgres://{}:{}@{}:{}/{}",
+        config.database.username,
+        config.database.password,
+        config.database.hostname,
+        config.database.port,
+        config.database.database
+    ))
+    .await?;
+    let mut conn = pool.acquire().await?;
+
+    // All accounts share the same password, so it only needs to be hashed once.
+    let hash = password_hash::create_hash(opts.password.as_bytes(), PasswordHashAlgorithm::Argon2)?;
+
+    let mut count = 0;
+    for id in 0..opts.clients {
+        let account_name = format!("{}-{}", opts.account_prefix, id);
+        match account::get_by_name(&mut conn, &account_name).await {
+            Ok(_) => continue,
+            Err(e) => match e.downcast_ref::<sqlx::Error>() {
+                Some(sqlx::Error::RowNotFound) => {}
+                Some(..) | None => bail!(e),
+            },
+        }
+
+        account::create(
+            &mut conn,
+            &Account {
+                id: -1,
+                name: account_name,
+                password: hash.clone(),
+                algorithm: PasswordHashAlgorithm::Argon2,
+                created_at: Utc::now(),
+                updated_at: Utc::now(),
+            },
+        )
+        .await?;
+        count += 1;
+    }
+    info!("Created {} accounts", count);
+    Ok(())
+}
+
+/// Runs a client through the login and keeps it in the lobby until the deadline is reached.
+/// Pings of the server are answered by the game client while it waits for the responses.
+async fn simulate_client(id: usize, settings: &Settings, stats: &Stats) -> Result<()> {
+    let account_name = format!("{}-{}", settings.account_prefix, id);
+
+    let start = Instant::now();
+    let ticket = authenticate(&settings.web_addr, &account_name, &settings.password).await?;
+    record(&stats.auth, start.elapsed());
+    debug!("Authenticated account {}", account_name);
+
+    let start = Instant::now();
+    let mut client = GameClient::connect(&settings.game_addr, settings.version.clone()).await?;
+    // The server answers the version check once the login was verified too.
+    client
+        .send(&CCheckVersion {
+            version: vec![
+                CCheckVersionEntry {
+                    index: 0,
+                    value: settings.version.version,
+                },
+                CCheckVersionEntry { index: 1, value: 0 },
+            ],
+        })
+        .await?;
+    client
+        .send(&CLoginArbiter {
+            master_account_name: account_name,
+            ticket: ticket.into_bytes(),
+            unk1: 0,
+            unk2: 0,
+            region: Region::Europe,
+            patch_version: 9002,
+        })
+        .await?;
+    ensure!(
+        client.recv_packet::<SCheckVersion>().await?.ok,
+        "Client version was rejected"
+    );
+    ensure!(
+        client.recv_packet::<SLoginArbiter>().await?.success,
+        "Login was rejected"
+    );
+    record(&stats.login, start.elapsed());
+    debug!("Logged in");
+
+    while Instant::now() < settings.deadline {
+        let start = Instant::now();
+        client.send(&CGetUserList {}).await?;
+        client.recv_packet::<SGetUserList>().await?;
+        record(&stats.user_list, start.elapsed());
+        task::sleep(settings.interval).await;
+    }
+    Ok(())
+}
+
+fn record(latencies: &Mutex<Vec<Duration>>, latency: Duration) {
+    latencies.lock().unwrap().push(latency);
+}
+
+/// Authenticates the account at the web server and returns the login ticket.
+async fn authenticate(web_addr: &str, account_name: &str, password: &str) -> Result<String> {
+    let body = format!(
+        "accountname={}&password={}",
+        url_encode(account_name),
+        url_encode(password)
+    );
+    let (status, data) = http_request(web_addr, "POST", "/auth", Some(&body)).await?;
+    let response: AuthResponse =
+        serde_json::from_slice(&data).context("Can't parse auth response")?;
+    ensure!(
+        status == 200 && !response.ticket.is_empty(),
+        "Authentication failed: {} ({})",
+        response.result_message,
+        response.result_code
+    );
+    Ok(response.ticket)
+}
+
+/// Fetches the current metrics of the server.
+async fn fetch_metrics(metrics_addr: &str) -> Result<MetricsSnapshot> {
+    let (status, data) = http_request(metrics_addr, "GET", "/metrics", None).await?;
+    ensure!(status == 200, "Server responded with status {}", status);
+    serde_json::from_slice(&data).context("Can't parse metrics")
+}
+
+/// Sends a HTTP/1.1 request with an optional form body and returns the status code and body of
+/// the response. The connection is closed after every request.
+async fn http_request(
+    addr: &str,
+    method: &str,
+    path: &str,
+    form: Option<&str>,
+) -> Result<(u16, Vec<u8>)> {
+    let mut stream = TcpStream::connect(addr)
+        .await
+        .context(format!("Can't connect to web server {}", addr))?;
+
+    let mut request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n",
+        method, path, addr
+    );
+    if let Some(form) = form {
+        request.push_str(&format!(
+            "Content-Type: application/x-www-form-urlencoded\r\nContent-Length: {}\r\n\r\n{}",
+            form.len(),
+            form
+        ));
+    } else {
+        request.push_str("\r\n");
+    }
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+    parse_http_response(&response)
+}
+
+fn parse_http_response(response: &[u8]) -> Result<(u16, Vec<u8>)> {
+    let header_end = match response.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(pos) => pos,
+        None => bail!("Incomplete HTTP response"),
+    };
+    let header = String::from_utf8_lossy(&response[..header_end]);
+    let body = &response[header_end + 4..];
+
+    let mut lines = header.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|line| line.split(' ').nth(1))
+        .and_then(|status| status.parse::<u16>().ok())
+        .context("Invalid HTTP status line")?;
+    let chunked = lines.any(|line| {
+        let line = line.to_ascii_lowercase();
+        line.starts_with("transfer-encoding:") && line.contains("chunked")
+    });
+
+    if chunked {
+        Ok((status, decode_chunked(body)?))
+    } else {
+        Ok((status, body.to_vec()))
+    }
+}
+
+fn decode_chunked(mut data: &[u8]) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = match data.windows(2).position(|w| w == b"\r\n") {
+            Some(pos) => pos,
+            None => bail!("Incomplete chunk header"),
+        };
+        let size_line = String::from_utf8_lossy(&data[..line_end]);
+        let size_value = size_line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size_value, 16)
+            .context(format!("Invalid chunk size {}", size_value))?;
+        data = &data[line_end + 2..];
+        if size == 0 {
+            return Ok(body);
+        }
+        ensure!(data.len() >= size, "Incomplete chunk");
+        body.extend_from_slice(&data[..size]);
+        data = &data[size..];
+        if data.starts_with(b"\r\n") {
+            data = &data[2..];
+        }
+    }
+}
+
+/// Percent-encodes a value of a form.
+fn url_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for b in value.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+fn print_report(opts: &Opts, elapsed: Duration, stats: &Stats, metrics: &MetricsSnapshot) {
+    println!(
+        "{} clients finished after {:.1}s, {} failed",
+        opts.clients,
+        elapsed.as_secs_f64(),
+        stats.failed_clients.load(Ordering::Relaxed)
+    );
+    println!(
+        "{:<10} {:>8} {:>10} {:>10} {:>10} {:>10}",
+        "", "count", "p50", "p90", "p99", "max"
+    );
+    print_latencies("auth", &stats.auth.lock().unwrap());
+    print_latencies("login", &stats.login.lock().unwrap());
+    print_latencies("user list", &stats.user_list.lock().unwrap());
+    println!(
+        "Server: {} ticks, {} tick overruns",
+        metrics.ticks, metrics.tick_overruns
+    );
+    println!(
+        "Delivery: {} dropped events, {} coalesced events, {} dropped slow connections",
+        metrics.dropped_events, metrics.coalesced_events, metrics.slow_connection_drops
+    );
+}
+
+fn print_latencies(name: &str, latencies: &[Duration]) {
+    let mut latencies = latencies.to_vec();
+    latencies.sort();
+    let percentile = |p: f64| match latencies.len() {
+        0 => Duration::default(),
+        len => latencies[((len - 1) as f64 * p).round() as usize],
+    };
+    println!(
+        "{:<10} {:>8} {:>10.1?} {:>10.1?} {:>10.1?} {:>10.1?}",
+        name,
+        latencies.len(),
+        percentile(0.5),
+        percentile(0.9),
+        percentile(0.99),
+        percentile(1.0)
+    );
+}
@@ -1,14 +1,17 @@
 #![warn(clippy::all)]
 
-use std::fs::File;
+use std::fs;
 use std::io::Read;
 use std::path::PathBuf;
 use std::process;
+use std::sync::Arc;
 
+use ansi_term::Colour;
 use anyhow::{bail, ensure, Context};
 use byteorder::{ByteOrder, LittleEndian};
 use clap::Clap;
 use hex::encode;
+use shipyard::EntityId;
 use tracing::{debug, error, info, warn};
 use tracing_subscriber::filter::EnvFilter;
 use tracing_subscriber::fmt::Layer;
@@ -17,9 +20,12 @@ use tracing_subscriber::registry::Registry;
 
 use almetica::config::read_configuration;
 use almetica::crypt::CryptSession;
-use almetica::dataloader::load_opcode_mapping;
+use almetica::ecs::event::{detached_connection_id, Event};
+use almetica::pcap::{is_capture, read_conversations};
 use almetica::protocol::opcode::Opcode;
-use almetica::Result;
+use almetica::protocol::version::ProtocolVersion;
+use almetica::protocol::{split_integrity_header, INTEGRITY_HEADER_LENGTH, PACKET_HEADER_LENGTH};
+use almetica::{AlmeticaError, Result};
 
 #[derive(Clap)]
 #[clap(version = "0.0.1", author = "Almetica <almetica@protonmail.com>")]
@@ -27,6 +33,18 @@ struct Opts {
     #[clap(short = "c", long = "config", default_value = "config.yaml")]
     config: PathBuf,
 
+    /// Name of the client version of the stream. Defaults to the first configured version.
+    #[clap(short = "p", long = "protocol")]
+    protocol: Option<String>,
+
+    /// Disables the colored output of the packet direction.
+    #[clap(long = "no-color")]
+    no_color: bool,
+
+    /// Server port of the connections inside pcap files. Defaults to the configured game port.
+    #[clap(short = "P", long = "port")]
+    port: Option<u16>,
+
     #[clap(name = "FILE", parse(from_os_str))]
     files: Vec<PathBuf>,
 }
@@ -47,8 +65,9 @@ fn init_logging() {
     tracing::subscriber::set_global_default(subscriber).unwrap();
 }
 
-/// Parses the given tcp stream dump.
-/// File should be binary and contain the data as an array of items specified as:
+/// Parses the given tcp stream dumps and prints the decoded packets.
+/// A file is either a pcap / pcapng capture or binary and contains the data as an array of
+/// items specified as:
 ///    i8  server (1) or client (0)
 ///    i64 length of packet data
 ///    PACKET DATA BYTES
@@ -59,56 +78,76 @@ fn run() -> Result<()> {
         "Can't read configuration file {}",
         &opts.config.display(),
     ))?;
-    let (opcode_mapping, _reverse_opcode_mapping) = load_opcode_mapping(&config.data.path)
-        .context(format!(
-            "Can't read opcode mapping file {}",
-            &opts.config.display(),
-        ))?;
+    let version = Arc::new(ProtocolVersion::load(
+        &config.data,
+        opts.protocol.as_deref(),
+    )?);
 
     info!(
-        "Loaded opcode mapping table with {} entries",
-        opcode_mapping
-            .iter()
-            .filter(|&op| *op != Opcode::UNKNOWN)
-            .count()
+        "Loaded opcode mapping table with {} entries and integrity list with {} entries",
+        version.reverse_opcode_table.len(),
+        version.integrity_opcodes.len()
     );
 
+    let connection_id = detached_connection_id();
+
+    let port = opts.port.unwrap_or(config.server.game_port);
+    let color = !opts.no_color;
+
     info!("Start parsing stream.");
     for path in opts.files {
-        let mut file = File::open(path.clone())?;
-
-        let mut buffer: [u8; 9] = [0; 9];
-        let mut sp = StreamParser {
-            state: -1,
-            num_unknown: 0,
-            num_packets: 0,
-            crypt_session: None,
-            opcode: opcode_mapping.clone(),
-            client_key_1: vec![0; 128],
-            client_key_2: vec![0; 128],
-            server_key_1: vec![0; 128],
-            server_key_2: vec![0; 128],
-            tmp_buffer: [Vec::with_capacity(4096), Vec::with_capacity(4096)],
-        };
+        let data = fs::read(&path).context(format!("Can't read file {}", path.display()))?;
 
-        loop {
-            let read = file.read(&mut buffer)?;
-            if read == 0 {
-                info!("Reached end of stream.");
-                break;
+        if is_capture(&data) {
+            let conversations = read_conversations(&data, port)
+                .context(format!("Can't read capture file {}", path.display()))?;
+            info!(
+                "Found {} connections with server port {}",
+                conversations.len(),
+                port
+            );
+            for conversation in conversations {
+                info!("Parsing connection of client {}", conversation.client);
+                let mut sp = StreamParser::new(version.clone(), connection_id, color);
+                for mut stream_data in conversation.data {
+                    // The encryption can't continue over data that is missing from the capture.
+                    if stream_data.missing > 0 {
+                        warn!(
+                            "Stopped parsing connection of client {}: {} bytes are missing",
+                            conversation.client, stream_data.missing
+                        );
+                        break;
+                    }
+                    if let Err(e) =
+                        sp.parse_packet(stream_data.from_server as usize, &mut stream_data.payload)
+                    {
+                        error!(
+                            "Stopped parsing connection of client {}: {:?}",
+                            conversation.client, e
+                        );
+                        break;
+                    }
+                }
+                sp.report();
             }
-            let is_server = buffer[0] as usize;
-            let length = LittleEndian::read_i64(&buffer[1..]) as usize;
+        } else {
+            let mut reader = data.as_slice();
+            let mut buffer: [u8; 9] = [0; 9];
+            let mut sp = StreamParser::new(version.clone(), connection_id, color);
+            loop {
+                if reader.is_empty() {
+                    info!("Reached end of stream.");
+                    break;
+                }
+                reader.read_exact(&mut buffer)?;
+                let is_server = buffer[0] as usize;
+                let length = LittleEndian::read_i64(&buffer[1..]) as usize;
 
-            let mut payload_buffer = vec![0; length];
-            file.read_exact(&mut payload_buffer[..length as usize])?;
-            sp.parse_packet(is_server, &mut payload_buffer)?;
-        }
-        if sp.num_unknown > 0 {
-            warn!(
-                "Found {} of {} packets with unknown type!",
-                sp.num_unknown, sp.num_packets
-            );
+                let mut payload_buffer = vec![0; length];
+                reader.read_exact(&mut payload_buffer[..length as usize])?;
+                sp.parse_packet(is_server, &mut payload_buffer)?;
+            }
+            sp.report();
         }
     }
     info!("Finished parsing files.");
@@ -119,21 +158,87 @@ fn run() -> Result<()> {
 struct StreamParser {
     state: i8,
     num_unknown: usize,
+    num_undecoded: usize,
+    num_partial: usize,
     num_packets: usize,
     crypt_session: Option<CryptSession>,
-    opcode: Vec<Opcode>,
+    version: Arc<ProtocolVersion>,
+    connection_id: EntityId,
+    color: bool,
     client_key_1: Vec<u8>,
     client_key_2: Vec<u8>,
     server_key_1: Vec<u8>,
     server_key_2: Vec<u8>,
+    handshake_buffer: [Vec<u8>; 2],
     tmp_buffer: [Vec<u8>; 2],
 }
 
 impl StreamParser {
-    /// Parses a packet in the payload. Handles the crypt session initialization.
+    fn new(version: Arc<ProtocolVersion>, connection_id: EntityId, color: bool) -> StreamParser {
+        StreamParser {
+            state: -1,
+            num_unknown: 0,
+            num_undecoded: 0,
+            num_partial: 0,
+            num_packets: 0,
+            crypt_session: None,
+            version,
+            connection_id,
+            color,
+            client_key_1: vec![0; 128],
+            client_key_2: vec![0; 128],
+            server_key_1: vec![0; 128],
+            server_key_2: vec![0; 128],
+            handshake_buffer: [Vec::with_capacity(256), Vec::with_capacity(256)],
+            tmp_buffer: [Vec::with_capacity(4096), Vec::with_capacity(4096)],
+        }
+    }
+
+    /// Reports the statistics of the parsed stream.
+    fn report(&self) {
+        if self.num_unknown > 0 {
+            warn!(
+                "Found {} of {} packets with unknown type!",
+                self.num_unknown, self.num_packets
+            );
+        }
+        if self.num_undecoded > 0 {
+            warn!(
+                "Couldn't decode {} of {} packets!",
+                self.num_undecoded, self.num_packets
+            );
+        }
+        if self.num_partial > 0 {
+            warn!(
+                "Found {} of {} packets that were not fully decoded!",
+                self.num_partial, self.num_packets
+            );
+        }
+    }
+
+    /// Parses the packets in the payload. Handles the crypt session initialization.
     pub fn parse_packet(&mut self, is_server: usize, payload: &mut Vec<u8>) -> Result<()> {
         if self.state != 4 {
-            self.init_crypt_session(is_server, payload)?;
+            // The payload is not necessarily split at the boundaries of the handshake.
+            self.handshake_buffer[is_server].append(payload);
+            self.init_crypt_session()?;
+            if self.state != 4 {
+                return Ok(());
+            }
+
+            // Everything that was send after the handshake is already encrypted.
+            let mut client_data = self.handshake_buffer[0].split_off(0);
+            self.decrypt_packets(0, &mut client_data)?;
+            let mut server_data = self.handshake_buffer[1].split_off(0);
+            self.decrypt_packets(1, &mut server_data)?;
+            return Ok(());
+        }
+
+        self.decrypt_packets(is_server, payload)
+    }
+
+    fn decrypt_packets(&mut self, is_server: usize, payload: &mut Vec<u8>) -> Result<()> {
+        if payload.is_empty() {
             return Ok(());
         }
 
@@ -157,9 +262,14 @@ impl StreamParser {
             }
             let length = LittleEndian::read_u16(&self.tmp_buffer[is_server][0..2]) as usize;
             let opcode = LittleEndian::read_u16(&self.tmp_buffer[is_server][2..4]);
+            ensure!(
+                length >= PACKET_HEADER_LENGTH,
+                "Invalid packet length {}",
+                length
+            );
             if length <= self.tmp_buffer[is_server].len() {
-                let packet_type = &self.opcode[opcode as usize];
-                if *packet_type == Opcode::UNKNOWN {
+                let packet_type = self.version.opcode_table[opcode as usize];
+                if packet_type == Opcode::UNKNOWN {
                     self.num_unknown += 1;
                 }
 
@@ -168,22 +278,7 @@ impl StreamParser {
                 self.tmp_buffer[is_server].copy_within(length.., 0);
                 self.tmp_buffer[is_server].resize(self.tmp_buffer[is_server].len() - length, 0);
 
-                if is_server == 1 {
-                    info!(
-                        "Found packet {:?} from server. Length: {} Rest: {}",
-                        packet_type,
-                        packet_data.len(),
-                        self.tmp_buffer[1].len()
-                    );
-                } else {
-                    info!(
-                        "Found packet {:?} from client. Length: {} Rest: {}",
-                        packet_type,
-                        packet_data.len(),
-                        self.tmp_buffer[0].len()
-                    );
-                }
-                debug!("{:#x?}", packet_data);
+                self.print_packet(is_server, packet_type, packet_data);
                 self.num_packets += 1;
             } else {
                 return Ok(());
@@ -191,49 +286,158 @@ impl StreamParser {
         }
     }
 
-    fn init_crypt_session(&mut self, is_server: usize, mut payload: &[u8]) -> Result<()> {
-        match self.state {
-            -1 => {
-                ensure!(is_server == 1, "Unexpected packet from client");
-                let magic_word = LittleEndian::read_u32(&payload[..4]);
-                if magic_word != 1 {
-                    bail!("No magic word found in stream");
+    /// Decodes the packet and prints it. Falls back to a hex dump if the packet can't be decoded.
+    fn print_packet(&mut self, is_server: usize, packet_type: Opcode, mut packet_data: Vec<u8>) {
+        let direction = if is_server == 1 {
+            self.paint(Colour::Blue, "S -> C")
+        } else {
+            self.paint(Colour::Green, "C -> S")
+        };
+        println!(
+            "{} {:?} ({} bytes)",
+            direction,
+            packet_type,
+            packet_data.len()
+        );
+        debug!("{:#x?}", packet_data);
+
+        if packet_type == Opcode::UNKNOWN {
+            println!("{}", encode(&packet_data));
+            return;
+        }
+
+        // Only the client sends the integrity header.
+        let mut data_offset = PACKET_HEADER_LENGTH;
+        if is_server == 0 && self.version.integrity_opcodes.contains(&packet_type) {
+            match split_integrity_header(packet_data.clone()) {
+                Ok((integrity_value, data)) => {
+                    println!("Integrity value: {:#010x}", integrity_value);
+                    packet_data = data;
+                    data_offset += INTEGRITY_HEADER_LENGTH;
+                }
+                Err(e) => {
+                    self.num_undecoded += 1;
+                    warn!("Can't decode packet {:?}: {}", packet_type, e);
+                    println!("{}", encode(&packet_data));
+                    return;
+                }
+            }
+        }
+
+        let length = packet_data.len();
+        match Event::decode_packet(
+            self.connection_id,
+            packet_type,
+            packet_data.clone(),
+            data_offset,
+            self.version.layout,
+        ) {
+            Ok((event, consumed)) => {
+                if let Some(packet) = event.packet() {
+                    println!("{:#?}", packet);
+                }
+                if consumed < length {
+                    self.num_partial += 1;
+                    warn!(
+                        "Packet {:?} was only decoded with {} of {} bytes",
+                        packet_type, consumed, length
+                    );
+                    println!(
+                        "{}",
+                        self.paint(
+                            Colour::Yellow,
+                            &format!("Rest: {}", encode(&packet_data[consumed..]))
+                        )
+                    );
                 }
-                self.state = 0;
             }
-            0 => {
-                ensure!(is_server == 0, "Unexpected packet from server");
-                payload.read_exact(&mut self.client_key_1)?;
-                self.state = 1;
+            Err(e)
+                if matches!(
+                    e.downcast_ref::<AlmeticaError>(),
+                    Some(AlmeticaError::NoEventMappingForPacket)
+                ) =>
+            {
+                // There is no packet struct defined for this packet yet.
+                println!("{}", encode(&packet_data));
             }
-            1 => {
-                ensure!(is_server == 1, "Unexpected packet from client");
-                payload.read_exact(&mut self.server_key_1)?;
-                self.state = 2;
+            Err(e) => {
+                self.num_undecoded += 1;
+                warn!("Can't decode packet {:?}: {}", packet_type, e);
+                println!("{}", encode(&packet_data));
             }
-            2 => {
-                ensure!(is_server == 0, "Unexpected packet from server");
-                payload.read_exact(&mut self.client_key_2)?;
-                self.state = 3;
+        }
+    }
+
+    fn paint(&self, colour: Colour, text: &str) -> String {
+        if self.color {
+            colour.bold().paint(text).to_string()
+        } else {
+            text.to_string()
+        }
+    }
+
+    /// Reads the magic word and the keys of the handshake once enough data was received.
+    fn init_crypt_session(&mut self) -> Result<()> {
+        loop {
+            let (is_server, length) = match self.state {
+                -1 => (1, 4),
+                0 | 2 => (0, 128),
+                1 | 3 => (1, 128),
+                _ => bail!("Unexpected crypt init sequence"),
+            };
+            if is_server == 1 {
+                ensure!(
+                    self.handshake_buffer[0].is_empty(),
+                    "Unexpected packet from client"
+                );
+            } else {
+                ensure!(
+                    self.handshake_buffer[1].is_empty(),
+                    "Unexpected packet from server"
+                );
+            }
+            if self.handshake_buffer[is_server].len() < length {
+                return Ok(());
             }
-            3 => {
-                ensure!(is_server == 1, "Unexpected packet from client");
-                payload.read_exact(&mut self.server_key_2)?;
-
-                debug!("ClientKey1 {}", encode(&self.client_key_1));
-                debug!("ClientKey2 {}", encode(&self.client_key_2));
-                debug!("ServerKey1 {}", encode(&self.server_key_1));
-                debug!("ServerKey2 {}", encode(&self.server_key_2));
-
-                self.crypt_session = Some(CryptSession::new(
-                    [self.client_key_1.clone(), self.client_key_2.clone()],
-                    [self.server_key_1.clone(), self.server_key_2.clone()],
-                ));
-                self.state = 4;
-                info!("Crypt session initialized.");
+            let data: Vec<u8> = self.handshake_buffer[is_server].drain(..length).collect();
+
+            match self.state {
+                -1 => {
+                    let magic_word = LittleEndian::read_u32(&data);
+                    if magic_word != 1 {
+                        bail!("No magic word found in stream");
+                    }
+                    self.state = 0;
+                }
+                0 => {
+                    self.client_key_1 = data;
+                    self.state = 1;
+                }
+                1 => {
+                    self.server_key_1 = data;
+                    self.state = 2;
+                }
+                2 => {
+                    self.client_key_2 = data;
+                    self.state = 3;
+                }
+                _ => {
+                    self.server_key_2 = data;
+
+                    debug!("ClientKey1 {}", encode(&self.client_key_1));
+                    debug!("ClientKey2 {}", encode(&self.client_key_2));
+                    debug!("ServerKey1 {}", encode(&self.server_key_1));
+                    debug!("ServerKey2 {}", encode(&self.server_key_2));
+
+                    self.crypt_session = Some(CryptSession::new(
+                        [self.client_key_1.clone(), self.client_key_2.clone()],
+                        [self.server_key_1.clone(), self.server_key_2.clone()],
+                    ));
+                    self.state = 4;
+                    info!("Crypt session initialized.");
+                    return Ok(());
+                }
             }
-            _ => bail!("Unexpected crypt init sequence"),
         }
-        Ok(())
     }
 }
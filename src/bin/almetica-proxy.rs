@@ -0,0 +1,357 @@
+#![warn(clippy::all)]
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{ensure, Context};
+use async_macros::select;
+use async_std::io::timeout;
+use async_std::net::{TcpListener, TcpStream};
+use async_std::prelude::*;
+use async_std::task;
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+use chrono::Utc;
+use clap::Clap;
+use rand::rngs::OsRng;
+use rand_core::RngCore;
+use shipyard::EntityId;
+use tracing::{debug, error, info, info_span, warn};
+use tracing_futures::Instrument;
+use tracing_subscriber::filter::EnvFilter;
+use tracing_subscriber::fmt::Layer;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::registry::Registry;
+
+use almetica::config::{read_configuration, Configuration};
+use almetica::crypt::CryptSession;
+use almetica::ecs::event::{detached_connection_id, Event};
+use almetica::protocol::client::GameClient;
+use almetica::protocol::version::ProtocolVersion;
+use almetica::protocol::{split_integrity_header, INTEGRITY_HEADER_LENGTH, PACKET_HEADER_LENGTH};
+use almetica::Result;
+
+#[derive(Clap)]
+#[clap(version = "0.0.1", author = "Almetica <almetica@protonmail.com>")]
+struct Opts {
+    #[clap(short = "c", long = "config", default_value = "config.yaml")]
+    config: PathBuf,
+
+    /// Address of the upstream server (host:port).
+    #[clap(short = "u", long = "upstream")]
+    upstream: String,
+
+    /// Address the proxy listens on. Defaults to the configured hostname and game port.
+    #[clap(short = "l", long = "listen")]
+    listen: Option<String>,
+
+    /// Folder the session dumps are written into.
+    #[clap(short = "o", long = "output", default_value = ".")]
+    output: PathBuf,
+
+    /// Logs the decoded packets with the opcode mapping of the given client version.
+    #[clap(short = "p", long = "protocol")]
+    protocol: Option<String>,
+}
+
+#[async_std::main]
+async fn main() {
+    init_logging();
+
+    if let Err(e) = run().await {
+        error!("Error while executing program: {:?}", e);
+        process::exit(1);
+    }
+}
+
+fn init_logging() {
+    let fmt_layer = Layer::default().with_target(false);
+    let filter_layer = EnvFilter::from_default_env();
+    let subscriber = Registry::default().with(filter_layer).with(fmt_layer);
+    tracing::subscriber::set_global_default(subscriber).unwrap();
+}
+
+/// Accepts the connections of the clients and proxies them to the upstream server.
+/// Every session is written into it's own dump file, which can be read by `almetica-parse-stream`.
+async fn run() -> Result<()> {
+    let opts: Opts = Opts::parse();
+    let config = read_configuration(&opts.config).context(format!(
+        "Can't read configuration file {}",
+        &opts.config.display(),
+    ))?;
+
+    let decoder = match &opts.protocol {
+        Some(name) => Some(Arc::new(Decoder::new(&config, name)?)),
+        None => None,
+    };
+
+    fs::create_dir_all(&opts.output).context(format!(
+        "Can't create output folder {}",
+        opts.output.display()
+    ))?;
+
+    let listen_string = opts
+        .listen
+        .clone()
+        .unwrap_or_else(|| format!("{}:{}", config.server.hostname, config.server.game_port));
+    info!("listening on tcp://{}", listen_string);
+    let listener = TcpListener::bind(listen_string).await?;
+
+    loop {
+        match listener.accept().await {
+            Ok((socket, addr)) => {
+                let upstream = opts.upstream.clone();
+                let decoder = decoder.clone();
+                let path = opts.output.join(format!(
+                    "session-{}-{}.bin",
+                    Utc::now().format("%Y%m%d-%H%M%S"),
+                    addr.port()
+                ));
+
+                task::spawn(
+                    async move {
+                        info!("Incoming connection");
+                        match proxy_connection(socket, &upstream, &path, decoder).await {
+                            Ok(_) => info!("Connection closed"),
+                            Err(e) => warn!("Error while proxying connection: {:?}", e),
+                        }
+                    }
+                    .instrument(info_span!("socket", %addr)),
+                );
+            }
+            Err(e) => error!("Failed to open connection: {:?}", e),
+        }
+    }
+}
+
+/// Connects to the upstream server and forwards the packets of both sides until one of them
+/// closes the connection.
+async fn proxy_connection(
+    mut client: TcpStream,
+    upstream: &str,
+    path: &Path,
+    decoder: Option<Arc<Decoder>>,
+) -> Result<()> {
+    let mut server = TcpStream::connect(upstream)
+        .await
+        .context(format!("Can't connect to upstream server {}", upstream))?;
+    let server_session = GameClient::init_crypto(&mut server)
+        .await
+        .context("Can't exchange the keys with the upstream server")?;
+
+    info!("Writing session dump to {}", path.display());
+    let dump = Dump::create(path)?;
+    let client_session = accept_crypto(&mut client, &dump)
+        .await
+        .context("Can't exchange the keys with the client")?;
+
+    let client_session = Mutex::new(client_session);
+    let server_session = Mutex::new(server_session);
+
+    let client_to_server = forward(
+        false,
+        &client,
+        &server,
+        &client_session,
+        &server_session,
+        &dump,
+        &decoder,
+    );
+    let server_to_client = forward(
+        true,
+        &server,
+        &client,
+        &server_session,
+        &client_session,
+        &dump,
+        &decoder,
+    );
+    select!(client_to_server, server_to_client).await
+}
+
+/// Performs the key exchange of `GameSession::init_crypto` with the client. The proxy takes the
+/// role of the server and records the exchanged keys, so that the dump can be decrypted later.
+async fn accept_crypto(stream: &mut TcpStream, dump: &Dump) -> Result<CryptSession> {
+    let timeout_dur = Duration::from_secs(5);
+
+    let magic_word_buffer: [u8; 4] = [0x01, 0x00, 0x00, 0x00];
+    let mut client_key_1 = vec![0; 128];
+    let mut client_key_2 = vec![0; 128];
+    let mut server_key_1 = vec![0; 128];
+    let mut server_key_2 = vec![0; 128];
+
+    timeout(timeout_dur, stream.write_all(&magic_word_buffer))
+        .await
+        .context("Can't send magic word")?;
+    dump.write(true, &magic_word_buffer)?;
+
+    timeout(timeout_dur, stream.read_exact(&mut client_key_1))
+        .await
+        .context("Can't read client key 1")?;
+    dump.write(false, &client_key_1)?;
+
+    OsRng.fill_bytes(&mut server_key_1);
+    timeout(timeout_dur, stream.write_all(&server_key_1))
+        .await
+        .context("Can't write server key 1")?;
+    dump.write(true, &server_key_1)?;
+
+    timeout(timeout_dur, stream.read_exact(&mut client_key_2))
+        .await
+        .context("Can't read client key 2")?;
+    dump.write(false, &client_key_2)?;
+
+    OsRng.fill_bytes(&mut server_key_2);
+    timeout(timeout_dur, stream.write_all(&server_key_2))
+        .await
+        .context("Can't write server key 2")?;
+    dump.write(true, &server_key_2)?;
+    debug!("Exchanged keys with the client");
+
+    Ok(CryptSession::new(
+        [client_key_1, client_key_2],
+        [server_key_1, server_key_2],
+    ))
+}
+
+/// Forwards the packets of one side to the other side. Packets are decrypted with the session of
+/// the source and encrypted again with the session of the destination.
+async fn forward(
+    from_server: bool,
+    mut source: &TcpStream,
+    mut destination: &TcpStream,
+    source_session: &Mutex<CryptSession>,
+    destination_session: &Mutex<CryptSession>,
+    dump: &Dump,
+    decoder: &Option<Arc<Decoder>>,
+) -> Result<()> {
+    let mut header_buf = [0u8; 4];
+    loop {
+        match source.read_exact(&mut header_buf).await {
+            Ok(()) => {}
+            // Connection was closed
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+
+        let mut packet = header_buf.to_vec();
+        apply_crypt(source_session, from_server, &mut header_buf);
+        let packet_length = LittleEndian::read_u16(&header_buf[0..2]) as usize;
+        ensure!(
+            packet_length >= 4,
+            "Invalid packet length {}",
+            packet_length
+        );
+
+        packet.resize(packet_length, 0);
+        source.read_exact(&mut packet[4..]).await?;
+        if !from_server {
+            dump.write(false, &packet)?;
+        }
+
+        // The stream cipher already advanced over the header.
+        packet[..4].copy_from_slice(&header_buf);
+        apply_crypt(source_session, from_server, &mut packet[4..]);
+        if let Some(decoder) = decoder {
+            decoder.log(from_server, &packet);
+        }
+
+        apply_crypt(destination_session, from_server, &mut packet);
+        if from_server {
+            dump.write(true, &packet)?;
+        }
+        destination.write_all(&packet).await?;
+    }
+}
+
+fn apply_crypt(session: &Mutex<CryptSession>, from_server: bool, data: &mut [u8]) {
+    let mut session = session.lock().unwrap();
+    if from_server {
+        session.crypt_server_data(data);
+    } else {
+        session.crypt_client_data(data);
+    }
+}
+
+/// Writes the data of the client connection in the dump format of `almetica-parse-stream`:
+///    i8  server (1) or client (0)
+///    i64 length of packet data
+///    PACKET DATA BYTES
+///
+struct Dump {
+    file: Mutex<File>,
+}
+
+impl Dump {
+    fn create(path: &Path) -> Result<Dump> {
+        let file =
+            File::create(path).context(format!("Can't create dump file {}", path.display()))?;
+        Ok(Dump {
+            file: Mutex::new(file),
+        })
+    }
+
+    fn write(&self, from_server: bool, data: &[u8]) -> Result<()> {
+        let mut record = Vec::with_capacity(9 + data.len());
+        record.write_i8(from_server as i8)?;
+        record.write_i64::<LittleEndian>(data.len() as i64)?;
+        record.extend_from_slice(data);
+        self.file.lock().unwrap().write_all(&record)?;
+        Ok(())
+    }
+}
+
+/// Decodes the proxied packets for logging.
+struct Decoder {
+    version: ProtocolVersion,
+    connection_id: EntityId,
+}
+
+impl Decoder {
+    fn new(config: &Configuration, name: &str) -> Result<Decoder> {
+        Ok(Decoder {
+            version: ProtocolVersion::load(&config.data, Some(name))?,
+            connection_id: detached_connection_id(),
+        })
+    }
+
+    fn log(&self, from_server: bool, packet: &[u8]) {
+        let direction = if from_server { "S -> C" } else { "C -> S" };
+        let opcode = self.version.opcode_table[LittleEndian::read_u16(&packet[2..4]) as usize];
+        let mut packet_data = packet[PACKET_HEADER_LENGTH..].to_vec();
+        let mut data_offset = PACKET_HEADER_LENGTH;
+        // Only the client sends the integrity header.
+        if !from_server && self.version.integrity_opcodes.contains(&opcode) {
+            match split_integrity_header(packet_data) {
+                Ok((integrity_value, data)) => {
+                    debug!(
+                        "Integrity value of packet {:?}: {:#010x}",
+                        opcode, integrity_value
+                    );
+                    packet_data = data;
+                    data_offset += INTEGRITY_HEADER_LENGTH;
+                }
+                Err(e) => {
+                    warn!("{} {:?}: {}", direction, opcode, e);
+                    return;
+                }
+            }
+        }
+        match Event::decode_packet(
+            self.connection_id,
+            opcode,
+            packet_data,
+            data_offset,
+            self.version.layout,
+        ) {
+            Ok((event, _)) => match event.packet() {
+                Some(decoded) => info!("{} {:?}: {:?}", direction, opcode, decoded),
+                None => info!("{} {:?} ({} bytes)", direction, opcode, packet.len() - 4),
+            },
+            Err(_) => info!("{} {:?} ({} bytes)", direction, opcode, packet.len() - 4),
+        }
+    }
+}
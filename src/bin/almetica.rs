@@ -1,8 +1,8 @@
 #![warn(clippy::all)]
-use std::collections::HashMap;
 use std::path::PathBuf;
 use std::process;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{bail, Context};
 use async_macros::join;
@@ -20,15 +20,18 @@ use tracing_subscriber::registry::Registry;
 
 use almetica::config::{read_configuration, Configuration};
 use almetica::crypt::password_hash;
-use almetica::dataloader::load_opcode_mapping;
 use almetica::ecs::event::Event;
 use almetica::ecs::world::Multiverse;
+use almetica::gamedata::GameData;
+use almetica::metrics::Metrics;
 use almetica::model::embedded::migrations;
 use almetica::model::entity::Account;
-use almetica::model::repository::account;
+use almetica::model::repository::{account, user};
 use almetica::model::PasswordHashAlgorithm;
 use almetica::networkserver;
 use almetica::protocol::opcode::Opcode;
+use almetica::protocol::system_message::SystemMessageTable;
+use almetica::protocol::version::ProtocolRegistry;
 use almetica::webserver;
 use almetica::Result;
 use chrono::Utc;
@@ -131,17 +134,39 @@ async fn run_command(matches: &ArgMatches) -> Result<()> {
 }
 
 async fn start_server(_matches: &ArgMatches, config: &Configuration) -> Result<()> {
-    info!("Reading opcode mapping file");
-    let (opcode_mapping, reverse_opcode_mapping) = load_opcode_mapping(&config.data.path).context(
-        format!("Can't read opcode mapping file {:?}", &config.data.path),
-    )?;
+    info!("Loading game data");
+    let game_data = GameData::load(&config.data)
+        .context(format!("Can't load game data from {:?}", &config.data.path))?;
 
     info!(
-        "Loaded opcode mapping table with {} entries",
-        opcode_mapping
-            .iter()
-            .filter(|&op| *op != Opcode::UNKNOWN)
-            .count()
+        "Loaded game data with {} items, {} skills, {} NPCs and {} zones",
+        game_data.items.len(),
+        game_data.skills.len(),
+        game_data.npcs.len(),
+        game_data.zones.len()
+    );
+
+    let registry = ProtocolRegistry::from_game_data(&config.data, &game_data)
+        .context("Can't create the protocol registry")?;
+
+    for version in registry.versions() {
+        info!(
+            "Loaded client version {} ({}) with {} opcodes and {} integrity entries",
+            version.name,
+            version.version,
+            version
+                .opcode_table
+                .iter()
+                .filter(|&op| *op != Opcode::UNKNOWN)
+                .count(),
+            version.integrity_opcodes.len()
+        );
+    }
+
+    let system_messages = SystemMessageTable::new(game_data.system_messages.clone());
+    info!(
+        "Loaded system message table with {} entries",
+        system_messages.len()
     );
 
     info!("Running database migrations");
@@ -151,23 +176,27 @@ async fn start_server(_matches: &ArgMatches, config: &Configuration) -> Result<(
     let pool = sqlx_pool(&config).await?;
 
     info!("Starting the ECS multiverse");
-    let (multiverse_handle, global_tx_channel) = start_multiverse(config.clone(), pool.clone());
+    let (multiverse_handle, global_tx_channel, metrics) = start_multiverse(
+        config.clone(),
+        pool.clone(),
+        system_messages,
+        Arc::new(game_data),
+    );
+
+    info!("Starting the user purge task");
+    let purge_handle = start_user_purge(pool.clone());
 
     info!("Starting the web server");
-    let web_handle = start_web_server(pool, config.clone());
+    let web_handle = start_web_server(pool, config.clone(), metrics);
 
     info!("Starting the network server");
-    let network_handle = start_network_server(
-        global_tx_channel,
-        opcode_mapping,
-        reverse_opcode_mapping,
-        config.clone(),
-    );
+    let network_handle = start_network_server(global_tx_channel, registry, config.clone());
 
-    let (multiverse_res, web_server_res, network_server_res) =
-        join!(multiverse_handle, web_handle, network_handle).await;
+    let (multiverse_res, purge_res, web_server_res, network_server_res) =
+        join!(multiverse_handle, purge_handle, web_handle, network_handle).await;
 
     multiverse_res.context("Error while running the multiverse")?;
+    purge_res.context("Error while running the user purge task")?;
     web_server_res.context("Error while running the web server")?;
     network_server_res.context("Error while running the network server")?;
 
@@ -190,26 +219,50 @@ fn run_db_migrations(config: &Configuration) -> Result<()> {
     .context("Can't run migrations")
 }
 
-/// Starts the multiverse on a new thread and returns a channel into the global world.
+/// Starts the multiverse on a new thread and returns a channel into the global world and it's metrics.
 fn start_multiverse(
     config: Configuration,
     pool: PgPool,
-) -> (JoinHandle<Result<()>>, Sender<Arc<Event>>) {
+    system_messages: SystemMessageTable,
+    game_data: Arc<GameData>,
+) -> (JoinHandle<Result<()>>, Sender<Arc<Event>>, Arc<Metrics>) {
     let mut multiverse = Multiverse::new();
     let rx = multiverse.get_global_input_event_channel();
+    let metrics = multiverse.metrics();
 
     let join_handle = task::spawn_blocking(move || {
-        multiverse.run(pool, config);
+        multiverse.run(pool, config, system_messages, game_data);
         Ok(())
     });
 
-    (join_handle, rx)
+    (join_handle, rx, metrics)
+}
+
+/// Starts the task that periodically removes users whose deletion grace period has expired.
+fn start_user_purge(pool: PgPool) -> JoinHandle<Result<()>> {
+    task::spawn(async move {
+        loop {
+            match pool.acquire().await {
+                Ok(mut conn) => match user::delete_expired(&mut conn).await {
+                    Ok(0) => {}
+                    Ok(count) => info!("Purged {} users marked for deletion", count),
+                    Err(e) => warn!("Can't purge users marked for deletion: {:?}", e),
+                },
+                Err(e) => warn!("Can't acquire database connection for user purge: {:?}", e),
+            }
+            task::sleep(Duration::from_secs(60)).await;
+        }
+    })
 }
 
 /// Starts the web server handling all HTTP requests.
-fn start_web_server(pool: PgPool, config: Configuration) -> JoinHandle<Result<()>> {
+fn start_web_server(
+    pool: PgPool,
+    config: Configuration,
+    metrics: Arc<Metrics>,
+) -> JoinHandle<Result<()>> {
     task::spawn(async {
-        webserver::run(pool, config)
+        webserver::run(pool, config, metrics)
             .await
             .context("Can't run the web server")
     })
@@ -218,11 +271,10 @@ fn start_web_server(pool: PgPool, config: Configuration) -> JoinHandle<Result<()
 /// Starts the network server that handles all TCP game client connections.
 fn start_network_server(
     global_channel: Sender<Arc<Event>>,
-    map: Vec<Opcode>,
-    reverse_map: HashMap<Opcode, u16>,
+    registry: ProtocolRegistry,
     config: Configuration,
 ) -> JoinHandle<Result<()>> {
-    task::spawn(async { networkserver::run(global_channel, map, reverse_map, config).await })
+    task::spawn(async { networkserver::run(global_channel, registry, config).await })
 }
 
 fn tokio_postgres_config(config: &Configuration) -> tokio_postgres::Config {
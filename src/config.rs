@@ -4,6 +4,7 @@ use std::path::PathBuf;
 
 use serde::Deserialize;
 
+use crate::datacenter::Architecture;
 use crate::*;
 
 #[derive(Clone, Debug, Deserialize)]
@@ -21,6 +22,9 @@ pub struct ServerConfiguration {
     pub web_port: u16,
     #[serde(alias = "game-port")]
     pub game_port: u16,
+    /// Port on the loopback interface that serves the metrics. The metrics are disabled if unset.
+    #[serde(alias = "metrics-port", default)]
+    pub metrics_port: Option<u16>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -35,11 +39,67 @@ pub struct DatabaseConfiguration {
 #[derive(Clone, Debug, Deserialize)]
 pub struct DataConfiguration {
     pub path: PathBuf,
+    #[serde(default)]
+    pub datacenter: DataCenterConfiguration,
+    #[serde(default = "default_versions")]
+    pub versions: Vec<VersionConfiguration>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct DataCenterConfiguration {
+    pub file: PathBuf,
+    pub architecture: Architecture,
+}
+
+impl Default for DataCenterConfiguration {
+    fn default() -> Self {
+        Self {
+            file: PathBuf::from("DataCenter_Final_EUR.dat"),
+            architecture: Architecture::X64,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct VersionConfiguration {
+    pub name: String,
+    pub version: i32,
+    #[serde(alias = "skill-id-length")]
+    pub skill_id_length: Option<usize>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct GameConfiguration {
     pub pvp: bool,
+    #[serde(
+        alias = "max-users-per-account",
+        default = "default_max_users_per_account"
+    )]
+    pub max_users_per_account: i32,
+    #[serde(alias = "user-name-blacklist", default)]
+    pub user_name_blacklist: Vec<String>,
+    #[serde(
+        alias = "user-deletion-grace-hours",
+        default = "default_user_deletion_grace_hours"
+    )]
+    pub user_deletion_grace_hours: i32,
+}
+
+/// The client version that was supported before the versions were configurable.
+fn default_versions() -> Vec<VersionConfiguration> {
+    vec![VersionConfiguration {
+        name: "93.02".to_string(),
+        version: 366_222,
+        skill_id_length: None,
+    }]
+}
+
+fn default_max_users_per_account() -> i32 {
+    12
+}
+
+fn default_user_deletion_grace_hours() -> i32 {
+    24
 }
 
 pub fn read_configuration(path: &PathBuf) -> Result<Configuration> {
@@ -47,3 +107,83 @@ pub fn read_configuration(path: &PathBuf) -> Result<Configuration> {
     let configuration = serde_yaml::from_reader(f)?;
     Ok(configuration)
 }
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Returns a configuration that can be used inside tests.
+    pub fn get_configuration() -> Configuration {
+        Configuration {
+            server: ServerConfiguration {
+                hostname: "127.0.0.1".to_string(),
+                web_port: 8080,
+                game_port: 10001,
+                metrics_port: None,
+            },
+            database: DatabaseConfiguration {
+                hostname: "127.0.0.1".to_string(),
+                port: 5432,
+                username: "almetica".to_string(),
+                password: "almetica".to_string(),
+                database: "almetica".to_string(),
+            },
+            data: DataConfiguration {
+                path: PathBuf::from("."),
+                datacenter: DataCenterConfiguration {
+                    file: PathBuf::from("DataCenter_Final_EUR.dat"),
+                    architecture: Architecture::X64,
+                },
+                versions: vec![VersionConfiguration {
+                    name: "93.02".to_string(),
+                    version: 366_222,
+                    skill_id_length: None,
+                }],
+            },
+            game: GameConfiguration {
+                pvp: true,
+                max_users_per_account: 12,
+                user_name_blacklist: vec!["almetica".to_string()],
+                user_deletion_grace_hours: 24,
+            },
+        }
+    }
+
+    #[test]
+    fn test_read_configuration_without_new_keys() -> Result<()> {
+        let configuration: Configuration = serde_yaml::from_str(
+            r#"
+server:
+    hostname: 127.0.0.1
+    web-port: 8080
+    game-port: 10001
+database:
+    hostname: 127.0.0.1
+    port: 5432
+    username: almetica
+    password: almetica
+    database: almetica
+data:
+    path: /data
+game:
+    pvp: true
+"#,
+        )?;
+
+        assert_eq!(configuration.server.metrics_port, None);
+        assert_eq!(
+            configuration.data.datacenter.file,
+            PathBuf::from("DataCenter_Final_EUR.dat")
+        );
+        assert_eq!(
+            configuration.data.datacenter.architecture,
+            Architecture::X64
+        );
+        assert_eq!(configuration.data.versions.len(), 1);
+        assert_eq!(configuration.data.versions[0].version, 366_222);
+        assert_eq!(configuration.game.max_users_per_account, 12);
+        assert!(configuration.game.user_name_blacklist.is_empty());
+        assert_eq!(configuration.game.user_deletion_grace_hours, 24);
+        Ok(())
+    }
+}
@@ -0,0 +1,1091 @@
+/// Module that parses the datacenter file of the TERA client.
+///
+/// The datacenter is a tree of elements. Every element has a name, typed attributes and children.
+/// After the decryption and decompression (see `dataloader::read_datacenter_file`) the file
+/// contains the following regions:
+///
+/// ```text
+/// header         32 bytes (format version, timestamp, revision and unknown values)
+/// extensions     simple region with 16 byte entries (unused)
+/// attributes     segmented region of attributes
+/// elements       segmented region of elements (the first element is the root)
+/// values         string table with 1024 hash buckets (attribute values)
+/// names          string table with 512 hash buckets (element and attribute names)
+/// footer         u32 (always 0)
+/// ```
+///
+/// A simple region is an u32 count followed by the entries. A segmented region is an u32 count of
+/// segments, and every segment is an u32 capacity and an u32 count of used entries, followed by
+/// the entries for the whole capacity. Entries reference each other with an address that consists
+/// of the segment index and the entry index inside the segment (both u16).
+///
+/// A string table is a segmented region of UCS2 characters, the hash buckets (each a simple region
+/// of 16 byte entries) and a simple region with the address of every string. Names are referenced
+/// by their 1-based index in the address region, values directly by their address.
+///
+/// Elements can be queried with a simple path syntax, that selects children by name and
+/// optionally by the value of attributes:
+///
+/// ```ignore
+/// let items = datacenter.query("ItemData/Item[@id=100]")?;
+/// let name = items[0].string("name")?;
+/// ```
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use anyhow::{bail, ensure, Context};
+use byteorder::{ByteOrder, LittleEndian};
+use serde::Deserialize;
+
+use crate::dataloader::read_datacenter_file;
+use crate::Result;
+
+/// Length of the header in bytes.
+const HEADER_LENGTH: usize = 32;
+/// Length of an entry in the extension region.
+const EXTENSION_LENGTH: usize = 16;
+/// Length of an entry in the hash buckets of the string tables.
+const HASH_ENTRY_LENGTH: usize = 16;
+/// Number of hash buckets in the value string table.
+const VALUE_BUCKET_COUNT: usize = 1024;
+/// Number of hash buckets in the name string table.
+const NAME_BUCKET_COUNT: usize = 512;
+/// Name of the attribute that holds the text value of an element.
+const VALUE_ATTRIBUTE_NAME: &str = "__value__";
+
+/// Architecture of the client the datacenter was build for. The 64 bit client pads attributes and
+/// the addresses of elements to 8 bytes.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Architecture {
+    X86,
+    X64,
+}
+
+impl Architecture {
+    fn padding(self) -> usize {
+        match self {
+            Architecture::X86 => 0,
+            Architecture::X64 => 4,
+        }
+    }
+
+    fn attribute_length(self) -> usize {
+        8 + self.padding()
+    }
+
+    fn element_length(self) -> usize {
+        16 + 2 * self.padding()
+    }
+}
+
+/// Value of an attribute.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Value<'a> {
+    Int(i32),
+    Float(f32),
+    Bool(bool),
+    String(&'a str),
+}
+
+impl<'a> Value<'a> {
+    pub fn as_int(&self) -> Option<i32> {
+        match self {
+            Value::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_float(&self) -> Option<f32> {
+        match self {
+            Value::Float(value) => Some(*value),
+            // Integer values are valid floats too.
+            Value::Int(value) => Some(*value as f32),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&'a str> {
+        match self {
+            Value::String(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> fmt::Display for Value<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(value) => write!(f, "{}", value),
+            Value::Float(value) => write!(f, "{}", value),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::String(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// The parsed datacenter. All references inside the datacenter are validated while parsing, so
+/// that the elements can be accessed without further checks.
+#[derive(Debug)]
+pub struct DataCenter {
+    elements: Vec<Vec<RawElement>>,
+    attributes: Vec<Vec<RawAttribute>>,
+    values: Vec<String>,
+    names: Vec<String>,
+}
+
+impl DataCenter {
+    /// Decrypts, decompresses and parses the datacenter file of the client.
+    pub fn read(key: &[u8], iv: &[u8], data: Vec<u8>, architecture: Architecture) -> Result<Self> {
+        let data = read_datacenter_file(key, iv, data)?;
+        DataCenter::parse(&data, architecture)
+    }
+
+    /// Parses the decrypted and decompressed data of the datacenter.
+    pub fn parse(data: &[u8], architecture: Architecture) -> Result<Self> {
+        let mut reader = Reader::new(data);
+        let padding = architecture.padding();
+
+        reader.skip(HEADER_LENGTH).context("Can't read header")?;
+        read_simple_region(&mut reader, EXTENSION_LENGTH, |_| Ok(()))
+            .context("Can't read extension region")?;
+
+        let raw_attributes =
+            read_segmented_region(&mut reader, architecture.attribute_length(), |r| {
+                let name = r.read_u16()?;
+                let type_info = r.read_u16()?;
+                let value = r.read_u32()?;
+                r.skip(padding)?;
+                Ok((name, type_info, value))
+            })
+            .context("Can't read attribute region")?;
+
+        let raw_elements = read_segmented_region(&mut reader, architecture.element_length(), |r| {
+            let name = r.read_u16()?;
+            let _extended = r.read_u16()?;
+            let attribute_count = r.read_u16()?;
+            let child_count = r.read_u16()?;
+            let attributes = r.read_address()?;
+            r.skip(padding)?;
+            let children = r.read_address()?;
+            r.skip(padding)?;
+            Ok(RawElement {
+                name: name as usize,
+                attributes: Range::new(attributes, attribute_count),
+                children: Range::new(children, child_count),
+            })
+        })
+        .context("Can't read element region")?;
+
+        let values = StringTable::read(&mut reader, VALUE_BUCKET_COUNT)
+            .context("Can't read value string table")?;
+        let names = StringTable::read(&mut reader, NAME_BUCKET_COUNT)
+            .context("Can't read name string table")?;
+        let names = names.indexed_strings()?;
+
+        let footer = reader.read_u32().context("Can't read footer")?;
+        ensure!(footer == 0, "Invalid footer {}", footer);
+
+        let mut value_index = HashMap::with_capacity(values.strings.len());
+        let mut value_strings = Vec::with_capacity(values.strings.len());
+        for (address, value) in values.strings.into_iter() {
+            value_index.insert(address, value_strings.len());
+            value_strings.push(value);
+        }
+
+        let attributes = raw_attributes
+            .into_iter()
+            .map(|segment| {
+                segment
+                    .into_iter()
+                    .map(|(name, type_info, value)| {
+                        RawAttribute::new(name as usize, type_info, value, &value_index)
+                    })
+                    .collect::<Result<Vec<RawAttribute>>>()
+            })
+            .collect::<Result<Vec<Vec<RawAttribute>>>>()?;
+
+        let datacenter = DataCenter {
+            elements: raw_elements,
+            attributes,
+            values: value_strings,
+            names,
+        };
+        datacenter.validate()?;
+        Ok(datacenter)
+    }
+
+    /// Returns the root element of the tree.
+    pub fn root(&self) -> Element<'_> {
+        Element {
+            datacenter: self,
+            raw: &self.elements[0][0],
+        }
+    }
+
+    /// Returns all elements that match the path, starting at the children of the root element.
+    pub fn query(&self, path: &str) -> Result<Vec<Element<'_>>> {
+        self.root().query(path)
+    }
+
+    /// Checks that all names and ranges reference existing entries and that the elements form a
+    /// tree, in which every element is the child of at most one element.
+    fn validate(&self) -> Result<()> {
+        ensure!(
+            matches!(self.elements.first(), Some(segment) if !segment.is_empty()),
+            "Datacenter has no root element"
+        );
+
+        for segment in self.attributes.iter() {
+            for attribute in segment.iter() {
+                self.validate_name(attribute.name)?;
+            }
+        }
+
+        for segment in self.elements.iter() {
+            for element in segment.iter() {
+                self.validate_name(element.name)?;
+                validate_range(&element.attributes, &self.attributes)
+                    .context("Invalid attribute range of element")?;
+                validate_range(&element.children, &self.elements)
+                    .context("Invalid children range of element")?;
+            }
+        }
+
+        // Cycles would let the traversal of the tree run forever.
+        let mut visited = HashSet::new();
+        let mut stack = vec![(0, 0)];
+        while let Some((segment, index)) = stack.pop() {
+            ensure!(
+                visited.insert((segment, index)),
+                "Element {} of segment {} is referenced more than once",
+                index,
+                segment
+            );
+            let children = &self.elements[segment][index].children;
+            stack.extend(
+                (children.start..children.start + children.count).map(|i| (children.segment, i)),
+            );
+        }
+        Ok(())
+    }
+
+    fn validate_name(&self, index: usize) -> Result<()> {
+        ensure!(
+            index > 0 && index <= self.names.len(),
+            "Invalid name index {}",
+            index
+        );
+        Ok(())
+    }
+
+    fn name(&self, index: usize) -> &str {
+        &self.names[index - 1]
+    }
+}
+
+/// An element of the datacenter tree.
+#[derive(Clone, Copy)]
+pub struct Element<'a> {
+    datacenter: &'a DataCenter,
+    raw: &'a RawElement,
+}
+
+impl<'a> Element<'a> {
+    /// Returns the name of the element.
+    pub fn name(&self) -> &'a str {
+        self.datacenter.name(self.raw.name)
+    }
+
+    /// Returns the text value of the element.
+    pub fn value(&self) -> Option<&'a str> {
+        self.raw_attributes()
+            .iter()
+            .find(|attribute| self.datacenter.name(attribute.name) == VALUE_ATTRIBUTE_NAME)
+            .and_then(|attribute| self.resolve(attribute).as_str())
+    }
+
+    /// Returns all attributes of the element (without the text value).
+    pub fn attributes(&self) -> Vec<(&'a str, Value<'a>)> {
+        self.raw_attributes()
+            .iter()
+            .map(|attribute| {
+                (
+                    self.datacenter.name(attribute.name),
+                    self.resolve(attribute),
+                )
+            })
+            .filter(|(name, _)| *name != VALUE_ATTRIBUTE_NAME)
+            .collect()
+    }
+
+    /// Returns the attribute with the given name.
+    pub fn attribute(&self, name: &str) -> Option<Value<'a>> {
+        self.raw_attributes()
+            .iter()
+            .find(|attribute| self.datacenter.name(attribute.name) == name)
+            .map(|attribute| self.resolve(attribute))
+    }
+
+    /// Returns the integer attribute with the given name.
+    pub fn int(&self, name: &str) -> Result<i32> {
+        self.typed_attribute(name, "int", Value::as_int)
+    }
+
+    /// Returns the float attribute with the given name.
+    pub fn float(&self, name: &str) -> Result<f32> {
+        self.typed_attribute(name, "float", Value::as_float)
+    }
+
+    /// Returns the boolean attribute with the given name.
+    pub fn bool(&self, name: &str) -> Result<bool> {
+        self.typed_attribute(name, "bool", Value::as_bool)
+    }
+
+    /// Returns the string attribute with the given name.
+    pub fn string(&self, name: &str) -> Result<&'a str> {
+        self.typed_attribute(name, "string", Value::as_str)
+    }
+
+    /// Returns all children of the element.
+    pub fn children(&self) -> Vec<Element<'a>> {
+        entries(&self.raw.children, &self.datacenter.elements)
+            .iter()
+            .map(|raw| Element {
+                datacenter: self.datacenter,
+                raw,
+            })
+            .collect()
+    }
+
+    /// Returns the first child with the given name.
+    pub fn child(&self, name: &str) -> Option<Element<'a>> {
+        self.children()
+            .into_iter()
+            .find(|child| child.name() == name)
+    }
+
+    /// Returns all elements that match the path, starting at the children of this element.
+    ///
+    /// A path consists of steps separated by `/`. Every step selects the children with the given
+    /// name (`*` selects all children) and can filter them with predicates like `[@id=100]`.
+    pub fn query(&self, path: &str) -> Result<Vec<Element<'a>>> {
+        let steps = parse_path(path)?;
+        let mut current = vec![*self];
+        for step in steps.iter() {
+            current = current
+                .iter()
+                .flat_map(|element| element.children())
+                .filter(|child| step.matches(child))
+                .collect();
+        }
+        Ok(current)
+    }
+
+    fn raw_attributes(&self) -> &'a [RawAttribute] {
+        entries(&self.raw.attributes, &self.datacenter.attributes)
+    }
+
+    fn resolve(&self, attribute: &RawAttribute) -> Value<'a> {
+        match attribute.value {
+            RawValue::Int(value) => Value::Int(value),
+            RawValue::Float(value) => Value::Float(value),
+            RawValue::Bool(value) => Value::Bool(value),
+            RawValue::String(index) => Value::String(&self.datacenter.values[index]),
+        }
+    }
+
+    fn typed_attribute<T>(
+        &self,
+        name: &str,
+        type_name: &str,
+        convert: fn(&Value<'a>) -> Option<T>,
+    ) -> Result<T> {
+        let value = self.attribute(name).context(format!(
+            "Element {} has no attribute {}",
+            self.name(),
+            name
+        ))?;
+        convert(&value).context(format!(
+            "Attribute {} of element {} is not a {}: {:?}",
+            name,
+            self.name(),
+            type_name,
+            value
+        ))
+    }
+}
+
+impl<'a> fmt::Debug for Element<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Element")
+            .field("name", &self.name())
+            .field("attributes", &self.attributes())
+            .finish()
+    }
+}
+
+/// Address of an entry inside a segmented region.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+struct Address {
+    segment: u16,
+    element: u16,
+}
+
+/// Range of entries inside a segment.
+#[derive(Debug)]
+struct Range {
+    segment: usize,
+    start: usize,
+    count: usize,
+}
+
+impl Range {
+    fn new(address: Address, count: u16) -> Self {
+        Range {
+            segment: address.segment as usize,
+            start: address.element as usize,
+            count: count as usize,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct RawElement {
+    name: usize,
+    attributes: Range,
+    children: Range,
+}
+
+#[derive(Debug)]
+struct RawAttribute {
+    name: usize,
+    value: RawValue,
+}
+
+impl RawAttribute {
+    fn new(
+        name: usize,
+        type_info: u16,
+        value: u32,
+        value_index: &HashMap<Address, usize>,
+    ) -> Result<Self> {
+        let value = match type_info & 0b11 {
+            1 if (type_info >> 2) & 1 == 1 => RawValue::Bool(value != 0),
+            1 => RawValue::Int(value as i32),
+            2 => RawValue::Float(f32::from_bits(value)),
+            3 => {
+                let address = Address {
+                    segment: value as u16,
+                    element: (value >> 16) as u16,
+                };
+                let index = value_index
+                    .get(&address)
+                    .context(format!("Invalid string address {:?}", address))?;
+                RawValue::String(*index)
+            }
+            code => bail!("Unknown attribute type code {}", code),
+        };
+        Ok(RawAttribute { name, value })
+    }
+}
+
+#[derive(Debug)]
+enum RawValue {
+    Int(i32),
+    Float(f32),
+    Bool(bool),
+    String(usize),
+}
+
+/// A string table with the strings of all segments and the addresses of the indexed strings.
+struct StringTable {
+    strings: Vec<(Address, String)>,
+    addresses: Vec<Address>,
+}
+
+impl StringTable {
+    fn read(reader: &mut Reader, bucket_count: usize) -> Result<Self> {
+        let segments = read_segmented_region(reader, 2, |r| r.read_u16())?;
+        for _ in 0..bucket_count {
+            read_simple_region(reader, HASH_ENTRY_LENGTH, |_| Ok(()))?;
+        }
+        let addresses = read_simple_region(reader, 4, |r| r.read_address())?;
+
+        // Every string starts at the beginning of a segment or after the terminator of the
+        // previous string.
+        let mut strings = Vec::new();
+        for (segment_index, segment) in segments.iter().enumerate() {
+            let mut start = 0;
+            for (i, c) in segment.iter().enumerate() {
+                if *c == 0 {
+                    let string = String::from_utf16(&segment[start..i]).context(format!(
+                        "Invalid string in segment {} at {}",
+                        segment_index, start
+                    ))?;
+                    let address = Address {
+                        segment: segment_index as u16,
+                        element: start as u16,
+                    };
+                    strings.push((address, string));
+                    start = i + 1;
+                }
+            }
+        }
+
+        Ok(StringTable { strings, addresses })
+    }
+
+    /// Returns the strings in the order of the address region.
+    fn indexed_strings(&self) -> Result<Vec<String>> {
+        let lookup: HashMap<&Address, &String> = self.strings.iter().map(|(a, s)| (a, s)).collect();
+        self.addresses
+            .iter()
+            .map(|address| {
+                lookup
+                    .get(address)
+                    .map(|s| s.to_string())
+                    .context(format!("Invalid string address {:?}", address))
+            })
+            .collect()
+    }
+}
+
+/// Returns the entries of a validated range.
+fn entries<'a, T>(range: &Range, region: &'a [Vec<T>]) -> &'a [T] {
+    if range.count == 0 {
+        return &[];
+    }
+    &region[range.segment][range.start..range.start + range.count]
+}
+
+fn validate_range<T>(range: &Range, region: &[Vec<T>]) -> Result<()> {
+    if range.count == 0 {
+        return Ok(());
+    }
+    let segment = region
+        .get(range.segment)
+        .context(format!("Invalid segment {}", range.segment))?;
+    ensure!(
+        range.start + range.count <= segment.len(),
+        "Range {}..{} is outside of segment {} with {} entries",
+        range.start,
+        range.start + range.count,
+        range.segment,
+        segment.len()
+    );
+    Ok(())
+}
+
+fn read_simple_region<T, F>(reader: &mut Reader, size: usize, mut read: F) -> Result<Vec<T>>
+where
+    F: FnMut(&mut Reader) -> Result<T>,
+{
+    let count = reader.read_u32()? as usize;
+    ensure!(
+        reader.remaining() / size >= count,
+        "Region with {} entries is larger than the remaining data",
+        count
+    );
+    (0..count).map(|_| read(reader)).collect()
+}
+
+fn read_segmented_region<T, F>(reader: &mut Reader, size: usize, mut read: F) -> Result<Vec<Vec<T>>>
+where
+    F: FnMut(&mut Reader) -> Result<T>,
+{
+    let count = reader.read_u32()? as usize;
+    let mut segments = Vec::new();
+    for _ in 0..count {
+        let capacity = reader.read_u32()? as usize;
+        let used = reader.read_u32()? as usize;
+        ensure!(
+            used <= capacity,
+            "Segment uses {} of {} entries",
+            used,
+            capacity
+        );
+        ensure!(
+            reader.remaining() / size >= capacity,
+            "Segment with {} entries is larger than the remaining data",
+            capacity
+        );
+        let entries = (0..used)
+            .map(|_| read(reader))
+            .collect::<Result<Vec<T>>>()?;
+        reader.skip((capacity - used) * size)?;
+        segments.push(entries);
+    }
+    Ok(segments)
+}
+
+/// Bounds checked reader of the little endian datacenter data.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data, pos: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    fn read(&mut self, size: usize) -> Result<&'a [u8]> {
+        ensure!(
+            size <= self.remaining(),
+            "Unexpected end of data at {} while reading {} bytes",
+            self.pos,
+            size
+        );
+        let data = &self.data[self.pos..self.pos + size];
+        self.pos += size;
+        Ok(data)
+    }
+
+    fn skip(&mut self, size: usize) -> Result<()> {
+        self.read(size).map(|_| ())
+    }
+
+    fn read_u16(&mut self) -> Result<u16> {
+        Ok(LittleEndian::read_u16(self.read(2)?))
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        Ok(LittleEndian::read_u32(self.read(4)?))
+    }
+
+    fn read_address(&mut self) -> Result<Address> {
+        let segment = self.read_u16()?;
+        let element = self.read_u16()?;
+        Ok(Address { segment, element })
+    }
+}
+
+/// A step of a query path.
+#[derive(Debug, PartialEq)]
+struct Step {
+    name: String,
+    predicates: Vec<(String, String)>,
+}
+
+impl Step {
+    fn matches(&self, element: &Element) -> bool {
+        (self.name == "*" || self.name == element.name())
+            && self.predicates.iter().all(|(name, expected)| {
+                matches!(element.attribute(name), Some(value) if value.to_string() == *expected)
+            })
+    }
+}
+
+fn parse_path(path: &str) -> Result<Vec<Step>> {
+    path.split('/')
+        .filter(|step| !step.is_empty())
+        .map(parse_step)
+        .collect()
+}
+
+fn parse_step(step: &str) -> Result<Step> {
+    let (name, mut rest) = match step.find('[') {
+        Some(i) => (&step[..i], &step[i..]),
+        None => (step, ""),
+    };
+    ensure!(!name.is_empty(), "Step {} has no element name", step);
+
+    let mut predicates = Vec::new();
+    while !rest.is_empty() {
+        ensure!(
+            rest.starts_with("[@"),
+            "Expected predicate in step {}",
+            step
+        );
+        let end = rest
+            .find(']')
+            .context(format!("Predicate of step {} is not closed", step))?;
+        let predicate = &rest[2..end];
+        let equals = predicate
+            .find('=')
+            .context(format!("Predicate of step {} has no value", step))?;
+        let value = predicate[equals + 1..].trim_matches(|c| c == '"' || c == '\'');
+        predicates.push((predicate[..equals].to_string(), value.to_string()));
+        rest = &rest[end + 1..];
+    }
+
+    Ok(Step {
+        name: name.to_string(),
+        predicates,
+    })
+}
+
+/// Writes datacenter files for the tests.
+#[cfg(test)]
+pub(crate) mod builder {
+    use byteorder::WriteBytesExt;
+
+    use super::*;
+
+    pub(crate) enum TestValue {
+        Int(i32),
+        Float(f32),
+        Bool(bool),
+        String(&'static str),
+    }
+
+    pub(crate) struct TestElement {
+        name: &'static str,
+        attributes: Vec<(&'static str, TestValue)>,
+        children: Vec<TestElement>,
+    }
+
+    pub(crate) fn element(
+        name: &'static str,
+        attributes: Vec<(&'static str, TestValue)>,
+        children: Vec<TestElement>,
+    ) -> TestElement {
+        TestElement {
+            name,
+            attributes,
+            children,
+        }
+    }
+
+    /// Writes the tree in the datacenter format. Every region has a single segment.
+    pub(crate) struct Writer {
+        architecture: Architecture,
+        elements: Vec<(u16, u16, u16, u16, u16)>,
+        attributes: Vec<(u16, u16, u32)>,
+        values: Vec<u16>,
+        names: Vec<String>,
+    }
+
+    impl Writer {
+        pub(crate) fn write(root: &TestElement, architecture: Architecture) -> Vec<u8> {
+            let mut writer = Writer {
+                architecture,
+                elements: vec![(0, 0, 0, 0, 0)],
+                attributes: Vec::new(),
+                values: Vec::new(),
+                names: Vec::new(),
+            };
+            writer.elements[0] = writer.add_element(root);
+            writer.finish()
+        }
+
+        fn name(&mut self, name: &str) -> u16 {
+            match self.names.iter().position(|n| n == name) {
+                Some(i) => i as u16 + 1,
+                None => {
+                    self.names.push(name.to_string());
+                    self.names.len() as u16
+                }
+            }
+        }
+
+        fn add_element(&mut self, element: &TestElement) -> (u16, u16, u16, u16, u16) {
+            let name = self.name(element.name);
+
+            let attribute_start = self.attributes.len() as u16;
+            for (attribute_name, value) in element.attributes.iter() {
+                let attribute_name = self.name(attribute_name);
+                let (type_info, value) = match value {
+                    TestValue::Int(v) => (1, *v as u32),
+                    TestValue::Float(v) => (2, v.to_bits()),
+                    TestValue::Bool(v) => (0b101, *v as u32),
+                    TestValue::String(v) => {
+                        let start = self.values.len() as u32;
+                        self.values.extend(v.encode_utf16());
+                        self.values.push(0);
+                        (3, start << 16)
+                    }
+                };
+                self.attributes.push((attribute_name, type_info, value));
+            }
+
+            let children_start = self.elements.len();
+            self.elements
+                .extend(element.children.iter().map(|_| (0, 0, 0, 0, 0)));
+            for (i, child) in element.children.iter().enumerate() {
+                self.elements[children_start + i] = self.add_element(child);
+            }
+
+            (
+                name,
+                element.attributes.len() as u16,
+                element.children.len() as u16,
+                attribute_start,
+                children_start as u16,
+            )
+        }
+
+        fn finish(self) -> Vec<u8> {
+            let padding = vec![0u8; self.architecture.padding()];
+            let mut data = vec![0u8; HEADER_LENGTH];
+
+            // Extensions
+            data.write_u32::<LittleEndian>(0).unwrap();
+
+            write_segment_header(&mut data, self.attributes.len());
+            for (name, type_info, value) in self.attributes.iter() {
+                data.write_u16::<LittleEndian>(*name).unwrap();
+                data.write_u16::<LittleEndian>(*type_info).unwrap();
+                data.write_u32::<LittleEndian>(*value).unwrap();
+                data.extend(&padding);
+            }
+
+            write_segment_header(&mut data, self.elements.len());
+            for (name, attribute_count, child_count, attributes, children) in self.elements.iter() {
+                data.write_u16::<LittleEndian>(*name).unwrap();
+                data.write_u16::<LittleEndian>(0).unwrap();
+                data.write_u16::<LittleEndian>(*attribute_count).unwrap();
+                data.write_u16::<LittleEndian>(*child_count).unwrap();
+                data.write_u32::<LittleEndian>((*attributes as u32) << 16)
+                    .unwrap();
+                data.extend(&padding);
+                data.write_u32::<LittleEndian>((*children as u32) << 16)
+                    .unwrap();
+                data.extend(&padding);
+            }
+
+            write_string_table(&mut data, &self.values, &[], VALUE_BUCKET_COUNT);
+
+            let mut names = Vec::new();
+            let mut addresses = Vec::new();
+            for name in self.names.iter() {
+                addresses.push(names.len() as u32);
+                names.extend(name.encode_utf16());
+                names.push(0);
+            }
+            write_string_table(&mut data, &names, &addresses, NAME_BUCKET_COUNT);
+
+            // Footer
+            data.write_u32::<LittleEndian>(0).unwrap();
+            data
+        }
+    }
+
+    fn write_segment_header(data: &mut Vec<u8>, used: usize) {
+        data.write_u32::<LittleEndian>(1).unwrap();
+        data.write_u32::<LittleEndian>(used as u32).unwrap();
+        data.write_u32::<LittleEndian>(used as u32).unwrap();
+    }
+
+    fn write_string_table(data: &mut Vec<u8>, chars: &[u16], starts: &[u32], buckets: usize) {
+        write_segment_header(data, chars.len());
+        for c in chars.iter() {
+            data.write_u16::<LittleEndian>(*c).unwrap();
+        }
+        for _ in 0..buckets {
+            data.write_u32::<LittleEndian>(0).unwrap();
+        }
+        data.write_u32::<LittleEndian>(starts.len() as u32).unwrap();
+        for start in starts.iter() {
+            data.write_u32::<LittleEndian>(start << 16).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::builder::*;
+    use super::*;
+
+    fn test_tree() -> TestElement {
+        element(
+            "__root__",
+            vec![],
+            vec![
+                element(
+                    "ItemData",
+                    vec![],
+                    vec![
+                        element(
+                            "Item",
+                            vec![
+                                ("id", TestValue::Int(100)),
+                                ("name", TestValue::String("Sword")),
+                                ("weight", TestValue::Float(1.5)),
+                                ("tradable", TestValue::Bool(true)),
+                            ],
+                            vec![],
+                        ),
+                        element(
+                            "Item",
+                            vec![
+                                ("id", TestValue::Int(101)),
+                                ("name", TestValue::String("Shield")),
+                                ("weight", TestValue::Int(3)),
+                                ("tradable", TestValue::Bool(false)),
+                            ],
+                            vec![element(
+                                "Description",
+                                vec![("__value__", TestValue::String("A round shield"))],
+                                vec![],
+                            )],
+                        ),
+                    ],
+                ),
+                element(
+                    "ZoneData",
+                    vec![],
+                    vec![element(
+                        "Zone",
+                        vec![
+                            ("id", TestValue::Int(13)),
+                            ("name", TestValue::String("Island")),
+                        ],
+                        vec![],
+                    )],
+                ),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_parse_tree() -> Result<()> {
+        for architecture in &[Architecture::X86, Architecture::X64] {
+            let data = Writer::write(&test_tree(), *architecture);
+            let datacenter = DataCenter::parse(&data, *architecture)?;
+
+            let root = datacenter.root();
+            assert_eq!(root.name(), "__root__");
+            let children: Vec<&str> = root.children().iter().map(|c| c.name()).collect();
+            assert_eq!(children, vec!["ItemData", "ZoneData"]);
+
+            let item_data = root.child("ItemData").unwrap();
+            let items = item_data.children();
+            assert_eq!(items.len(), 2);
+            assert_eq!(items[0].int("id")?, 100);
+            assert_eq!(items[0].string("name")?, "Sword");
+            assert!((items[0].float("weight")? - 1.5).abs() < 0.001);
+            assert!((items[1].float("weight")? - 3.0).abs() < 0.001);
+            assert!(items[0].bool("tradable")?);
+            assert!(!items[1].bool("tradable")?);
+            assert_eq!(items[1].attributes().len(), 4);
+
+            assert!(items[0].int("name").is_err());
+            assert!(items[0].int("missing").is_err());
+
+            let description = items[1].child("Description").unwrap();
+            assert_eq!(description.value(), Some("A round shield"));
+            assert!(description.attributes().is_empty());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_query() -> Result<()> {
+        let data = Writer::write(&test_tree(), Architecture::X86);
+        let datacenter = DataCenter::parse(&data, Architecture::X86)?;
+
+        assert_eq!(datacenter.query("ItemData/Item")?.len(), 2);
+        assert_eq!(datacenter.query("*/*")?.len(), 3);
+        assert_eq!(datacenter.query("ZoneData/Item")?.len(), 0);
+
+        let items = datacenter.query("ItemData/Item[@id=101]")?;
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].string("name")?, "Shield");
+
+        let items = datacenter.query("/ItemData/Item[@name='Sword'][@tradable=true]")?;
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].int("id")?, 100);
+
+        let descriptions = datacenter.query("ItemData/Item[@id=101]/Description")?;
+        assert_eq!(descriptions.len(), 1);
+
+        let zones = datacenter
+            .root()
+            .child("ZoneData")
+            .unwrap()
+            .query("Zone[@id=13]")?;
+        assert_eq!(zones[0].string("name")?, "Island");
+
+        assert!(datacenter.query("ItemData/Item[id=101]").is_err());
+        assert!(datacenter.query("ItemData/Item[@id=101").is_err());
+        assert!(datacenter.query("ItemData/[@id=101]").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_malformed_data() {
+        let data = Writer::write(&test_tree(), Architecture::X86);
+        for length in 0..data.len() {
+            assert!(DataCenter::parse(&data[..length], Architecture::X86).is_err());
+        }
+        assert!(DataCenter::parse(&data, Architecture::X64).is_err());
+    }
+
+    #[test]
+    fn test_parse_invalid_references() {
+        let mut data = Writer::write(&test_tree(), Architecture::X86);
+        // The first attribute is directly after the header, the extension region and the
+        // segment header. Point it's name outside of the name table.
+        let offset = HEADER_LENGTH + 4 + 12;
+        LittleEndian::write_u16(&mut data[offset..], 1000);
+        assert!(DataCenter::parse(&data, Architecture::X86).is_err());
+    }
+
+    #[test]
+    fn test_validate_self_referencing_child() {
+        let element = |children: Range| RawElement {
+            name: 1,
+            attributes: Range {
+                segment: 0,
+                start: 0,
+                count: 0,
+            },
+            children,
+        };
+        let mut datacenter = DataCenter {
+            elements: vec![vec![element(Range {
+                segment: 0,
+                start: 0,
+                count: 0,
+            })]],
+            attributes: vec![],
+            values: vec![],
+            names: vec!["__root__".to_string()],
+        };
+        assert!(datacenter.validate().is_ok());
+
+        // The root element is it's own child.
+        datacenter.elements[0][0] = element(Range {
+            segment: 0,
+            start: 0,
+            count: 1,
+        });
+        assert!(datacenter.validate().is_err());
+
+        // Both children of the root element point to the same element.
+        datacenter.elements = vec![
+            vec![element(Range {
+                segment: 1,
+                start: 0,
+                count: 2,
+            })],
+            vec![
+                element(Range {
+                    segment: 1,
+                    start: 1,
+                    count: 1,
+                }),
+                element(Range {
+                    segment: 0,
+                    start: 0,
+                    count: 0,
+                }),
+            ],
+        ];
+        assert!(datacenter.validate().is_err());
+    }
+}
@@ -1,5 +1,5 @@
 /// Module to read data files
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::{BufReader, Read};
 use std::path::PathBuf;
@@ -10,6 +10,7 @@ use byteorder::{ByteOrder, LittleEndian};
 use cfb_mode::stream_cipher::{NewStreamCipher, StreamCipher};
 use cfb_mode::Cfb;
 use flate2::{Decompress, FlushDecompress};
+use serde::Deserialize;
 
 use crate::protocol::opcode::Opcode;
 use crate::*;
@@ -42,6 +43,37 @@ pub fn read_datacenter_file(key: &[u8], iv: &[u8], mut data: Vec<u8>) -> Result<
     Ok(buffer)
 }
 
+/// The key and IV of the datacenter file as hex strings.
+#[derive(Deserialize)]
+struct DataCenterKey {
+    key: String,
+    iv: String,
+}
+
+/// Load the key and IV of the datacenter file from a file.
+pub fn load_datacenter_key(data_path: &PathBuf) -> Result<(Vec<u8>, Vec<u8>)> {
+    let mut path = data_path.clone();
+    path.push("key.yaml");
+    let file = File::open(path)?;
+    let mut buffered = BufReader::new(file);
+    read_datacenter_key(&mut buffered)
+}
+
+/// Read the key file and returns the decoded key and IV.
+pub fn read_datacenter_key<T: ?Sized>(reader: &mut T) -> Result<(Vec<u8>, Vec<u8>)>
+where
+    T: Read,
+{
+    let key_file: DataCenterKey = serde_yaml::from_reader(reader)?;
+    let key = hex::decode(key_file.key.trim())?;
+    let iv = hex::decode(key_file.iv.trim())?;
+    ensure!(
+        key.len() == 16 && iv.len() == 16,
+        "KEY and IV must be 128 bits long (16 bytes)"
+    );
+    Ok((key, iv))
+}
+
 /// Load opcode mapping from a file (normal and reverse lookup)
 pub fn load_opcode_mapping(data_path: &PathBuf) -> Result<(Vec<Opcode>, HashMap<Opcode, u16>)> {
     let mut path = data_path.clone();
@@ -67,6 +99,42 @@ where
     Ok(opcode_table)
 }
 
+/// Load the list of packets that contain an integrity header (client version >= 93).
+pub fn load_integrity_list(data_path: &PathBuf) -> Result<HashSet<Opcode>> {
+    let mut path = data_path.clone();
+    path.push("integrity.yaml");
+    let file = File::open(path)?;
+    let mut buffered = BufReader::new(file);
+    read_integrity_list(&mut buffered)
+}
+
+/// Read the integrity list file and returns the opcodes of all packets in it.
+pub fn read_integrity_list<T: ?Sized>(reader: &mut T) -> Result<HashSet<Opcode>>
+where
+    T: Read,
+{
+    let integrity_list: Vec<Opcode> = serde_yaml::from_reader(reader)?;
+    Ok(integrity_list.into_iter().collect())
+}
+
+/// Load the names of the system messages from a file.
+pub fn load_system_messages(data_path: &PathBuf) -> Result<Vec<String>> {
+    let mut path = data_path.clone();
+    path.push("messages.yaml");
+    let file = File::open(path)?;
+    let mut buffered = BufReader::new(file);
+    read_system_messages(&mut buffered)
+}
+
+/// Read the system message file and returns the names of all messages in the order of the client.
+pub fn read_system_messages<T: ?Sized>(reader: &mut T) -> Result<Vec<String>>
+where
+    T: Read,
+{
+    let names: Vec<String> = serde_yaml::from_reader(reader)?;
+    Ok(names)
+}
+
 pub fn calculate_reverse_map(opcode_mapping: &[Opcode]) -> HashMap<Opcode, u16> {
     let mut c: i32 = -1;
     let mut reverse_opcode_mapping = opcode_mapping
@@ -93,6 +161,7 @@ mod tests {
     use rand_core::RngCore;
 
     use super::super::protocol::opcode::Opcode;
+    use super::super::protocol::system_message::SystemMessageTable;
     use super::super::*;
     use super::*;
 
@@ -122,6 +191,72 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_integrity_list_creation() -> Result<()> {
+        let mut file = Vec::new();
+        file.write_all(
+            "
+                - C_PLAYER_LOCATION
+                - C_CHAT
+                - C_PLAYER_LOCATION
+                "
+            .as_bytes(),
+        )?;
+
+        let list = read_integrity_list(&mut file.as_slice())?;
+
+        assert_eq!(list.len(), 2);
+        assert!(list.contains(&Opcode::C_PLAYER_LOCATION));
+        assert!(list.contains(&Opcode::C_CHAT));
+        assert!(!list.contains(&Opcode::C_WHISPER));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_system_message_reading() -> Result<()> {
+        let mut file = Vec::new();
+        file.write_all(
+            "
+                - SMT_UNDEFINED
+                - SMT_LOBBY_CANNOT_CONNECT
+                - SMT_GENERAL_NOT_IN_THE_WORLD
+                "
+            .as_bytes(),
+        )?;
+
+        let table = SystemMessageTable::new(read_system_messages(&mut file.as_slice())?);
+
+        assert_eq!(table.len(), 3);
+        assert_eq!(table.id("SMT_UNDEFINED"), Some(0));
+        assert_eq!(table.id("SMT_GENERAL_NOT_IN_THE_WORLD"), Some(2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_datacenter_key_reading() -> Result<()> {
+        let mut file = Vec::new();
+        file.write_all(
+            "
+                key: E1B1C4666F64681889BC8A5594387E2D
+                iv: 1F494C6BB424C916CA44BB1C64CEAA41
+                "
+            .as_bytes(),
+        )?;
+
+        let (key, iv) = read_datacenter_key(&mut file.as_slice())?;
+
+        assert_eq!(key, hex::decode("E1B1C4666F64681889BC8A5594387E2D")?);
+        assert_eq!(iv, hex::decode("1F494C6BB424C916CA44BB1C64CEAA41")?);
+
+        let mut file = Vec::new();
+        file.write_all("key: E1B1\niv: 1F49".as_bytes())?;
+        assert!(read_datacenter_key(&mut file.as_slice()).is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn test_read_datacenter_file() -> Result<()> {
         let size = 1024 * 1024;
@@ -1,10 +1,11 @@
 /// Module holds the components that the ECS use.
+use std::collections::{HashMap, VecDeque};
 use std::time::Instant;
 
 use shipyard::EntityId;
 
 use crate::ecs::event::EcsEvent;
-use crate::model::Region;
+use crate::model::{Angle, Region, Vec3};
 
 /// Incoming event.
 pub struct IncomingEvent(pub EcsEvent);
@@ -14,6 +15,7 @@ pub struct OutgoingEvent(pub EcsEvent);
 
 /// Tracks the connection and login information of an user.
 pub struct Connection {
+    pub account_id: Option<i64>,
     pub verified: bool,
     pub version_checked: bool,
     pub region: Option<Region>,
@@ -24,7 +26,61 @@ pub struct Connection {
 /// Holds the connection entity id from the global world for using in a local world.
 pub struct ConnectionID(pub EntityId);
 
+/// Tracks the spawn process of the selected user of a connection in a local world.
+pub struct UserSpawn {
+    pub user_id: i32,
+    pub status: UserSpawnStatus,
+}
+
+/// Status of the spawn process of a user.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UserSpawnStatus {
+    /// The user needs to be loaded from the database.
+    Requesting,
+    /// The client is loading the zone.
+    Waiting,
+    /// The user is spawned in the world.
+    Spawned,
+}
+
+/// Location of an entity inside the world.
+pub struct Location {
+    pub zone: i32,
+    pub location: Vec3,
+    pub rotation: Angle,
+    pub last_update: Instant,
+}
+
+/// Holds the local entities a user can currently see together with their game ID.
+#[derive(Default)]
+pub struct Visibility {
+    pub visible: HashMap<EntityId, u64>,
+}
+
+/// The user a connection selected to enter the game with.
+pub struct SelectedUser {
+    pub user_id: i32,
+    pub name: String,
+}
+
+/// Tracks the recent chat messages of a user.
+#[derive(Default)]
+pub struct ChatState {
+    pub recent_messages: VecDeque<Instant>,
+}
+
+/// The largest visibility range the server accepts from a client.
+pub const MAX_VISIBILITY_RANGE: u32 = 10_000;
+
 /// Holds the configuration settings of a user that are needed at runtime.
 pub struct Settings {
     pub visibility_range: u32,
 }
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            visibility_range: 2000,
+        }
+    }
+}
@@ -9,6 +9,9 @@
 /// handling the state between the server systems.
 ///
 /// A event always has a target: Global ECS, local ECS or a connection.
+/// The opcode and the target of packet events are defined on the packet with `#[derive(Packet)]`.
+/// Every packet needs it's event in the `assemble_event!` invocation below, which the derive
+/// checks at compile time.
 ///
 /// Messages from the connections  to the ECS are always requests.
 /// Messages from the ECS to the Connections are always responses.
@@ -23,12 +26,18 @@ use shipyard::*;
 
 use crate::protocol::opcode::Opcode;
 use crate::protocol::packet::*;
-use crate::protocol::serde::{from_vec, to_vec};
+use crate::protocol::serde::{from_vec_with_layout, to_vec_with_layout, PacketLayout};
+use crate::protocol::PACKET_HEADER_LENGTH;
 use crate::{AlmeticaError, Result};
 
 /// EcsEvent events. We use `Arc` so that we don't need to copy packet data around.
 pub type EcsEvent = Arc<Event>;
 
+/// Returns a connection id for tools that decode packets into events outside of the worlds.
+pub fn detached_connection_id() -> EntityId {
+    World::new().borrow::<EntitiesViewMut>().add_entity((), ())
+}
+
 /// The target of the event.
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum EventTarget {
@@ -40,10 +49,10 @@ pub enum EventTarget {
 macro_rules! assemble_event {
     (
     Packet Events {
-        $($p_ty:ident{packet: $p_packet_type:ty $(, $p_arg_name:ident: $p_arg_type:ty)*}, $p_opcode:ident, $p_target:ident;)*
+        $($p_ty:ident{packet: $p_packet_type:ty $(, $p_arg_name:ident: $p_arg_type:ty)*};)*
     }
     System Events {
-        $($e_ty:ident{$($e_arg_name:ident: $e_arg_type:ty)*}, $e_target:ident;)*
+        $($e_ty:ident{$($e_arg_name:ident: $e_arg_type:ty),*}, $e_target:ident;)*
     }
     ) => {
         /// Event enum for all events.
@@ -56,11 +65,19 @@ macro_rules! assemble_event {
 
         impl Event {
             /// Creates a new Request/Response event for the given opcode & packet data.
-            pub fn new_from_packet(connection_id: EntityId, opcode: Opcode, packet_data: Vec<u8>) -> Result<Event> {
+            /// The packet data starts `data_offset` bytes after the start of the packet.
+            pub fn new_from_packet(connection_id: EntityId, opcode: Opcode, packet_data: Vec<u8>, data_offset: usize) -> Result<Event> {
+                let (event, _) = Event::decode_packet(connection_id, opcode, packet_data, data_offset, PacketLayout::default())?;
+                Ok(event)
+            }
+
+            /// Creates a new Request/Response event like `new_from_packet` with the packet layout of a client
+            /// version and returns the number of bytes of the packet data that were decoded.
+            pub fn decode_packet(connection_id: EntityId, opcode: Opcode, packet_data: Vec<u8>, data_offset: usize, layout: PacketLayout) -> Result<(Event, usize)> {
                 match opcode {
-                    $(Opcode::$p_opcode => {
-                        let packet = from_vec(packet_data)?;
-                        Ok(Event::$p_ty{connection_id: connection_id, packet})
+                    $(<$p_packet_type as Packet>::OPCODE => {
+                        let (packet, consumed) = from_vec_with_layout(packet_data, data_offset, layout)?;
+                        Ok((Event::$p_ty{connection_id: connection_id, packet}, consumed))
                     },)*
                     _ => bail!(AlmeticaError::NoEventMappingForPacket),
                 }
@@ -77,20 +94,33 @@ macro_rules! assemble_event {
 
             /// Get the data from a packet event.
             pub fn data(&self) -> Result<Option<Vec<u8>>> {
+                self.data_with_layout(PacketLayout::default())
+            }
+
+            /// Get the data from a packet event with the packet layout of a client version.
+            pub fn data_with_layout(&self, layout: PacketLayout) -> Result<Option<Vec<u8>>> {
                 match self {
                     $(Event::$p_ty{packet, ..} => {
-                        let data = to_vec(packet)?;
+                        let data = to_vec_with_layout(packet, PACKET_HEADER_LENGTH, layout)?;
                         Ok(Some(data))
                     },)*
                     _ => Ok(None),
                 }
             }
 
+            /// Get the packet of a packet event.
+            pub fn packet(&self) -> Option<&dyn fmt::Debug> {
+                match self {
+                    $(Event::$p_ty{packet, ..} => Some(packet),)*
+                    _ => None,
+                }
+            }
+
             /// Get the opcode from a packet event.
             pub fn opcode(&self) -> Option<Opcode> {
                 match self {
                     $(Event::$p_ty{..} => {
-                        Some(Opcode::$p_opcode)
+                        Some(<$p_packet_type as Packet>::OPCODE)
                     },)*
                     _ => None,
                 }
@@ -100,7 +130,7 @@ macro_rules! assemble_event {
             pub fn target(&self) -> EventTarget {
                 match self {
                     Event::RequestRegisterConnection{..} => EventTarget::Global,
-                    $(Event::$p_ty{..} => EventTarget::$p_target,)*
+                    $(Event::$p_ty{..} => <$p_packet_type as Packet>::TARGET,)*
                     $(Event::$e_ty{..} => EventTarget::$e_target,)*
                 }
             }
@@ -120,30 +150,61 @@ macro_rules! assemble_event {
 
 assemble_event! {
     Packet Events {
-        RequestLoginArbiter{packet: CLoginArbiter}, C_LOGIN_ARBITER, Global;
-        ResponseLoginArbiter{packet: SLoginArbiter}, S_LOGIN_ARBITER, Connection;
-        RequestCheckVersion{packet: CCheckVersion}, C_CHECK_VERSION, Global;
-        ResponseCheckVersion{packet: SCheckVersion}, S_CHECK_VERSION, Connection;
-        ResponseLoadingScreenControlInfo{packet: SLoadingScreenControlInfo}, S_LOADING_SCREEN_CONTROL_INFO, Connection;
-        ResponseRemainPlayTime{packet: SRemainPlayTime}, S_REMAIN_PLAY_TIME, Connection;
-        ResponseLoginAccountInfo{packet: SLoginAccountInfo}, S_LOGIN_ACCOUNT_INFO, Connection;
-        RequestSetVisibleRange{packet: CSetVisibleRange}, C_SET_VISIBLE_RANGE, Global;
-        RequestGetUserList{packet: CGetUserList}, C_GET_USER_LIST, Global;
-        ResponseGetUserList{packet: SGetUserList}, S_GET_USER_LIST, Global;
-        RequestPong{packet: CPong}, C_PONG, Global;
-        ResponsePing{packet: SPing}, S_PING, Connection;
-        RequestCanCreateUser{packet: CCanCreateUser}, C_CAN_CREATE_USER, Global;
-        ResponseCanCreateUser{packet: SCanCreateUser}, S_CAN_CREATE_USER, Connection;
-        RequestCheckUserName{packet: CCheckUserName}, C_CHECK_USERNAME, Global;
-        ResponseCheckUserName{packet: SCheckUserName}, S_CHECK_USERNAME, Connection;
-        RequestCreateUser{packet: CCreateUser}, C_CREATE_USER, Global;
-        ResponseCreateUser{packet: SCreateUser}, S_CREATE_USER, Connection;
+        RequestLoginArbiter{packet: CLoginArbiter};
+        ResponseLoginArbiter{packet: SLoginArbiter};
+        RequestCheckVersion{packet: CCheckVersion};
+        ResponseCheckVersion{packet: SCheckVersion};
+        ResponseLoadingScreenControlInfo{packet: SLoadingScreenControlInfo};
+        ResponseRemainPlayTime{packet: SRemainPlayTime};
+        ResponseLoginAccountInfo{packet: SLoginAccountInfo};
+        RequestSetVisibleRange{packet: CSetVisibleRange};
+        RequestGetUserList{packet: CGetUserList};
+        ResponseGetUserList{packet: SGetUserList};
+        ResponseAccountPackageList{packet: SAccountPackageList};
+        RequestGetUserGuildLogo{packet: CGetUserGuildLogo};
+        ResponseImageData{packet: SImageData};
+        ResponseGuildName{packet: SGuildName};
+        ResponseItemCustomString{packet: SItemCustomString};
+        RequestPong{packet: CPong};
+        ResponsePing{packet: SPing};
+        RequestCanCreateUser{packet: CCanCreateUser};
+        ResponseCanCreateUser{packet: SCanCreateUser};
+        RequestCheckUserName{packet: CCheckUserName};
+        ResponseCheckUserName{packet: SCheckUserName};
+        RequestCreateUser{packet: CCreateUser};
+        ResponseCreateUser{packet: SCreateUser};
+        RequestDeleteUser{packet: CDeleteUser};
+        ResponseDeleteUser{packet: SDeleteUser};
+        RequestCancelDeleteUser{packet: CCancelDeleteUser};
+        ResponseCancelDeleteUser{packet: SCancelDeleteUser};
+        RequestSelectUser{packet: CSelectUser};
+        ResponseLogin{packet: SLogin};
+        ResponseLoadTopo{packet: SLoadTopo};
+        RequestLoadTopoFin{packet: CLoadTopoFin};
+        ResponseSpawnMe{packet: SSpawnMe};
+        RequestPlayerLocation{packet: CPlayerLocation};
+        ResponseUserLocation{packet: SUserLocation};
+        ResponseSpawnUser{packet: SSpawnUser};
+        ResponseDespawnUser{packet: SDespawnUser};
+        RequestChat{packet: CChat};
+        ResponseChat{packet: SChat};
+        RequestWhisper{packet: CWhisper};
+        ResponseWhisper{packet: SWhisper};
+        ResponseSystemMessage{packet: SSystemMessage};
     }
     System Events {
         // The connection will get it's EntityId returned with this message after registration.
         ResponseRegisterConnection{}, Connection;
         // The connection will be dropped after it receives this message.
         ResponseDropConnection{}, Connection;
+        // The connection will send all events with a local target to the given local world after it receives this message.
+        ResponseLocalWorldHandOff{local_world_id: u64, user_id: i32, visibility_range: u32, request_channel: Sender<EcsEvent>}, Connection;
+        // The connection registers itself, it's selected user and settings in the local world it was handed off to.
+        RequestRegisterLocalConnection{user_id: i32, visibility_range: u32, response_channel: Sender<EcsEvent>}, Local;
+        // The connection unregisters itself from the local world it was handed off to.
+        RequestUnregisterLocalConnection{}, Local;
+        // A local world forwards a message of the connection's user into the global chat channel.
+        RequestGlobalChat{message: String}, Global;
     }
 }
 
@@ -165,7 +226,8 @@ mod tests {
             0x2, 0x0, 0x8, 0x0, 0x8, 0x0, 0x14, 0x0, 0x0, 0x0, 0x0, 0x0, 0x1d, 0x8a, 0x5, 0x0,
             0x14, 0x0, 0x0, 0x0, 0x1, 0x0, 0x0, 0x0, 0xce, 0x7b, 0x5, 0x0,
         ];
-        let event = Event::new_from_packet(entity, Opcode::C_CHECK_VERSION, data)?;
+        let event =
+            Event::new_from_packet(entity, Opcode::C_CHECK_VERSION, data, PACKET_HEADER_LENGTH)?;
         if let Event::RequestCheckVersion {
             connection_id: entity_id,
             packet,
@@ -200,6 +262,20 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_target_local() -> Result<()> {
+        let entity = World::new().borrow::<EntitiesViewMut>().add_entity((), ());
+        let (response_channel, _) = channel(1);
+        let org = Event::RequestRegisterLocalConnection {
+            connection_id: entity,
+            user_id: 1,
+            visibility_range: 2000,
+            response_channel,
+        };
+        assert_eq!(org.target(), EventTarget::Local);
+        Ok(())
+    }
+
     #[test]
     fn test_target_connection() -> Result<()> {
         let entity = World::new().borrow::<EntitiesViewMut>().add_entity((), ());
@@ -231,6 +307,27 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_event_packet_some() -> Result<()> {
+        let entity = World::new().borrow::<EntitiesViewMut>().add_entity((), ());
+        let org = Event::ResponseCheckVersion {
+            connection_id: entity,
+            packet: SCheckVersion { ok: true },
+        };
+        let packet = org.packet().expect("Packet event without packet");
+        assert_eq!(format!("{:?}", packet), "SCheckVersion { ok: true }");
+        Ok(())
+    }
+
+    #[test]
+    fn test_event_packet_none() -> Result<()> {
+        let (response_channel, _) = channel(1);
+        let org = Event::RequestRegisterConnection { response_channel };
+
+        assert!(org.packet().is_none());
+        Ok(())
+    }
+
     #[test]
     fn test_event_connection_some() -> Result<()> {
         let entity = World::new().borrow::<EntitiesViewMut>().add_entity((), ());
@@ -1,21 +1,221 @@
 /// Module that hold the definitions for Resources used by the ECS.
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 
 use async_std::sync::{Receiver, Sender};
 use shipyard::EntityId;
 
-use crate::ecs::event::EcsEvent;
+use crate::ecs::event::{EcsEvent, Event};
+use crate::model::Vec3;
 
 /// Holds the Receiver channel of a world.
 pub struct EventRxChannel {
     pub channel: Receiver<EcsEvent>,
 }
 
+/// Holds the Sender channel of the global world. Local worlds use it to reach the global world.
+pub struct GlobalWorldChannel {
+    pub channel: Sender<EcsEvent>,
+}
+
 /// Holds the Entity to response channel mapping
 pub struct ConnectionMapping(pub HashMap<EntityId, Sender<EcsEvent>>);
 
+/// Holds the outbound queues of the connections. Events wait in the queue of their connection
+/// until its response channel has free capacity.
+#[derive(Default)]
+pub struct OutboundQueues(pub HashMap<EntityId, OutboundQueue>);
+
+/// Events that are waiting for their delivery to a connection.
+#[derive(Default)]
+pub struct OutboundQueue {
+    pub events: VecDeque<EcsEvent>,
+    /// The connection is dropped once the queue is delivered.
+    pub closing: bool,
+}
+
+/// How an event is treated when it's connection doesn't keep up with the world.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeliveryClass {
+    /// Needs to be delivered, since the client state would diverge otherwise.
+    Critical,
+    /// Can be dropped.
+    Droppable,
+    /// Replaces a queued event with the same key. Can be dropped.
+    Coalescable(u64),
+}
+
+/// Policy for the delivery of events to slow connections.
+///
+/// Non-critical events are dropped once the queue of a connection reaches the drop threshold.
+/// Connections whose queue grows beyond the maximum length are disconnected, so that a single slow
+/// client can't hold back the memory of the world.
+#[derive(Clone, Debug)]
+pub struct DeliveryPolicy {
+    pub drop_threshold: usize,
+    pub max_queue_length: usize,
+}
+
+impl Default for DeliveryPolicy {
+    fn default() -> Self {
+        DeliveryPolicy {
+            drop_threshold: 256,
+            max_queue_length: 1024,
+        }
+    }
+}
+
+impl DeliveryPolicy {
+    /// Returns the delivery class of the event.
+    pub fn classify(&self, event: &Event) -> DeliveryClass {
+        match event {
+            // Only the latest location of an user is of interest.
+            Event::ResponseUserLocation { packet, .. } => {
+                DeliveryClass::Coalescable(packet.game_id)
+            }
+            Event::ResponseChat { .. } => DeliveryClass::Droppable,
+            _ => DeliveryClass::Critical,
+        }
+    }
+}
+
 /// Holds a list with EntityIds marked for deletion.
 #[derive(Clone)]
 pub struct DeletionList(pub Vec<EntityId>);
 
+/// Holds a list with connections that the multiverse should hand off to a local world.
+#[derive(Clone)]
+pub struct HandOffList(pub Vec<LocalWorldHandOff>);
+
+/// Hand off of a connection, it's selected user and settings to the local world with the given name.
+#[derive(Clone, Debug)]
+pub struct LocalWorldHandOff {
+    pub connection_id: EntityId,
+    pub user_id: i32,
+    pub visibility_range: u32,
+    pub world_name: String,
+}
+
+/// Spatial index that sorts the entities of a world into square cells on the x/y plane.
+pub struct SpatialGrid {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<(EntityId, Vec3)>>,
+}
+
+impl SpatialGrid {
+    pub fn new(cell_size: f32) -> Self {
+        SpatialGrid {
+            cell_size,
+            cells: HashMap::new(),
+        }
+    }
+
+    /// Removes all entities from the grid.
+    pub fn clear(&mut self) {
+        self.cells.clear();
+    }
+
+    /// Inserts an entity at the given location.
+    pub fn insert(&mut self, id: EntityId, location: Vec3) {
+        let cell = self.cell(location.x, location.y);
+        self.cells
+            .entry(cell)
+            .or_insert_with(Vec::new)
+            .push((id, location));
+    }
+
+    /// Returns all entities that are inside the given range of the location.
+    pub fn query(&self, location: &Vec3, range: f32) -> Vec<EntityId> {
+        let (min_x, min_y) = self.cell(location.x - range, location.y - range);
+        let (max_x, max_y) = self.cell(location.x + range, location.y + range);
+
+        let mut found = Vec::new();
+        for x in min_x..=max_x {
+            for y in min_y..=max_y {
+                if let Some(cell) = self.cells.get(&(x, y)) {
+                    found.extend(
+                        cell.iter()
+                            .filter(|(_, other)| other.distance(location) <= range)
+                            .map(|(id, _)| *id),
+                    );
+                }
+            }
+        }
+        found
+    }
+
+    fn cell(&self, x: f32, y: f32) -> (i32, i32) {
+        (
+            (x / self.cell_size).floor() as i32,
+            (y / self.cell_size).floor() as i32,
+        )
+    }
+}
+
 pub struct WorldId(pub u64);
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use shipyard::*;
+
+    use super::*;
+
+    #[test]
+    fn test_spatial_grid_query() {
+        let world = World::new();
+        let mut entities = world.borrow::<EntitiesViewMut>();
+        let near = entities.add_entity((), ());
+        let border = entities.add_entity((), ());
+        let far = entities.add_entity((), ());
+        let negative = entities.add_entity((), ());
+
+        let mut grid = SpatialGrid::new(100.0);
+        grid.insert(
+            near,
+            Vec3 {
+                x: 10.0,
+                y: 10.0,
+                z: 0.0,
+            },
+        );
+        grid.insert(
+            border,
+            Vec3 {
+                x: 250.0,
+                y: 0.0,
+                z: 0.0,
+            },
+        );
+        grid.insert(
+            far,
+            Vec3 {
+                x: 251.0,
+                y: 0.0,
+                z: 0.0,
+            },
+        );
+        grid.insert(
+            negative,
+            Vec3 {
+                x: -120.0,
+                y: -40.0,
+                z: 0.0,
+            },
+        );
+
+        let origin = Vec3 {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        };
+        let found: HashSet<EntityId> = grid.query(&origin, 250.0).into_iter().collect();
+        let expected: HashSet<EntityId> = vec![near, border, negative].into_iter().collect();
+        assert_eq!(found, expected);
+
+        assert_eq!(grid.query(&origin, 5.0), vec![]);
+
+        grid.clear();
+        assert_eq!(grid.query(&origin, 250.0), vec![]);
+    }
+}
@@ -1,22 +1,36 @@
 /// Module that holds all systems used by the ECS.
+mod chat_manager;
 mod cleaner;
 mod connection_manager;
 mod event_receiver;
 mod event_sender;
+mod local_connection_manager;
+mod movement_manager;
 mod settings_manager;
 mod user_manager;
+mod user_spawner;
+mod visibility_manager;
 
+pub use chat_manager::{chat_manager_system, local_chat_manager_system};
 pub use cleaner::cleaner_system;
 pub use connection_manager::connection_manager_system;
 pub use event_receiver::event_receiver_system;
 pub use event_sender::event_sender_system;
+pub use local_connection_manager::local_connection_manager_system;
+pub use movement_manager::movement_manager_system;
 pub use settings_manager::settings_manager_system;
 pub use user_manager::user_manager_system;
+pub use user_spawner::user_spawner_system;
+pub use visibility_manager::visibility_manager_system;
+
+use std::sync::Arc;
 
 use shipyard::*;
 use tracing::{debug, trace};
 
-use crate::ecs::component::OutgoingEvent;
+use crate::ecs::component::{ConnectionID, OutgoingEvent};
+use crate::ecs::event::Event;
+use crate::protocol::system_message::SystemMessage;
 
 /// Send an outgoing event.
 pub fn send_event(
@@ -28,3 +42,32 @@ pub fn send_event(
     trace!("Event data: {:?}", event.0);
     entities.add_entity(outgoing_events, event);
 }
+
+/// Send a system message to the connection.
+pub fn send_system_message(
+    connection_id: EntityId,
+    message: &SystemMessage,
+    outgoing_events: &mut ViewMut<OutgoingEvent>,
+    entities: &mut EntitiesViewMut,
+) {
+    send_event(
+        OutgoingEvent(Arc::new(Event::ResponseSystemMessage {
+            connection_id,
+            packet: message.packet(),
+        })),
+        outgoing_events,
+        entities,
+    );
+}
+
+/// Finds the local entity of a connection that was handed off to a local world.
+pub fn find_local_entity(
+    connection_id: EntityId,
+    connection_ids: &View<ConnectionID>,
+) -> Option<EntityId> {
+    connection_ids
+        .iter()
+        .with_id()
+        .find(|(_, id)| id.0 == connection_id)
+        .map(|(local_id, _)| local_id)
+}
@@ -0,0 +1,737 @@
+/// Routes the chat messages and whispers of the users to their audience.
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, ensure, Context};
+use futures_util::FutureExt;
+use shipyard::*;
+use tracing::{debug, error, info_span};
+
+use crate::ecs::component::{
+    ChatState, ConnectionID, IncomingEvent, OutgoingEvent, SelectedUser, UserSpawn,
+    UserSpawnStatus, Visibility,
+};
+use crate::ecs::event::Event;
+use crate::ecs::resource::{GlobalWorldChannel, WorldId};
+use crate::ecs::system::{find_local_entity, send_event, send_system_message};
+use crate::model::entity::User;
+use crate::model::ChatChannel;
+use crate::protocol::packet::*;
+use crate::protocol::system_message::SystemMessageTable;
+use crate::Result;
+
+/// Maximal length of a message in characters. Messages include the HTML markup of the client.
+const MAX_MESSAGE_LENGTH: usize = 1000;
+/// Number of messages a user can send inside the rate limit window.
+const RATE_LIMIT_MESSAGES: usize = 5;
+/// Time window of the chat rate limit.
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(5);
+
+/// Chat manager handles the whispers and the global chat channel inside the global world.
+pub fn chat_manager_system(
+    incoming_events: View<IncomingEvent>,
+    mut outgoing_events: ViewMut<OutgoingEvent>,
+    selected_users: View<SelectedUser>,
+    mut chat_states: ViewMut<ChatState>,
+    mut entities: EntitiesViewMut,
+    system_messages: UniqueView<SystemMessageTable>,
+    world_id: UniqueView<WorldId>,
+) {
+    let span = info_span!("world", world_id = world_id.0);
+    let _enter = span.enter();
+
+    (&incoming_events).iter().for_each(|event| match &*event.0 {
+        Event::RequestWhisper {
+            connection_id,
+            packet,
+        } => {
+            if let Err(e) = handle_whisper(
+                &packet,
+                *connection_id,
+                &selected_users,
+                &mut chat_states,
+                &system_messages,
+                &mut outgoing_events,
+                &mut entities,
+            ) {
+                error!("Rejecting whisper request: {:?}", e);
+            }
+        }
+        Event::RequestGlobalChat {
+            connection_id,
+            message,
+        } => {
+            if let Err(e) = handle_global_chat(
+                &message,
+                *connection_id,
+                &selected_users,
+                &mut outgoing_events,
+                &mut entities,
+            ) {
+                error!("Rejecting global chat request: {:?}", e);
+            }
+        }
+        _ => { /* Ignore all other packets */ }
+    });
+}
+
+/// Local chat manager handles the chat channels of the users inside a local world.
+pub fn local_chat_manager_system(
+    incoming_events: View<IncomingEvent>,
+    mut outgoing_events: ViewMut<OutgoingEvent>,
+    connection_ids: View<ConnectionID>,
+    user_spawns: View<UserSpawn>,
+    users: View<User>,
+    visibilities: View<Visibility>,
+    mut chat_states: ViewMut<ChatState>,
+    mut entities: EntitiesViewMut,
+    global_channel: UniqueView<GlobalWorldChannel>,
+    world_id: UniqueView<WorldId>,
+) {
+    let span = info_span!("world", world_id = world_id.0);
+    let _enter = span.enter();
+
+    (&incoming_events).iter().for_each(|event| match &*event.0 {
+        Event::RequestChat {
+            connection_id,
+            packet,
+        } => {
+            if let Err(e) = handle_chat(
+                &packet,
+                *connection_id,
+                &connection_ids,
+                &user_spawns,
+                &users,
+                &visibilities,
+                &mut chat_states,
+                &global_channel,
+                &mut outgoing_events,
+                &mut entities,
+            ) {
+                error!("Rejecting chat request: {:?}", e);
+            }
+        }
+        _ => { /* Ignore all other packets */ }
+    });
+}
+
+fn handle_chat(
+    packet: &CChat,
+    connection_id: EntityId,
+    connection_ids: &View<ConnectionID>,
+    user_spawns: &View<UserSpawn>,
+    users: &View<User>,
+    visibilities: &View<Visibility>,
+    chat_states: &mut ViewMut<ChatState>,
+    global_channel: &GlobalWorldChannel,
+    outgoing_events: &mut ViewMut<OutgoingEvent>,
+    entities: &mut EntitiesViewMut,
+) -> Result<()> {
+    let span = info_span!("connection", connection = ?connection_id);
+    let _enter = span.enter();
+
+    debug!("Chat event incoming");
+
+    let local_id = find_local_entity(connection_id, connection_ids)
+        .context("Connection is not registered in the local world")?;
+
+    let user_spawn = user_spawns
+        .try_get(local_id)
+        .context("Could not find user spawn component for entity")?;
+    ensure!(
+        user_spawn.status == UserSpawnStatus::Spawned,
+        "User is not spawned yet"
+    );
+
+    let user = users
+        .try_get(local_id)
+        .context("Could not find user component for entity")?;
+
+    let channel = ChatChannel::from_id(packet.channel)
+        .context(format!("Unknown chat channel {}", packet.channel))?;
+
+    let chat_state = get_chat_state(local_id, chat_states, entities)?;
+    validate_message(chat_state, &packet.message, Instant::now())?;
+
+    let audience: Vec<EntityId> = match channel {
+        // The user and every user that can see the user.
+        ChatChannel::Say => (connection_ids, visibilities)
+            .iter()
+            .filter(|(_, visibility)| visibility.visible.contains_key(&local_id))
+            .map(|(observer_connection_id, _)| observer_connection_id.0)
+            .chain(std::iter::once(connection_id))
+            .collect(),
+        // Every spawned user inside the local world.
+        ChatChannel::Area | ChatChannel::Trade => (connection_ids, user_spawns)
+            .iter()
+            .filter(|(_, spawn)| spawn.status == UserSpawnStatus::Spawned)
+            .map(|(other_connection_id, _)| other_connection_id.0)
+            .collect(),
+        // The global world knows the users of all local worlds.
+        ChatChannel::Global => {
+            forward_global_chat(connection_id, &packet.message, global_channel)?;
+            return Ok(());
+        }
+        // TODO Route the messages once parties and guilds exist.
+        ChatChannel::Party | ChatChannel::Guild | ChatChannel::Whisper => {
+            bail!("Chat channel {:?} is not supported yet", channel);
+        }
+    };
+
+    for receiver_id in audience {
+        send_event(
+            assemble_chat(
+                receiver_id,
+                channel,
+                &user.name,
+                user.id as u64,
+                &packet.message,
+            ),
+            outgoing_events,
+            entities,
+        );
+    }
+
+    Ok(())
+}
+
+fn forward_global_chat(
+    connection_id: EntityId,
+    message: &str,
+    global_channel: &GlobalWorldChannel,
+) -> Result<()> {
+    let event = Arc::new(Event::RequestGlobalChat {
+        connection_id,
+        message: message.to_string(),
+    });
+    // The tick must not block on a full channel.
+    let channel = &global_channel.channel;
+    ensure!(
+        !channel.is_full() && channel.send(event).now_or_never().is_some(),
+        "Dropping global chat message because the channel of the global world is full"
+    );
+    Ok(())
+}
+
+fn handle_global_chat(
+    message: &str,
+    connection_id: EntityId,
+    selected_users: &View<SelectedUser>,
+    outgoing_events: &mut ViewMut<OutgoingEvent>,
+    entities: &mut EntitiesViewMut,
+) -> Result<()> {
+    let span = info_span!("connection", connection = ?connection_id);
+    let _enter = span.enter();
+
+    debug!("Global chat event incoming");
+
+    let author = selected_users
+        .try_get(connection_id)
+        .context("Connection has not selected a user")?;
+
+    let receivers: Vec<EntityId> = selected_users.iter().with_id().map(|(id, _)| id).collect();
+    for receiver_id in receivers {
+        send_event(
+            assemble_chat(
+                receiver_id,
+                ChatChannel::Global,
+                &author.name,
+                author.user_id as u64,
+                message,
+            ),
+            outgoing_events,
+            entities,
+        );
+    }
+
+    Ok(())
+}
+
+fn handle_whisper(
+    packet: &CWhisper,
+    connection_id: EntityId,
+    selected_users: &View<SelectedUser>,
+    chat_states: &mut ViewMut<ChatState>,
+    system_messages: &SystemMessageTable,
+    outgoing_events: &mut ViewMut<OutgoingEvent>,
+    entities: &mut EntitiesViewMut,
+) -> Result<()> {
+    let span = info_span!("connection", connection = ?connection_id);
+    let _enter = span.enter();
+
+    debug!("Whisper event incoming");
+
+    let author = selected_users
+        .try_get(connection_id)
+        .context("Connection has not selected a user")?;
+
+    let chat_state = get_chat_state(connection_id, chat_states, entities)?;
+    validate_message(chat_state, &packet.message, Instant::now())?;
+
+    let target = packet.target.to_lowercase();
+    let (recipient_id, recipient) = match selected_users
+        .iter()
+        .with_id()
+        .find(|(_, selected_user)| selected_user.name.to_lowercase() == target)
+    {
+        Some(recipient) => recipient,
+        None => {
+            // Inform the author that the recipient is not online.
+            let message = system_messages
+                .message("SMT_GENERAL_NOT_IN_THE_WORLD")?
+                .with("UserName", &packet.target);
+            send_system_message(connection_id, &message, outgoing_events, entities);
+            bail!("Can't find recipient {}", packet.target);
+        }
+    };
+
+    // The author receives the whisper too, so that it shows up in it's chat window.
+    for receiver_id in &[recipient_id, connection_id] {
+        send_event(
+            assemble_whisper(*receiver_id, author, &recipient.name, &packet.message),
+            outgoing_events,
+            entities,
+        );
+    }
+
+    Ok(())
+}
+
+/// Returns the chat state of the entity. Creates the chat state if the entity has none yet.
+fn get_chat_state<'a>(
+    id: EntityId,
+    chat_states: &'a mut ViewMut<ChatState>,
+    entities: &mut EntitiesViewMut,
+) -> Result<&'a mut ChatState> {
+    if chat_states.try_get(id).is_err() {
+        entities.add_component(&mut *chat_states, ChatState::default(), id);
+    }
+    chat_states
+        .try_get(id)
+        .context("Could not find chat state component for entity")
+}
+
+/// Checks the length of the message and the rate limit.
+fn validate_message(chat_state: &mut ChatState, message: &str, now: Instant) -> Result<()> {
+    let length = message.chars().count();
+    ensure!(
+        length > 0 && length <= MAX_MESSAGE_LENGTH,
+        "Message length of {} is out of bounds",
+        length
+    );
+
+    // Forget the messages that left the rate limit window.
+    while let Some(sent) = chat_state.recent_messages.front() {
+        if now.duration_since(*sent) < RATE_LIMIT_WINDOW {
+            break;
+        }
+        chat_state.recent_messages.pop_front();
+    }
+    ensure!(
+        chat_state.recent_messages.len() < RATE_LIMIT_MESSAGES,
+        "User exceeded the chat rate limit"
+    );
+    chat_state.recent_messages.push_back(now);
+
+    Ok(())
+}
+
+fn assemble_chat(
+    connection_id: EntityId,
+    channel: ChatChannel,
+    name: &str,
+    author_id: u64,
+    message: &str,
+) -> OutgoingEvent {
+    OutgoingEvent(Arc::new(Event::ResponseChat {
+        connection_id,
+        packet: SChat {
+            name: name.to_string(),
+            message: message.to_string(),
+            channel: channel.id().unwrap_or_default(),
+            author_id,
+            unk1: false,
+            gm: false,
+            unk2: false,
+        },
+    }))
+}
+
+fn assemble_whisper(
+    connection_id: EntityId,
+    author: &SelectedUser,
+    recipient: &str,
+    message: &str,
+) -> OutgoingEvent {
+    OutgoingEvent(Arc::new(Event::ResponseWhisper {
+        connection_id,
+        packet: SWhisper {
+            author: author.name.clone(),
+            recipient: recipient.to_string(),
+            message: message.to_string(),
+            game_id: author.user_id as u64,
+            unk1: false,
+            gm: false,
+            unk2: false,
+        },
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::sync::{channel, Receiver};
+    use async_std::task;
+    use chrono::{TimeZone, Utc};
+
+    use crate::ecs::component::{Location, Settings};
+    use crate::ecs::event::EcsEvent;
+    use crate::ecs::resource::{DeletionList, SpatialGrid};
+    use crate::ecs::system::user_spawner::START_ZONE;
+    use crate::ecs::system::{cleaner_system, visibility_manager_system};
+    use crate::model::{Class, Customization, Gender, Race, Vec3};
+
+    use super::*;
+
+    fn setup_local() -> (World, Receiver<EcsEvent>) {
+        let (tx_channel, rx_channel) = channel(10);
+
+        let world = World::new();
+        world.add_unique(WorldId(1));
+        world.add_unique(DeletionList(vec![]));
+        world.add_unique(SpatialGrid::new(1_000.0));
+        world.add_unique(GlobalWorldChannel {
+            channel: tx_channel,
+        });
+
+        (world, rx_channel)
+    }
+
+    fn setup_global() -> World {
+        let world = World::new();
+        world.add_unique(WorldId(0));
+        world.add_unique(SystemMessageTable::new(vec![
+            "SMT_UNDEFINED".to_string(),
+            "SMT_GENERAL_NOT_IN_THE_WORLD".to_string(),
+        ]));
+        world
+    }
+
+    /// Spawns a user at the given x coordinate in a local world and returns it's global connection ID.
+    fn spawn_user(world: &World, global_world: &World, user_id: i32, x: f32) -> EntityId {
+        let connection_id = global_world.borrow::<EntitiesViewMut>().add_entity((), ());
+
+        world.run(
+            |mut entities: EntitiesViewMut,
+             mut connection_ids: ViewMut<ConnectionID>,
+             mut user_spawns: ViewMut<UserSpawn>,
+             mut users: ViewMut<User>,
+             mut locations: ViewMut<Location>,
+             mut settings: ViewMut<Settings>| {
+                entities.add_entity(
+                    (
+                        &mut connection_ids,
+                        &mut user_spawns,
+                        &mut users,
+                        &mut locations,
+                        &mut settings,
+                    ),
+                    (
+                        ConnectionID(connection_id),
+                        UserSpawn {
+                            user_id,
+                            status: UserSpawnStatus::Spawned,
+                        },
+                        User {
+                            id: user_id,
+                            account_id: 1,
+                            name: format!("User{}", user_id),
+                            gender: Gender::Female,
+                            race: Race::HighElf,
+                            class: Class::Priest,
+                            shape: vec![],
+                            details: vec![],
+                            appearance: Customization { data: vec![] },
+                            appearance2: 100,
+                            playtime: 0,
+                            deletion_time: None,
+                            created_at: Utc.ymd(1995, 7, 8).and_hms(9, 10, 11),
+                        },
+                        Location {
+                            zone: START_ZONE,
+                            location: Vec3 { x, y: 0.0, z: 0.0 },
+                            rotation: 0,
+                            last_update: Instant::now(),
+                        },
+                        Settings {
+                            visibility_range: 1000,
+                        },
+                    ),
+                );
+            },
+        );
+
+        connection_id
+    }
+
+    /// Registers a connection with a selected user in the global world.
+    fn select_user(world: &World, user_id: i32, name: &str) -> EntityId {
+        world.run(
+            |mut entities: EntitiesViewMut, mut selected_users: ViewMut<SelectedUser>| {
+                entities.add_entity(
+                    &mut selected_users,
+                    SelectedUser {
+                        user_id,
+                        name: name.to_string(),
+                    },
+                )
+            },
+        )
+    }
+
+    fn send_chat(world: &World, connection_id: EntityId, channel: u32, message: &str) {
+        world.run(
+            |mut entities: EntitiesViewMut, mut events: ViewMut<IncomingEvent>| {
+                entities.add_entity(
+                    &mut events,
+                    IncomingEvent(Arc::new(Event::RequestChat {
+                        connection_id,
+                        packet: CChat {
+                            message: message.to_string(),
+                            channel,
+                        },
+                    })),
+                );
+            },
+        );
+    }
+
+    fn send_whisper(world: &World, connection_id: EntityId, target: &str) {
+        world.run(
+            |mut entities: EntitiesViewMut, mut events: ViewMut<IncomingEvent>| {
+                entities.add_entity(
+                    &mut events,
+                    IncomingEvent(Arc::new(Event::RequestWhisper {
+                        connection_id,
+                        packet: CWhisper {
+                            target: target.to_string(),
+                            message: "<FONT>Hi</FONT>".to_string(),
+                        },
+                    })),
+                );
+            },
+        );
+    }
+
+    /// Returns the receivers of all chat responses.
+    fn chat_receivers(world: &World) -> Vec<EntityId> {
+        world
+            .borrow::<View<OutgoingEvent>>()
+            .iter()
+            .filter_map(|event| match &*event.0 {
+                Event::ResponseChat { connection_id, .. } => Some(*connection_id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_validate_message() {
+        let now = Instant::now();
+        let mut chat_state = ChatState::default();
+
+        assert!(validate_message(&mut chat_state, "", now).is_err());
+        let message = "a".repeat(MAX_MESSAGE_LENGTH + 1);
+        assert!(validate_message(&mut chat_state, &message, now).is_err());
+
+        for _ in 0..RATE_LIMIT_MESSAGES {
+            assert!(validate_message(&mut chat_state, "Hello", now).is_ok());
+        }
+        assert!(validate_message(&mut chat_state, "Hello", now).is_err());
+
+        let later = now + RATE_LIMIT_WINDOW;
+        assert!(validate_message(&mut chat_state, "Hello", later).is_ok());
+    }
+
+    #[test]
+    fn test_say_chat() {
+        let (world, _rx_channel) = setup_local();
+        let global_world = World::new();
+
+        let connection1 = spawn_user(&world, &global_world, 1, 0.0);
+        let connection2 = spawn_user(&world, &global_world, 2, 500.0);
+        let _connection3 = spawn_user(&world, &global_world, 3, 5_000.0);
+        world.run(visibility_manager_system);
+        world.run(cleaner_system);
+
+        send_chat(&world, connection1, 0, "<FONT>Hello</FONT>");
+        world.run(local_chat_manager_system);
+
+        let receivers = chat_receivers(&world);
+        assert_eq!(receivers.len(), 2);
+        assert!(receivers.contains(&connection1));
+        assert!(receivers.contains(&connection2));
+    }
+
+    #[test]
+    fn test_area_chat() {
+        let (world, _rx_channel) = setup_local();
+        let global_world = World::new();
+
+        let connection1 = spawn_user(&world, &global_world, 1, 0.0);
+        let _connection2 = spawn_user(&world, &global_world, 2, 500.0);
+        let _connection3 = spawn_user(&world, &global_world, 3, 5_000.0);
+
+        send_chat(&world, connection1, 3, "<FONT>Hello</FONT>");
+        world.run(local_chat_manager_system);
+
+        assert_eq!(chat_receivers(&world).len(), 3);
+    }
+
+    #[test]
+    fn test_chat_rate_limit() {
+        let (world, _rx_channel) = setup_local();
+        let global_world = World::new();
+
+        let connection1 = spawn_user(&world, &global_world, 1, 0.0);
+
+        for _ in 0..RATE_LIMIT_MESSAGES + 1 {
+            send_chat(&world, connection1, 0, "<FONT>Hello</FONT>");
+        }
+        world.run(local_chat_manager_system);
+
+        assert_eq!(chat_receivers(&world).len(), RATE_LIMIT_MESSAGES);
+    }
+
+    #[test]
+    fn test_unsupported_chat_channel() {
+        let (world, _rx_channel) = setup_local();
+        let global_world = World::new();
+
+        let connection1 = spawn_user(&world, &global_world, 1, 0.0);
+
+        send_chat(&world, connection1, 1, "<FONT>Hello</FONT>");
+        send_chat(&world, connection1, 100, "<FONT>Hello</FONT>");
+        world.run(local_chat_manager_system);
+
+        assert_eq!(chat_receivers(&world).len(), 0);
+    }
+
+    #[test]
+    fn test_global_chat_forwarding() {
+        let (world, rx_channel) = setup_local();
+        let global_world = setup_global();
+
+        let connection1 = spawn_user(&world, &global_world, 1, 0.0);
+        let connection2 = select_user(&global_world, 2, "Asuna");
+        global_world.run(
+            |mut entities: EntitiesViewMut, mut selected_users: ViewMut<SelectedUser>| {
+                entities.add_component(
+                    &mut selected_users,
+                    SelectedUser {
+                        user_id: 1,
+                        name: "Almetica".to_string(),
+                    },
+                    connection1,
+                );
+            },
+        );
+
+        send_chat(&world, connection1, 27, "<FONT>Hello</FONT>");
+        world.run(local_chat_manager_system);
+
+        assert_eq!(chat_receivers(&world).len(), 0);
+
+        // The local world forwards the message to the global world.
+        let event = task::block_on(rx_channel.recv()).unwrap();
+        if let Event::RequestGlobalChat {
+            connection_id,
+            message,
+        } = &*event
+        {
+            assert_eq!(*connection_id, connection1);
+            assert_eq!(message, "<FONT>Hello</FONT>");
+        } else {
+            panic!("Couldn't find global chat request");
+        }
+
+        global_world.run(
+            |mut entities: EntitiesViewMut, mut events: ViewMut<IncomingEvent>| {
+                entities.add_entity(&mut events, IncomingEvent(event));
+            },
+        );
+        global_world.run(chat_manager_system);
+
+        global_world.run(|events: View<OutgoingEvent>| {
+            let mut receivers = Vec::new();
+            (&events).iter().for_each(|event| {
+                if let Event::ResponseChat {
+                    connection_id,
+                    packet,
+                } = &*event.0
+                {
+                    assert_eq!(packet.name, "Almetica");
+                    assert_eq!(packet.channel, 27);
+                    receivers.push(*connection_id);
+                }
+            });
+            assert_eq!(receivers.len(), 2);
+            assert!(receivers.contains(&connection1));
+            assert!(receivers.contains(&connection2));
+        });
+    }
+
+    #[test]
+    fn test_whisper() {
+        let world = setup_global();
+        let author = select_user(&world, 1, "Almetica");
+        let recipient = select_user(&world, 2, "Asuna");
+        let _other = select_user(&world, 3, "Kirito");
+
+        send_whisper(&world, author, "asuna");
+        world.run(chat_manager_system);
+
+        world.run(|events: View<OutgoingEvent>| {
+            let mut receivers = Vec::new();
+            (&events).iter().for_each(|event| {
+                if let Event::ResponseWhisper {
+                    connection_id,
+                    packet,
+                } = &*event.0
+                {
+                    assert_eq!(packet.author, "Almetica");
+                    assert_eq!(packet.recipient, "Asuna");
+                    assert_eq!(packet.game_id, 1);
+                    receivers.push(*connection_id);
+                }
+            });
+            assert_eq!(receivers.len(), 2);
+            assert!(receivers.contains(&author));
+            assert!(receivers.contains(&recipient));
+        });
+    }
+
+    #[test]
+    fn test_whisper_unknown_recipient() {
+        let world = setup_global();
+        let author = select_user(&world, 1, "Almetica");
+
+        send_whisper(&world, author, "Asuna");
+        world.run(chat_manager_system);
+
+        world.run(|events: View<OutgoingEvent>| {
+            assert_eq!(events.iter().count(), 1);
+            (&events).iter().for_each(|event| {
+                if let Event::ResponseSystemMessage {
+                    connection_id,
+                    packet,
+                } = &*event.0
+                {
+                    assert_eq!(*connection_id, author);
+                    assert_eq!(packet.message, "@1\u{b}UserName\u{b}Asuna");
+                } else {
+                    panic!("Expected a system message");
+                }
+            });
+        });
+    }
+}
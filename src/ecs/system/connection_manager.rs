@@ -9,6 +9,7 @@ use shipyard::*;
 use sqlx::PgPool;
 use tracing::{debug, error, info, info_span, trace};
 
+use crate::config::Configuration;
 use crate::ecs::component::{Connection, IncomingEvent, OutgoingEvent};
 use crate::ecs::event::{EcsEvent, Event};
 use crate::ecs::resource::{ConnectionMapping, DeletionList, WorldId};
@@ -28,6 +29,7 @@ pub fn connection_manager_system(
     pool: UniqueView<PgPool>,
     mut deletion_list: UniqueViewMut<DeletionList>,
     world_id: UniqueView<WorldId>,
+    config: UniqueView<Configuration>,
 ) {
     let span = info_span!("world", world_id = world_id.0);
     let _enter = span.enter();
@@ -50,6 +52,7 @@ pub fn connection_manager_system(
             if let Err(e) = handle_request_check_version(
                 *connection_id,
                 &packet,
+                &config,
                 &mut connections,
                 &mut outgoing_events,
                 &mut entities,
@@ -134,6 +137,7 @@ fn handle_connection_registration(
     let connection_id = entities.add_entity(
         connections,
         Connection {
+            account_id: None,
             verified: false,
             version_checked: false,
             region: None,
@@ -159,6 +163,7 @@ fn handle_connection_registration(
 fn handle_request_check_version(
     connection_id: EntityId,
     packet: &CCheckVersion,
+    config: &Configuration,
     mut connections: &mut ViewMut<Connection>,
     outgoing_events: &mut ViewMut<OutgoingEvent>,
     entities: &mut EntitiesViewMut,
@@ -176,12 +181,22 @@ fn handle_request_check_version(
         )
     );
 
-    // TODO properly do the version verification? Define version 0 and 1 in the config file?
     debug!(
         "Version 1: {} version 2: {}",
         packet.version[0].value, packet.version[1].value
     );
 
+    let version = packet
+        .version
+        .iter()
+        .find(|entry| entry.index == 0)
+        .map(|entry| entry.value)
+        .context("Version array contains no entry with index 0")?;
+    ensure!(
+        config.data.versions.iter().any(|v| v.version == version),
+        format!("Client version {} is not supported", version)
+    );
+
     let mut connection = (&mut connections)
         .try_get(connection_id)
         .context("Could not find connection component for entity")?;
@@ -221,22 +236,25 @@ fn handle_request_login_arbiter(
             .await
             .context("Couldn't acquire connection from pool")?;
 
-        if !loginticket::is_ticket_valid(&mut conn, &packet.master_account_name, &ticket)
-            .await
-            .context("Error while executing query for account")?
-        {
-            return Err(anyhow!("Ticket not valid"));
-        }
+        let account_id =
+            match loginticket::validate_ticket(&mut conn, &packet.master_account_name, &ticket)
+                .await
+                .context("Error while executing query for account")?
+            {
+                Some(account_id) => account_id,
+                None => return Err(anyhow!("Ticket not valid")),
+            };
 
         info!(
-            "Account {} provided a valid ticket {}",
-            packet.master_account_name, ticket
+            "Account {} with ID {} provided a valid ticket {}",
+            packet.master_account_name, account_id, ticket
         );
 
         let mut connection = (&mut connections)
             .try_get(connection_id)
             .context("Could not find connection component for entity")?;
 
+        connection.account_id = Some(account_id);
         connection.verified = true;
         connection.region = Some(packet.region);
 
@@ -307,11 +325,11 @@ fn check_and_handle_post_initialization(
     entities: &mut EntitiesViewMut,
 ) {
     if connection.verified && connection.version_checked {
-        if let Some(region) = connection.region {
+        if let (Some(region), Some(account_id)) = (connection.region, connection.account_id) {
             // Now that the client is vetted, we need to send him some specific packets in order for him to progress.
             debug!("Sending connection post initialization commands");
 
-            // FIXME get from configuration (server name and PVP setting) and database (account_id). Set the account_id in the connection component!
+            // FIXME get from configuration (server name and PVP setting).
             send_event(
                 accept_check_version(connection_id),
                 outgoing_events,
@@ -333,12 +351,12 @@ fn check_and_handle_post_initialization(
                 entities,
             );
             send_event(
-                assemble_login_account_info(connection_id, "Almetica".to_string(), 456_456),
+                assemble_login_account_info(connection_id, "Almetica".to_string(), account_id),
                 outgoing_events,
                 entities,
             );
         } else {
-            error!("Region was not set in connection component");
+            error!("Region or account ID was not set in connection component");
         }
     }
 }
@@ -453,6 +471,7 @@ mod tests {
     use shipyard::*;
     use sqlx::{PgConnection, PgPool};
 
+    use crate::config::tests::get_configuration;
     use crate::ecs::component::{IncomingEvent, OutgoingEvent};
     use crate::ecs::event::Event;
     use crate::ecs::system::cleaner_system;
@@ -469,6 +488,7 @@ mod tests {
     fn setup(pool: PgPool) -> World {
         let world = World::new();
         world.add_unique(WorldId(0));
+        world.add_unique(get_configuration());
         world.add_unique(DeletionList(vec![]));
 
         let map = HashMap::new();
@@ -481,6 +501,7 @@ mod tests {
     fn setup_with_connection(pool: PgPool) -> (World, EntityId) {
         let world = World::new();
         world.add_unique(WorldId(0));
+        world.add_unique(get_configuration());
         world.add_unique(DeletionList(vec![]));
 
         let connection_id = world.run(
@@ -488,6 +509,7 @@ mod tests {
                 entities.add_entity(
                     &mut connections,
                     Connection {
+                        account_id: None,
                         verified: false,
                         version_checked: false,
                         region: None,
@@ -505,7 +527,7 @@ mod tests {
         (world, connection_id)
     }
 
-    async fn create_login(conn: &mut PgConnection) -> Result<(String, String)> {
+    async fn create_login(conn: &mut PgConnection) -> Result<(i64, String, String)> {
         let acc = account::create(
             conn,
             &Account {
@@ -519,7 +541,7 @@ mod tests {
         )
         .await?;
         let ticket = loginticket::upsert_ticket(conn, acc.id).await?;
-        Ok((acc.name, ticket.ticket))
+        Ok((acc.id, acc.name, ticket.ticket))
     }
 
     #[test]
@@ -647,12 +669,64 @@ mod tests {
         db_test(test)
     }
 
+    #[test]
+    fn test_check_version_unsupported() -> Result<()> {
+        async fn test(pool: PgPool) -> Result<()> {
+            let (world, connection_id) = setup_with_connection(pool);
+
+            world.run(
+                |mut entities: EntitiesViewMut, mut events: ViewMut<IncomingEvent>| {
+                    entities.add_entity(
+                        &mut events,
+                        IncomingEvent(Arc::new(Event::RequestCheckVersion {
+                            connection_id,
+                            packet: CCheckVersion {
+                                version: vec![
+                                    CCheckVersionEntry {
+                                        index: 0,
+                                        value: 363_037,
+                                    },
+                                    CCheckVersionEntry {
+                                        index: 1,
+                                        value: 365_535,
+                                    },
+                                ],
+                            },
+                        })),
+                    )
+                },
+            );
+
+            world.run(connection_manager_system);
+
+            let count = world
+                .borrow::<View<OutgoingEvent>>()
+                .iter()
+                .filter(|event| match &*event.0 {
+                    Event::ResponseCheckVersion { packet, .. } => !packet.ok,
+                    Event::ResponseDropConnection { .. } => true,
+                    _ => false,
+                })
+                .count();
+            assert_eq!(count, 2);
+
+            let invalid_count = world
+                .borrow::<View<Connection>>()
+                .iter()
+                .filter(|connection| !connection.version_checked)
+                .count();
+            assert_eq!(invalid_count, 1);
+            Ok(())
+        }
+        db_test(test)
+    }
+
     #[test]
     fn test_login_arbiter_valid() -> Result<()> {
         async fn test(pool: PgPool) -> Result<()> {
             let mut conn = pool.acquire().await?;
             let (world, connection_id) = setup_with_connection(pool);
-            let (account_name, ticket) = create_login(&mut conn).await?;
+            let (account_id, account_name, ticket) = create_login(&mut conn).await?;
 
             world.run(
                 |mut entities: EntitiesViewMut, mut events: ViewMut<IncomingEvent>| {
@@ -681,6 +755,10 @@ mod tests {
                 .filter(|connection| connection.verified)
                 .count();
             assert_eq!(valid_count, 1);
+
+            world.run(|connections: View<Connection>| {
+                assert_eq!(connections[connection_id].account_id, Some(account_id));
+            });
             Ok(())
         }
         db_test(test)
@@ -691,7 +769,7 @@ mod tests {
         async fn test(pool: PgPool) -> Result<()> {
             let mut conn = pool.acquire().await?;
             let (world, connection_id) = setup_with_connection(pool);
-            let (account_name, mut ticket) = create_login(&mut conn).await?;
+            let (_, account_name, mut ticket) = create_login(&mut conn).await?;
 
             // Make ticket invalid
             ticket.make_ascii_uppercase();
@@ -731,7 +809,7 @@ mod tests {
             let invalid_count = world
                 .borrow::<View<Connection>>()
                 .iter()
-                .filter(|connection| !connection.verified)
+                .filter(|connection| !connection.verified && connection.account_id.is_none())
                 .count();
             assert_eq!(invalid_count, 1);
             Ok(())
@@ -744,7 +822,7 @@ mod tests {
         async fn test(pool: PgPool) -> Result<()> {
             let mut conn = pool.acquire().await?;
             let world = setup(pool);
-            let (account_name, ticket) = create_login(&mut conn).await?;
+            let (account_id, account_name, ticket) = create_login(&mut conn).await?;
             let (tx_channel, _rx_channel) = channel(10);
 
             world.run(
@@ -869,6 +947,7 @@ mod tests {
                 {
                     assert_eq!(*connection_id, con);
                     assert!(!packet.server_name.trim().is_empty());
+                    assert_eq!(packet.account_id, account_id);
                 } else {
                     panic!("received packets in wrong order");
                 }
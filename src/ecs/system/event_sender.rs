@@ -1,59 +1,176 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use async_std::sync::Sender;
-use async_std::task;
+use futures_util::FutureExt;
 use shipyard::*;
-use tracing::{debug, error, info_span, trace};
+use tracing::{debug, error, info_span, trace, warn};
 
 use crate::ecs::component::*;
 use crate::ecs::event::{EcsEvent, Event};
-use crate::ecs::resource::{ConnectionMapping, WorldId};
-use tracing_futures::Instrument;
+use crate::ecs::resource::{
+    ConnectionMapping, DeliveryClass, DeliveryPolicy, OutboundQueue, OutboundQueues, WorldId,
+};
+use crate::metrics::Metrics;
 
 /// Event sender sends all outgoing events to the connection / local worlds.
+///
+/// Events are queued per connection and delivered without blocking the tick. Connections that
+/// don't keep up are handled by the delivery policy of the world.
 pub fn event_sender_system(
     outgoing_events: View<OutgoingEvent>,
     mut connection_mapping: UniqueViewMut<ConnectionMapping>,
+    mut outbound_queues: UniqueViewMut<OutboundQueues>,
+    policy: UniqueView<DeliveryPolicy>,
     world_id: UniqueView<WorldId>,
+    metrics: UniqueView<Arc<Metrics>>,
 ) {
     let span = info_span!("world", world_id = world_id.0);
     let _enter = span.enter();
 
     (&outgoing_events).iter().for_each(|event| {
-        send_event_to_connection(&event, &mut connection_mapping.0);
+        queue_event(
+            &event,
+            &connection_mapping.0,
+            &mut outbound_queues.0,
+            &policy,
+            &metrics,
+        );
     });
+
+    deliver_events(
+        &mut connection_mapping.0,
+        &mut outbound_queues.0,
+        &policy,
+        &metrics,
+    );
 }
 
-fn send_event_to_connection(
+/// Puts the event into the outbound queue of it's connection.
+fn queue_event(
     event: &OutgoingEvent,
+    connection_mapping: &HashMap<EntityId, Sender<EcsEvent>>,
+    outbound_queues: &mut HashMap<EntityId, OutboundQueue>,
+    policy: &DeliveryPolicy,
+    metrics: &Metrics,
+) {
+    let connection_id = match event.0.connection_id() {
+        Some(connection_id) => connection_id,
+        None => {
+            error!("Event didn't had an connection attached");
+            return;
+        }
+    };
+
+    let span = info_span!("connection", connection = ?connection_id);
+    let _enter = span.enter();
+
+    if !connection_mapping.contains_key(&connection_id) {
+        debug!("Couldn't find a channel mapping for the connection");
+        return;
+    }
+
+    let queue = outbound_queues.entry(connection_id).or_default();
+    if queue.closing {
+        debug!("Discarding event {} for closing connection", *event.0);
+        return;
+    }
+
+    let class = policy.classify(&event.0);
+    if let DeliveryClass::Coalescable(..) = class {
+        if let Some(queued) = queue
+            .events
+            .iter_mut()
+            .find(|queued| policy.classify(queued) == class)
+        {
+            trace!("Coalescing event {}", *event.0);
+            *queued = event.0.clone();
+            metrics.add_coalesced_event();
+            return;
+        }
+    }
+
+    if class != DeliveryClass::Critical && queue.events.len() >= policy.drop_threshold {
+        warn!("Dropping event {} for slow connection", *event.0);
+        metrics.add_dropped_event();
+        return;
+    }
+
+    debug!("Queueing event {}", *event.0);
+    if let Event::ResponseDropConnection { .. } = *event.0 {
+        queue.closing = true;
+    }
+    queue.events.push_back(event.0.clone());
+    metrics.add_queued_events(1);
+}
+
+/// Delivers the queued events until the channels of the connections are full. Connections
+/// whose queue grows beyond the limit of the policy are dropped.
+fn deliver_events(
     connection_mapping: &mut HashMap<EntityId, Sender<EcsEvent>>,
+    outbound_queues: &mut HashMap<EntityId, OutboundQueue>,
+    policy: &DeliveryPolicy,
+    metrics: &Metrics,
 ) {
-    if let Some(connection_id) = event.0.connection_id() {
+    let mut finished = Vec::new();
+    let mut dropped = Vec::new();
+
+    for (connection_id, queue) in outbound_queues.iter_mut() {
         let span = info_span!("connection", connection = ?connection_id);
         let _enter = span.enter();
 
-        if let Some(channel) = connection_mapping.get_mut(&connection_id) {
-            debug!("Sending event {}", *event.0);
-            if !channel.is_full() {
-                task::block_on(async {
-                    channel.send(event.0.clone()).await;
-                })
-                .instrument(span.clone());
-                trace!("Finished sending event {}", *event.0);
-            } else {
-                error!("Dropping event for connection because channel is full");
+        let channel = match connection_mapping.get(connection_id) {
+            Some(channel) => channel,
+            None => {
+                debug!("Discarding queue of unregistered connection");
+                finished.push(*connection_id);
+                continue;
             }
-            if let Event::ResponseDropConnection { connection_id } = *event.0 {
-                connection_mapping.remove(&connection_id);
+        };
+
+        while let Some(event) = queue.events.front() {
+            if !try_send(channel, event.clone()) {
+                break;
             }
-        } else {
-            debug!("Couldn't find a channel mapping for the connection");
+            trace!("Finished sending event {}", **event);
+            queue.events.pop_front();
+            metrics.remove_queued_events(1);
         }
-    } else {
-        error!("Event didn't had an connection attached");
+
+        if queue.events.is_empty() {
+            finished.push(*connection_id);
+            if queue.closing {
+                dropped.push(*connection_id);
+            }
+        } else if queue.events.len() > policy.max_queue_length {
+            warn!(
+                "Dropping slow connection with {} queued events",
+                queue.events.len()
+            );
+            metrics.add_slow_connection_drop();
+            finished.push(*connection_id);
+            dropped.push(*connection_id);
+        }
+    }
+
+    for connection_id in finished {
+        if let Some(queue) = outbound_queues.remove(&connection_id) {
+            metrics.remove_queued_events(queue.events.len() as u64);
+        }
+    }
+    // The connection closes once the world drops it's side of the channel.
+    for connection_id in dropped {
+        connection_mapping.remove(&connection_id);
     }
 }
 
+/// Sends the event without blocking. Returns false if the channel of the connection is full.
+fn try_send(channel: &Sender<EcsEvent>, event: EcsEvent) -> bool {
+    // The world is the only sender of the channel, so a send into a channel with free capacity
+    // completes at once.
+    !channel.is_full() && channel.send(event).now_or_never().is_some()
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
@@ -65,18 +182,27 @@ mod tests {
 
     use crate::ecs::component::Connection;
     use crate::ecs::event::Event;
+    use crate::ecs::resource::DeletionList;
+    use crate::ecs::system::cleaner_system;
+    use crate::model::Vec3;
+    use crate::protocol::packet::{SChat, SUserLocation};
 
     use super::*;
 
     fn setup() -> (World, EntityId, Receiver<Arc<Event>>) {
         let world = World::new();
         world.add_unique(WorldId(0));
+        world.add_unique(Arc::new(Metrics::new()));
+        world.add_unique(OutboundQueues::default());
+        world.add_unique(DeliveryPolicy::default());
+        world.add_unique(DeletionList(Vec::new()));
 
         let connection_id = world.run(
             |mut entities: EntitiesViewMut, mut connections: ViewMut<Connection>| {
                 entities.add_entity(
                     &mut connections,
                     Connection {
+                        account_id: None,
                         verified: false,
                         version_checked: false,
                         region: None,
@@ -163,4 +289,171 @@ mod tests {
         });
         assert_eq!(count, 1);
     }
+
+    fn add_events(world: &World, events: Vec<Event>) {
+        world.run(
+            |mut entities: EntitiesViewMut, mut outgoing_events: ViewMut<OutgoingEvent>| {
+                for event in events {
+                    entities.add_entity(&mut outgoing_events, OutgoingEvent(Arc::new(event)));
+                }
+            },
+        );
+    }
+
+    fn register_events(connection_id: EntityId, count: usize) -> Vec<Event> {
+        (0..count)
+            .map(|_| Event::ResponseRegisterConnection { connection_id })
+            .collect()
+    }
+
+    fn user_location(connection_id: EntityId, time: u32) -> Event {
+        Event::ResponseUserLocation {
+            connection_id,
+            packet: SUserLocation {
+                game_id: 1,
+                location: Vec3 {
+                    x: 0.0,
+                    y: 0.0,
+                    z: 0.0,
+                },
+                rotation: 0,
+                look_direction: 0,
+                speed: 0,
+                destination: Vec3 {
+                    x: 0.0,
+                    y: 0.0,
+                    z: 0.0,
+                },
+                move_type: 0,
+                in_shuttle: false,
+                time,
+            },
+        }
+    }
+
+    fn queue_length(world: &World, connection_id: EntityId) -> usize {
+        world
+            .borrow::<UniqueView<OutboundQueues>>()
+            .0
+            .get(&connection_id)
+            .map(|queue| queue.events.len())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn test_queue_events_of_full_channel() {
+        let (world, connection_id, channel) = setup();
+
+        // The channel of the connection can hold 128 events.
+        add_events(&world, register_events(connection_id, 130));
+        world.run(event_sender_system);
+        world.run(cleaner_system);
+
+        assert_eq!(channel.len(), 128);
+        assert_eq!(queue_length(&world, connection_id), 2);
+        assert_eq!(
+            world
+                .borrow::<UniqueView<Arc<Metrics>>>()
+                .snapshot()
+                .queued_events,
+            2
+        );
+
+        // The queued events are delivered once the connection catches up.
+        task::block_on(async {
+            for _ in 0..10 {
+                channel.recv().await;
+            }
+        });
+        world.run(event_sender_system);
+
+        assert_eq!(channel.len(), 120);
+        assert_eq!(queue_length(&world, connection_id), 0);
+        let metrics = world.borrow::<UniqueView<Arc<Metrics>>>().snapshot();
+        assert_eq!(metrics.queued_events, 0);
+        assert_eq!(metrics.dropped_events, 0);
+    }
+
+    #[test]
+    fn test_drop_events_of_slow_connection() {
+        let (world, connection_id, channel) = setup();
+        world
+            .borrow::<UniqueViewMut<DeliveryPolicy>>()
+            .drop_threshold = 1;
+
+        let mut events = register_events(connection_id, 129);
+        events.push(Event::ResponseChat {
+            connection_id,
+            packet: SChat {
+                name: "Almetica".to_string(),
+                message: "Hello".to_string(),
+                channel: 0,
+                author_id: 1,
+                unk1: false,
+                gm: false,
+                unk2: false,
+            },
+        });
+        add_events(&world, events);
+        world.run(event_sender_system);
+
+        // Critical events are queued, the chat message is dropped.
+        assert_eq!(channel.len(), 128);
+        assert_eq!(queue_length(&world, connection_id), 1);
+        let metrics = world.borrow::<UniqueView<Arc<Metrics>>>().snapshot();
+        assert_eq!(metrics.dropped_events, 1);
+        assert_eq!(metrics.queued_events, 1);
+    }
+
+    #[test]
+    fn test_coalesce_location_events() {
+        let (world, connection_id, channel) = setup();
+
+        let mut events = register_events(connection_id, 128);
+        events.push(user_location(connection_id, 1));
+        events.push(user_location(connection_id, 2));
+        add_events(&world, events);
+        world.run(event_sender_system);
+
+        assert_eq!(channel.len(), 128);
+        assert_eq!(queue_length(&world, connection_id), 1);
+        assert_eq!(
+            world
+                .borrow::<UniqueView<Arc<Metrics>>>()
+                .snapshot()
+                .coalesced_events,
+            1
+        );
+
+        let queues = world.borrow::<UniqueView<OutboundQueues>>();
+        match &*queues.0[&connection_id].events[0] {
+            Event::ResponseUserLocation { packet, .. } => assert_eq!(packet.time, 2),
+            _ => panic!("Couldn't find user location event"),
+        }
+    }
+
+    #[test]
+    fn test_drop_slow_connection() {
+        let (world, connection_id, channel) = setup();
+        world
+            .borrow::<UniqueViewMut<DeliveryPolicy>>()
+            .max_queue_length = 1;
+
+        add_events(&world, register_events(connection_id, 130));
+        world.run(event_sender_system);
+
+        // The world dropped it's side of the channel.
+        assert_eq!(world.borrow::<UniqueView<ConnectionMapping>>().0.len(), 0);
+        assert_eq!(queue_length(&world, connection_id), 0);
+        let metrics = world.borrow::<UniqueView<Arc<Metrics>>>().snapshot();
+        assert_eq!(metrics.slow_connection_drops, 1);
+        assert_eq!(metrics.queued_events, 0);
+
+        task::block_on(async {
+            for _ in 0..128 {
+                channel.recv().await;
+            }
+            assert!(channel.recv().await.is_none());
+        });
+    }
 }
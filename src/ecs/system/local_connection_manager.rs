@@ -0,0 +1,231 @@
+use async_std::sync::Sender;
+use shipyard::*;
+use tracing::{debug, info_span};
+
+use crate::ecs::component::{ConnectionID, IncomingEvent, Settings, UserSpawn, UserSpawnStatus};
+use crate::ecs::event::{EcsEvent, Event};
+use crate::ecs::resource::{ConnectionMapping, DeletionList, WorldId};
+
+/// Local connection manager handles the connections that were handed off to a local world.
+pub fn local_connection_manager_system(
+    incoming_events: View<IncomingEvent>,
+    mut connection_ids: ViewMut<ConnectionID>,
+    mut user_spawns: ViewMut<UserSpawn>,
+    mut settings: ViewMut<Settings>,
+    mut entities: EntitiesViewMut,
+    mut connection_map: UniqueViewMut<ConnectionMapping>,
+    mut deletion_list: UniqueViewMut<DeletionList>,
+    world_id: UniqueView<WorldId>,
+) {
+    let span = info_span!("world", world_id = world_id.0);
+    let _enter = span.enter();
+
+    (&incoming_events).iter().for_each(|event| match &*event.0 {
+        Event::RequestRegisterLocalConnection {
+            connection_id,
+            user_id,
+            visibility_range,
+            response_channel,
+        } => handle_local_connection_registration(
+            *connection_id,
+            *user_id,
+            *visibility_range,
+            &response_channel,
+            &mut connection_ids,
+            &mut user_spawns,
+            &mut settings,
+            &mut entities,
+            &mut connection_map,
+        ),
+        Event::RequestUnregisterLocalConnection { connection_id } => {
+            handle_local_connection_unregistration(
+                *connection_id,
+                &connection_ids,
+                &mut connection_map,
+                &mut deletion_list,
+            )
+        }
+        _ => { /* Ignore all other packets */ }
+    });
+}
+
+fn handle_local_connection_registration(
+    connection_id: EntityId,
+    user_id: i32,
+    visibility_range: u32,
+    response_channel: &Sender<EcsEvent>,
+    connection_ids: &mut ViewMut<ConnectionID>,
+    user_spawns: &mut ViewMut<UserSpawn>,
+    settings: &mut ViewMut<Settings>,
+    entities: &mut EntitiesViewMut,
+    connection_map: &mut UniqueViewMut<ConnectionMapping>,
+) {
+    let span = info_span!("connection", connection = ?connection_id);
+    let _enter = span.enter();
+
+    debug!("Local registration event incoming");
+
+    // The event sender uses the connection ID of the global world to find the response channel.
+    if connection_map
+        .0
+        .insert(connection_id, response_channel.clone())
+        .is_some()
+    {
+        debug!("Connection was already registered. Replaced response channel");
+        return;
+    }
+
+    // The user spawner picks up the selected user of the connection.
+    let local_id = entities.add_entity(
+        (connection_ids, user_spawns, settings),
+        (
+            ConnectionID(connection_id),
+            UserSpawn {
+                user_id,
+                status: UserSpawnStatus::Requesting,
+            },
+            Settings { visibility_range },
+        ),
+    );
+
+    debug!("Registered connection locally as {:?}", local_id);
+}
+
+fn handle_local_connection_unregistration(
+    connection_id: EntityId,
+    connection_ids: &ViewMut<ConnectionID>,
+    connection_map: &mut UniqueViewMut<ConnectionMapping>,
+    deletion_list: &mut UniqueViewMut<DeletionList>,
+) {
+    let span = info_span!("connection", connection = ?connection_id);
+    let _enter = span.enter();
+
+    debug!("Local unregistration event incoming");
+
+    connection_map.0.remove(&connection_id);
+
+    let mut local_ids: Vec<EntityId> = connection_ids
+        .iter()
+        .with_id()
+        .filter(|(_, id)| id.0 == connection_id)
+        .map(|(local_id, _)| local_id)
+        .collect();
+    deletion_list.0.append(&mut local_ids);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use async_std::sync::channel;
+    use shipyard::*;
+
+    use crate::ecs::component::{ConnectionID, IncomingEvent};
+    use crate::ecs::event::Event;
+    use crate::ecs::system::cleaner_system;
+
+    use super::*;
+
+    fn setup() -> (World, EntityId) {
+        let world = World::new();
+        world.add_unique(WorldId(1));
+        world.add_unique(DeletionList(vec![]));
+        world.add_unique(ConnectionMapping(HashMap::new()));
+
+        // The connection ID is an entity of the global world.
+        let connection_id = World::new().borrow::<EntitiesViewMut>().add_entity((), ());
+
+        (world, connection_id)
+    }
+
+    #[test]
+    fn test_local_connection_registration() {
+        let (world, connection_id) = setup();
+        let (tx_channel, _rx_channel) = channel(10);
+
+        world.run(
+            |mut entities: EntitiesViewMut, mut events: ViewMut<IncomingEvent>| {
+                entities.add_entity(
+                    &mut events,
+                    IncomingEvent(Arc::new(Event::RequestRegisterLocalConnection {
+                        connection_id,
+                        user_id: 1,
+                        visibility_range: 2000,
+                        response_channel: tx_channel.clone(),
+                    })),
+                );
+            },
+        );
+
+        world.run(local_connection_manager_system);
+
+        assert!(world
+            .borrow::<UniqueView<ConnectionMapping>>()
+            .0
+            .contains_key(&connection_id));
+
+        let count = world
+            .borrow::<View<ConnectionID>>()
+            .iter()
+            .filter(|id| id.0 == connection_id)
+            .count();
+        assert_eq!(count, 1);
+
+        let count = world
+            .borrow::<View<UserSpawn>>()
+            .iter()
+            .filter(|spawn| spawn.user_id == 1 && spawn.status == UserSpawnStatus::Requesting)
+            .count();
+        assert_eq!(count, 1);
+
+        let count = world
+            .borrow::<View<Settings>>()
+            .iter()
+            .filter(|settings| settings.visibility_range == 2000)
+            .count();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_local_connection_unregistration() {
+        let (world, connection_id) = setup();
+        let (tx_channel, _rx_channel) = channel(10);
+
+        world.run(
+            |mut entities: EntitiesViewMut, mut events: ViewMut<IncomingEvent>| {
+                entities.add_entity(
+                    &mut events,
+                    IncomingEvent(Arc::new(Event::RequestRegisterLocalConnection {
+                        connection_id,
+                        user_id: 1,
+                        visibility_range: 2000,
+                        response_channel: tx_channel.clone(),
+                    })),
+                );
+            },
+        );
+
+        world.run(local_connection_manager_system);
+        world.run(cleaner_system);
+
+        world.run(
+            |mut entities: EntitiesViewMut, mut events: ViewMut<IncomingEvent>| {
+                entities.add_entity(
+                    &mut events,
+                    IncomingEvent(Arc::new(Event::RequestUnregisterLocalConnection {
+                        connection_id,
+                    })),
+                );
+            },
+        );
+
+        world.run(local_connection_manager_system);
+        world.run(cleaner_system);
+
+        assert!(world.borrow::<UniqueView<ConnectionMapping>>().0.is_empty());
+        assert_eq!(world.borrow::<View<ConnectionID>>().iter().count(), 0);
+        assert_eq!(world.borrow::<View<UserSpawn>>().iter().count(), 0);
+        assert_eq!(world.borrow::<View<Settings>>().iter().count(), 0);
+    }
+}
@@ -0,0 +1,395 @@
+/// Validates the movement of spawned users and informs the users that can see them.
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::{ensure, Context};
+use shipyard::*;
+use tracing::{debug, error, info_span};
+
+use crate::ecs::component::{
+    ConnectionID, IncomingEvent, Location, OutgoingEvent, UserSpawn, UserSpawnStatus, Visibility,
+};
+use crate::ecs::event::Event;
+use crate::ecs::resource::WorldId;
+use crate::ecs::system::{find_local_entity, send_event};
+use crate::model::entity::User;
+use crate::protocol::packet::*;
+use crate::Result;
+
+// TODO use the real boundaries of the zone once we have the zone data.
+/// Lowest z coordinate a user can be located at.
+const MIN_Z: f32 = -20_000.0;
+/// Highest z coordinate a user can be located at.
+const MAX_Z: f32 = 20_000.0;
+/// Maximal distance a user can travel with a single location update.
+const MAX_TELEPORT_DISTANCE: f32 = 2_000.0;
+/// Maximal speed of a user in units per second.
+const MAX_SPEED: f32 = 400.0;
+/// Distance a user can travel on top of the maximal speed to compensate network jitter.
+const SPEED_TOLERANCE: f32 = 100.0;
+/// Run speed of a user as announced with the login packet.
+const RUN_SPEED: i16 = 150;
+
+pub fn movement_manager_system(
+    incoming_events: View<IncomingEvent>,
+    mut outgoing_events: ViewMut<OutgoingEvent>,
+    connection_ids: View<ConnectionID>,
+    user_spawns: View<UserSpawn>,
+    users: View<User>,
+    mut locations: ViewMut<Location>,
+    visibilities: View<Visibility>,
+    mut entities: EntitiesViewMut,
+    world_id: UniqueView<WorldId>,
+) {
+    let span = info_span!("world", world_id = world_id.0);
+    let _enter = span.enter();
+
+    (&incoming_events).iter().for_each(|event| {
+        if let Event::RequestPlayerLocation {
+            connection_id,
+            packet,
+        } = &*event.0
+        {
+            if let Err(e) = handle_player_location(
+                &packet,
+                *connection_id,
+                &connection_ids,
+                &user_spawns,
+                &users,
+                &mut locations,
+                &visibilities,
+                &mut outgoing_events,
+                &mut entities,
+            ) {
+                error!("Rejecting player location request: {:?}", e);
+            }
+        }
+    });
+}
+
+fn handle_player_location(
+    packet: &CPlayerLocation,
+    connection_id: EntityId,
+    connection_ids: &View<ConnectionID>,
+    user_spawns: &View<UserSpawn>,
+    users: &View<User>,
+    mut locations: &mut ViewMut<Location>,
+    visibilities: &View<Visibility>,
+    outgoing_events: &mut ViewMut<OutgoingEvent>,
+    entities: &mut EntitiesViewMut,
+) -> Result<()> {
+    let span = info_span!("connection", connection = ?connection_id);
+    let _enter = span.enter();
+
+    debug!("Player location event incoming");
+
+    let local_id = find_local_entity(connection_id, connection_ids)
+        .context("Connection is not registered in the local world")?;
+
+    let user_spawn = user_spawns
+        .try_get(local_id)
+        .context("Could not find user spawn component for entity")?;
+    ensure!(
+        user_spawn.status == UserSpawnStatus::Spawned,
+        "User is not spawned yet"
+    );
+
+    let user = users
+        .try_get(local_id)
+        .context("Could not find user component for entity")?;
+
+    // TODO correct the location of the client once we know how to
+    let now = Instant::now();
+    let mut location = (&mut locations)
+        .try_get(local_id)
+        .context("Could not find location component for entity")?;
+    validate_movement(&location, packet, now)?;
+
+    location.location = packet.location;
+    location.rotation = packet.rotation;
+    location.last_update = now;
+
+    // Every user that can see the moving user is informed.
+    let observers: Vec<EntityId> = (connection_ids, visibilities)
+        .iter()
+        .filter(|(_, visibility)| visibility.visible.contains_key(&local_id))
+        .map(|(observer_connection_id, _)| observer_connection_id.0)
+        .collect();
+
+    for observer_connection_id in observers {
+        send_event(
+            assemble_user_location(observer_connection_id, user, packet),
+            outgoing_events,
+            entities,
+        );
+    }
+
+    Ok(())
+}
+
+/// Checks if the movement from the current location to the requested location is plausible.
+fn validate_movement(current: &Location, packet: &CPlayerLocation, now: Instant) -> Result<()> {
+    ensure!(
+        packet.location.z >= MIN_Z && packet.location.z <= MAX_Z,
+        "Location {:?} is out of the z-bounds",
+        packet.location
+    );
+
+    let distance = current.location.distance(&packet.location);
+    ensure!(
+        distance <= MAX_TELEPORT_DISTANCE,
+        "User tried to teleport over a distance of {}",
+        distance
+    );
+
+    let elapsed = now.duration_since(current.last_update).as_secs_f32();
+    let max_distance = MAX_SPEED * elapsed + SPEED_TOLERANCE;
+    ensure!(
+        distance <= max_distance,
+        "User moved {} units in {} seconds, but only {} units are allowed",
+        distance,
+        elapsed,
+        max_distance
+    );
+
+    Ok(())
+}
+
+fn assemble_user_location(
+    connection_id: EntityId,
+    user: &User,
+    packet: &CPlayerLocation,
+) -> OutgoingEvent {
+    OutgoingEvent(Arc::new(Event::ResponseUserLocation {
+        connection_id,
+        packet: SUserLocation {
+            game_id: user.id as u64,
+            location: packet.location,
+            rotation: packet.rotation,
+            look_direction: packet.look_direction,
+            speed: RUN_SPEED,
+            destination: packet.destination,
+            move_type: packet.move_type,
+            in_shuttle: packet.in_shuttle,
+            time: packet.time,
+        },
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::{TimeZone, Utc};
+
+    use crate::ecs::component::Settings;
+    use crate::ecs::resource::{DeletionList, SpatialGrid};
+    use crate::ecs::system::user_spawner::START_ZONE;
+    use crate::ecs::system::{cleaner_system, visibility_manager_system};
+    use crate::model::{Class, Customization, Gender, Race, Vec3};
+
+    use super::*;
+
+    fn setup() -> World {
+        let world = World::new();
+        world.add_unique(WorldId(1));
+        world.add_unique(DeletionList(vec![]));
+        world.add_unique(SpatialGrid::new(1_000.0));
+        world
+    }
+
+    fn user(id: i32) -> User {
+        User {
+            id,
+            account_id: 1,
+            name: format!("User{}", id),
+            gender: Gender::Female,
+            race: Race::HighElf,
+            class: Class::Priest,
+            shape: vec![],
+            details: vec![],
+            appearance: Customization { data: vec![] },
+            appearance2: 100,
+            playtime: 0,
+            deletion_time: None,
+            created_at: Utc.ymd(1995, 7, 8).and_hms(9, 10, 11),
+        }
+    }
+
+    fn location(x: f32, last_update: Instant) -> Location {
+        Location {
+            zone: START_ZONE,
+            location: Vec3 { x, y: 0.0, z: 0.0 },
+            rotation: 0,
+            last_update,
+        }
+    }
+
+    fn player_location(x: f32, z: f32) -> CPlayerLocation {
+        CPlayerLocation {
+            location: Vec3 { x, y: 0.0, z },
+            rotation: 100,
+            look_direction: 0,
+            destination: Vec3 { x, y: 0.0, z },
+            move_type: 0,
+            jump_distance: 0,
+            in_shuttle: false,
+            time: 1000,
+        }
+    }
+
+    /// Spawns a user at the given x coordinate and returns it's global connection ID.
+    fn spawn_user(world: &World, global_world: &World, user_id: i32, x: f32) -> EntityId {
+        let connection_id = global_world.borrow::<EntitiesViewMut>().add_entity((), ());
+
+        world.run(
+            |mut entities: EntitiesViewMut,
+             mut connection_ids: ViewMut<ConnectionID>,
+             mut user_spawns: ViewMut<UserSpawn>,
+             mut users: ViewMut<User>,
+             mut locations: ViewMut<Location>,
+             mut settings: ViewMut<Settings>| {
+                entities.add_entity(
+                    (
+                        &mut connection_ids,
+                        &mut user_spawns,
+                        &mut users,
+                        &mut locations,
+                        &mut settings,
+                    ),
+                    (
+                        ConnectionID(connection_id),
+                        UserSpawn {
+                            user_id,
+                            status: UserSpawnStatus::Spawned,
+                        },
+                        user(user_id),
+                        location(x, Instant::now() - Duration::from_secs(1)),
+                        Settings {
+                            visibility_range: 1000,
+                        },
+                    ),
+                );
+            },
+        );
+
+        connection_id
+    }
+
+    /// Lets the users see each other and removes the resulting spawn events.
+    fn update_visibility(world: &World) {
+        world.run(visibility_manager_system);
+        world.run(cleaner_system);
+    }
+
+    fn move_user(world: &World, connection_id: EntityId, packet: CPlayerLocation) {
+        world.run(
+            |mut entities: EntitiesViewMut, mut events: ViewMut<IncomingEvent>| {
+                entities.add_entity(
+                    &mut events,
+                    IncomingEvent(Arc::new(Event::RequestPlayerLocation {
+                        connection_id,
+                        packet,
+                    })),
+                );
+            },
+        );
+
+        world.run(movement_manager_system);
+    }
+
+    #[test]
+    fn test_validate_movement() {
+        let now = Instant::now();
+        let current = location(0.0, now - Duration::from_secs(1));
+
+        assert!(validate_movement(&current, &player_location(300.0, 0.0), now).is_ok());
+        assert!(validate_movement(&current, &player_location(0.0, MAX_Z + 1.0), now).is_err());
+        assert!(validate_movement(&current, &player_location(0.0, MIN_Z - 1.0), now).is_err());
+        assert!(validate_movement(&current, &player_location(1_000.0, 0.0), now).is_err());
+
+        let current = location(0.0, now - Duration::from_secs(60));
+        assert!(validate_movement(&current, &player_location(1_900.0, 0.0), now).is_ok());
+        assert!(validate_movement(&current, &player_location(3_000.0, 0.0), now).is_err());
+    }
+
+    #[test]
+    fn test_player_location_broadcast() {
+        let world = setup();
+        let global_world = World::new();
+
+        let mover = spawn_user(&world, &global_world, 1, 0.0);
+        let observer = spawn_user(&world, &global_world, 2, 500.0);
+        let _far_observer = spawn_user(&world, &global_world, 3, 5_000.0);
+        update_visibility(&world);
+
+        move_user(&world, mover, player_location(100.0, 0.0));
+
+        world.run(|events: View<OutgoingEvent>| {
+            let list: Vec<&OutgoingEvent> = (&events).iter().collect();
+            assert_eq!(list.len(), 1);
+
+            if let Event::ResponseUserLocation {
+                connection_id,
+                packet,
+            } = &*list[0].0
+            {
+                assert_eq!(*connection_id, observer);
+                assert_eq!(packet.game_id, 1);
+                assert_eq!(packet.location.x, 100.0);
+                assert_eq!(packet.rotation, 100);
+            } else {
+                panic!("Couldn't find user location response");
+            }
+        });
+
+        let count = world
+            .borrow::<View<Location>>()
+            .iter()
+            .filter(|location| location.location.x == 100.0 && location.rotation == 100)
+            .count();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_player_location_teleport() {
+        let world = setup();
+        let global_world = World::new();
+
+        let mover = spawn_user(&world, &global_world, 1, 0.0);
+        let _observer = spawn_user(&world, &global_world, 2, 500.0);
+        update_visibility(&world);
+
+        move_user(&world, mover, player_location(10_000.0, 0.0));
+
+        assert_eq!(world.borrow::<View<OutgoingEvent>>().iter().count(), 0);
+
+        let count = world
+            .borrow::<View<Location>>()
+            .iter()
+            .filter(|location| location.location.x == 10_000.0)
+            .count();
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn test_player_location_not_spawned() {
+        let world = setup();
+        let global_world = World::new();
+
+        let mover = spawn_user(&world, &global_world, 1, 0.0);
+        let _observer = spawn_user(&world, &global_world, 2, 500.0);
+        update_visibility(&world);
+
+        world.run(|mut user_spawns: ViewMut<UserSpawn>| {
+            (&mut user_spawns)
+                .iter()
+                .filter(|spawn| spawn.user_id == 1)
+                .for_each(|spawn| spawn.status = UserSpawnStatus::Waiting);
+        });
+
+        move_user(&world, mover, player_location(100.0, 0.0));
+
+        assert_eq!(world.borrow::<View<OutgoingEvent>>().iter().count(), 0);
+    }
+}
@@ -1,7 +1,7 @@
 use shipyard::*;
 use tracing::{debug, info_span};
 
-use crate::ecs::component::{IncomingEvent, Settings};
+use crate::ecs::component::{IncomingEvent, Settings, MAX_VISIBILITY_RANGE};
 use crate::ecs::event::Event;
 use crate::ecs::resource::WorldId;
 use crate::protocol::packet::CSetVisibleRange;
@@ -40,14 +40,21 @@ fn handle_set_visible_range(
 
     debug!("Set visible range event incoming");
 
-    // TODO The local world need to know of this values. Send this value once the user enters the local world.
+    // The range is controlled by the client and determines how much of the world is queried.
+    if packet.range > MAX_VISIBILITY_RANGE {
+        debug!(
+            "Clamping visible range {} to {}",
+            packet.range, MAX_VISIBILITY_RANGE
+        );
+    }
+    let visibility_range = packet.range.min(MAX_VISIBILITY_RANGE);
+
+    // TODO The local world receives this value once the user enters it. Forward later changes to the local world.
     if let Ok(mut settings) = (&mut settings).try_get(connection_id) {
-        settings.visibility_range = packet.range;
+        settings.visibility_range = visibility_range;
     } else {
-        let user_settings = Settings {
-            visibility_range: packet.range,
-        };
-        entities.add_entity(settings, user_settings);
+        let user_settings = Settings { visibility_range };
+        entities.add_component(settings, user_settings, connection_id);
     }
 }
 
@@ -72,6 +79,7 @@ mod tests {
                 entities.add_entity(
                     &mut connections,
                     Connection {
+                        account_id: None,
                         verified: false,
                         version_checked: false,
                         region: None,
@@ -103,12 +111,40 @@ mod tests {
 
         world.run(settings_manager_system);
 
-        let valid_component_count = world
-            .borrow::<View<Settings>>()
-            .iter()
-            .filter(|component| component.visibility_range > 0)
-            .count();
+        world.run(|settings: View<Settings>| {
+            assert_eq!(settings.iter().count(), 1);
+            assert_eq!(
+                settings.try_get(connection_id).unwrap().visibility_range,
+                4234
+            );
+        });
+    }
+
+    #[test]
+    fn test_set_huge_visible_range() {
+        let (world, connection_id) = setup();
+
+        world.run(
+            |mut entities: EntitiesViewMut, mut events: ViewMut<IncomingEvent>| {
+                entities.add_entity(
+                    &mut events,
+                    IncomingEvent(Arc::new(Event::RequestSetVisibleRange {
+                        connection_id,
+                        packet: CSetVisibleRange {
+                            range: std::u32::MAX,
+                        },
+                    })),
+                );
+            },
+        );
+
+        world.run(settings_manager_system);
 
-        assert_eq!(valid_component_count, 1);
+        world.run(|settings: View<Settings>| {
+            assert_eq!(
+                settings.try_get(connection_id).unwrap().visibility_range,
+                MAX_VISIBILITY_RANGE
+            );
+        });
     }
 }
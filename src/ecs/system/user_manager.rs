@@ -1,24 +1,37 @@
 /// Handles the users of an account. Users in TERA terminology are the player characters of an account.
 use std::sync::Arc;
 
-use anyhow::ensure;
+use anyhow::{bail, ensure, Context};
+use async_std::task;
+use chrono::{DateTime, Duration, Utc};
 use lazy_static::lazy_static;
 use regex::Regex;
 use shipyard::*;
-use tracing::{debug, error, info_span};
+use sqlx::{PgConnection, PgPool};
+use tracing::{debug, error, info, info_span};
 
-use crate::ecs::component::{IncomingEvent, OutgoingEvent};
+use crate::config::Configuration;
+use crate::ecs::component::{Connection, IncomingEvent, OutgoingEvent, SelectedUser, Settings};
 use crate::ecs::event::Event;
-use crate::ecs::resource::WorldId;
+use crate::ecs::resource::{HandOffList, LocalWorldHandOff, WorldId};
 use crate::ecs::system::send_event;
-use crate::model::{Class, Customization, Gender, Race, Vec3, Vec3a};
+use crate::ecs::system::user_spawner::START_ZONE;
+use crate::model::entity::User;
+use crate::model::repository::user;
+use crate::model::{Vec3, Vec3a};
 use crate::protocol::packet::*;
 use crate::Result;
 
 pub fn user_manager_system(
     incoming_events: View<IncomingEvent>,
     mut outgoing_events: ViewMut<OutgoingEvent>,
+    connections: View<Connection>,
+    settings: View<Settings>,
+    mut selected_users: ViewMut<SelectedUser>,
     mut entities: EntitiesViewMut,
+    config: UniqueView<Configuration>,
+    pool: UniqueView<PgPool>,
+    mut hand_off_list: UniqueViewMut<HandOffList>,
     world_id: UniqueView<WorldId>,
 ) {
     let span = info_span!("world", world_id = world_id.0);
@@ -27,18 +40,46 @@ pub fn user_manager_system(
     // TODO We need to persist users that are dropped. Create a field in a component to listen to (after X seconds after disconnect?)
     (&incoming_events).iter().for_each(|event| match &*event.0 {
         Event::RequestCanCreateUser { connection_id, .. } => {
-            handle_can_create_user(*connection_id, &mut outgoing_events, &mut entities);
+            if let Err(e) = handle_can_create_user(
+                *connection_id,
+                &connections,
+                &config,
+                &pool,
+                &mut outgoing_events,
+                &mut entities,
+            ) {
+                error!("Rejecting can create user request: {:?}", e);
+                send_event(
+                    assemble_can_create_user_response(*connection_id, false),
+                    &mut outgoing_events,
+                    &mut entities,
+                );
+            }
         }
         Event::RequestGetUserList { connection_id, .. } => {
-            handle_user_list(*connection_id, &mut outgoing_events, &mut entities);
+            if let Err(e) = handle_user_list(
+                *connection_id,
+                &connections,
+                &config,
+                &pool,
+                &mut outgoing_events,
+                &mut entities,
+            ) {
+                error!("Can't handle user list request: {:?}", e);
+            }
         }
         Event::RequestCheckUserName {
             connection_id,
             packet,
         } => {
-            if let Err(e) =
-                handle_check_user_name(&packet, *connection_id, &mut outgoing_events, &mut entities)
-            {
+            if let Err(e) = handle_check_user_name(
+                &packet,
+                *connection_id,
+                &config,
+                &pool,
+                &mut outgoing_events,
+                &mut entities,
+            ) {
                 error!("Rejecting check user name request: {:?}", e);
                 send_event(
                     assemble_check_user_name_response(*connection_id, false),
@@ -47,202 +88,306 @@ pub fn user_manager_system(
                 );
             }
         }
+        Event::RequestCreateUser {
+            connection_id,
+            packet,
+        } => {
+            if let Err(e) = handle_create_user(
+                &packet,
+                *connection_id,
+                &connections,
+                &config,
+                &pool,
+                &mut outgoing_events,
+                &mut entities,
+            ) {
+                error!("Rejecting create user request: {:?}", e);
+                send_event(
+                    assemble_create_user_response(*connection_id, false),
+                    &mut outgoing_events,
+                    &mut entities,
+                );
+            }
+        }
+        Event::RequestDeleteUser {
+            connection_id,
+            packet,
+        } => {
+            if let Err(e) = handle_delete_user(
+                &packet,
+                *connection_id,
+                &connections,
+                &config,
+                &pool,
+                &mut outgoing_events,
+                &mut entities,
+            ) {
+                error!("Rejecting delete user request: {:?}", e);
+                send_event(
+                    assemble_delete_user_response(*connection_id, false),
+                    &mut outgoing_events,
+                    &mut entities,
+                );
+            }
+        }
+        Event::RequestCancelDeleteUser {
+            connection_id,
+            packet,
+        } => {
+            if let Err(e) = handle_cancel_delete_user(
+                &packet,
+                *connection_id,
+                &connections,
+                &pool,
+                &mut outgoing_events,
+                &mut entities,
+            ) {
+                error!("Rejecting cancel delete user request: {:?}", e);
+                send_event(
+                    assemble_cancel_delete_user_response(*connection_id, false),
+                    &mut outgoing_events,
+                    &mut entities,
+                );
+            }
+        }
+        Event::RequestSelectUser {
+            connection_id,
+            packet,
+        } => {
+            if let Err(e) = handle_select_user(
+                &packet,
+                *connection_id,
+                &connections,
+                &settings,
+                &mut selected_users,
+                &pool,
+                &mut hand_off_list,
+                &mut entities,
+            ) {
+                error!("Rejecting select user request: {:?}", e);
+            }
+        }
         _ => { /* Ignore all other events */ }
     });
 }
 
 fn handle_user_list(
     connection_id: EntityId,
+    connections: &View<Connection>,
+    config: &Configuration,
+    pool: &PgPool,
     outgoing_events: &mut ViewMut<OutgoingEvent>,
     entities: &mut EntitiesViewMut,
-) {
+) -> Result<()> {
+    let span = info_span!("connection", connection = ?connection_id);
+    let _enter = span.enter();
+
     debug!("Get user list event incoming");
 
-    // TODO Just a mock. Proper DB handling comes later.
+    let account_id = get_account_id(connection_id, connections)?;
+
+    let users = task::block_on(async {
+        let mut conn = pool
+            .acquire()
+            .await
+            .context("Couldn't acquire connection from pool")?;
+        user::list(&mut conn, account_id).await
+    })
+    .context("Can't query the users of the account")?;
+
+    let now = Utc::now();
+    let characters = users
+        .iter()
+        .enumerate()
+        .map(|(i, user)| assemble_user_list_character(user, i as i32 + 1, now))
+        .collect();
+
     let event = OutgoingEvent(Arc::new(Event::ResponseGetUserList {
         connection_id,
         packet: SGetUserList {
-            characters: vec![SGetUserListCharacter {
-                custom_strings: vec![SGetUserListCharacterCustomString {
-                    string: "Pantsu".to_string(),
-                    id: 254_312,
-                }],
-                name: "Almetica".to_string(),
-                details: vec![
-                    0, 7, 0, 12, 0, 0, 0, 0, 26, 24, 20, 0, 0, 13, 7, 0, 16, 0, 16, 16, 0, 0, 0,
-                    14, 17, 29, 12, 24, 26, 16, 7, 3,
-                ],
-                shape: vec![
-                    1, 19, 16, 19, 19, 16, 19, 19, 19, 16, 16, 16, 16, 15, 15, 15, 16, 19, 10, 0,
-                    22, 23, 9, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-                    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-                ],
-                guild_name: "".to_string(),
-                db_id: 2_000_131,
-                gender: Gender::Female,
-                race: Race::ElinPopori,
-                class: Class::Lancer,
-                level: 65,
-                hp: 121_111,
-                mp: 2000,
-                world_id: 1,
-                guard_id: 2,
-                section_id: 8,
-                last_logout_time: 1_584_074_481,
-                is_deleting: false,
-                delete_time: 86400,
-                delete_remain_sec: -1_585_902_611,
-                weapon: 28369,
-                earring1: 96399,
-                earring2: 96398,
-                body: 96281,
-                hand: 96283,
-                feet: 96285,
-                unk_item7: 0,
-                ring1: 96392,
-                ring2: 96391,
-                underwear: 179_035,
-                head: 50056,
-                face: 5,
-                appearance: Customization {
-                    data: vec![0, 0, 0, 0, 0, 0, 0, 0],
-                },
-                is_second_character: false,
-                admin_level: 0,
-                is_banned: false,
-                ban_end_time: 0,
-                ban_remain_sec: -1_585_989_011,
-                rename_needed: 0,
-                weapon_model: 0,
-                unk_model2: 0,
-                unk_model3: 0,
-                body_model: 0,
-                hand_model: 0,
-                feet_model: 0,
-                unk_model7: 0,
-                unk_model8: 0,
-                unk_model9: 0,
-                unk_model10: 0,
-                unk_dye1: 0,
-                unk_dye2: 0,
-                weapon_dye: 0,
-                body_dye: 0,
-                hand_dye: 0,
-                feet_dye: 0,
-                unk_dye7: 0,
-                unk_dye8: 0,
-                unk_dye9: 0,
-                underwear_dye: 0,
-                style_back_dye: 0,
-                style_head_dye: 0,
-                style_face_dye: 0,
-                style_head: 177_018,
-                style_face: 0,
-                style_back: 0,
-                style_weapon: 170_029,
-                style_body: 177_761,
-                style_footprint: 0,
-                style_body_dye: 421_075_260,
-                weapon_enchant: 15,
-                rest_bonus_xp: 292_832_832,
-                max_rest_bonus_xp: 292_832_844,
-                show_face: true,
-                style_head_scale: 1.0,
-                style_head_rotation: Vec3a { x: 0, y: 0, z: 0 },
-                style_head_translation: Vec3 {
-                    x: 0.0,
-                    y: 0.0,
-                    z: 0.0,
-                },
-                style_head_translation_debug: Vec3 {
-                    x: 0.0,
-                    y: 0.0,
-                    z: 0.0,
-                },
-                style_faces_scale: 1.0,
-                style_face_rotation: Vec3a { x: 0, y: 0, z: 0 },
-                style_face_translation: Vec3 {
-                    x: 0.0,
-                    y: 0.0,
-                    z: 0.0,
-                },
-                style_face_translation_debug: Vec3 {
-                    x: 0.0,
-                    y: 0.0,
-                    z: 0.0,
-                },
-                style_back_scale: 1.0,
-                style_back_rotation: Vec3a { x: 0, y: 0, z: 0 },
-                style_back_translation: Vec3 {
-                    x: 0.0,
-                    y: 0.0,
-                    z: 0.0,
-                },
-                style_back_translation_debug: Vec3 {
-                    x: 0.0,
-                    y: 0.0,
-                    z: 0.0,
-                },
-                used_style_head_transform: false,
-                is_new_character: false,
-                tutorial_state: 0,
-                show_style: true,
-                appearance2: 100,
-                achievement_points: 13565,
-                laurel: 0,
-                position: 1,
-                guild_logo_id: 4521,
-                awakening_level: 0,
-                has_broker_sales: false,
-            }],
+            characters,
             veteran: false,
             bonus_buf_sec: 0,
-            max_characters: 12,
+            max_characters: config.game.max_users_per_account,
             first: true,
             more: false,
             left_del_time_account_over: 0,
             deletion_section_classify_level: 40,
-            delete_character_expire_hour1: 0,
-            delete_character_expire_hour2: 24,
+            delete_character_expire_hour1: config.game.user_deletion_grace_hours,
+            delete_character_expire_hour2: config.game.user_deletion_grace_hours,
         },
     }));
 
     send_event(event, outgoing_events, entities);
+
+    Ok(())
 }
 
 fn handle_can_create_user(
     connection_id: EntityId,
+    connections: &View<Connection>,
+    config: &Configuration,
+    pool: &PgPool,
     outgoing_events: &mut ViewMut<OutgoingEvent>,
     entities: &mut EntitiesViewMut,
-) {
+) -> Result<()> {
+    let span = info_span!("connection", connection = ?connection_id);
+    let _enter = span.enter();
+
     debug!("Can create user event incoming");
 
-    // TODO check the database for current count of users once user table is implemented (hardwired max of 20).
+    let account_id = get_account_id(connection_id, connections)?;
+
+    task::block_on(async {
+        let mut conn = pool
+            .acquire()
+            .await
+            .context("Couldn't acquire connection from pool")?;
+        ensure_user_limit_not_reached(&mut conn, account_id, config).await
+    })?;
 
     send_event(
         assemble_can_create_user_response(connection_id, true),
         outgoing_events,
         entities,
     );
+
+    Ok(())
 }
 
 fn handle_check_user_name(
     packet: &CCheckUserName,
     connection_id: EntityId,
+    config: &Configuration,
+    pool: &PgPool,
     outgoing_events: &mut ViewMut<OutgoingEvent>,
     entities: &mut EntitiesViewMut,
 ) -> Result<()> {
+    let span = info_span!("connection", connection = ?connection_id);
+    let _enter = span.enter();
+
     debug!("Check user name event incoming");
 
-    ensure!(
-        is_valid_user_name(&packet.name),
-        "Invalid username provided"
+    task::block_on(async {
+        let mut conn = pool
+            .acquire()
+            .await
+            .context("Couldn't acquire connection from pool")?;
+        ensure_user_name_available(&mut conn, &packet.name, config).await
+    })?;
+
+    send_event(
+        assemble_check_user_name_response(connection_id, true),
+        outgoing_events,
+        entities,
     );
 
-    // TODO check if the username is already present in the database
+    Ok(())
+}
+
+fn handle_create_user(
+    packet: &CCreateUser,
+    connection_id: EntityId,
+    connections: &View<Connection>,
+    config: &Configuration,
+    pool: &PgPool,
+    outgoing_events: &mut ViewMut<OutgoingEvent>,
+    entities: &mut EntitiesViewMut,
+) -> Result<()> {
+    let span = info_span!("connection", connection = ?connection_id);
+    let _enter = span.enter();
+
+    debug!("Create user event incoming");
+
+    let account_id = get_account_id(connection_id, connections)?;
+
+    // The case insensitive unique index of the user name protects us from concurrent creations
+    // with the same name.
+    let user = task::block_on(async {
+        let mut conn = pool
+            .acquire()
+            .await
+            .context("Couldn't acquire connection from pool")?;
+        ensure_user_limit_not_reached(&mut conn, account_id, config).await?;
+        ensure_user_name_available(&mut conn, &packet.name, config).await?;
+        user::create(
+            &mut conn,
+            &User {
+                id: -1,
+                account_id,
+                name: packet.name.clone(),
+                gender: packet.gender,
+                race: packet.race,
+                class: packet.class,
+                shape: packet.shape.clone(),
+                details: packet.details.clone(),
+                appearance: packet.appearance.clone(),
+                appearance2: packet.appearance2 as i32,
+                playtime: 0,
+                deletion_time: None,
+                created_at: Utc::now(),
+            },
+        )
+        .await
+    })
+    .context("Can't create user")?;
+
+    info!("Account {} created user {}", account_id, user.name);
 
     send_event(
-        assemble_check_user_name_response(connection_id, true),
+        assemble_create_user_response(connection_id, true),
+        outgoing_events,
+        entities,
+    );
+
+    Ok(())
+}
+
+fn handle_delete_user(
+    packet: &CDeleteUser,
+    connection_id: EntityId,
+    connections: &View<Connection>,
+    config: &Configuration,
+    pool: &PgPool,
+    outgoing_events: &mut ViewMut<OutgoingEvent>,
+    entities: &mut EntitiesViewMut,
+) -> Result<()> {
+    let span = info_span!("connection", connection = ?connection_id);
+    let _enter = span.enter();
+
+    debug!("Delete user event incoming");
+
+    let account_id = get_account_id(connection_id, connections)?;
+
+    task::block_on(async {
+        let mut conn = pool
+            .acquire()
+            .await
+            .context("Couldn't acquire connection from pool")?;
+        let user = get_account_user(&mut conn, packet.db_id, account_id).await?;
+        ensure!(
+            user.deletion_time.is_none(),
+            "User is already marked for deletion"
+        );
+
+        if config.game.user_deletion_grace_hours > 0 {
+            let deletion_time =
+                Utc::now() + Duration::hours(i64::from(config.game.user_deletion_grace_hours));
+            user::mark_for_deletion(&mut conn, user.id, deletion_time)
+                .await
+                .context("Can't mark user for deletion")?;
+            info!("User {} will be deleted at {}", user.name, deletion_time);
+        } else {
+            user::delete_by_id(&mut conn, user.id)
+                .await
+                .context("Can't delete user")?;
+            info!("User {} was deleted", user.name);
+        }
+        Ok(())
+    })?;
+
+    send_event(
+        assemble_delete_user_response(connection_id, true),
         outgoing_events,
         entities,
     );
@@ -250,6 +395,168 @@ fn handle_check_user_name(
     Ok(())
 }
 
+fn handle_cancel_delete_user(
+    packet: &CCancelDeleteUser,
+    connection_id: EntityId,
+    connections: &View<Connection>,
+    pool: &PgPool,
+    outgoing_events: &mut ViewMut<OutgoingEvent>,
+    entities: &mut EntitiesViewMut,
+) -> Result<()> {
+    let span = info_span!("connection", connection = ?connection_id);
+    let _enter = span.enter();
+
+    debug!("Cancel delete user event incoming");
+
+    let account_id = get_account_id(connection_id, connections)?;
+
+    task::block_on(async {
+        let mut conn = pool
+            .acquire()
+            .await
+            .context("Couldn't acquire connection from pool")?;
+        let user = get_account_user(&mut conn, packet.db_id, account_id).await?;
+        match user.deletion_time {
+            Some(deletion_time) => ensure!(
+                deletion_time > Utc::now(),
+                "Grace period of the user deletion has already expired"
+            ),
+            None => bail!("User is not marked for deletion"),
+        }
+
+        user::cancel_deletion(&mut conn, user.id)
+            .await
+            .context("Can't cancel the user deletion")?;
+        info!("Deletion of user {} was canceled", user.name);
+        Ok(())
+    })?;
+
+    send_event(
+        assemble_cancel_delete_user_response(connection_id, true),
+        outgoing_events,
+        entities,
+    );
+
+    Ok(())
+}
+
+/// Selects a user of the account and hands the connection off to the local world of the user.
+fn handle_select_user(
+    packet: &CSelectUser,
+    connection_id: EntityId,
+    connections: &View<Connection>,
+    settings: &View<Settings>,
+    selected_users: &mut ViewMut<SelectedUser>,
+    pool: &PgPool,
+    hand_off_list: &mut UniqueViewMut<HandOffList>,
+    entities: &mut EntitiesViewMut,
+) -> Result<()> {
+    let span = info_span!("connection", connection = ?connection_id);
+    let _enter = span.enter();
+
+    debug!("Select user event incoming");
+
+    let account_id = get_account_id(connection_id, connections)?;
+
+    let user = task::block_on(async {
+        let mut conn = pool
+            .acquire()
+            .await
+            .context("Couldn't acquire connection from pool")?;
+        get_account_user(&mut conn, packet.db_id, account_id).await
+    })?;
+    ensure!(
+        user.deletion_time.is_none(),
+        "Can't select a user that is marked for deletion"
+    );
+
+    info!("User {} was selected", user.name);
+
+    // The local world needs to know the settings of the user.
+    let visibility_range = match settings.try_get(connection_id) {
+        Ok(settings) => settings.visibility_range,
+        Err(_) => Settings::default().visibility_range,
+    };
+
+    // The local world spawns the user once the connection registered itself.
+    hand_off_list.0.push(LocalWorldHandOff {
+        connection_id,
+        user_id: user.id,
+        visibility_range,
+        world_name: format!("zone {}", START_ZONE),
+    });
+
+    // The global world needs to know the user of a connection to deliver whispers.
+    entities.add_component(
+        selected_users,
+        SelectedUser {
+            user_id: user.id,
+            name: user.name,
+        },
+        connection_id,
+    );
+
+    Ok(())
+}
+
+/// Returns the user with the given id. Fails if the user doesn't belong to the given account.
+async fn get_account_user(conn: &mut PgConnection, id: i32, account_id: i64) -> Result<User> {
+    let user = user::get_by_id(conn, id)
+        .await
+        .context(format!("Can't find user {}", id))?;
+    ensure!(
+        user.account_id == account_id,
+        format!("User {} doesn't belong to account {}", id, account_id)
+    );
+    Ok(user)
+}
+
+/// Returns the account id of the given connection. Fails if the connection is not authenticated.
+fn get_account_id(connection_id: EntityId, connections: &View<Connection>) -> Result<i64> {
+    let connection = connections
+        .try_get(connection_id)
+        .context("Could not find connection component for entity")?;
+    connection
+        .account_id
+        .context("Connection is not bound to an account")
+}
+
+/// Fails if the account already has the maximal allowed number of users.
+async fn ensure_user_limit_not_reached(
+    conn: &mut PgConnection,
+    account_id: i64,
+    config: &Configuration,
+) -> Result<()> {
+    let count = user::get_user_count(conn, account_id)
+        .await
+        .context("Can't query the user count of the account")?;
+    ensure!(
+        count < i64::from(config.game.max_users_per_account),
+        format!("Account already has {} users", count)
+    );
+    Ok(())
+}
+
+/// Fails if the user name is invalid, blacklisted or already taken.
+async fn ensure_user_name_available(
+    conn: &mut PgConnection,
+    name: &str,
+    config: &Configuration,
+) -> Result<()> {
+    ensure!(is_valid_user_name(name), "Invalid username provided");
+    ensure!(
+        !is_blacklisted_user_name(name, &config.game.user_name_blacklist),
+        "Blacklisted username provided"
+    );
+    ensure!(
+        !user::is_user_name_taken(conn, name)
+            .await
+            .context("Can't query if the username is taken")?,
+        "Username is already taken"
+    );
+    Ok(())
+}
+
 /// Only alphanumeric characters are currently allowed. The client in rather limited with it's font.
 fn is_valid_user_name(text: &str) -> bool {
     lazy_static! {
@@ -258,6 +565,14 @@ fn is_valid_user_name(text: &str) -> bool {
     RE.is_match(text)
 }
 
+/// A user name is blacklisted if it contains any word of the blacklist (case insensitive).
+fn is_blacklisted_user_name(text: &str, blacklist: &[String]) -> bool {
+    let text = text.to_lowercase();
+    blacklist
+        .iter()
+        .any(|word| text.contains(&word.to_lowercase()))
+}
+
 fn assemble_can_create_user_response(connection_id: EntityId, ok: bool) -> OutgoingEvent {
     OutgoingEvent(Arc::new(Event::ResponseCanCreateUser {
         connection_id,
@@ -265,6 +580,20 @@ fn assemble_can_create_user_response(connection_id: EntityId, ok: bool) -> Outgo
     }))
 }
 
+fn assemble_delete_user_response(connection_id: EntityId, ok: bool) -> OutgoingEvent {
+    OutgoingEvent(Arc::new(Event::ResponseDeleteUser {
+        connection_id,
+        packet: SDeleteUser { ok },
+    }))
+}
+
+fn assemble_cancel_delete_user_response(connection_id: EntityId, ok: bool) -> OutgoingEvent {
+    OutgoingEvent(Arc::new(Event::ResponseCancelDeleteUser {
+        connection_id,
+        packet: SCancelDeleteUser { ok },
+    }))
+}
+
 fn assemble_check_user_name_response(connection_id: EntityId, ok: bool) -> OutgoingEvent {
     OutgoingEvent(Arc::new(Event::ResponseCheckUserName {
         connection_id,
@@ -272,17 +601,168 @@ fn assemble_check_user_name_response(connection_id: EntityId, ok: bool) -> Outgo
     }))
 }
 
+fn assemble_create_user_response(connection_id: EntityId, ok: bool) -> OutgoingEvent {
+    OutgoingEvent(Arc::new(Event::ResponseCreateUser {
+        connection_id,
+        packet: SCreateUser { ok },
+    }))
+}
+
+fn assemble_user_list_character(
+    user: &User,
+    position: i32,
+    now: DateTime<Utc>,
+) -> SGetUserListCharacter {
+    let (is_deleting, delete_time, delete_remain_sec) = match user.deletion_time {
+        Some(deletion_time) => (
+            true,
+            deletion_time.timestamp(),
+            (deletion_time - now).num_seconds().max(0) as i32,
+        ),
+        None => (false, 0, 0),
+    };
+
+    // TODO Items, location, level and guild are static until their systems are implemented.
+    SGetUserListCharacter {
+        custom_strings: vec![],
+        name: user.name.clone(),
+        details: user.details.clone(),
+        shape: user.shape.clone(),
+        guild_name: "".to_string(),
+        db_id: user.id,
+        gender: user.gender,
+        race: user.race,
+        class: user.class,
+        level: 1,
+        hp: 0,
+        mp: 0,
+        world_id: 1,
+        guard_id: 2,
+        section_id: 8,
+        last_logout_time: 0,
+        is_deleting,
+        delete_time,
+        delete_remain_sec,
+        weapon: 0,
+        earring1: 0,
+        earring2: 0,
+        body: 0,
+        hand: 0,
+        feet: 0,
+        unk_item7: 0,
+        ring1: 0,
+        ring2: 0,
+        underwear: 0,
+        head: 0,
+        face: 0,
+        appearance: user.appearance.clone(),
+        is_second_character: false,
+        admin_level: 0,
+        is_banned: false,
+        ban_end_time: 0,
+        ban_remain_sec: 0,
+        rename_needed: 0,
+        weapon_model: 0,
+        unk_model2: 0,
+        unk_model3: 0,
+        body_model: 0,
+        hand_model: 0,
+        feet_model: 0,
+        unk_model7: 0,
+        unk_model8: 0,
+        unk_model9: 0,
+        unk_model10: 0,
+        unk_dye1: 0,
+        unk_dye2: 0,
+        weapon_dye: 0,
+        body_dye: 0,
+        hand_dye: 0,
+        feet_dye: 0,
+        unk_dye7: 0,
+        unk_dye8: 0,
+        unk_dye9: 0,
+        underwear_dye: 0,
+        style_back_dye: 0,
+        style_head_dye: 0,
+        style_face_dye: 0,
+        style_head: 0,
+        style_face: 0,
+        style_back: 0,
+        style_weapon: 0,
+        style_body: 0,
+        style_footprint: 0,
+        style_body_dye: 0,
+        weapon_enchant: 0,
+        rest_bonus_xp: 0,
+        max_rest_bonus_xp: 0,
+        show_face: true,
+        style_head_scale: 1.0,
+        style_head_rotation: Vec3a { x: 0, y: 0, z: 0 },
+        style_head_translation: Vec3 {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        },
+        style_head_translation_debug: Vec3 {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        },
+        style_faces_scale: 1.0,
+        style_face_rotation: Vec3a { x: 0, y: 0, z: 0 },
+        style_face_translation: Vec3 {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        },
+        style_face_translation_debug: Vec3 {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        },
+        style_back_scale: 1.0,
+        style_back_rotation: Vec3a { x: 0, y: 0, z: 0 },
+        style_back_translation: Vec3 {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        },
+        style_back_translation_debug: Vec3 {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        },
+        used_style_head_transform: false,
+        is_new_character: user.playtime == 0,
+        tutorial_state: 0,
+        show_style: true,
+        appearance2: user.appearance2,
+        achievement_points: 0,
+        laurel: 0,
+        position,
+        guild_logo_id: 0,
+        awakening_level: 0,
+        has_broker_sales: false,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
     use std::time::Instant;
 
+    use chrono::{TimeZone, Utc};
     use shipyard::*;
-    use sqlx::PgPool;
+    use sqlx::{PgConnection, PgPool};
 
+    use crate::config::tests::get_configuration;
     use crate::ecs::component::{Connection, IncomingEvent, OutgoingEvent};
     use crate::ecs::event::Event;
+    use crate::ecs::system::settings_manager_system;
+    use crate::model::entity::Account;
+    use crate::model::repository::account;
     use crate::model::tests::db_test;
+    use crate::model::{Class, Customization, Gender, PasswordHashAlgorithm, Race};
     use crate::Result;
 
     use super::*;
@@ -290,13 +770,16 @@ mod tests {
     fn setup_with_connection(pool: PgPool) -> (World, EntityId) {
         let world = World::new();
         world.add_unique(WorldId(0));
+        world.add_unique(get_configuration());
         world.add_unique(pool);
+        world.add_unique(HandOffList(vec![]));
 
         let connection_id = world.run(
             |mut entities: EntitiesViewMut, mut connections: ViewMut<Connection>| {
                 entities.add_entity(
                     &mut connections,
                     Connection {
+                        account_id: None,
                         verified: false,
                         version_checked: false,
                         region: None,
@@ -310,10 +793,73 @@ mod tests {
         (world, connection_id)
     }
 
+    async fn setup_with_account(pool: PgPool) -> Result<(World, EntityId, i64)> {
+        let mut conn = pool.acquire().await?;
+        let acc = create_account(&mut conn).await?;
+
+        let (world, connection_id) = setup_with_connection(pool);
+        world.run(|mut connections: ViewMut<Connection>| {
+            connections[connection_id].account_id = Some(acc.id);
+        });
+
+        Ok((world, connection_id, acc.id))
+    }
+
+    async fn create_account(conn: &mut PgConnection) -> Result<Account> {
+        Ok(account::create(
+            conn,
+            &Account {
+                id: -1,
+                name: "testaccount".to_string(),
+                password: "not-a-real-password-hash".to_string(),
+                algorithm: PasswordHashAlgorithm::Argon2,
+                created_at: Utc.ymd(1995, 7, 8).and_hms(9, 10, 11),
+                updated_at: Utc.ymd(1995, 7, 8).and_hms(9, 10, 11),
+            },
+        )
+        .await?)
+    }
+
+    fn get_create_user_packet(name: &str) -> CCreateUser {
+        CCreateUser {
+            name: name.to_string(),
+            details: vec![1u8, 2u8, 3u8],
+            shape: vec![4u8, 5u8, 6u8],
+            gender: Gender::Male,
+            race: Race::Aman,
+            class: Class::Sorcerer,
+            appearance: Customization {
+                data: vec![1u8, 2u8, 3u8, 4u8, 5u8, 6u8, 7u8, 8u8],
+            },
+            is_second_character: false,
+            appearance2: 100,
+        }
+    }
+
+    fn get_user(account_id: i64, name: &str) -> User {
+        User {
+            id: -1,
+            account_id,
+            name: name.to_string(),
+            gender: Gender::Female,
+            race: Race::HighElf,
+            class: Class::Priest,
+            shape: vec![],
+            details: vec![],
+            appearance: Customization {
+                data: vec![0u8, 0u8, 0u8, 0u8, 0u8, 0u8, 0u8, 0u8],
+            },
+            appearance2: 100,
+            playtime: 0,
+            deletion_time: None,
+            created_at: Utc.ymd(1995, 7, 8).and_hms(9, 10, 11),
+        }
+    }
+
     #[test]
     fn test_can_create_user_true() -> Result<()> {
         async fn test(pool: PgPool) -> Result<()> {
-            let (world, connection_id) = setup_with_connection(pool);
+            let (world, connection_id, _) = setup_with_account(pool).await?;
 
             world.run(
                 |mut entities: EntitiesViewMut, mut events: ViewMut<IncomingEvent>| {
@@ -347,6 +893,59 @@ mod tests {
         db_test(test)
     }
 
+    #[test]
+    fn test_can_create_user_false() -> Result<()> {
+        async fn test(pool: PgPool) -> Result<()> {
+            let mut conn = pool.acquire().await?;
+            let (world, connection_id, account_id) = setup_with_account(pool).await?;
+
+            for i in 0..get_configuration().game.max_users_per_account {
+                user::create(&mut conn, &get_user(account_id, &format!("User{}", i))).await?;
+            }
+
+            world.run(
+                |mut entities: EntitiesViewMut, mut events: ViewMut<IncomingEvent>| {
+                    entities.add_entity(
+                        &mut events,
+                        IncomingEvent(Arc::new(Event::RequestCanCreateUser {
+                            connection_id,
+                            packet: CCanCreateUser {},
+                        })),
+                    );
+                },
+            );
+
+            world.run(user_manager_system);
+
+            world.run(|events: ViewMut<OutgoingEvent>| {
+                let count = (&events)
+                    .iter()
+                    .filter(|event| match &*event.0 {
+                        Event::ResponseCanCreateUser { packet, .. } => !packet.ok,
+                        _ => false,
+                    })
+                    .count();
+                assert_eq!(count, 1);
+            });
+
+            Ok(())
+        }
+        db_test(test)
+    }
+
+    #[test]
+    fn test_is_blacklisted_user_name() {
+        let blacklist = vec!["admin".to_string(), "GM".to_string()];
+
+        assert!(is_blacklisted_user_name("Admin", &blacklist));
+        assert!(is_blacklisted_user_name("TheAdmin123", &blacklist));
+        assert!(is_blacklisted_user_name("gmHelper", &blacklist));
+
+        assert!(!is_blacklisted_user_name("Simple", &blacklist));
+        assert!(!is_blacklisted_user_name("Adm1n", &blacklist));
+        assert!(!is_blacklisted_user_name("Simple", &[]));
+    }
+
     #[test]
     fn test_is_valid_user_name() {
         // Valid user names
@@ -448,7 +1047,469 @@ mod tests {
         db_test(test)
     }
 
-    // TODO write test can_create_user_false() once user table is finished
-    // TODO write test check_user_name_double_username once user table is finished
-    // TODO write handle_user_list
+    #[test]
+    fn test_check_user_name_taken() -> Result<()> {
+        async fn test(pool: PgPool) -> Result<()> {
+            let mut conn = pool.acquire().await?;
+            let (world, connection_id, account_id) = setup_with_account(pool).await?;
+
+            user::create(&mut conn, &get_user(account_id, "TakenName")).await?;
+
+            world.run(
+                |mut entities: EntitiesViewMut, mut events: ViewMut<IncomingEvent>| {
+                    for name in &["TakenName", "takenname", "TAKENNAME"] {
+                        entities.add_entity(
+                            &mut events,
+                            IncomingEvent(Arc::new(Event::RequestCheckUserName {
+                                connection_id,
+                                packet: CCheckUserName {
+                                    name: name.to_string(),
+                                },
+                            })),
+                        );
+                    }
+                },
+            );
+
+            world.run(user_manager_system);
+
+            world.run(|events: ViewMut<OutgoingEvent>| {
+                let count = (&events)
+                    .iter()
+                    .filter(|event| match &*event.0 {
+                        Event::ResponseCheckUserName { packet, .. } => !packet.ok,
+                        _ => false,
+                    })
+                    .count();
+                assert_eq!(count, 3);
+            });
+
+            Ok(())
+        }
+        db_test(test)
+    }
+
+    #[test]
+    fn test_check_user_name_blacklisted() -> Result<()> {
+        async fn test(pool: PgPool) -> Result<()> {
+            let (world, connection_id) = setup_with_connection(pool);
+
+            world.run(
+                |mut entities: EntitiesViewMut, mut events: ViewMut<IncomingEvent>| {
+                    entities.add_entity(
+                        &mut events,
+                        IncomingEvent(Arc::new(Event::RequestCheckUserName {
+                            connection_id,
+                            packet: CCheckUserName {
+                                name: "AlmeticaFan".to_string(),
+                            },
+                        })),
+                    );
+                },
+            );
+
+            world.run(user_manager_system);
+
+            world.run(|events: ViewMut<OutgoingEvent>| {
+                let count = (&events)
+                    .iter()
+                    .filter(|event| match &*event.0 {
+                        Event::ResponseCheckUserName { packet, .. } => !packet.ok,
+                        _ => false,
+                    })
+                    .count();
+                assert_eq!(count, 1);
+            });
+
+            Ok(())
+        }
+        db_test(test)
+    }
+
+    #[test]
+    fn test_create_user() -> Result<()> {
+        async fn test(pool: PgPool) -> Result<()> {
+            let mut conn = pool.acquire().await?;
+            let (world, connection_id, account_id) = setup_with_account(pool).await?;
+
+            world.run(
+                |mut entities: EntitiesViewMut, mut events: ViewMut<IncomingEvent>| {
+                    entities.add_entity(
+                        &mut events,
+                        IncomingEvent(Arc::new(Event::RequestCreateUser {
+                            connection_id,
+                            packet: get_create_user_packet("Asuna"),
+                        })),
+                    );
+                },
+            );
+
+            world.run(user_manager_system);
+
+            world.run(|events: ViewMut<OutgoingEvent>| {
+                let count = (&events)
+                    .iter()
+                    .filter(|event| match &*event.0 {
+                        Event::ResponseCreateUser { packet, .. } => packet.ok,
+                        _ => false,
+                    })
+                    .count();
+                assert_eq!(count, 1);
+            });
+
+            let users = user::list(&mut conn, account_id).await?;
+            assert_eq!(users.len(), 1);
+            assert_eq!(users[0].name, "Asuna");
+            assert_eq!(users[0].race, Race::Aman);
+            assert_eq!(users[0].class, Class::Sorcerer);
+
+            Ok(())
+        }
+        db_test(test)
+    }
+
+    #[test]
+    fn test_create_user_without_account() -> Result<()> {
+        async fn test(pool: PgPool) -> Result<()> {
+            let (world, connection_id) = setup_with_connection(pool);
+
+            world.run(
+                |mut entities: EntitiesViewMut, mut events: ViewMut<IncomingEvent>| {
+                    entities.add_entity(
+                        &mut events,
+                        IncomingEvent(Arc::new(Event::RequestCreateUser {
+                            connection_id,
+                            packet: get_create_user_packet("Asuna"),
+                        })),
+                    );
+                },
+            );
+
+            world.run(user_manager_system);
+
+            world.run(|events: ViewMut<OutgoingEvent>| {
+                let count = (&events)
+                    .iter()
+                    .filter(|event| match &*event.0 {
+                        Event::ResponseCreateUser { packet, .. } => !packet.ok,
+                        _ => false,
+                    })
+                    .count();
+                assert_eq!(count, 1);
+            });
+
+            Ok(())
+        }
+        db_test(test)
+    }
+
+    #[test]
+    fn test_user_list() -> Result<()> {
+        async fn test(pool: PgPool) -> Result<()> {
+            let mut conn = pool.acquire().await?;
+            let (world, connection_id, account_id) = setup_with_account(pool).await?;
+
+            user::create(&mut conn, &get_user(account_id, "Kirito")).await?;
+            user::create(&mut conn, &get_user(account_id, "Asuna")).await?;
+
+            world.run(
+                |mut entities: EntitiesViewMut, mut events: ViewMut<IncomingEvent>| {
+                    entities.add_entity(
+                        &mut events,
+                        IncomingEvent(Arc::new(Event::RequestGetUserList {
+                            connection_id,
+                            packet: CGetUserList {},
+                        })),
+                    );
+                },
+            );
+
+            world.run(user_manager_system);
+
+            world.run(|events: ViewMut<OutgoingEvent>| {
+                let list: Vec<&OutgoingEvent> = (&events).iter().collect();
+                assert_eq!(list.len(), 1);
+
+                if let Event::ResponseGetUserList { packet, .. } = &*list[0].0 {
+                    assert_eq!(packet.characters.len(), 2);
+                    assert_eq!(packet.characters[0].name, "Kirito");
+                    assert_eq!(packet.characters[0].position, 1);
+                    assert_eq!(packet.characters[1].name, "Asuna");
+                    assert_eq!(packet.characters[1].position, 2);
+                    assert_eq!(packet.characters[1].class, Class::Priest);
+                } else {
+                    panic!("Couldn't find user list response");
+                }
+            });
+
+            Ok(())
+        }
+        db_test(test)
+    }
+
+    #[test]
+    fn test_delete_user() -> Result<()> {
+        async fn test(pool: PgPool) -> Result<()> {
+            let mut conn = pool.acquire().await?;
+            let (world, connection_id, account_id) = setup_with_account(pool).await?;
+
+            let db_user = user::create(&mut conn, &get_user(account_id, "Asuna")).await?;
+
+            world.run(
+                |mut entities: EntitiesViewMut, mut events: ViewMut<IncomingEvent>| {
+                    entities.add_entity(
+                        &mut events,
+                        IncomingEvent(Arc::new(Event::RequestDeleteUser {
+                            connection_id,
+                            packet: CDeleteUser { db_id: db_user.id },
+                        })),
+                    );
+                    entities.add_entity(
+                        &mut events,
+                        IncomingEvent(Arc::new(Event::RequestGetUserList {
+                            connection_id,
+                            packet: CGetUserList {},
+                        })),
+                    );
+                },
+            );
+
+            world.run(user_manager_system);
+
+            world.run(|events: ViewMut<OutgoingEvent>| {
+                let list: Vec<&OutgoingEvent> = (&events).iter().collect();
+                assert_eq!(list.len(), 2);
+
+                if let Event::ResponseDeleteUser { packet, .. } = &*list[0].0 {
+                    assert!(packet.ok);
+                } else {
+                    panic!("Couldn't find delete user response");
+                }
+
+                if let Event::ResponseGetUserList { packet, .. } = &*list[1].0 {
+                    assert_eq!(packet.characters.len(), 1);
+                    assert!(packet.characters[0].is_deleting);
+                    assert!(packet.characters[0].delete_remain_sec > 0);
+                    assert!(packet.characters[0].delete_remain_sec <= 24 * 60 * 60);
+                } else {
+                    panic!("Couldn't find user list response");
+                }
+            });
+
+            let marked_user = user::get_by_id(&mut conn, db_user.id).await?;
+            assert!(marked_user.deletion_time.is_some());
+
+            Ok(())
+        }
+        db_test(test)
+    }
+
+    #[test]
+    fn test_delete_user_of_other_account() -> Result<()> {
+        async fn test(pool: PgPool) -> Result<()> {
+            let mut conn = pool.acquire().await?;
+            let (world, connection_id, account_id) = setup_with_account(pool).await?;
+
+            let db_user = user::create(&mut conn, &get_user(account_id, "Asuna")).await?;
+            world.run(|mut connections: ViewMut<Connection>| {
+                connections[connection_id].account_id = Some(account_id + 1);
+            });
+
+            world.run(
+                |mut entities: EntitiesViewMut, mut events: ViewMut<IncomingEvent>| {
+                    entities.add_entity(
+                        &mut events,
+                        IncomingEvent(Arc::new(Event::RequestDeleteUser {
+                            connection_id,
+                            packet: CDeleteUser { db_id: db_user.id },
+                        })),
+                    );
+                },
+            );
+
+            world.run(user_manager_system);
+
+            world.run(|events: ViewMut<OutgoingEvent>| {
+                let count = (&events)
+                    .iter()
+                    .filter(|event| match &*event.0 {
+                        Event::ResponseDeleteUser { packet, .. } => !packet.ok,
+                        _ => false,
+                    })
+                    .count();
+                assert_eq!(count, 1);
+            });
+
+            let db_user = user::get_by_id(&mut conn, db_user.id).await?;
+            assert!(db_user.deletion_time.is_none());
+
+            Ok(())
+        }
+        db_test(test)
+    }
+
+    #[test]
+    fn test_cancel_delete_user() -> Result<()> {
+        async fn test(pool: PgPool) -> Result<()> {
+            let mut conn = pool.acquire().await?;
+            let (world, connection_id, account_id) = setup_with_account(pool).await?;
+
+            let db_user = user::create(&mut conn, &get_user(account_id, "Asuna")).await?;
+            user::mark_for_deletion(&mut conn, db_user.id, Utc::now() + Duration::hours(1)).await?;
+
+            world.run(
+                |mut entities: EntitiesViewMut, mut events: ViewMut<IncomingEvent>| {
+                    entities.add_entity(
+                        &mut events,
+                        IncomingEvent(Arc::new(Event::RequestCancelDeleteUser {
+                            connection_id,
+                            packet: CCancelDeleteUser { db_id: db_user.id },
+                        })),
+                    );
+                },
+            );
+
+            world.run(user_manager_system);
+
+            world.run(|events: ViewMut<OutgoingEvent>| {
+                let count = (&events)
+                    .iter()
+                    .filter(|event| match &*event.0 {
+                        Event::ResponseCancelDeleteUser { packet, .. } => packet.ok,
+                        _ => false,
+                    })
+                    .count();
+                assert_eq!(count, 1);
+            });
+
+            let db_user = user::get_by_id(&mut conn, db_user.id).await?;
+            assert!(db_user.deletion_time.is_none());
+
+            Ok(())
+        }
+        db_test(test)
+    }
+
+    #[test]
+    fn test_select_user() -> Result<()> {
+        async fn test(pool: PgPool) -> Result<()> {
+            let mut conn = pool.acquire().await?;
+            let (world, connection_id, account_id) = setup_with_account(pool).await?;
+
+            let db_user = user::create(&mut conn, &get_user(account_id, "Asuna")).await?;
+
+            world.run(
+                |mut entities: EntitiesViewMut, mut events: ViewMut<IncomingEvent>| {
+                    entities.add_entity(
+                        &mut events,
+                        IncomingEvent(Arc::new(Event::RequestSelectUser {
+                            connection_id,
+                            packet: CSelectUser {
+                                db_id: db_user.id,
+                                unk: 0,
+                            },
+                        })),
+                    );
+                },
+            );
+
+            world.run(user_manager_system);
+
+            world.run(|hand_off_list: UniqueView<HandOffList>| {
+                assert_eq!(hand_off_list.0.len(), 1);
+                assert_eq!(hand_off_list.0[0].connection_id, connection_id);
+                assert_eq!(hand_off_list.0[0].user_id, db_user.id);
+            });
+
+            world.run(|selected_users: View<SelectedUser>| {
+                let selected_user = selected_users.try_get(connection_id).unwrap();
+                assert_eq!(selected_user.user_id, db_user.id);
+                assert_eq!(selected_user.name, "Asuna");
+            });
+
+            Ok(())
+        }
+        db_test(test)
+    }
+
+    #[test]
+    fn test_select_user_with_visible_range() -> Result<()> {
+        async fn test(pool: PgPool) -> Result<()> {
+            let mut conn = pool.acquire().await?;
+            let (world, connection_id, account_id) = setup_with_account(pool).await?;
+
+            let db_user = user::create(&mut conn, &get_user(account_id, "Asuna")).await?;
+
+            world.run(
+                |mut entities: EntitiesViewMut, mut events: ViewMut<IncomingEvent>| {
+                    entities.add_entity(
+                        &mut events,
+                        IncomingEvent(Arc::new(Event::RequestSetVisibleRange {
+                            connection_id,
+                            packet: CSetVisibleRange { range: 4234 },
+                        })),
+                    );
+                    entities.add_entity(
+                        &mut events,
+                        IncomingEvent(Arc::new(Event::RequestSelectUser {
+                            connection_id,
+                            packet: CSelectUser {
+                                db_id: db_user.id,
+                                unk: 0,
+                            },
+                        })),
+                    );
+                },
+            );
+
+            world.run(settings_manager_system);
+            world.run(user_manager_system);
+
+            // The local world receives the visible range of the connection.
+            world.run(|hand_off_list: UniqueView<HandOffList>| {
+                assert_eq!(hand_off_list.0.len(), 1);
+                assert_eq!(hand_off_list.0[0].visibility_range, 4234);
+            });
+
+            Ok(())
+        }
+        db_test(test)
+    }
+
+    #[test]
+    fn test_select_user_of_other_account() -> Result<()> {
+        async fn test(pool: PgPool) -> Result<()> {
+            let mut conn = pool.acquire().await?;
+            let (world, connection_id, account_id) = setup_with_account(pool).await?;
+
+            let db_user = user::create(&mut conn, &get_user(account_id, "Asuna")).await?;
+            world.run(|mut connections: ViewMut<Connection>| {
+                connections[connection_id].account_id = Some(account_id + 1);
+            });
+
+            world.run(
+                |mut entities: EntitiesViewMut, mut events: ViewMut<IncomingEvent>| {
+                    entities.add_entity(
+                        &mut events,
+                        IncomingEvent(Arc::new(Event::RequestSelectUser {
+                            connection_id,
+                            packet: CSelectUser {
+                                db_id: db_user.id,
+                                unk: 0,
+                            },
+                        })),
+                    );
+                },
+            );
+
+            world.run(user_manager_system);
+
+            world.run(|hand_off_list: UniqueView<HandOffList>| {
+                assert!(hand_off_list.0.is_empty());
+            });
+
+            Ok(())
+        }
+        db_test(test)
+    }
 }
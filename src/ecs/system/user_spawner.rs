@@ -0,0 +1,509 @@
+/// Spawns the selected users of the connections that were handed off to a local world.
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::{ensure, Context};
+use async_std::task;
+use chrono::Utc;
+use shipyard::*;
+use sqlx::PgPool;
+use tracing::{debug, error, info, info_span};
+
+use crate::ecs::component::{
+    ConnectionID, IncomingEvent, Location, OutgoingEvent, UserSpawn, UserSpawnStatus,
+};
+use crate::ecs::event::Event;
+use crate::ecs::resource::{DeletionList, WorldId};
+use crate::ecs::system::{find_local_entity, send_event};
+use crate::model::entity::User;
+use crate::model::repository::user;
+use crate::model::Vec3;
+use crate::protocol::packet::*;
+use crate::Result;
+
+// TODO persist the location of the user once we have the zone data.
+/// Zone every user is spawned in.
+pub(crate) const START_ZONE: i32 = 13;
+/// Location inside the start zone every user is spawned at.
+const START_LOCATION: Vec3 = Vec3 {
+    x: 93_492.0,
+    y: -88_216.0,
+    z: -4_569.5,
+};
+
+pub fn user_spawner_system(
+    incoming_events: View<IncomingEvent>,
+    mut outgoing_events: ViewMut<OutgoingEvent>,
+    connection_ids: View<ConnectionID>,
+    mut user_spawns: ViewMut<UserSpawn>,
+    mut users: ViewMut<User>,
+    mut locations: ViewMut<Location>,
+    mut entities: EntitiesViewMut,
+    pool: UniqueView<PgPool>,
+    mut deletion_list: UniqueViewMut<DeletionList>,
+    world_id: UniqueView<WorldId>,
+) {
+    let span = info_span!("world", world_id = world_id.0);
+    let _enter = span.enter();
+
+    // Users of newly registered connections
+    let requested: Vec<(EntityId, EntityId, i32)> = (&connection_ids, &user_spawns)
+        .iter()
+        .with_id()
+        .filter(|(_, (_, spawn))| spawn.status == UserSpawnStatus::Requesting)
+        .map(|(local_id, (connection_id, spawn))| (local_id, connection_id.0, spawn.user_id))
+        .collect();
+
+    for (local_id, connection_id, user_id) in requested {
+        if let Err(e) = handle_user_loading(
+            local_id,
+            connection_id,
+            user_id,
+            &pool,
+            &mut user_spawns,
+            &mut users,
+            &mut locations,
+            &mut outgoing_events,
+            &mut entities,
+        ) {
+            error!("Can't load user {}: {:?}", user_id, e);
+            send_event(
+                assemble_drop_connection(connection_id),
+                &mut outgoing_events,
+                &mut entities,
+            );
+            deletion_list.0.push(local_id);
+        }
+    }
+
+    // Incoming events
+    (&incoming_events).iter().for_each(|event| {
+        if let Event::RequestLoadTopoFin { connection_id, .. } = &*event.0 {
+            if let Err(e) = handle_load_topo_fin(
+                *connection_id,
+                &connection_ids,
+                &mut user_spawns,
+                &users,
+                &locations,
+                &mut outgoing_events,
+                &mut entities,
+            ) {
+                error!("Rejecting load topo fin request: {:?}", e);
+            }
+        }
+    });
+}
+
+fn handle_user_loading(
+    local_id: EntityId,
+    connection_id: EntityId,
+    user_id: i32,
+    pool: &PgPool,
+    mut user_spawns: &mut ViewMut<UserSpawn>,
+    users: &mut ViewMut<User>,
+    locations: &mut ViewMut<Location>,
+    outgoing_events: &mut ViewMut<OutgoingEvent>,
+    entities: &mut EntitiesViewMut,
+) -> Result<()> {
+    let span = info_span!("connection", connection = ?connection_id);
+    let _enter = span.enter();
+
+    debug!("Loading user {}", user_id);
+
+    let user = task::block_on(async {
+        let mut conn = pool
+            .acquire()
+            .await
+            .context("Couldn't acquire connection from pool")?;
+        user::get_by_id(&mut conn, user_id).await
+    })
+    .context("Can't query the user")?;
+    ensure!(
+        user.deletion_time.is_none(),
+        "Can't spawn a user that is marked for deletion"
+    );
+
+    let location = Location {
+        zone: START_ZONE,
+        location: START_LOCATION,
+        rotation: 0,
+        last_update: Instant::now(),
+    };
+
+    // The client expects the login packet before it starts to load the zone.
+    send_event(
+        assemble_login(connection_id, &user),
+        outgoing_events,
+        entities,
+    );
+    send_event(
+        assemble_load_topo(connection_id, &location),
+        outgoing_events,
+        entities,
+    );
+
+    info!("User {} is entering zone {}", user.name, location.zone);
+
+    entities.add_component((&mut *users, &mut *locations), (user, location), local_id);
+    (&mut user_spawns)
+        .try_get(local_id)
+        .context("Could not find user spawn component for entity")?
+        .status = UserSpawnStatus::Waiting;
+
+    Ok(())
+}
+
+fn handle_load_topo_fin(
+    connection_id: EntityId,
+    connection_ids: &View<ConnectionID>,
+    mut user_spawns: &mut ViewMut<UserSpawn>,
+    users: &ViewMut<User>,
+    locations: &ViewMut<Location>,
+    outgoing_events: &mut ViewMut<OutgoingEvent>,
+    entities: &mut EntitiesViewMut,
+) -> Result<()> {
+    let span = info_span!("connection", connection = ?connection_id);
+    let _enter = span.enter();
+
+    debug!("Load topo fin event incoming");
+
+    let local_id = find_local_entity(connection_id, connection_ids)
+        .context("Connection is not registered in the local world")?;
+
+    let mut user_spawn = (&mut user_spawns)
+        .try_get(local_id)
+        .context("Could not find user spawn component for entity")?;
+    ensure!(
+        user_spawn.status == UserSpawnStatus::Waiting,
+        "User is not waiting for the zone to be loaded"
+    );
+
+    let user = users
+        .try_get(local_id)
+        .context("Could not find user component for entity")?;
+    let location = locations
+        .try_get(local_id)
+        .context("Could not find location component for entity")?;
+
+    send_event(
+        assemble_spawn_me(connection_id, user, location),
+        outgoing_events,
+        entities,
+    );
+    user_spawn.status = UserSpawnStatus::Spawned;
+
+    Ok(())
+}
+
+/// Calculates the template ID of the user model.
+pub(crate) fn template_id(user: &User) -> i32 {
+    10_101 + (user.race as i32 * 2 + user.gender as i32) * 100 + user.class as i32
+}
+
+fn assemble_login(connection_id: EntityId, user: &User) -> OutgoingEvent {
+    OutgoingEvent(Arc::new(Event::ResponseLogin {
+        connection_id,
+        packet: SLogin {
+            name: user.name.clone(),
+            details: user.details.clone(),
+            shape: user.shape.clone(),
+            template_id: template_id(user),
+            game_id: user.id as u64,
+            server_id: 1,
+            player_id: user.id,
+            relation: 0,
+            alive: true,
+            status: 0,
+            walk_speed: 50,
+            run_speed: 150,
+            appearance: user.appearance.clone(),
+            visible: true,
+            is_second_character: false,
+            level: 1,
+            gather_enchant: 0,
+            gather_unk: 0,
+            gather_plant: 0,
+            gather_mining: 0,
+            gather_energy: 0,
+            gather_bug: 0,
+            exp: 0,
+            total_exp: 840,
+            rest_current: 0,
+            rest_max: 0,
+            exp_bonus_percent: 1.0,
+            drop_bonus: 0,
+            weapon: 0,
+            body: 0,
+            hand: 0,
+            feet: 0,
+            underwear: 0,
+            head: 0,
+            face: 0,
+            server_time: Utc::now().timestamp(),
+            is_pk: false,
+            title_id: 0,
+            weapon_model: 0,
+            body_model: 0,
+            hand_model: 0,
+            feet_model: 0,
+            weapon_dye: 0,
+            body_dye: 0,
+            hand_dye: 0,
+            feet_dye: 0,
+            underwear_dye: 0,
+            style_back_dye: 0,
+            style_head_dye: 0,
+            style_face_dye: 0,
+            weapon_enchant: 0,
+            world_event_target: false,
+            infamy: 0,
+            show_face: true,
+            style_head: 0,
+            style_face: 0,
+            style_back: 0,
+            style_weapon: 0,
+            style_body: 0,
+            style_footprint: 0,
+            style_body_dye: 0,
+            show_style: true,
+            appearance2: user.appearance2,
+            scale: 1.0,
+            guild_logo_id: 0,
+        },
+    }))
+}
+
+fn assemble_load_topo(connection_id: EntityId, location: &Location) -> OutgoingEvent {
+    OutgoingEvent(Arc::new(Event::ResponseLoadTopo {
+        connection_id,
+        packet: SLoadTopo {
+            zone: location.zone,
+            location: location.location,
+            quick: false,
+        },
+    }))
+}
+
+fn assemble_spawn_me(connection_id: EntityId, user: &User, location: &Location) -> OutgoingEvent {
+    OutgoingEvent(Arc::new(Event::ResponseSpawnMe {
+        connection_id,
+        packet: SSpawnMe {
+            game_id: user.id as u64,
+            location: location.location,
+            rotation: location.rotation,
+            alive: true,
+            unk: false,
+        },
+    }))
+}
+
+fn assemble_drop_connection(connection_id: EntityId) -> OutgoingEvent {
+    OutgoingEvent(Arc::new(Event::ResponseDropConnection { connection_id }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use async_std::sync::channel;
+    use chrono::{TimeZone, Utc};
+    use sqlx::PgConnection;
+
+    use crate::ecs::resource::ConnectionMapping;
+    use crate::ecs::system::{cleaner_system, local_connection_manager_system};
+    use crate::model::entity::Account;
+    use crate::model::repository::account;
+    use crate::model::tests::db_test;
+    use crate::model::{Class, Customization, Gender, PasswordHashAlgorithm, Race};
+
+    use super::*;
+
+    fn setup(pool: PgPool) -> (World, EntityId) {
+        let world = World::new();
+        world.add_unique(WorldId(1));
+        world.add_unique(DeletionList(vec![]));
+        world.add_unique(ConnectionMapping(HashMap::new()));
+        world.add_unique(pool);
+
+        // The connection ID is an entity of the global world.
+        let connection_id = World::new().borrow::<EntitiesViewMut>().add_entity((), ());
+
+        (world, connection_id)
+    }
+
+    async fn create_user(conn: &mut PgConnection) -> Result<User> {
+        let acc = account::create(
+            conn,
+            &Account {
+                id: -1,
+                name: "testaccount".to_string(),
+                password: "not-a-real-password-hash".to_string(),
+                algorithm: PasswordHashAlgorithm::Argon2,
+                created_at: Utc.ymd(1995, 7, 8).and_hms(9, 10, 11),
+                updated_at: Utc.ymd(1995, 7, 8).and_hms(9, 10, 11),
+            },
+        )
+        .await?;
+
+        user::create(
+            conn,
+            &User {
+                id: -1,
+                account_id: acc.id,
+                name: "Asuna".to_string(),
+                gender: Gender::Female,
+                race: Race::HighElf,
+                class: Class::Priest,
+                shape: vec![],
+                details: vec![],
+                appearance: Customization {
+                    data: vec![0u8, 0u8, 0u8, 0u8, 0u8, 0u8, 0u8, 0u8],
+                },
+                appearance2: 100,
+                playtime: 0,
+                deletion_time: None,
+                created_at: Utc.ymd(1995, 7, 8).and_hms(9, 10, 11),
+            },
+        )
+        .await
+    }
+
+    fn register_connection(world: &World, connection_id: EntityId, user_id: i32) {
+        let (tx_channel, _rx_channel) = channel(10);
+
+        world.run(
+            |mut entities: EntitiesViewMut, mut events: ViewMut<IncomingEvent>| {
+                entities.add_entity(
+                    &mut events,
+                    IncomingEvent(Arc::new(Event::RequestRegisterLocalConnection {
+                        connection_id,
+                        user_id,
+                        visibility_range: 2000,
+                        response_channel: tx_channel.clone(),
+                    })),
+                );
+            },
+        );
+
+        world.run(local_connection_manager_system);
+        world.run(user_spawner_system);
+    }
+
+    #[test]
+    fn test_template_id() {
+        let mut user = User {
+            id: -1,
+            account_id: -1,
+            name: "Asuna".to_string(),
+            gender: Gender::Male,
+            race: Race::Human,
+            class: Class::Warrior,
+            shape: vec![],
+            details: vec![],
+            appearance: Customization { data: vec![] },
+            appearance2: 100,
+            playtime: 0,
+            deletion_time: None,
+            created_at: Utc.ymd(1995, 7, 8).and_hms(9, 10, 11),
+        };
+        assert_eq!(template_id(&user), 10_101);
+
+        user.gender = Gender::Female;
+        user.race = Race::ElinPopori;
+        user.class = Class::Valkyrie;
+        assert_eq!(template_id(&user), 11_012);
+    }
+
+    #[test]
+    fn test_user_spawn() -> Result<()> {
+        async fn test(pool: PgPool) -> Result<()> {
+            let mut conn = pool.acquire().await?;
+            let db_user = create_user(&mut conn).await?;
+            let (world, connection_id) = setup(pool);
+
+            register_connection(&world, connection_id, db_user.id);
+
+            world.run(|events: View<OutgoingEvent>| {
+                let list: Vec<&OutgoingEvent> = (&events).iter().collect();
+                assert_eq!(list.len(), 2);
+
+                if let Event::ResponseLogin { packet, .. } = &*list[0].0 {
+                    assert_eq!(packet.name, db_user.name);
+                    assert_eq!(packet.game_id, db_user.id as u64);
+                } else {
+                    panic!("received packets in wrong order");
+                }
+
+                if let Event::ResponseLoadTopo { packet, .. } = &*list[1].0 {
+                    assert_eq!(packet.zone, START_ZONE);
+                } else {
+                    panic!("received packets in wrong order");
+                }
+            });
+
+            world.run(cleaner_system);
+
+            world.run(
+                |mut entities: EntitiesViewMut, mut events: ViewMut<IncomingEvent>| {
+                    entities.add_entity(
+                        &mut events,
+                        IncomingEvent(Arc::new(Event::RequestLoadTopoFin {
+                            connection_id,
+                            packet: CLoadTopoFin {},
+                        })),
+                    );
+                },
+            );
+
+            world.run(user_spawner_system);
+
+            world.run(|events: View<OutgoingEvent>| {
+                let list: Vec<&OutgoingEvent> = (&events).iter().collect();
+                assert_eq!(list.len(), 1);
+
+                if let Event::ResponseSpawnMe { packet, .. } = &*list[0].0 {
+                    assert_eq!(packet.game_id, db_user.id as u64);
+                    assert_eq!(packet.location, START_LOCATION);
+                } else {
+                    panic!("Couldn't find spawn me response");
+                }
+            });
+
+            let count = world
+                .borrow::<View<UserSpawn>>()
+                .iter()
+                .filter(|spawn| spawn.status == UserSpawnStatus::Spawned)
+                .count();
+            assert_eq!(count, 1);
+
+            Ok(())
+        }
+        db_test(test)
+    }
+
+    #[test]
+    fn test_user_spawn_unknown_user() -> Result<()> {
+        async fn test(pool: PgPool) -> Result<()> {
+            let (world, connection_id) = setup(pool);
+
+            register_connection(&world, connection_id, 1_000_000);
+
+            world.run(|events: View<OutgoingEvent>| {
+                let count = (&events)
+                    .iter()
+                    .filter(|event| match &*event.0 {
+                        Event::ResponseDropConnection { .. } => true,
+                        _ => false,
+                    })
+                    .count();
+                assert_eq!(count, 1);
+            });
+
+            world.run(cleaner_system);
+            assert_eq!(world.borrow::<View<UserSpawn>>().iter().count(), 0);
+
+            Ok(())
+        }
+        db_test(test)
+    }
+}
@@ -0,0 +1,379 @@
+/// Tracks which entities each user can see and spawns / despawns them on the client.
+///
+/// Only users are handled right now. NPCs are left out, since no world spawns NPC entities yet and
+/// the S_SPAWN_NPC / S_DESPAWN_NPC packets are not defined.
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use shipyard::*;
+use tracing::{debug, info_span};
+
+use crate::ecs::component::{
+    ConnectionID, Location, OutgoingEvent, Settings, UserSpawn, UserSpawnStatus, Visibility,
+};
+use crate::ecs::event::Event;
+use crate::ecs::resource::{SpatialGrid, WorldId};
+use crate::ecs::system::send_event;
+use crate::ecs::system::user_spawner::template_id;
+use crate::model::entity::User;
+use crate::model::Vec3;
+use crate::protocol::packet::*;
+
+// TODO Spawn and despawn NPCs with S_SPAWN_NPC / S_DESPAWN_NPC once the worlds contain NPCs.
+// Until then this system only handles users.
+pub fn visibility_manager_system(
+    mut outgoing_events: ViewMut<OutgoingEvent>,
+    connection_ids: View<ConnectionID>,
+    user_spawns: View<UserSpawn>,
+    users: View<User>,
+    locations: View<Location>,
+    settings: View<Settings>,
+    mut visibilities: ViewMut<Visibility>,
+    mut entities: EntitiesViewMut,
+    mut grid: UniqueViewMut<SpatialGrid>,
+    world_id: UniqueView<WorldId>,
+) {
+    let span = info_span!("world", world_id = world_id.0);
+    let _enter = span.enter();
+
+    // Rebuild the spatial index with the current location of all spawned users.
+    grid.clear();
+    (&user_spawns, &locations)
+        .iter()
+        .with_id()
+        .filter(|(_, (spawn, _))| spawn.status == UserSpawnStatus::Spawned)
+        .for_each(|(id, (_, location))| grid.insert(id, location.location));
+
+    let observers: Vec<(EntityId, EntityId, Vec3, u32)> =
+        (&connection_ids, &user_spawns, &locations, &settings)
+            .iter()
+            .with_id()
+            .filter(|(_, (_, spawn, _, _))| spawn.status == UserSpawnStatus::Spawned)
+            .map(|(id, (connection_id, _, location, settings))| {
+                (
+                    id,
+                    connection_id.0,
+                    location.location,
+                    settings.visibility_range,
+                )
+            })
+            .collect();
+
+    for (local_id, connection_id, location, visibility_range) in observers {
+        if visibilities.try_get(local_id).is_err() {
+            entities.add_component(&mut visibilities, Visibility::default(), local_id);
+        }
+
+        let in_range: HashSet<EntityId> = grid
+            .query(&location, visibility_range as f32)
+            .into_iter()
+            .filter(|id| *id != local_id)
+            .collect();
+
+        update_visibility(
+            local_id,
+            connection_id,
+            &in_range,
+            &users,
+            &locations,
+            &mut visibilities,
+            &mut outgoing_events,
+            &mut entities,
+        );
+    }
+}
+
+/// Spawns the entities that entered and despawns the entities that left the visibility range.
+fn update_visibility(
+    local_id: EntityId,
+    connection_id: EntityId,
+    in_range: &HashSet<EntityId>,
+    users: &View<User>,
+    locations: &View<Location>,
+    mut visibilities: &mut ViewMut<Visibility>,
+    outgoing_events: &mut ViewMut<OutgoingEvent>,
+    entities: &mut EntitiesViewMut,
+) {
+    let span = info_span!("connection", connection = ?connection_id);
+    let _enter = span.enter();
+
+    let visibility = match (&mut visibilities).try_get(local_id) {
+        Ok(visibility) => visibility,
+        Err(_) => return,
+    };
+
+    let left: Vec<(EntityId, u64)> = visibility
+        .visible
+        .iter()
+        .filter(|(id, _)| !in_range.contains(id))
+        .map(|(id, game_id)| (*id, *game_id))
+        .collect();
+
+    for (id, game_id) in left {
+        debug!("User {} left the visibility range", game_id);
+        visibility.visible.remove(&id);
+        send_event(
+            assemble_despawn_user(connection_id, game_id),
+            outgoing_events,
+            entities,
+        );
+    }
+
+    for id in in_range {
+        if visibility.visible.contains_key(id) {
+            continue;
+        }
+
+        if let (Ok(user), Ok(location)) = (users.try_get(*id), locations.try_get(*id)) {
+            debug!("User {} entered the visibility range", user.id);
+            visibility.visible.insert(*id, user.id as u64);
+            send_event(
+                assemble_spawn_user(connection_id, user, location),
+                outgoing_events,
+                entities,
+            );
+        }
+    }
+}
+
+fn assemble_spawn_user(connection_id: EntityId, user: &User, location: &Location) -> OutgoingEvent {
+    OutgoingEvent(Arc::new(Event::ResponseSpawnUser {
+        connection_id,
+        packet: SSpawnUser {
+            name: user.name.clone(),
+            guild_name: "".to_string(),
+            guild_rank: "".to_string(),
+            details: user.details.clone(),
+            guild_title: "".to_string(),
+            guild_logo: "".to_string(),
+            shape: user.shape.clone(),
+            server_id: 1,
+            player_id: user.id,
+            game_id: user.id as u64,
+            location: location.location,
+            rotation: location.rotation,
+            relation: 0,
+            template_id: template_id(user),
+            visible: true,
+            alive: true,
+            appearance: user.appearance.clone(),
+            spawn_fx: false,
+            mount: 0,
+            pose: 0,
+            title_id: 0,
+            level: 1,
+            gm: false,
+            gm_invisible: false,
+            weapon: 0,
+            body: 0,
+            hand: 0,
+            feet: 0,
+            underwear: 0,
+            head: 0,
+            face: 0,
+            weapon_model: 0,
+            body_model: 0,
+            hand_model: 0,
+            feet_model: 0,
+            weapon_dye: 0,
+            body_dye: 0,
+            hand_dye: 0,
+            feet_dye: 0,
+            underwear_dye: 0,
+            style_head: 0,
+            style_face: 0,
+            style_back: 0,
+            style_weapon: 0,
+            style_body: 0,
+            style_footprint: 0,
+            weapon_enchant: 0,
+            is_pk: false,
+            infamy: 0,
+            show_face: true,
+            show_style: true,
+            appearance2: user.appearance2,
+            scale: 1.0,
+            guild_logo_id: 0,
+        },
+    }))
+}
+
+fn assemble_despawn_user(connection_id: EntityId, game_id: u64) -> OutgoingEvent {
+    OutgoingEvent(Arc::new(Event::ResponseDespawnUser {
+        connection_id,
+        packet: SDespawnUser { game_id, unk: 1 },
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use chrono::{TimeZone, Utc};
+
+    use crate::ecs::resource::DeletionList;
+    use crate::ecs::system::cleaner_system;
+    use crate::ecs::system::user_spawner::START_ZONE;
+    use crate::model::{Class, Customization, Gender, Race};
+
+    use super::*;
+
+    fn setup() -> World {
+        let world = World::new();
+        world.add_unique(WorldId(1));
+        world.add_unique(DeletionList(vec![]));
+        world.add_unique(SpatialGrid::new(1_000.0));
+        world
+    }
+
+    /// Spawns a user at the given x coordinate and returns it's local entity and global connection ID.
+    fn spawn_user(
+        world: &World,
+        global_world: &World,
+        user_id: i32,
+        x: f32,
+    ) -> (EntityId, EntityId) {
+        let connection_id = global_world.borrow::<EntitiesViewMut>().add_entity((), ());
+
+        let local_id = world.run(
+            |mut entities: EntitiesViewMut,
+             mut connection_ids: ViewMut<ConnectionID>,
+             mut user_spawns: ViewMut<UserSpawn>,
+             mut users: ViewMut<User>,
+             mut locations: ViewMut<Location>,
+             mut settings: ViewMut<Settings>| {
+                entities.add_entity(
+                    (
+                        &mut connection_ids,
+                        &mut user_spawns,
+                        &mut users,
+                        &mut locations,
+                        &mut settings,
+                    ),
+                    (
+                        ConnectionID(connection_id),
+                        UserSpawn {
+                            user_id,
+                            status: UserSpawnStatus::Spawned,
+                        },
+                        User {
+                            id: user_id,
+                            account_id: 1,
+                            name: format!("User{}", user_id),
+                            gender: Gender::Female,
+                            race: Race::HighElf,
+                            class: Class::Priest,
+                            shape: vec![],
+                            details: vec![],
+                            appearance: Customization { data: vec![] },
+                            appearance2: 100,
+                            playtime: 0,
+                            deletion_time: None,
+                            created_at: Utc.ymd(1995, 7, 8).and_hms(9, 10, 11),
+                        },
+                        Location {
+                            zone: START_ZONE,
+                            location: Vec3 { x, y: 0.0, z: 0.0 },
+                            rotation: 0,
+                            last_update: Instant::now(),
+                        },
+                        Settings {
+                            visibility_range: 1000,
+                        },
+                    ),
+                )
+            },
+        );
+
+        (local_id, connection_id)
+    }
+
+    fn move_user(world: &World, local_id: EntityId, x: f32) {
+        world.run(|mut locations: ViewMut<Location>| {
+            (&mut locations).try_get(local_id).unwrap().location.x = x;
+        });
+    }
+
+    /// Returns the game IDs of all spawned and despawned users for the given connection.
+    fn collect_events(world: &World, connection: EntityId) -> (Vec<u64>, Vec<u64>) {
+        let mut spawned = Vec::new();
+        let mut despawned = Vec::new();
+
+        world.run(|events: View<OutgoingEvent>| {
+            (&events).iter().for_each(|event| match &*event.0 {
+                Event::ResponseSpawnUser {
+                    connection_id,
+                    packet,
+                } if *connection_id == connection => spawned.push(packet.game_id),
+                Event::ResponseDespawnUser {
+                    connection_id,
+                    packet,
+                } if *connection_id == connection => despawned.push(packet.game_id),
+                _ => {}
+            });
+        });
+
+        (spawned, despawned)
+    }
+
+    #[test]
+    fn test_users_enter_visibility_range() {
+        let world = setup();
+        let global_world = World::new();
+
+        let (_, connection1) = spawn_user(&world, &global_world, 1, 0.0);
+        let (_, connection2) = spawn_user(&world, &global_world, 2, 500.0);
+        let (_, connection3) = spawn_user(&world, &global_world, 3, 5_000.0);
+
+        world.run(visibility_manager_system);
+
+        assert_eq!(collect_events(&world, connection1), (vec![2], vec![]));
+        assert_eq!(collect_events(&world, connection2), (vec![1], vec![]));
+        assert_eq!(collect_events(&world, connection3), (vec![], vec![]));
+
+        // Users that are already visible are not spawned again.
+        world.run(cleaner_system);
+        world.run(visibility_manager_system);
+
+        assert_eq!(world.borrow::<View<OutgoingEvent>>().iter().count(), 0);
+    }
+
+    #[test]
+    fn test_users_leave_visibility_range() {
+        let world = setup();
+        let global_world = World::new();
+
+        let (local1, connection1) = spawn_user(&world, &global_world, 1, 0.0);
+        let (_, connection2) = spawn_user(&world, &global_world, 2, 500.0);
+        let (_, connection3) = spawn_user(&world, &global_world, 3, 5_000.0);
+
+        world.run(visibility_manager_system);
+        world.run(cleaner_system);
+
+        move_user(&world, local1, 4_500.0);
+        world.run(visibility_manager_system);
+
+        assert_eq!(collect_events(&world, connection1), (vec![3], vec![2]));
+        assert_eq!(collect_events(&world, connection2), (vec![], vec![1]));
+        assert_eq!(collect_events(&world, connection3), (vec![1], vec![]));
+    }
+
+    #[test]
+    fn test_deleted_user_leaves_visibility_range() {
+        let world = setup();
+        let global_world = World::new();
+
+        let (local1, _) = spawn_user(&world, &global_world, 1, 0.0);
+        let (_, connection2) = spawn_user(&world, &global_world, 2, 500.0);
+
+        world.run(visibility_manager_system);
+        world.run(|mut deletion_list: UniqueViewMut<DeletionList>| {
+            deletion_list.0.push(local1);
+        });
+        world.run(cleaner_system);
+
+        world.run(visibility_manager_system);
+
+        assert_eq!(collect_events(&world, connection2), (vec![], vec![1]));
+    }
+}
@@ -1,21 +1,41 @@
 /// Module that handles the world generation and handling
 use std::collections::HashMap;
+use std::sync::Arc;
 use std::{thread, time};
 
 use async_std::sync::{channel, Sender};
 use shipyard::*;
 use sqlx::PgPool;
-use tracing::debug;
+use tracing::{debug, error, info, info_span, warn};
 
 use crate::config::Configuration;
-use crate::ecs::event::EcsEvent;
+use crate::ecs::component::{Connection, ConnectionID, Location, OutgoingEvent};
+use crate::ecs::event::{EcsEvent, Event};
 use crate::ecs::resource::*;
 use crate::ecs::system::*;
+use crate::gamedata::GameData;
+use crate::metrics::{Metrics, WorldMetrics};
+use crate::protocol::system_message::SystemMessageTable;
+
+/// Adds every system as a workload of it's own and returns the names of the workloads in the
+/// order they need to run.
+macro_rules! add_systems {
+    ($world:expr, $($system:ident),+ $(,)?) => {{
+        $(
+            $world
+                .add_workload(stringify!($system))
+                .with_system(system!($system))
+                .build();
+        )+
+        vec![$(stringify!($system)),+]
+    }};
+}
 
 /// Holds the ECS for the global world and all instanced worlds.
 pub struct Multiverse {
     pub(crate) global_handle: WorldHandle,
-    pub(crate) _instanced_handles: HashMap<String, World>,
+    pub(crate) local_handles: HashMap<String, LocalWorldHandle>,
+    pub(crate) metrics: Arc<Metrics>,
 }
 
 impl Multiverse {
@@ -25,65 +45,156 @@ impl Multiverse {
     }
 
     /// Starts the main loop of the global world.
-    pub fn run(&mut self, pool: PgPool, config: Configuration) {
+    pub fn run(
+        &mut self,
+        pool: PgPool,
+        config: Configuration,
+        system_messages: SystemMessageTable,
+        game_data: Arc<GameData>,
+    ) {
         let world = &mut self.global_handle.world;
 
-        // Copy configuration and db pool into the global resources so that systems can access them.
-        world.add_unique(config);
-        world.add_unique(pool);
-
-        // Build the workload
-        const GLOBAL_WORLD_TICK: &str = "GLOBAL_WORLD_TICK";
-        world
-            .add_workload(GLOBAL_WORLD_TICK)
-            .with_system(system!(event_receiver_system))
-            .with_system(system!(connection_manager_system))
-            .with_system(system!(settings_manager_system))
-            .with_system(system!(user_manager_system))
-            .with_system(system!(event_sender_system))
-            .with_system(system!(cleaner_system))
-            .build();
+        // Copy configuration, db pool, system messages and game data into the global resources so that systems can access them.
+        world.add_unique(config.clone());
+        world.add_unique(pool.clone());
+        world.add_unique(system_messages.clone());
+        world.add_unique(game_data.clone());
+
+        // Every system runs as it's own workload, so that the tick can be timed per system.
+        let systems = add_systems!(
+            world,
+            event_receiver_system,
+            connection_manager_system,
+            settings_manager_system,
+            user_manager_system,
+            chat_manager_system,
+            event_sender_system,
+            cleaner_system,
+        );
+        let world_metrics = self.metrics.register_world(0, "global", &systems);
 
         // Global tick rate is at best 50ms (20 Hz)
         let min_duration = time::Duration::from_millis(50);
         loop {
             let start = time::Instant::now();
 
-            world.run_workload(GLOBAL_WORLD_TICK);
+            let world = &self.global_handle.world;
+            record_event_queue_depth(world, &world_metrics);
+            let slowest = run_systems(world, &systems, &world_metrics);
+            world_metrics.set_entity_count(
+                "connections",
+                world.borrow::<View<Connection>>().iter().count(),
+            );
+            self.process_hand_offs(&pool, &config, &system_messages, &game_data);
 
             let elapsed = start.elapsed();
+            record_tick(
+                "global",
+                elapsed,
+                min_duration,
+                slowest,
+                &world_metrics,
+                &self.metrics,
+            );
             if elapsed < min_duration {
                 thread::sleep(min_duration - elapsed);
             }
         }
     }
 
+    /// Get the metrics that are shared by all worlds.
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
     /// Get the Input Event Channel of the global world
     pub fn get_global_input_event_channel(&self) -> Sender<EcsEvent> {
         self.global_handle.tx_channel.clone()
     }
+
+    /// Hands off the connections that the global world marked for a hand off to their local world.
+    /// Local worlds are spawned once the first connection is handed off to them.
+    pub(crate) fn process_hand_offs(
+        &mut self,
+        pool: &PgPool,
+        config: &Configuration,
+        system_messages: &SystemMessageTable,
+        game_data: &Arc<GameData>,
+    ) {
+        let hand_offs: Vec<LocalWorldHandOff> = self
+            .global_handle
+            .world
+            .borrow::<UniqueViewMut<HandOffList>>()
+            .0
+            .drain(..)
+            .collect();
+
+        for hand_off in hand_offs {
+            let span = info_span!("connection", connection = ?hand_off.connection_id);
+            let _enter = span.enter();
+
+            if !self.local_handles.contains_key(&hand_off.world_name) {
+                // The global world has the ID 0.
+                let id = self.local_handles.len() as u64 + 1;
+                let handle = LocalWorldHandle::spawn(
+                    id,
+                    &hand_off.world_name,
+                    self.global_handle.tx_channel.clone(),
+                    pool.clone(),
+                    config.clone(),
+                    system_messages.clone(),
+                    game_data.clone(),
+                    self.metrics.clone(),
+                );
+                self.local_handles
+                    .insert(hand_off.world_name.clone(), handle);
+            }
+            let local_handle = &self.local_handles[&hand_off.world_name];
+
+            let world = &self.global_handle.world;
+            if world
+                .borrow::<UniqueView<ConnectionMapping>>()
+                .0
+                .contains_key(&hand_off.connection_id)
+            {
+                debug!(
+                    "Handing off connection to local world {} with ID {}",
+                    hand_off.world_name, local_handle.id
+                );
+                // The hand off is delivered behind the events that are already queued for the connection.
+                let event = Event::ResponseLocalWorldHandOff {
+                    connection_id: hand_off.connection_id,
+                    local_world_id: local_handle.id,
+                    user_id: hand_off.user_id,
+                    visibility_range: hand_off.visibility_range,
+                    request_channel: local_handle.tx_channel.clone(),
+                };
+                world.run(
+                    |mut entities: EntitiesViewMut, mut outgoing_events: ViewMut<OutgoingEvent>| {
+                        send_event(
+                            OutgoingEvent(Arc::new(event)),
+                            &mut outgoing_events,
+                            &mut entities,
+                        );
+                    },
+                );
+            } else {
+                error!("Couldn't find a channel mapping for the connection to hand off");
+            }
+        }
+    }
 }
 
 impl Default for Multiverse {
     fn default() -> Self {
-        let world = World::new();
         let id = 0;
+        let (world, tx_channel) = create_world(id);
         debug!("Global world created with ID {}", id);
 
-        // Create channels to send data to and from the global world.
-        // At most 1024 events can be queued between server ticks
-        let (tx_channel, rx_channel) = channel(1024);
-
-        world.add_unique(WorldId(id));
-        world.add_unique(EventRxChannel {
-            channel: rx_channel,
-        });
-
-        let map: HashMap<EntityId, Sender<EcsEvent>> = HashMap::with_capacity(512);
-        world.add_unique(ConnectionMapping(map));
+        world.add_unique(HandOffList(Vec::with_capacity(64)));
 
-        let vec: Vec<EntityId> = Vec::with_capacity(512);
-        world.add_unique(DeletionList(vec));
+        let metrics = Arc::new(Metrics::new());
+        world.add_unique(metrics.clone());
 
         Multiverse {
             global_handle: WorldHandle {
@@ -91,9 +202,81 @@ impl Default for Multiverse {
                 tx_channel,
                 world,
             },
-            _instanced_handles: HashMap::new(),
+            local_handles: HashMap::new(),
+            metrics,
+        }
+    }
+}
+
+/// Creates a new world with the resources every world needs and returns it together with it's input event channel.
+fn create_world(id: u64) -> (World, Sender<EcsEvent>) {
+    let world = World::new();
+
+    // Create channels to send data to and from the world.
+    // At most 1024 events can be queued between server ticks
+    let (tx_channel, rx_channel) = channel(1024);
+
+    world.add_unique(WorldId(id));
+    world.add_unique(EventRxChannel {
+        channel: rx_channel,
+    });
+
+    let map: HashMap<EntityId, Sender<EcsEvent>> = HashMap::with_capacity(512);
+    world.add_unique(ConnectionMapping(map));
+
+    let vec: Vec<EntityId> = Vec::with_capacity(512);
+    world.add_unique(DeletionList(vec));
+
+    world.add_unique(OutboundQueues::default());
+    world.add_unique(DeliveryPolicy::default());
+
+    (world, tx_channel)
+}
+
+/// Runs the systems of a tick and records their durations. Returns the slowest system.
+fn run_systems(
+    world: &World,
+    systems: &[&'static str],
+    world_metrics: &WorldMetrics,
+) -> (&'static str, time::Duration) {
+    let mut slowest = ("", time::Duration::default());
+    for (index, system) in systems.iter().enumerate() {
+        let start = time::Instant::now();
+        world.run_workload(*system);
+        let elapsed = start.elapsed();
+
+        world_metrics.record_system(index, elapsed);
+        if elapsed > slowest.1 {
+            slowest = (*system, elapsed);
         }
     }
+    slowest
+}
+
+/// Records the number of events that are waiting in the input event channel of the world.
+fn record_event_queue_depth(world: &World, world_metrics: &WorldMetrics) {
+    let depth = world.borrow::<UniqueView<EventRxChannel>>().channel.len();
+    world_metrics.set_event_queue_depth(depth);
+}
+
+/// Records the duration of a tick and warns if it exceeded the tick rate.
+fn record_tick(
+    world_name: &str,
+    elapsed: time::Duration,
+    budget: time::Duration,
+    slowest: (&'static str, time::Duration),
+    world_metrics: &WorldMetrics,
+    metrics: &Metrics,
+) {
+    let overrun = elapsed > budget;
+    world_metrics.record_tick(elapsed, overrun);
+    metrics.add_tick(overrun);
+    if overrun {
+        warn!(
+            "Tick of world {} took {:?} and exceeded it's budget of {:?}. Slowest system was {} with {:?}",
+            world_name, elapsed, budget, slowest.0, slowest.1
+        );
+    }
 }
 
 /// Handle for a world.
@@ -104,6 +287,98 @@ pub struct WorldHandle {
     pub world: World,
 }
 
+/// Handle for a local world (zone or dungeon), which runs on it's own thread.
+/// Connections that were handed off to the local world can register their connection by using the
+/// `Event::RequestRegisterLocalConnection` event.
+pub struct LocalWorldHandle {
+    pub id: u64,
+    pub tx_channel: Sender<EcsEvent>,
+    pub join_handle: thread::JoinHandle<()>,
+}
+
+impl LocalWorldHandle {
+    /// Spawns a new local world on it's own thread and starts it's main loop.
+    fn spawn(
+        id: u64,
+        name: &str,
+        global_channel: Sender<EcsEvent>,
+        pool: PgPool,
+        config: Configuration,
+        system_messages: SystemMessageTable,
+        game_data: Arc<GameData>,
+        metrics: Arc<Metrics>,
+    ) -> LocalWorldHandle {
+        let (world, tx_channel) = create_world(id);
+        info!("Local world {} created with ID {}", name, id);
+        let name = name.to_string();
+
+        let join_handle = thread::spawn(move || {
+            // Copy configuration, db pool, system messages and game data into the local resources so that systems can access them.
+            world.add_unique(config);
+            world.add_unique(pool);
+            world.add_unique(system_messages);
+            world.add_unique(game_data);
+            world.add_unique(metrics.clone());
+
+            // Events that concern all worlds are forwarded to the global world.
+            world.add_unique(GlobalWorldChannel {
+                channel: global_channel,
+            });
+
+            // Entities are sorted into cells of 1000 units for the visibility calculation.
+            world.add_unique(SpatialGrid::new(1_000.0));
+
+            // Every system runs as it's own workload, so that the tick can be timed per system.
+            let systems = add_systems!(
+                world,
+                event_receiver_system,
+                local_connection_manager_system,
+                user_spawner_system,
+                movement_manager_system,
+                visibility_manager_system,
+                local_chat_manager_system,
+                event_sender_system,
+                cleaner_system,
+            );
+            let world_metrics = metrics.register_world(id, &name, &systems);
+
+            // Local tick rate is at best 50ms (20 Hz)
+            let min_duration = time::Duration::from_millis(50);
+            loop {
+                let start = time::Instant::now();
+
+                record_event_queue_depth(&world, &world_metrics);
+                let slowest = run_systems(&world, &systems, &world_metrics);
+                world_metrics.set_entity_count(
+                    "connections",
+                    world.borrow::<View<ConnectionID>>().iter().count(),
+                );
+                world_metrics
+                    .set_entity_count("located", world.borrow::<View<Location>>().iter().count());
+
+                let elapsed = start.elapsed();
+                record_tick(
+                    &name,
+                    elapsed,
+                    min_duration,
+                    slowest,
+                    &world_metrics,
+                    &metrics,
+                );
+                if elapsed < min_duration {
+                    thread::sleep(min_duration - elapsed);
+                }
+            }
+        });
+
+        LocalWorldHandle {
+            id,
+            tx_channel,
+            join_handle,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
@@ -112,7 +387,9 @@ mod tests {
     use async_std::future::timeout;
     use async_std::sync::channel;
 
+    use crate::config::tests::get_configuration;
     use crate::ecs::event::Event;
+    use crate::model::tests::db_test;
     use crate::Result;
 
     use super::*;
@@ -133,4 +410,122 @@ mod tests {
 
         Ok(())
     }
+
+    #[async_std::test]
+    async fn test_tick_instrumentation() -> Result<()> {
+        let (world, tx_channel) = create_world(0);
+        let metrics = Metrics::new();
+        let systems = add_systems!(world, event_receiver_system, cleaner_system);
+        let world_metrics = metrics.register_world(0, "test", &systems);
+
+        let (tx, _) = channel(128);
+        tx_channel
+            .send(Arc::new(Event::RequestRegisterConnection {
+                response_channel: tx,
+            }))
+            .await;
+
+        record_event_queue_depth(&world, &world_metrics);
+        let slowest = run_systems(&world, &systems, &world_metrics);
+        assert!(systems.contains(&slowest.0));
+        record_tick(
+            "test",
+            Duration::from_millis(60),
+            Duration::from_millis(50),
+            slowest,
+            &world_metrics,
+            &metrics,
+        );
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.ticks, 1);
+        assert_eq!(snapshot.tick_overruns, 1);
+
+        let world_snapshot = &snapshot.worlds[0];
+        assert_eq!(world_snapshot.event_queue_depth, 1);
+        assert_eq!(world_snapshot.tick_overruns, 1);
+        assert_eq!(world_snapshot.systems.len(), 2);
+        assert_eq!(world_snapshot.systems[0].name, "event_receiver_system");
+        assert_eq!(world_snapshot.systems[0].durations.count, 1);
+        assert_eq!(world_snapshot.systems[1].name, "cleaner_system");
+        assert_eq!(world_snapshot.systems[1].durations.count, 1);
+
+        // The event was received by the world.
+        assert!(tx_channel.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_local_world_hand_off() -> Result<()> {
+        async fn test(pool: PgPool) -> Result<()> {
+            let mut m = Multiverse::new();
+            let (tx, rx) = channel(128);
+
+            let connection_id = m
+                .global_handle
+                .world
+                .borrow::<EntitiesViewMut>()
+                .add_entity((), ());
+            m.global_handle
+                .world
+                .borrow::<UniqueViewMut<ConnectionMapping>>()
+                .0
+                .insert(connection_id, tx);
+
+            for _ in 0..2 {
+                m.global_handle
+                    .world
+                    .borrow::<UniqueViewMut<HandOffList>>()
+                    .0
+                    .push(LocalWorldHandOff {
+                        connection_id,
+                        user_id: 1,
+                        visibility_range: 2000,
+                        world_name: "test".to_string(),
+                    });
+                m.process_hand_offs(
+                    &pool,
+                    &get_configuration(),
+                    &SystemMessageTable::default(),
+                    &Arc::new(GameData::default()),
+                );
+            }
+
+            // The local world is only spawned once.
+            assert_eq!(m.local_handles.len(), 1);
+            assert_eq!(m.local_handles["test"].id, 1);
+
+            // The hand offs are delivered through the outbound queue of the connection.
+            assert!(rx.is_empty());
+            m.global_handle.world.run(event_sender_system);
+
+            for _ in 0..2 {
+                let event = timeout(Duration::from_millis(100), rx.recv()).await?;
+                if let Some(Event::ResponseLocalWorldHandOff {
+                    local_world_id,
+                    user_id,
+                    visibility_range,
+                    ..
+                }) = event.as_deref()
+                {
+                    assert_eq!(*local_world_id, 1);
+                    assert_eq!(*user_id, 1);
+                    assert_eq!(*visibility_range, 2000);
+                } else {
+                    panic!("Couldn't find local world hand off event");
+                }
+            }
+
+            assert!(m
+                .global_handle
+                .world
+                .borrow::<UniqueView<HandOffList>>()
+                .0
+                .is_empty());
+
+            Ok(())
+        }
+        db_test(test)
+    }
 }
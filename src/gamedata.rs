@@ -0,0 +1,449 @@
+/// Module that holds the static game data of the client.
+///
+/// The game data is extracted from the datacenter and the data files (opcode tables, integrity
+/// lists and system messages) of the data path. Since parsing the datacenter is slow, the
+/// extracted data is stored in a binary cache file inside the data path. The cache contains the
+/// hash of all source files and is rebuilt automatically once one of them changes.
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{ensure, Context};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use crate::config::DataConfiguration;
+use crate::datacenter::{DataCenter, Element};
+use crate::dataloader::{
+    load_datacenter_key, load_integrity_list, load_opcode_mapping, load_system_messages,
+};
+use crate::protocol::opcode::Opcode;
+use crate::Result;
+
+/// Name of the cache file inside the data path.
+const CACHE_FILE_NAME: &str = "gamedata.bin";
+/// Magic bytes at the start of the cache file.
+const CACHE_MAGIC: &[u8; 4] = b"ALGD";
+/// Version of the cache format. Needs to be increased once the layout of the game data changes.
+const CACHE_FORMAT_VERSION: u32 = 1;
+
+/// The static game data.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct GameData {
+    pub items: HashMap<i32, ItemTemplate>,
+    /// Skills by their template ID and skill ID.
+    pub skills: HashMap<(i32, i32), SkillTemplate>,
+    /// NPCs by their hunting zone ID and template ID.
+    pub npcs: HashMap<(i32, i32), NpcTemplate>,
+    pub zones: HashMap<i32, ZoneTemplate>,
+    /// Names of the system messages in the order of the client.
+    pub system_messages: Vec<String>,
+    /// Opcode tables of the client versions by their name.
+    pub versions: HashMap<String, VersionTables>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ItemTemplate {
+    pub id: i32,
+    pub name: String,
+    pub category: String,
+    pub level: i32,
+    pub rare_grade: i32,
+    pub max_stack: i32,
+    pub required_level: i32,
+    pub sell_price: i32,
+    pub tradable: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SkillTemplate {
+    pub id: i32,
+    pub template_id: i32,
+    pub name: String,
+    pub skill_type: String,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct NpcTemplate {
+    pub id: i32,
+    pub hunting_zone_id: i32,
+    pub name: String,
+    pub size: String,
+    pub scale: f32,
+    pub elite: bool,
+    pub villager: bool,
+}
+
+/// A zone (continent) of the world.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ZoneTemplate {
+    pub id: i32,
+    pub hunting_zones: Vec<i32>,
+}
+
+/// The opcode mapping and the integrity list of a client version.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct VersionTables {
+    pub opcodes: HashMap<Opcode, u16>,
+    pub integrity: HashSet<Opcode>,
+}
+
+/// Header of the cache file.
+#[derive(Deserialize, Serialize)]
+struct CacheHeader {
+    format_version: u32,
+    source_hash: Vec<u8>,
+}
+
+impl GameData {
+    /// Loads the game data from the cache. Rebuilds the cache if it is missing or outdated.
+    pub fn load(config: &DataConfiguration) -> Result<GameData> {
+        let source_hash = hash_sources(config).context("Can't hash the game data sources")?;
+        let cache_path = config.path.join(CACHE_FILE_NAME);
+
+        if cache_path.exists() {
+            match read_cache(&cache_path, &source_hash) {
+                Ok(Some(game_data)) => return Ok(game_data),
+                Ok(None) => info!("Game data cache is outdated"),
+                Err(e) => warn!("Can't read the game data cache: {:?}", e),
+            }
+        }
+
+        info!("Building the game data cache");
+        let game_data = GameData::build(config)?;
+        write_cache(&cache_path, &source_hash, &game_data).context(format!(
+            "Can't write the game data cache {}",
+            cache_path.display()
+        ))?;
+        Ok(game_data)
+    }
+
+    /// Builds the game data from the datacenter and the data files.
+    pub fn build(config: &DataConfiguration) -> Result<GameData> {
+        let (key, iv) = load_datacenter_key(&config.path).context("Can't read key file")?;
+        let path = config.path.join(&config.datacenter.file);
+        let data =
+            fs::read(&path).context(format!("Can't read datacenter file {}", path.display()))?;
+        let datacenter = DataCenter::read(&key, &iv, data, config.datacenter.architecture)
+            .context("Can't parse datacenter file")?;
+
+        let mut game_data = GameData::from_datacenter(&datacenter)?;
+        game_data.system_messages =
+            load_system_messages(&config.path).context("Can't read system message file")?;
+
+        for version in config.versions.iter() {
+            let path = config.path.join(&version.name);
+            let (_, opcodes) = load_opcode_mapping(&path).context(format!(
+                "Can't read opcode mapping file of version {}",
+                version.name
+            ))?;
+            let integrity = load_integrity_list(&path).context(format!(
+                "Can't read integrity list file of version {}",
+                version.name
+            ))?;
+            game_data
+                .versions
+                .insert(version.name.clone(), VersionTables { opcodes, integrity });
+        }
+
+        Ok(game_data)
+    }
+
+    /// Extracts the items, skills, NPCs and zones of the datacenter.
+    pub fn from_datacenter(datacenter: &DataCenter) -> Result<GameData> {
+        let mut game_data = GameData::default();
+
+        for item in datacenter.query("ItemData/Item")? {
+            let template = ItemTemplate {
+                id: item.int("id")?,
+                name: string_or_default(&item, "name"),
+                category: string_or_default(&item, "category"),
+                level: int_or_default(&item, "level"),
+                rare_grade: int_or_default(&item, "rareGrade"),
+                max_stack: int_or_default(&item, "maxStack"),
+                required_level: int_or_default(&item, "requiredLevel"),
+                sell_price: int_or_default(&item, "sellPrice"),
+                tradable: bool_or_default(&item, "tradable"),
+            };
+            game_data.items.insert(template.id, template);
+        }
+
+        for skill in datacenter.query("SkillData/Skill")? {
+            let template = SkillTemplate {
+                id: skill.int("id")?,
+                template_id: skill.int("templateId")?,
+                name: string_or_default(&skill, "name"),
+                skill_type: string_or_default(&skill, "type"),
+            };
+            game_data
+                .skills
+                .insert((template.template_id, template.id), template);
+        }
+
+        for npc_data in datacenter.query("NpcData")? {
+            let hunting_zone_id = npc_data.int("huntingZoneId")?;
+            for npc in npc_data.query("Template")? {
+                let template = NpcTemplate {
+                    id: npc.int("id")?,
+                    hunting_zone_id,
+                    name: string_or_default(&npc, "name"),
+                    size: string_or_default(&npc, "size"),
+                    scale: npc
+                        .attribute("scale")
+                        .and_then(|v| v.as_float())
+                        .unwrap_or(1.0),
+                    elite: bool_or_default(&npc, "elite"),
+                    villager: bool_or_default(&npc, "villager"),
+                };
+                game_data
+                    .npcs
+                    .insert((hunting_zone_id, template.id), template);
+            }
+        }
+
+        for zone in datacenter.query("ContinentData/Continent")? {
+            let template = ZoneTemplate {
+                id: zone.int("id")?,
+                hunting_zones: zone
+                    .query("HuntingZone")?
+                    .iter()
+                    .map(|hunting_zone| hunting_zone.int("id"))
+                    .collect::<Result<Vec<i32>>>()?,
+            };
+            game_data.zones.insert(template.id, template);
+        }
+
+        Ok(game_data)
+    }
+}
+
+fn int_or_default(element: &Element, name: &str) -> i32 {
+    element
+        .attribute(name)
+        .and_then(|v| v.as_int())
+        .unwrap_or_default()
+}
+
+fn bool_or_default(element: &Element, name: &str) -> bool {
+    element
+        .attribute(name)
+        .and_then(|v| v.as_bool())
+        .unwrap_or_default()
+}
+
+fn string_or_default(element: &Element, name: &str) -> String {
+    element
+        .attribute(name)
+        .and_then(|v| v.as_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Hashes the content of all files the game data is build from.
+fn hash_sources(config: &DataConfiguration) -> Result<Vec<u8>> {
+    let mut files = vec![
+        config.path.join(&config.datacenter.file),
+        config.path.join("key.yaml"),
+        config.path.join("messages.yaml"),
+    ];
+    for version in config.versions.iter() {
+        files.push(config.path.join(&version.name).join("opcode.yaml"));
+        files.push(config.path.join(&version.name).join("integrity.yaml"));
+    }
+
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 1024 * 1024];
+    for path in files.iter() {
+        // The path is part of the hash, so that renaming a version invalidates the cache.
+        hasher.input(path.to_string_lossy().as_bytes());
+        let mut file = File::open(path).context(format!("Can't open {}", path.display()))?;
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.input(&buffer[..read]);
+        }
+    }
+    Ok(hasher.result().to_vec())
+}
+
+/// Reads the game data from the cache file. Returns `None` if the cache is outdated.
+fn read_cache(path: &Path, source_hash: &[u8]) -> Result<Option<GameData>> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    ensure!(&magic == CACHE_MAGIC, "File is not a game data cache");
+
+    let header: CacheHeader = bincode::deserialize_from(&mut reader)?;
+    if header.format_version != CACHE_FORMAT_VERSION || header.source_hash != source_hash {
+        return Ok(None);
+    }
+
+    let game_data = bincode::deserialize_from(&mut reader)?;
+    Ok(Some(game_data))
+}
+
+/// Writes the game data into the cache file. The file is replaced atomically, so that an
+/// interrupted write doesn't leave a broken cache behind.
+fn write_cache(path: &Path, source_hash: &[u8], game_data: &GameData) -> Result<()> {
+    let mut tmp_path = PathBuf::from(path);
+    tmp_path.set_extension("tmp");
+
+    {
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        writer.write_all(CACHE_MAGIC)?;
+        let header = CacheHeader {
+            format_version: CACHE_FORMAT_VERSION,
+            source_hash: source_hash.to_vec(),
+        };
+        bincode::serialize_into(&mut writer, &header)?;
+        bincode::serialize_into(&mut writer, game_data)?;
+        writer.flush()?;
+    }
+
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use rand::rngs::OsRng;
+    use rand_core::RngCore;
+
+    use crate::datacenter::builder::{element, TestValue, Writer};
+    use crate::datacenter::Architecture;
+
+    use super::*;
+
+    fn datacenter() -> Result<DataCenter> {
+        let root = element(
+            "__root__",
+            vec![],
+            vec![
+                element(
+                    "ItemData",
+                    vec![],
+                    vec![element(
+                        "Item",
+                        vec![
+                            ("id", TestValue::Int(100)),
+                            ("name", TestValue::String("sword_01")),
+                            ("category", TestValue::String("dual")),
+                            ("maxStack", TestValue::Int(1)),
+                            ("tradable", TestValue::Bool(true)),
+                        ],
+                        vec![],
+                    )],
+                ),
+                element(
+                    "SkillData",
+                    vec![],
+                    vec![element(
+                        "Skill",
+                        vec![
+                            ("id", TestValue::Int(10100)),
+                            ("templateId", TestValue::Int(11101)),
+                            ("type", TestValue::String("normal")),
+                        ],
+                        vec![],
+                    )],
+                ),
+                element(
+                    "NpcData",
+                    vec![("huntingZoneId", TestValue::Int(13))],
+                    vec![element(
+                        "Template",
+                        vec![
+                            ("id", TestValue::Int(1000)),
+                            ("scale", TestValue::Float(1.5)),
+                            ("villager", TestValue::Bool(true)),
+                        ],
+                        vec![],
+                    )],
+                ),
+                element(
+                    "ContinentData",
+                    vec![],
+                    vec![element(
+                        "Continent",
+                        vec![("id", TestValue::Int(13))],
+                        vec![
+                            element("HuntingZone", vec![("id", TestValue::Int(13))], vec![]),
+                            element("HuntingZone", vec![("id", TestValue::Int(14))], vec![]),
+                        ],
+                    )],
+                ),
+            ],
+        );
+        DataCenter::parse(&Writer::write(&root, Architecture::X64), Architecture::X64)
+    }
+
+    fn cache_path() -> PathBuf {
+        env::temp_dir().join(format!("almetica-gamedata-{}.bin", OsRng.next_u64()))
+    }
+
+    #[test]
+    fn test_from_datacenter() -> Result<()> {
+        let game_data = GameData::from_datacenter(&datacenter()?)?;
+
+        let item = &game_data.items[&100];
+        assert_eq!(item.name, "sword_01");
+        assert_eq!(item.category, "dual");
+        assert_eq!(item.max_stack, 1);
+        assert_eq!(item.level, 0);
+        assert!(item.tradable);
+
+        let skill = &game_data.skills[&(11101, 10100)];
+        assert_eq!(skill.skill_type, "normal");
+
+        let npc = &game_data.npcs[&(13, 1000)];
+        assert!(npc.villager);
+        assert!(!npc.elite);
+        assert!((npc.scale - 1.5).abs() < 0.001);
+
+        assert_eq!(game_data.zones[&13].hunting_zones, vec![13, 14]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cache_round_trip() -> Result<()> {
+        let mut game_data = GameData::from_datacenter(&datacenter()?)?;
+        game_data.system_messages = vec!["SMT_UNDEFINED".to_string()];
+        game_data.versions.insert(
+            "93.02".to_string(),
+            VersionTables {
+                opcodes: vec![(Opcode::C_CHECK_VERSION, 19900)].into_iter().collect(),
+                integrity: vec![Opcode::C_CHAT].into_iter().collect(),
+            },
+        );
+
+        let path = cache_path();
+        write_cache(&path, &[1, 2, 3], &game_data)?;
+
+        let cached = read_cache(&path, &[1, 2, 3]);
+        let outdated = read_cache(&path, &[1, 2, 4]);
+        fs::remove_file(&path)?;
+
+        assert_eq!(cached?, Some(game_data));
+        assert_eq!(outdated?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_cache() -> Result<()> {
+        let path = cache_path();
+        fs::write(&path, b"not a cache")?;
+        let result = read_cache(&path, &[1, 2, 3]);
+        fs::remove_file(&path)?;
+
+        assert!(result.is_err());
+        Ok(())
+    }
+}
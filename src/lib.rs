@@ -2,10 +2,14 @@
 #![recursion_limit = "256"]
 pub mod config;
 pub mod crypt;
+pub mod datacenter;
 pub mod dataloader;
 pub mod ecs;
+pub mod gamedata;
+pub mod metrics;
 pub mod model;
 pub mod networkserver;
+pub mod pcap;
 pub mod protocol;
 pub mod webserver;
 
@@ -0,0 +1,398 @@
+/// Module that collects runtime metrics of the multiverse.
+///
+/// The counters are shared between all worlds and are exposed by the web server, so that tools
+/// like `almetica-loadtest` can observe the server while it's under load. Every world also records
+/// the timings of it's ticks and systems.
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Counters that are updated by the worlds.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    dropped_events: AtomicU64,
+    coalesced_events: AtomicU64,
+    queued_events: AtomicU64,
+    slow_connection_drops: AtomicU64,
+    tick_overruns: AtomicU64,
+    ticks: AtomicU64,
+    worlds: Mutex<Vec<Arc<WorldMetrics>>>,
+}
+
+impl Metrics {
+    /// Creates a new set of counters.
+    pub fn new() -> Metrics {
+        Default::default()
+    }
+
+    /// Counts an event that was dropped because the connection doesn't keep up.
+    pub fn add_dropped_event(&self) {
+        self.dropped_events.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts an event that replaced an older event in the outbound queue of a connection.
+    pub fn add_coalesced_event(&self) {
+        self.coalesced_events.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Adds events to the number of events waiting in the outbound queues.
+    pub fn add_queued_events(&self, count: u64) {
+        self.queued_events.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Removes delivered or discarded events from the number of events waiting in the outbound
+    /// queues.
+    pub fn remove_queued_events(&self, count: u64) {
+        self.queued_events.fetch_sub(count, Ordering::Relaxed);
+    }
+
+    /// Counts a connection that was dropped because it didn't keep up with the world.
+    pub fn add_slow_connection_drop(&self) {
+        self.slow_connection_drops.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a finished tick of a world. Ticks that took longer than the tick rate are overruns.
+    pub fn add_tick(&self, overrun: bool) {
+        self.ticks.fetch_add(1, Ordering::Relaxed);
+        if overrun {
+            self.tick_overruns.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Registers a world with the names of the systems it runs every tick.
+    pub fn register_world(
+        &self,
+        id: u64,
+        name: &str,
+        systems: &[&'static str],
+    ) -> Arc<WorldMetrics> {
+        let world_metrics = Arc::new(WorldMetrics {
+            id,
+            name: name.to_string(),
+            tick: Histogram::default(),
+            systems: systems
+                .iter()
+                .map(|system| (*system, Histogram::default()))
+                .collect(),
+            tick_overruns: AtomicU64::new(0),
+            event_queue_depth: AtomicU64::new(0),
+            entity_counts: Mutex::new(BTreeMap::new()),
+        });
+        self.worlds.lock().unwrap().push(world_metrics.clone());
+        world_metrics
+    }
+
+    /// Returns the current values of the counters.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            dropped_events: self.dropped_events.load(Ordering::Relaxed),
+            coalesced_events: self.coalesced_events.load(Ordering::Relaxed),
+            queued_events: self.queued_events.load(Ordering::Relaxed),
+            slow_connection_drops: self.slow_connection_drops.load(Ordering::Relaxed),
+            tick_overruns: self.tick_overruns.load(Ordering::Relaxed),
+            ticks: self.ticks.load(Ordering::Relaxed),
+            worlds: self
+                .worlds
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|world| world.snapshot())
+                .collect(),
+        }
+    }
+}
+
+/// Timings and gauges of a single world.
+#[derive(Debug)]
+pub struct WorldMetrics {
+    id: u64,
+    name: String,
+    tick: Histogram,
+    systems: Vec<(&'static str, Histogram)>,
+    tick_overruns: AtomicU64,
+    event_queue_depth: AtomicU64,
+    entity_counts: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+impl WorldMetrics {
+    /// Records the duration of the system with the given index.
+    pub fn record_system(&self, index: usize, duration: Duration) {
+        self.systems[index].1.record(duration);
+    }
+
+    /// Records the duration of a tick. Ticks that took longer than the tick rate are overruns.
+    pub fn record_tick(&self, duration: Duration, overrun: bool) {
+        self.tick.record(duration);
+        if overrun {
+            self.tick_overruns.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Sets the number of events that are waiting in the input event channel of the world.
+    pub fn set_event_queue_depth(&self, depth: usize) {
+        self.event_queue_depth
+            .store(depth as u64, Ordering::Relaxed);
+    }
+
+    /// Sets the number of entities of the given kind.
+    pub fn set_entity_count(&self, kind: &'static str, count: usize) {
+        self.entity_counts
+            .lock()
+            .unwrap()
+            .insert(kind, count as u64);
+    }
+
+    /// Returns the current values of the timings and gauges.
+    pub fn snapshot(&self) -> WorldMetricsSnapshot {
+        WorldMetricsSnapshot {
+            id: self.id,
+            name: self.name.clone(),
+            ticks: self.tick.snapshot(),
+            tick_overruns: self.tick_overruns.load(Ordering::Relaxed),
+            event_queue_depth: self.event_queue_depth.load(Ordering::Relaxed),
+            entity_counts: self
+                .entity_counts
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(kind, count)| (kind.to_string(), *count))
+                .collect(),
+            systems: self
+                .systems
+                .iter()
+                .map(|(name, histogram)| SystemMetricsSnapshot {
+                    name: name.to_string(),
+                    durations: histogram.snapshot(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Upper bounds of the histogram buckets in microseconds. The last bucket holds all larger values.
+const BUCKET_BOUNDS: [u64; 12] = [
+    50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000,
+];
+
+/// Histogram of durations with fixed buckets.
+#[derive(Debug, Default)]
+pub struct Histogram {
+    buckets: [AtomicU64; BUCKET_BOUNDS.len() + 1],
+    count: AtomicU64,
+    sum: AtomicU64,
+    max: AtomicU64,
+}
+
+impl Histogram {
+    /// Records a duration.
+    pub fn record(&self, duration: Duration) {
+        let micros = duration.as_micros() as u64;
+        let index = BUCKET_BOUNDS
+            .iter()
+            .position(|bound| micros <= *bound)
+            .unwrap_or(BUCKET_BOUNDS.len());
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(micros, Ordering::Relaxed);
+        self.max.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    /// Returns the current values of the histogram.
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        HistogramSnapshot {
+            count: self.count.load(Ordering::Relaxed),
+            sum_us: self.sum.load(Ordering::Relaxed),
+            max_us: self.max.load(Ordering::Relaxed),
+            buckets: self
+                .buckets
+                .iter()
+                .enumerate()
+                .map(|(i, bucket)| Bucket {
+                    le_us: BUCKET_BOUNDS.get(i).copied(),
+                    count: bucket.load(Ordering::Relaxed),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The values of the counters at a point of time.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct MetricsSnapshot {
+    pub dropped_events: u64,
+    pub coalesced_events: u64,
+    pub queued_events: u64,
+    pub slow_connection_drops: u64,
+    pub tick_overruns: u64,
+    pub ticks: u64,
+    pub worlds: Vec<WorldMetricsSnapshot>,
+}
+
+impl MetricsSnapshot {
+    /// Returns the difference of the counters to an earlier snapshot. Gauges and the metrics of the
+    /// worlds keep their current value.
+    pub fn since(&self, earlier: &MetricsSnapshot) -> MetricsSnapshot {
+        MetricsSnapshot {
+            dropped_events: self.dropped_events.saturating_sub(earlier.dropped_events),
+            coalesced_events: self
+                .coalesced_events
+                .saturating_sub(earlier.coalesced_events),
+            queued_events: self.queued_events,
+            slow_connection_drops: self
+                .slow_connection_drops
+                .saturating_sub(earlier.slow_connection_drops),
+            tick_overruns: self.tick_overruns.saturating_sub(earlier.tick_overruns),
+            ticks: self.ticks.saturating_sub(earlier.ticks),
+            worlds: self.worlds.clone(),
+        }
+    }
+}
+
+/// The timings and gauges of a world at a point of time.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct WorldMetricsSnapshot {
+    pub id: u64,
+    pub name: String,
+    pub ticks: HistogramSnapshot,
+    pub tick_overruns: u64,
+    pub event_queue_depth: u64,
+    pub entity_counts: BTreeMap<String, u64>,
+    pub systems: Vec<SystemMetricsSnapshot>,
+}
+
+/// The durations of a system at a point of time.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct SystemMetricsSnapshot {
+    pub name: String,
+    pub durations: HistogramSnapshot,
+}
+
+/// The values of a histogram at a point of time. Durations are given in microseconds.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct HistogramSnapshot {
+    pub count: u64,
+    pub sum_us: u64,
+    pub max_us: u64,
+    pub buckets: Vec<Bucket>,
+}
+
+/// Number of values that are less or equal to the bound of the bucket and larger than the bound of
+/// the previous bucket. The last bucket has no bound.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Bucket {
+    pub le_us: Option<u64>,
+    pub count: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot() {
+        let metrics = Metrics::new();
+        metrics.add_dropped_event();
+        metrics.add_coalesced_event();
+        metrics.add_queued_events(3);
+        metrics.remove_queued_events(1);
+        metrics.add_slow_connection_drop();
+        metrics.add_tick(false);
+        metrics.add_tick(true);
+
+        assert_eq!(
+            metrics.snapshot(),
+            MetricsSnapshot {
+                dropped_events: 1,
+                coalesced_events: 1,
+                queued_events: 2,
+                slow_connection_drops: 1,
+                tick_overruns: 1,
+                ticks: 2,
+                worlds: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn test_snapshot_since() {
+        let metrics = Metrics::new();
+        metrics.add_tick(true);
+        metrics.add_queued_events(2);
+        let earlier = metrics.snapshot();
+        metrics.add_dropped_event();
+        metrics.add_tick(false);
+
+        assert_eq!(
+            metrics.snapshot().since(&earlier),
+            MetricsSnapshot {
+                dropped_events: 1,
+                queued_events: 2,
+                ticks: 1,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_histogram() {
+        let histogram = Histogram::default();
+        histogram.record(Duration::from_micros(40));
+        histogram.record(Duration::from_micros(50));
+        histogram.record(Duration::from_millis(3));
+        histogram.record(Duration::from_secs(1));
+
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.count, 4);
+        assert_eq!(snapshot.sum_us, 1_003_090);
+        assert_eq!(snapshot.max_us, 1_000_000);
+        assert_eq!(snapshot.buckets.len(), 13);
+        assert_eq!(
+            snapshot.buckets[0],
+            Bucket {
+                le_us: Some(50),
+                count: 2
+            }
+        );
+        assert_eq!(
+            snapshot.buckets[6],
+            Bucket {
+                le_us: Some(5_000),
+                count: 1
+            }
+        );
+        assert_eq!(
+            snapshot.buckets[12],
+            Bucket {
+                le_us: None,
+                count: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_world_metrics() {
+        let metrics = Metrics::new();
+        let world_metrics = metrics.register_world(1, "test", &["first", "second"]);
+        world_metrics.record_system(1, Duration::from_millis(1));
+        world_metrics.record_tick(Duration::from_millis(60), true);
+        world_metrics.set_event_queue_depth(12);
+        world_metrics.set_entity_count("connections", 3);
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.worlds.len(), 1);
+        let world = &snapshot.worlds[0];
+        assert_eq!(world.id, 1);
+        assert_eq!(world.name, "test");
+        assert_eq!(world.ticks.count, 1);
+        assert_eq!(world.tick_overruns, 1);
+        assert_eq!(world.event_queue_depth, 12);
+        assert_eq!(world.entity_counts["connections"], 3);
+        assert_eq!(world.systems[0].name, "first");
+        assert_eq!(world.systems[0].durations.count, 0);
+        assert_eq!(world.systems[1].name, "second");
+        assert_eq!(world.systems[1].durations.count, 1);
+    }
+}
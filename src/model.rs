@@ -9,10 +9,13 @@ pub mod embedded {
 use std::fmt;
 
 use byteorder::{ByteOrder, LittleEndian};
+#[cfg(test)]
+use proptest_derive::Arbitrary;
 use serde::de::{self, Visitor};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 #[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(test, derive(Arbitrary))]
 pub enum Region {
     International = 0,
     Korea = 1,
@@ -26,6 +29,7 @@ pub enum Region {
 }
 
 #[derive(Clone, Copy, Debug, Deserialize, Serialize, sqlx::Type, PartialEq)]
+#[cfg_attr(test, derive(Arbitrary))]
 #[sqlx(rename = "gender")]
 pub enum Gender {
     #[sqlx(rename = "male")]
@@ -35,6 +39,7 @@ pub enum Gender {
 }
 
 #[derive(Clone, Copy, Debug, Deserialize, Serialize, sqlx::Type, PartialEq)]
+#[cfg_attr(test, derive(Arbitrary))]
 #[sqlx(rename = "race")]
 pub enum Race {
     #[sqlx(rename = "human")]
@@ -52,6 +57,7 @@ pub enum Race {
 }
 
 #[derive(Clone, Copy, Debug, Deserialize, Serialize, sqlx::Type, PartialEq)]
+#[cfg_attr(test, derive(Arbitrary))]
 #[sqlx(rename = "user_class")]
 pub enum Class {
     #[sqlx(rename = "warrior")]
@@ -82,9 +88,50 @@ pub enum Class {
     Valkyrie = 12,
 }
 
+/// Chat channels a user can send messages to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ChatChannel {
+    Say,
+    Party,
+    Guild,
+    Area,
+    Trade,
+    Global,
+    Whisper,
+}
+
+impl ChatChannel {
+    /// Returns the chat channel of the given channel ID used inside the chat packets.
+    pub fn from_id(id: u32) -> Option<ChatChannel> {
+        match id {
+            0 => Some(ChatChannel::Say),
+            1 => Some(ChatChannel::Party),
+            2 => Some(ChatChannel::Guild),
+            3 => Some(ChatChannel::Area),
+            4 => Some(ChatChannel::Trade),
+            27 => Some(ChatChannel::Global),
+            _ => None,
+        }
+    }
+
+    /// Returns the channel ID used inside the chat packets. Whispers use their own packets.
+    pub fn id(self) -> Option<u32> {
+        match self {
+            ChatChannel::Say => Some(0),
+            ChatChannel::Party => Some(1),
+            ChatChannel::Guild => Some(2),
+            ChatChannel::Area => Some(3),
+            ChatChannel::Trade => Some(4),
+            ChatChannel::Global => Some(27),
+            ChatChannel::Whisper => None,
+        }
+    }
+}
+
 pub type Angle = i16;
 
 #[derive(Clone, Copy, Debug, Deserialize, Serialize, sqlx::Type, PartialEq)]
+#[cfg_attr(test, derive(Arbitrary))]
 pub struct Vec3 {
     pub x: f32,
     pub y: f32,
@@ -92,17 +139,37 @@ pub struct Vec3 {
 }
 
 #[derive(Clone, Copy, Debug, Deserialize, Serialize, sqlx::Type, PartialEq)]
+#[cfg_attr(test, derive(Arbitrary))]
 pub struct Vec3a {
     pub x: i32,
     pub y: i32,
     pub z: i32,
 }
 
-// type skill_id = [u8; 4]; // Patch < 74
-// type skill_id = [u8; 8]; // Path >= 74
+impl Vec3 {
+    /// Calculates the euclidean distance to the given point.
+    pub fn distance(&self, other: &Vec3) -> f32 {
+        let x = self.x - other.x;
+        let y = self.y - other.y;
+        let z = self.z - other.z;
+        (x * x + y * y + z * z).sqrt()
+    }
+}
+
+/// Id of a skill. It's written with 4 bytes before patch 74 and with 8 bytes since then, which is
+/// defined by the packet layout of the client version.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[serde(rename = "SkillId")]
+pub struct SkillId(pub u64);
 
 #[derive(Clone, Debug, sqlx::Type, PartialEq)]
+#[cfg_attr(test, derive(Arbitrary))]
 pub struct Customization {
+    #[cfg_attr(
+        test,
+        proptest(strategy = "proptest::collection::vec(proptest::arbitrary::any::<u8>(), 8)")
+    )]
     pub data: Vec<u8>,
 }
 
@@ -273,6 +340,32 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_vec3_distance() {
+        let a = Vec3 {
+            x: 1.0,
+            y: 2.0,
+            z: 3.0,
+        };
+        let b = Vec3 {
+            x: 4.0,
+            y: 6.0,
+            z: 3.0,
+        };
+        assert!((a.distance(&b) - 5.0).abs() < std::f32::EPSILON);
+        assert!((b.distance(&a) - 5.0).abs() < std::f32::EPSILON);
+        assert!(a.distance(&a).abs() < std::f32::EPSILON);
+    }
+
+    #[test]
+    fn test_chat_channel_id() {
+        assert_eq!(ChatChannel::from_id(0), Some(ChatChannel::Say));
+        assert_eq!(ChatChannel::from_id(27), Some(ChatChannel::Global));
+        assert_eq!(ChatChannel::from_id(5), None);
+        assert_eq!(ChatChannel::Area.id(), Some(3));
+        assert_eq!(ChatChannel::Whisper.id(), None);
+    }
+
     #[test]
     fn test_customization_serialization() -> Result<()> {
         let value = Customization {
@@ -8,6 +8,7 @@
 ///    * ```Customization```
 ///    * Custom types / ```enum``` based on the above.
 ///    * DateTime<Utc>
+///    * ```Option``` of the above (nullable columns)
 use chrono::{DateTime, Utc};
 
 use crate::model::*;
@@ -34,6 +35,7 @@ pub struct LoginTicket {
     pub created_at: DateTime<Utc>,
 }
 
+/// User is a player character of an account.
 #[derive(Debug, sqlx::FromRow)]
 #[sqlx(rename = "account_user")]
 #[sqlx(rename_all = "lowercase")]
@@ -50,5 +52,7 @@ pub struct User {
     pub appearance: Customization,
     pub appearance2: i32,
     pub playtime: i64, // Playtime in seconds.
+    // Set if the user is marked for deletion.
+    pub deletion_time: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
 }
@@ -0,0 +1,2 @@
+ALTER TABLE account_user
+    ALTER COLUMN appearance2 TYPE INTEGER USING 0;
@@ -0,0 +1,2 @@
+ALTER TABLE account_user
+    ADD COLUMN deletion_time TIMESTAMP WITH TIME ZONE DEFAULT NULL;
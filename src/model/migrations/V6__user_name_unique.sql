@@ -0,0 +1,2 @@
+CREATE UNIQUE INDEX account_user_lower_name_idx
+    ON account_user (lower(name));
@@ -24,8 +24,13 @@ pub async fn upsert_ticket(conn: &mut PgConnection, account_id: i64) -> Result<L
     .await?)
 }
 
-/// Tests if the given ticket is valid. A ticket can only be used one time. Should be called in a transaction.
-pub async fn is_ticket_valid(conn: &mut PgConnection, name: &str, ticket: &str) -> Result<bool> {
+/// Validates the given ticket and returns the ID of the account it belongs to if it's valid.
+/// A ticket can only be used one time. Should be called in a transaction.
+pub async fn validate_ticket(
+    conn: &mut PgConnection,
+    name: &str,
+    ticket: &str,
+) -> Result<Option<i64>> {
     // We have to manually re-borrow the transaction. &mut *conn will take a &mut PgConnection and
     // produce a &mut PgConnection that is held for the lifetime required by the function.
     // This is normally done implicitly by Rust. It's not in this case due to fetch_*() being
@@ -48,7 +53,7 @@ pub async fn is_ticket_valid(conn: &mut PgConnection, name: &str, ticket: &str)
     .await?
     {
         Some((id,)) => id,
-        None => return Ok(false),
+        None => return Ok(None),
     };
 
     sqlx::query("UPDATE login_ticket SET used = 'true' WHERE account_id = $1")
@@ -56,7 +61,7 @@ pub async fn is_ticket_valid(conn: &mut PgConnection, name: &str, ticket: &str)
         .execute(&mut *conn)
         .await?;
 
-    Ok(true)
+    Ok(Some(account_id))
 }
 
 #[cfg(test)]
@@ -124,9 +129,14 @@ pub mod tests {
 
             let ticket = upsert_ticket(&mut conn, account.id).await?;
             assert!(!ticket.ticket.is_empty());
-            assert!(is_ticket_valid(&mut conn, &account.name, &ticket.ticket).await?);
+            assert_eq!(
+                validate_ticket(&mut conn, &account.name, &ticket.ticket).await?,
+                Some(account.id)
+            );
             // Ticket can only be used one time
-            assert!(!is_ticket_valid(&mut conn, &account.name, &ticket.ticket).await?);
+            assert!(validate_ticket(&mut conn, &account.name, &ticket.ticket)
+                .await?
+                .is_none());
 
             Ok(())
         }
@@ -153,7 +163,11 @@ pub mod tests {
             .await?;
 
             upsert_ticket(&mut conn, account.id).await?;
-            assert!(!is_ticket_valid(&mut conn, &account.name, "not a valid ticket").await?);
+            assert!(
+                validate_ticket(&mut conn, &account.name, "not a valid ticket")
+                    .await?
+                    .is_none()
+            );
 
             Ok(())
         }
@@ -180,7 +194,9 @@ pub mod tests {
             .await?;
 
             let ticket = upsert_ticket(&mut conn, account.id).await?;
-            assert!(!is_ticket_valid(&mut conn, &"not-a-user", &ticket.ticket).await?);
+            assert!(validate_ticket(&mut conn, &"not-a-user", &ticket.ticket)
+                .await?
+                .is_none());
 
             Ok(())
         }
@@ -0,0 +1,409 @@
+/// Handles the users of an account.
+use chrono::{DateTime, Utc};
+use sqlx::prelude::*;
+use sqlx::PgConnection;
+
+use crate::model::entity::User;
+use crate::Result;
+
+/// Creates a new user.
+pub async fn create(conn: &mut PgConnection, user: &User) -> Result<User> {
+    Ok(sqlx::query_as::<_, User>(
+        r#"INSERT INTO account_user
+        (account_id, name, gender, race, user_class, shape, details, appearance, appearance2, playtime)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        RETURNING *"#,
+    )
+    .bind(user.account_id)
+    .bind(&user.name)
+    .bind(&user.gender)
+    .bind(&user.race)
+    .bind(&user.class)
+    .bind(&user.shape)
+    .bind(&user.details)
+    .bind(&user.appearance)
+    .bind(user.appearance2)
+    .bind(user.playtime)
+    .fetch_one(conn)
+    .await?)
+}
+
+/// Lists all users of an account ordered by their creation. Users with an expired deletion time are omitted.
+pub async fn list(conn: &mut PgConnection, account_id: i64) -> Result<Vec<User>> {
+    Ok(sqlx::query_as::<_, User>(
+        r#"SELECT * FROM account_user
+        WHERE account_id = $1
+        AND (deletion_time IS NULL OR deletion_time > CURRENT_TIMESTAMP)
+        ORDER BY created_at, id"#,
+    )
+    .bind(account_id)
+    .fetch_all(conn)
+    .await?)
+}
+
+/// Finds a user by id.
+pub async fn get_by_id(conn: &mut PgConnection, id: i32) -> Result<User> {
+    Ok(
+        sqlx::query_as::<_, User>("SELECT * FROM account_user WHERE id = $1")
+            .bind(id)
+            .fetch_one(conn)
+            .await?,
+    )
+}
+
+/// Returns the number of users of an account.
+pub async fn get_user_count(conn: &mut PgConnection, account_id: i64) -> Result<i64> {
+    let (count,): (i64,) =
+        sqlx::query_as("SELECT COUNT(*) FROM account_user WHERE account_id = $1")
+            .bind(account_id)
+            .fetch_one(conn)
+            .await?;
+    Ok(count)
+}
+
+/// Tests if the user name is already taken. User names are compared case insensitive.
+pub async fn is_user_name_taken(conn: &mut PgConnection, name: &str) -> Result<bool> {
+    let (taken,): (bool,) =
+        sqlx::query_as("SELECT EXISTS(SELECT 1 FROM account_user WHERE lower(name) = lower($1))")
+            .bind(name)
+            .fetch_one(conn)
+            .await?;
+    Ok(taken)
+}
+
+/// Renames a user.
+pub async fn rename(conn: &mut PgConnection, id: i32, name: &str) -> Result<()> {
+    sqlx::query("UPDATE account_user SET name = $1 WHERE id = $2")
+        .bind(name)
+        .bind(id)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Marks a user for deletion. The user will be deleted once the deletion time has passed.
+pub async fn mark_for_deletion(
+    conn: &mut PgConnection,
+    id: i32,
+    deletion_time: DateTime<Utc>,
+) -> Result<()> {
+    sqlx::query("UPDATE account_user SET deletion_time = $1 WHERE id = $2")
+        .bind(deletion_time)
+        .bind(id)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Cancels the deletion of a user.
+pub async fn cancel_deletion(conn: &mut PgConnection, id: i32) -> Result<()> {
+    sqlx::query("UPDATE account_user SET deletion_time = NULL WHERE id = $1")
+        .bind(id)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Deletes all users which deletion time has passed. Returns the number of deleted users.
+pub async fn delete_expired(conn: &mut PgConnection) -> Result<u64> {
+    Ok(
+        sqlx::query("DELETE FROM account_user WHERE deletion_time <= CURRENT_TIMESTAMP")
+            .execute(conn)
+            .await?,
+    )
+}
+
+/// Deletes a user with the given id.
+pub async fn delete_by_id(conn: &mut PgConnection, id: i32) -> Result<()> {
+    sqlx::query("DELETE FROM account_user WHERE id = $1")
+        .bind(id)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use chrono::prelude::*;
+    use chrono::Duration;
+    use sqlx::PgPool;
+
+    use crate::model::entity::{Account, User};
+    use crate::model::repository::account;
+    use crate::model::tests::db_test;
+    use crate::model::{Class, Customization, Gender, PasswordHashAlgorithm, Race};
+    use crate::Result;
+
+    use super::*;
+
+    async fn create_account(conn: &mut PgConnection) -> Result<Account> {
+        Ok(account::create(
+            conn,
+            &Account {
+                id: -1,
+                name: "testaccount".to_string(),
+                password: "not-a-real-password-hash".to_string(),
+                algorithm: PasswordHashAlgorithm::Argon2,
+                created_at: Utc.ymd(1995, 7, 8).and_hms(9, 10, 11),
+                updated_at: Utc.ymd(1995, 7, 8).and_hms(9, 10, 11),
+            },
+        )
+        .await?)
+    }
+
+    fn get_user(account_id: i64, name: &str) -> User {
+        User {
+            id: -1,
+            account_id,
+            name: name.to_string(),
+            gender: Gender::Female,
+            race: Race::Castanic,
+            class: Class::Warrior,
+            shape: vec![1u8, 2u8, 3u8],
+            details: vec![4u8, 5u8, 6u8],
+            appearance: Customization {
+                data: vec![7u8, 8u8, 9u8, 10u8, 11u8, 12u8, 13u8, 14u8],
+            },
+            appearance2: 104,
+            playtime: 0,
+            deletion_time: None,
+            created_at: Utc.ymd(1995, 7, 8).and_hms(9, 10, 11),
+        }
+    }
+
+    #[test]
+    fn test_create_user() -> Result<()> {
+        // FIXME into an async closure once stable
+        async fn test(pool: PgPool) -> Result<()> {
+            let mut conn = pool.acquire().await.unwrap();
+            let account = create_account(&mut conn).await?;
+
+            let org_user = get_user(account.id, "testuser");
+            let db_user = create(&mut conn, &org_user).await?;
+
+            assert_ne!(org_user.id, db_user.id);
+            assert_eq!(org_user.account_id, db_user.account_id);
+            assert_eq!(org_user.name, db_user.name);
+            assert_eq!(org_user.gender, db_user.gender);
+            assert_eq!(org_user.race, db_user.race);
+            assert_eq!(org_user.class, db_user.class);
+            assert_eq!(org_user.shape, db_user.shape);
+            assert_eq!(org_user.details, db_user.details);
+            assert_eq!(org_user.appearance, db_user.appearance);
+            assert_eq!(org_user.appearance2, db_user.appearance2);
+            assert_eq!(org_user.playtime, db_user.playtime);
+            assert_eq!(org_user.deletion_time, db_user.deletion_time);
+            assert_ne!(org_user.created_at, db_user.created_at);
+            Ok(())
+        }
+        db_test(test)
+    }
+
+    #[test]
+    fn test_create_user_with_taken_name() -> Result<()> {
+        // FIXME into an async closure once stable
+        async fn test(pool: PgPool) -> Result<()> {
+            let mut conn = pool.acquire().await.unwrap();
+            let account = create_account(&mut conn).await?;
+
+            create(&mut conn, &get_user(account.id, "testuser")).await?;
+            assert!(create(&mut conn, &get_user(account.id, "testuser"))
+                .await
+                .is_err());
+            Ok(())
+        }
+        db_test(test)
+    }
+
+    #[test]
+    fn test_list() -> Result<()> {
+        // FIXME into an async closure once stable
+        async fn test(pool: PgPool) -> Result<()> {
+            let mut conn = pool.acquire().await.unwrap();
+            let account = create_account(&mut conn).await?;
+
+            for i in 1..=3i32 {
+                create(&mut conn, &get_user(account.id, &format!("testuser{}", i))).await?;
+            }
+
+            let users = list(&mut conn, account.id).await?;
+            assert_eq!(users.len(), 3);
+            assert_eq!(users[0].name, "testuser1");
+            assert_eq!(users[1].name, "testuser2");
+            assert_eq!(users[2].name, "testuser3");
+
+            let users = list(&mut conn, account.id + 1).await?;
+            assert!(users.is_empty());
+            Ok(())
+        }
+        db_test(test)
+    }
+
+    #[test]
+    fn test_get_by_id() -> Result<()> {
+        // FIXME into an async closure once stable
+        async fn test(pool: PgPool) -> Result<()> {
+            let mut conn = pool.acquire().await.unwrap();
+            let account = create_account(&mut conn).await?;
+
+            for i in 1..=10i32 {
+                create(&mut conn, &get_user(account.id, &format!("testuser{}", i))).await?;
+            }
+
+            let db_user = get_by_id(&mut conn, 5).await?;
+            assert_eq!(db_user.id, 5);
+            assert_eq!(db_user.name, "testuser5");
+            Ok(())
+        }
+        db_test(test)
+    }
+
+    #[test]
+    fn test_get_user_count() -> Result<()> {
+        // FIXME into an async closure once stable
+        async fn test(pool: PgPool) -> Result<()> {
+            let mut conn = pool.acquire().await.unwrap();
+            let account = create_account(&mut conn).await?;
+
+            assert_eq!(get_user_count(&mut conn, account.id).await?, 0);
+
+            for i in 1..=4i32 {
+                create(&mut conn, &get_user(account.id, &format!("testuser{}", i))).await?;
+            }
+
+            assert_eq!(get_user_count(&mut conn, account.id).await?, 4);
+            Ok(())
+        }
+        db_test(test)
+    }
+
+    #[test]
+    fn test_is_user_name_taken() -> Result<()> {
+        // FIXME into an async closure once stable
+        async fn test(pool: PgPool) -> Result<()> {
+            let mut conn = pool.acquire().await.unwrap();
+            let account = create_account(&mut conn).await?;
+
+            create(&mut conn, &get_user(account.id, "testuser")).await?;
+
+            assert!(is_user_name_taken(&mut conn, "testuser").await?);
+            assert!(is_user_name_taken(&mut conn, "TestUser").await?);
+            assert!(!is_user_name_taken(&mut conn, "otheruser").await?);
+            Ok(())
+        }
+        db_test(test)
+    }
+
+    #[test]
+    fn test_create_user_name_case_insensitive_unique() -> Result<()> {
+        // FIXME into an async closure once stable
+        async fn test(pool: PgPool) -> Result<()> {
+            let mut conn = pool.acquire().await.unwrap();
+            let account = create_account(&mut conn).await?;
+
+            create(&mut conn, &get_user(account.id, "testuser")).await?;
+            assert!(create(&mut conn, &get_user(account.id, "TestUser"))
+                .await
+                .is_err());
+            Ok(())
+        }
+        db_test(test)
+    }
+
+    #[test]
+    fn test_rename() -> Result<()> {
+        // FIXME into an async closure once stable
+        async fn test(pool: PgPool) -> Result<()> {
+            let mut conn = pool.acquire().await.unwrap();
+            let account = create_account(&mut conn).await?;
+
+            let db_user = create(&mut conn, &get_user(account.id, "testuser")).await?;
+            rename(&mut conn, db_user.id, "renameduser").await?;
+
+            let renamed_user = get_by_id(&mut conn, db_user.id).await?;
+            assert_eq!(renamed_user.name, "renameduser");
+            Ok(())
+        }
+        db_test(test)
+    }
+
+    #[test]
+    fn test_mark_and_cancel_deletion() -> Result<()> {
+        // FIXME into an async closure once stable
+        async fn test(pool: PgPool) -> Result<()> {
+            let mut conn = pool.acquire().await.unwrap();
+            let account = create_account(&mut conn).await?;
+
+            let db_user = create(&mut conn, &get_user(account.id, "testuser")).await?;
+            let deletion_time = Utc::now() + Duration::hours(1);
+
+            mark_for_deletion(&mut conn, db_user.id, deletion_time).await?;
+            let marked_user = get_by_id(&mut conn, db_user.id).await?;
+            assert!(marked_user.deletion_time.is_some());
+
+            // Users inside the grace period are still listed.
+            assert_eq!(list(&mut conn, account.id).await?.len(), 1);
+
+            cancel_deletion(&mut conn, db_user.id).await?;
+            let restored_user = get_by_id(&mut conn, db_user.id).await?;
+            assert!(restored_user.deletion_time.is_none());
+            Ok(())
+        }
+        db_test(test)
+    }
+
+    #[test]
+    fn test_delete_expired() -> Result<()> {
+        // FIXME into an async closure once stable
+        async fn test(pool: PgPool) -> Result<()> {
+            let mut conn = pool.acquire().await.unwrap();
+            let account = create_account(&mut conn).await?;
+
+            let expired_user = create(&mut conn, &get_user(account.id, "expired")).await?;
+            let pending_user = create(&mut conn, &get_user(account.id, "pending")).await?;
+            create(&mut conn, &get_user(account.id, "normal")).await?;
+
+            mark_for_deletion(
+                &mut conn,
+                expired_user.id,
+                Utc::now() - Duration::seconds(1),
+            )
+            .await?;
+            mark_for_deletion(&mut conn, pending_user.id, Utc::now() + Duration::hours(1)).await?;
+
+            // Expired users are not listed anymore, even if they were not purged yet.
+            assert_eq!(list(&mut conn, account.id).await?.len(), 2);
+
+            assert_eq!(delete_expired(&mut conn).await?, 1);
+            assert_eq!(get_user_count(&mut conn, account.id).await?, 2);
+            Ok(())
+        }
+        db_test(test)
+    }
+
+    #[test]
+    fn test_delete_by_id() -> Result<()> {
+        // FIXME into an async closure once stable
+        async fn test(pool: PgPool) -> Result<()> {
+            let mut conn = pool.acquire().await.unwrap();
+            let account = create_account(&mut conn).await?;
+
+            for i in 1..=10i32 {
+                create(&mut conn, &get_user(account.id, &format!("testuser{}", i))).await?;
+            }
+
+            delete_by_id(&mut conn, 5).await?;
+            if let Err(e) = get_by_id(&mut conn, 5).await {
+                match e.downcast_ref::<sqlx::Error>() {
+                    Some(sqlx::Error::RowNotFound) => Ok(()),
+                    Some(..) => Err(e),
+                    None => Err(e),
+                }?
+            } else {
+                panic!("record was not deleted");
+            }
+            Ok(())
+        }
+        db_test(test)
+    }
+}
@@ -1,5 +1,4 @@
 /// The module of the network server that handles the TCP connections to the clients.
-use std::collections::HashMap;
 use std::sync::Arc;
 
 use async_std::net::TcpListener;
@@ -10,42 +9,32 @@ use tracing_futures::Instrument;
 
 use crate::config::Configuration;
 use crate::ecs::event::EcsEvent;
-use crate::protocol::opcode::Opcode;
+use crate::protocol::version::ProtocolRegistry;
 use crate::protocol::GameSession;
 use crate::Result;
 
 /// Main loop for the network server
 pub async fn run(
     global_channel: Sender<EcsEvent>,
-    map: Vec<Opcode>,
-    reverse_map: HashMap<Opcode, u16>,
+    registry: ProtocolRegistry,
     config: Configuration,
 ) -> Result<()> {
     let listen_string = format!("{}:{}", config.server.hostname, config.server.game_port);
     info!("listening on tcp://{}", listen_string);
     let listener = TcpListener::bind(listen_string).await?;
 
-    let arc_map = Arc::new(map);
-    let arc_reverse_map = Arc::new(reverse_map);
+    let arc_registry = Arc::new(registry);
 
     loop {
         match listener.accept().await {
             Ok((mut socket, addr)) => {
                 let thread_channel = global_channel.clone();
-                let thread_opcode_map = arc_map.clone();
-                let thread_reverse_map = arc_reverse_map.clone();
+                let thread_registry = arc_registry.clone();
 
                 task::spawn(
                     async move {
                         info!("Incoming connection");
-                        match GameSession::new(
-                            &mut socket,
-                            thread_channel,
-                            thread_opcode_map,
-                            thread_reverse_map,
-                        )
-                        .await
-                        {
+                        match GameSession::new(&mut socket, thread_channel, thread_registry).await {
                             Ok(mut session) => {
                                 let connection_id = session.connection_id;
                                 match session
@@ -0,0 +1,736 @@
+/// Module that reads the TCP connections out of pcap and pcapng capture files.
+///
+/// Every frame of the capture is decoded down to it's TCP segment. The segments of all
+/// connections with the given server port are reassembled into continuous streams, so that
+/// retransmitted, overlapping and out of order segments are handled. IP fragmentation and IPv6
+/// extension headers are not supported.
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use anyhow::{bail, ensure, Context};
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use tracing::warn;
+
+use crate::Result;
+
+const PCAP_MAGIC_MICROSECONDS: u32 = 0xa1b2_c3d4;
+const PCAP_MAGIC_NANOSECONDS: u32 = 0xa1b2_3c4d;
+const PCAP_HEADER_LENGTH: usize = 24;
+const PCAP_RECORD_HEADER_LENGTH: usize = 16;
+
+const PCAPNG_SECTION_HEADER_BLOCK: u32 = 0x0a0d_0d0a;
+const PCAPNG_INTERFACE_DESCRIPTION_BLOCK: u32 = 0x1;
+const PCAPNG_SIMPLE_PACKET_BLOCK: u32 = 0x3;
+const PCAPNG_ENHANCED_PACKET_BLOCK: u32 = 0x6;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LOOP: u32 = 108;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+
+const IP_PROTOCOL_TCP: u8 = 6;
+const TCP_FLAG_SYN: u8 = 0x2;
+
+/// Number of out of order segments a stream buffers before it skips a missing segment.
+const MAX_PENDING_SEGMENTS: usize = 256;
+
+/// A link layer frame of the capture.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frame<'a> {
+    pub link_type: u32,
+    pub data: &'a [u8],
+}
+
+/// Data that was send in one direction of a TCP connection.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StreamData {
+    pub from_server: bool,
+    /// Number of bytes before the payload that are missing from the capture.
+    pub missing: usize,
+    pub payload: Vec<u8>,
+}
+
+/// A reassembled TCP connection between a client and the server.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Conversation {
+    pub client: SocketAddr,
+    pub data: Vec<StreamData>,
+}
+
+/// Returns true if the data starts with the magic bytes of a pcap or pcapng file.
+pub fn is_capture(data: &[u8]) -> bool {
+    if data.len() < 4 {
+        return false;
+    }
+    let magic = LittleEndian::read_u32(data);
+    [
+        PCAP_MAGIC_MICROSECONDS,
+        PCAP_MAGIC_NANOSECONDS,
+        PCAP_MAGIC_MICROSECONDS.swap_bytes(),
+        PCAP_MAGIC_NANOSECONDS.swap_bytes(),
+        PCAPNG_SECTION_HEADER_BLOCK,
+    ]
+    .contains(&magic)
+}
+
+/// Reads all TCP connections to the given server port in the order they were opened.
+pub fn read_conversations(data: &[u8], server_port: u16) -> Result<Vec<Conversation>> {
+    let mut conversations: Vec<Conversation> = Vec::new();
+    let mut streams: Vec<(SocketAddr, usize, [Stream; 2])> = Vec::new();
+
+    for frame in read_frames(data)? {
+        let segment = match ip_packet(&frame).and_then(tcp_segment) {
+            Some(segment) => segment,
+            None => continue,
+        };
+        let (client, from_server) = if segment.source.port() == server_port {
+            (segment.destination, true)
+        } else if segment.destination.port() == server_port {
+            (segment.source, false)
+        } else {
+            continue;
+        };
+
+        // A client that connects again with the same port starts a new conversation.
+        let is_connect = !from_server && segment.flags & TCP_FLAG_SYN != 0;
+        let index = match streams.iter().position(|(c, _, _)| *c == client) {
+            Some(index) if !is_connect => index,
+            index => {
+                if let Some(index) = index {
+                    let (_, conversation, stream) = streams.remove(index);
+                    finish_streams(&mut conversations[conversation], stream);
+                }
+                conversations.push(Conversation {
+                    client,
+                    data: Vec::new(),
+                });
+                streams.push((client, conversations.len() - 1, Default::default()));
+                streams.len() - 1
+            }
+        };
+
+        let (_, conversation, stream) = &mut streams[index];
+        for (missing, payload) in stream[from_server as usize].push(&segment) {
+            conversations[*conversation].data.push(StreamData {
+                from_server,
+                missing,
+                payload,
+            });
+        }
+    }
+
+    // Data after a segment that is missing at the end of the capture is still returned.
+    for (_, conversation, stream) in streams {
+        finish_streams(&mut conversations[conversation], stream);
+    }
+
+    conversations.retain(|c| !c.data.is_empty());
+    Ok(conversations)
+}
+
+/// Adds the data that is still pending in the streams of a conversation.
+fn finish_streams(conversation: &mut Conversation, streams: [Stream; 2]) {
+    for (from_server, mut stream) in streams.iter().cloned().enumerate() {
+        for (missing, payload) in stream.finish() {
+            conversation.data.push(StreamData {
+                from_server: from_server == 1,
+                missing,
+                payload,
+            });
+        }
+    }
+}
+
+/// Reads all frames of a pcap or pcapng file.
+pub fn read_frames(data: &[u8]) -> Result<Vec<Frame<'_>>> {
+    ensure!(data.len() >= 4, "Capture file is too short");
+    if LittleEndian::read_u32(data) == PCAPNG_SECTION_HEADER_BLOCK {
+        read_pcapng_frames(data)
+    } else {
+        read_pcap_frames(data)
+    }
+}
+
+fn read_pcap_frames(data: &[u8]) -> Result<Vec<Frame<'_>>> {
+    ensure!(data.len() >= PCAP_HEADER_LENGTH, "Pcap header is too short");
+    let magic = LittleEndian::read_u32(data);
+    let big_endian = if magic == PCAP_MAGIC_MICROSECONDS || magic == PCAP_MAGIC_NANOSECONDS {
+        false
+    } else if magic.swap_bytes() == PCAP_MAGIC_MICROSECONDS
+        || magic.swap_bytes() == PCAP_MAGIC_NANOSECONDS
+    {
+        true
+    } else {
+        bail!("Unknown capture file format");
+    };
+    // The upper bits of the link type can contain the FCS length.
+    let link_type = read_u32(&data[20..24], big_endian) & 0x0fff_ffff;
+
+    let mut frames = Vec::new();
+    let mut pos = PCAP_HEADER_LENGTH;
+    while pos < data.len() {
+        let header = data
+            .get(pos..pos + PCAP_RECORD_HEADER_LENGTH)
+            .context("Pcap record header is truncated")?;
+        let captured_length = read_u32(&header[8..12], big_endian) as usize;
+        let start = pos + PCAP_RECORD_HEADER_LENGTH;
+        let frame = start
+            .checked_add(captured_length)
+            .and_then(|end| data.get(start..end))
+            .context("Pcap record is truncated")?;
+        frames.push(Frame {
+            link_type,
+            data: frame,
+        });
+        pos = start + captured_length;
+    }
+    Ok(frames)
+}
+
+fn read_pcapng_frames(data: &[u8]) -> Result<Vec<Frame<'_>>> {
+    let mut frames = Vec::new();
+    let mut link_types = Vec::new();
+    let mut big_endian = false;
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let header = data
+            .get(pos..pos + 12)
+            .context("Pcapng block header is truncated")?;
+
+        // The block type of the section header is the same in both byte orders. Every section
+        // defines the byte order of it's blocks.
+        if LittleEndian::read_u32(header) == PCAPNG_SECTION_HEADER_BLOCK {
+            let magic = LittleEndian::read_u32(&header[8..12]);
+            big_endian = if magic == PCAPNG_BYTE_ORDER_MAGIC {
+                false
+            } else if magic.swap_bytes() == PCAPNG_BYTE_ORDER_MAGIC {
+                true
+            } else {
+                bail!("Invalid byte order magic {:#x} in pcapng section", magic);
+            };
+            link_types.clear();
+        }
+
+        let block_type = read_u32(&header[0..4], big_endian);
+        let length = read_u32(&header[4..8], big_endian) as usize;
+        ensure!(
+            length >= 12 && length & 0x3 == 0,
+            "Invalid pcapng block length {}",
+            length
+        );
+        let block = pos
+            .checked_add(length)
+            .and_then(|end| data.get(pos..end))
+            .context("Pcapng block is truncated")?;
+        let body = &block[8..length - 4];
+
+        match block_type {
+            PCAPNG_INTERFACE_DESCRIPTION_BLOCK => {
+                ensure!(body.len() >= 8, "Pcapng interface block is too short");
+                link_types.push(read_u16(&body[0..2], big_endian) as u32);
+            }
+            PCAPNG_ENHANCED_PACKET_BLOCK => {
+                ensure!(body.len() >= 20, "Pcapng packet block is too short");
+                let interface = read_u32(&body[0..4], big_endian) as usize;
+                let captured_length = read_u32(&body[12..16], big_endian) as usize;
+                let link_type = *link_types
+                    .get(interface)
+                    .context(format!("Unknown pcapng interface {}", interface))?;
+                let frame = 20usize
+                    .checked_add(captured_length)
+                    .and_then(|end| body.get(20..end))
+                    .context("Pcapng packet block is truncated")?;
+                frames.push(Frame {
+                    link_type,
+                    data: frame,
+                });
+            }
+            PCAPNG_SIMPLE_PACKET_BLOCK => {
+                ensure!(body.len() >= 4, "Pcapng packet block is too short");
+                let link_type = *link_types
+                    .first()
+                    .context("Pcapng packet block without interface")?;
+                // Simple packet blocks only contain the original length of the frame.
+                let original_length = read_u32(&body[0..4], big_endian) as usize;
+                let captured_length = original_length.min(body.len() - 4);
+                frames.push(Frame {
+                    link_type,
+                    data: &body[4..4 + captured_length],
+                });
+            }
+            _ => {}
+        }
+        pos += length;
+    }
+    Ok(frames)
+}
+
+fn read_u16(data: &[u8], big_endian: bool) -> u16 {
+    if big_endian {
+        BigEndian::read_u16(data)
+    } else {
+        LittleEndian::read_u16(data)
+    }
+}
+
+fn read_u32(data: &[u8], big_endian: bool) -> u32 {
+    if big_endian {
+        BigEndian::read_u32(data)
+    } else {
+        LittleEndian::read_u32(data)
+    }
+}
+
+/// Returns the IP packet of the frame.
+fn ip_packet<'a>(frame: &Frame<'a>) -> Option<&'a [u8]> {
+    let data = frame.data;
+    match frame.link_type {
+        LINKTYPE_ETHERNET => {
+            let mut offset = 12;
+            let mut ethertype = BigEndian::read_u16(data.get(offset..offset + 2)?);
+            while ethertype == ETHERTYPE_VLAN {
+                offset += 4;
+                ethertype = BigEndian::read_u16(data.get(offset..offset + 2)?);
+            }
+            ip_payload(ethertype, data.get(offset + 2..)?)
+        }
+        // The address family of the loopback header is in the byte order of the capturing host.
+        // The IP version is checked when decoding the IP header instead.
+        LINKTYPE_NULL | LINKTYPE_LOOP => data.get(4..),
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => Some(data),
+        LINKTYPE_LINUX_SLL => ip_payload(BigEndian::read_u16(data.get(14..16)?), data.get(16..)?),
+        LINKTYPE_LINUX_SLL2 => ip_payload(BigEndian::read_u16(data.get(0..2)?), data.get(20..)?),
+        _ => None,
+    }
+}
+
+fn ip_payload(ethertype: u16, data: &[u8]) -> Option<&[u8]> {
+    match ethertype {
+        ETHERTYPE_IPV4 | ETHERTYPE_IPV6 => Some(data),
+        _ => None,
+    }
+}
+
+/// A TCP segment of the capture.
+#[derive(Clone, Copy, Debug)]
+struct TcpSegment<'a> {
+    source: SocketAddr,
+    destination: SocketAddr,
+    sequence: u32,
+    flags: u8,
+    payload: &'a [u8],
+}
+
+/// Decodes the TCP segment of the IP packet.
+fn tcp_segment(packet: &[u8]) -> Option<TcpSegment<'_>> {
+    let (source, destination, segment) = match packet.first()? >> 4 {
+        4 => {
+            let header_length = (packet[0] & 0xf) as usize * 4;
+            // Fragmented packets are not supported.
+            let fragment = BigEndian::read_u16(packet.get(6..8)?);
+            if fragment & 0x3fff != 0 || *packet.get(9)? != IP_PROTOCOL_TCP {
+                return None;
+            }
+            // The total length is zero for packets that were captured with TCP segmentation offload.
+            let total_length = match BigEndian::read_u16(packet.get(2..4)?) as usize {
+                0 => packet.len(),
+                length => length,
+            };
+            let source = packet.get(12..16)?;
+            let destination = packet.get(16..20)?;
+            (
+                IpAddr::V4(Ipv4Addr::new(source[0], source[1], source[2], source[3])),
+                IpAddr::V4(Ipv4Addr::new(
+                    destination[0],
+                    destination[1],
+                    destination[2],
+                    destination[3],
+                )),
+                packet.get(header_length..total_length)?,
+            )
+        }
+        6 => {
+            if *packet.get(6)? != IP_PROTOCOL_TCP {
+                return None;
+            }
+            let payload_length = BigEndian::read_u16(packet.get(4..6)?) as usize;
+            let mut source = [0; 16];
+            source.copy_from_slice(packet.get(8..24)?);
+            let mut destination = [0; 16];
+            destination.copy_from_slice(packet.get(24..40)?);
+            (
+                IpAddr::V6(Ipv6Addr::from(source)),
+                IpAddr::V6(Ipv6Addr::from(destination)),
+                packet.get(40..40 + payload_length)?,
+            )
+        }
+        _ => return None,
+    };
+
+    let header_length = (segment.get(12)? >> 4) as usize * 4;
+    Some(TcpSegment {
+        source: SocketAddr::new(source, BigEndian::read_u16(segment.get(0..2)?)),
+        destination: SocketAddr::new(destination, BigEndian::read_u16(segment.get(2..4)?)),
+        sequence: BigEndian::read_u32(segment.get(4..8)?),
+        flags: *segment.get(13)?,
+        payload: segment.get(header_length..)?,
+    })
+}
+
+/// Reassembles one direction of a TCP connection.
+#[derive(Clone, Debug, Default)]
+struct Stream {
+    next_sequence: Option<u32>,
+    pending: Vec<(u32, Vec<u8>)>,
+    missing: usize,
+}
+
+impl Stream {
+    /// Adds the segment to the stream and returns the data that is now available in order
+    /// together with the number of bytes that are missing before it.
+    fn push(&mut self, segment: &TcpSegment) -> Vec<(usize, Vec<u8>)> {
+        let mut sequence = segment.sequence;
+        if segment.flags & TCP_FLAG_SYN != 0 {
+            // The SYN flag occupies one sequence number.
+            sequence = sequence.wrapping_add(1);
+            self.next_sequence = Some(sequence);
+        }
+        if segment.payload.is_empty() {
+            return Vec::new();
+        }
+        // Streams that were already open when the capture started begin with the first segment.
+        self.next_sequence.get_or_insert(sequence);
+        self.pending.push((sequence, segment.payload.to_vec()));
+
+        let mut available = self.take_available();
+        if self.pending.len() > MAX_PENDING_SEGMENTS {
+            // A segment is missing from the capture, so the stream continues after the gap.
+            self.skip_gap();
+            available.append(&mut self.take_available());
+        }
+        available
+    }
+
+    /// Returns the pending data at the end of the capture. Missing segments are skipped.
+    fn finish(&mut self) -> Vec<(usize, Vec<u8>)> {
+        let mut available = Vec::new();
+        while !self.pending.is_empty() {
+            self.skip_gap();
+            available.append(&mut self.take_available());
+        }
+        available
+    }
+
+    /// Removes the pending segments that continue the stream and returns their new data.
+    fn take_available(&mut self) -> Vec<(usize, Vec<u8>)> {
+        let mut available = Vec::new();
+        let mut next_sequence = match self.next_sequence {
+            Some(next_sequence) => next_sequence,
+            None => return available,
+        };
+
+        // Sequence numbers wrap around, so they are compared by their distance.
+        while let Some(index) = self
+            .pending
+            .iter()
+            .position(|(sequence, _)| sequence.wrapping_sub(next_sequence) as i32 <= 0)
+        {
+            let (sequence, payload) = self.pending.swap_remove(index);
+            // Skip the data that was already received with a retransmitted segment.
+            let received = next_sequence.wrapping_sub(sequence) as usize;
+            if received < payload.len() {
+                next_sequence = sequence.wrapping_add(payload.len() as u32);
+                available.push((mem::take(&mut self.missing), payload[received..].to_vec()));
+            }
+        }
+        self.next_sequence = Some(next_sequence);
+        available
+    }
+
+    /// Continues the stream at the lowest pending sequence number.
+    fn skip_gap(&mut self) {
+        let next_sequence = self.next_sequence.unwrap_or_default();
+        if let Some(sequence) = self
+            .pending
+            .iter()
+            .map(|(sequence, _)| *sequence)
+            .min_by_key(|sequence| sequence.wrapping_sub(next_sequence))
+        {
+            let missing = sequence.wrapping_sub(next_sequence) as usize;
+            warn!("Skipping {} missing bytes of a TCP stream", missing);
+            self.missing += missing;
+            self.next_sequence = Some(sequence);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::WriteBytesExt;
+
+    use super::*;
+
+    const SERVER_PORT: u16 = 10001;
+    const CLIENT_PORT: u16 = 50000;
+
+    /// Builds an ethernet frame with an IPv4 TCP segment.
+    fn ethernet_frame(from_server: bool, sequence: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let (source, destination) = if from_server {
+            ([10, 0, 0, 1], [10, 0, 0, 2])
+        } else {
+            ([10, 0, 0, 2], [10, 0, 0, 1])
+        };
+        let (source_port, destination_port) = if from_server {
+            (SERVER_PORT, CLIENT_PORT)
+        } else {
+            (CLIENT_PORT, SERVER_PORT)
+        };
+
+        let mut frame = vec![0; 12];
+        frame.write_u16::<BigEndian>(ETHERTYPE_IPV4).unwrap();
+
+        frame.push(0x45);
+        frame.push(0);
+        frame
+            .write_u16::<BigEndian>(20 + 20 + payload.len() as u16)
+            .unwrap();
+        frame.extend_from_slice(&[0, 0, 0x40, 0, 64, IP_PROTOCOL_TCP, 0, 0]);
+        frame.extend_from_slice(&source);
+        frame.extend_from_slice(&destination);
+
+        frame.write_u16::<BigEndian>(source_port).unwrap();
+        frame.write_u16::<BigEndian>(destination_port).unwrap();
+        frame.write_u32::<BigEndian>(sequence).unwrap();
+        frame.write_u32::<BigEndian>(0).unwrap();
+        frame.push(5 << 4);
+        frame.push(flags);
+        frame.extend_from_slice(&[0; 6]);
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    fn pcap(frames: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.write_u32::<LittleEndian>(PCAP_MAGIC_MICROSECONDS)
+            .unwrap();
+        data.write_u16::<LittleEndian>(2).unwrap();
+        data.write_u16::<LittleEndian>(4).unwrap();
+        data.extend_from_slice(&[0; 8]);
+        data.write_u32::<LittleEndian>(65535).unwrap();
+        data.write_u32::<LittleEndian>(LINKTYPE_ETHERNET).unwrap();
+        for frame in frames {
+            data.extend_from_slice(&[0; 8]);
+            data.write_u32::<LittleEndian>(frame.len() as u32).unwrap();
+            data.write_u32::<LittleEndian>(frame.len() as u32).unwrap();
+            data.extend_from_slice(frame);
+        }
+        data
+    }
+
+    fn pcapng(frames: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut block = |block_type: u32, body: &[u8]| {
+            let length = 12 + ((body.len() + 3) & !3) as u32;
+            data.write_u32::<BigEndian>(block_type).unwrap();
+            data.write_u32::<BigEndian>(length).unwrap();
+            data.extend_from_slice(body);
+            data.resize(data.len() + (length as usize - 12 - body.len()), 0);
+            data.write_u32::<BigEndian>(length).unwrap();
+        };
+
+        let mut section = Vec::new();
+        section
+            .write_u32::<BigEndian>(PCAPNG_BYTE_ORDER_MAGIC)
+            .unwrap();
+        section.extend_from_slice(&[0, 1, 0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+        block(PCAPNG_SECTION_HEADER_BLOCK, &section);
+
+        let mut interface = Vec::new();
+        interface
+            .write_u16::<BigEndian>(LINKTYPE_ETHERNET as u16)
+            .unwrap();
+        interface.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        block(PCAPNG_INTERFACE_DESCRIPTION_BLOCK, &interface);
+
+        for frame in frames {
+            let mut packet = vec![0; 12];
+            packet.write_u32::<BigEndian>(frame.len() as u32).unwrap();
+            packet.write_u32::<BigEndian>(frame.len() as u32).unwrap();
+            packet.extend_from_slice(frame);
+            block(PCAPNG_ENHANCED_PACKET_BLOCK, &packet);
+        }
+        data
+    }
+
+    fn frames() -> Vec<Vec<u8>> {
+        vec![
+            ethernet_frame(false, 100, TCP_FLAG_SYN, &[]),
+            ethernet_frame(true, 5000, TCP_FLAG_SYN, &[]),
+            ethernet_frame(true, 5001, 0, &[1, 0, 0, 0]),
+            // Out of order segments of the client.
+            ethernet_frame(false, 105, 0, &[5, 6]),
+            ethernet_frame(false, 101, 0, &[1, 2, 3]),
+            // Retransmission that overlaps with already received data.
+            ethernet_frame(false, 102, 0, &[2, 3, 4]),
+            ethernet_frame(true, 5005, 0, &[2]),
+        ]
+    }
+
+    fn expected() -> Vec<StreamData> {
+        vec![
+            StreamData {
+                from_server: true,
+                missing: 0,
+                payload: vec![1, 0, 0, 0],
+            },
+            StreamData {
+                from_server: false,
+                missing: 0,
+                payload: vec![1, 2, 3],
+            },
+            StreamData {
+                from_server: false,
+                missing: 0,
+                payload: vec![4],
+            },
+            StreamData {
+                from_server: false,
+                missing: 0,
+                payload: vec![5, 6],
+            },
+            StreamData {
+                from_server: true,
+                missing: 0,
+                payload: vec![2],
+            },
+        ]
+    }
+
+    #[test]
+    fn test_is_capture() {
+        assert!(is_capture(&pcap(&[])));
+        assert!(is_capture(&pcapng(&[])));
+        assert!(!is_capture(&[1, 4, 0, 0, 0, 0, 0, 0, 0]));
+        assert!(!is_capture(&[]));
+    }
+
+    #[test]
+    fn test_read_pcap() -> Result<()> {
+        let conversations = read_conversations(&pcap(&frames()), SERVER_PORT)?;
+        assert_eq!(conversations.len(), 1);
+        assert_eq!(
+            conversations[0].client,
+            "10.0.0.2:50000".parse::<SocketAddr>()?
+        );
+        assert_eq!(conversations[0].data, expected());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_pcapng() -> Result<()> {
+        let conversations = read_conversations(&pcapng(&frames()), SERVER_PORT)?;
+        assert_eq!(conversations.len(), 1);
+        assert_eq!(conversations[0].data, expected());
+        Ok(())
+    }
+
+    #[test]
+    fn test_other_port_is_ignored() -> Result<()> {
+        let conversations = read_conversations(&pcap(&frames()), SERVER_PORT + 1)?;
+        assert!(conversations.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_reconnect() -> Result<()> {
+        let mut frames = frames();
+        frames.push(ethernet_frame(false, 900, TCP_FLAG_SYN, &[]));
+        frames.push(ethernet_frame(false, 901, 0, &[7]));
+
+        let conversations = read_conversations(&pcap(&frames), SERVER_PORT)?;
+        assert_eq!(conversations.len(), 2);
+        assert_eq!(
+            conversations[1].data,
+            vec![StreamData {
+                from_server: false,
+                missing: 0,
+                payload: vec![7],
+            }]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_segment() -> Result<()> {
+        let mut frames = vec![
+            ethernet_frame(false, 100, TCP_FLAG_SYN, &[]),
+            ethernet_frame(false, 101, 0, &[1]),
+        ];
+        // The segment with sequence 102 is missing.
+        for i in 0..=MAX_PENDING_SEGMENTS as u32 {
+            frames.push(ethernet_frame(false, 103 + i, 0, &[i as u8]));
+        }
+
+        let conversations = read_conversations(&pcap(&frames), SERVER_PORT)?;
+        assert_eq!(conversations.len(), 1);
+        let data = &conversations[0].data;
+        assert_eq!(data.len(), MAX_PENDING_SEGMENTS + 2);
+        assert_eq!(data[0].payload, vec![1]);
+        assert_eq!(data[1].missing, 1);
+        for (i, data) in data[1..].iter().enumerate() {
+            assert_eq!(data.payload, vec![i as u8]);
+        }
+        assert!(data[2..].iter().all(|data| data.missing == 0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_segment_at_end_of_capture() -> Result<()> {
+        let frames = vec![
+            ethernet_frame(false, 100, TCP_FLAG_SYN, &[]),
+            ethernet_frame(false, 101, 0, &[1]),
+            // The segment with sequence 102 is missing.
+            ethernet_frame(false, 103, 0, &[3]),
+        ];
+
+        let conversations = read_conversations(&pcap(&frames), SERVER_PORT)?;
+        assert_eq!(conversations.len(), 1);
+        assert_eq!(
+            conversations[0].data,
+            vec![
+                StreamData {
+                    from_server: false,
+                    missing: 0,
+                    payload: vec![1],
+                },
+                StreamData {
+                    from_server: false,
+                    missing: 1,
+                    payload: vec![3],
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncated_capture() {
+        let mut data = pcap(&frames());
+        data.truncate(data.len() - 1);
+        assert!(read_conversations(&data, SERVER_PORT).is_err());
+
+        let mut data = pcapng(&frames());
+        data.truncate(data.len() - 4);
+        assert!(read_conversations(&data, SERVER_PORT).is_err());
+    }
+}
@@ -1,14 +1,20 @@
 /// Module that implements the network protocol used by TERA.
+pub mod client;
+// cargo-fuzz builds the crate with `--cfg fuzzing`.
+#[cfg(any(test, fuzzing))]
+pub mod fuzz;
 pub mod opcode;
 pub mod packet;
 pub mod serde;
+pub mod system_message;
+pub mod version;
 
-use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
 
-use anyhow::{bail, Context};
+use anyhow::{bail, ensure, Context};
 use async_macros::select;
+use async_std::future;
 use async_std::io::timeout;
 use async_std::net::TcpStream;
 use async_std::prelude::*;
@@ -22,30 +28,41 @@ use tracing::{debug, error, info, trace, warn};
 use crate::crypt::CryptSession;
 use crate::ecs::event::{EcsEvent, Event, EventTarget};
 use crate::protocol::opcode::Opcode;
+use crate::protocol::version::{ProtocolRegistry, ProtocolVersion};
 use crate::{AlmeticaError, Result};
 
+/// Length of the header of every packet (length and opcode).
+pub const PACKET_HEADER_LENGTH: usize = 4;
+
+/// Length of the integrity header of the packets in the integrity list (client version >= 93).
+/// It follows the packet header.
+pub const INTEGRITY_HEADER_LENGTH: usize = 4;
+
 enum ConnectionHandleEvent {
     Rx(usize),
     GlobalTx(Option<EcsEvent>),
-    #[allow(dead_code)]
-    LocalTx(Option<EcsEvent>), // FIXME remove me once implemented
+    LocalTx(Option<EcsEvent>),
 }
 
 /// Abstracts the game network protocol session.
 pub struct GameSession<'a> {
     pub connection_id: EntityId,
+    // Integrity value of the last received packet in the integrity list of the client version.
+    pub last_integrity_value: Option<u32>,
     stream: &'a mut TcpStream,
     cipher: CryptSession,
-    opcode_table: Arc<Vec<Opcode>>,
-    reverse_opcode_table: Arc<HashMap<Opcode, u16>>,
+    // Supported client versions
+    registry: Arc<ProtocolRegistry>,
+    // Client version of the session. Selected with the first packet (C_CHECK_VERSION).
+    version: Option<Arc<ProtocolVersion>>,
     // Sending channel TO the global world
     global_request_channel: Sender<EcsEvent>,
     // Receiving channel FROM the global world
     global_response_channel: Receiver<EcsEvent>,
     // Sending channel TO the instance world
-    _instance_request_channel: Option<Sender<EcsEvent>>,
+    instance_request_channel: Option<Sender<EcsEvent>>,
     // Receiving channel FROM the instance world
-    _instance_response_channel: Option<Receiver<EcsEvent>>,
+    instance_response_channel: Option<Receiver<EcsEvent>>,
     write_timeout_dur: Duration,
     read_timeout_dur: Duration,
     peek_timeout_dur: Duration,
@@ -56,8 +73,7 @@ impl<'a> GameSession<'a> {
     pub async fn new(
         stream: &'a mut TcpStream,
         global_request_channel: Sender<EcsEvent>,
-        opcode_table: Arc<Vec<Opcode>>,
-        reverse_opcode_table: Arc<HashMap<Opcode, u16>>,
+        registry: Arc<ProtocolRegistry>,
     ) -> Result<GameSession<'a>> {
         // Initialize the stream cipher with the client.
         let cipher = GameSession::init_crypto(stream).await?;
@@ -81,14 +97,15 @@ impl<'a> GameSession<'a> {
 
         Ok(GameSession {
             connection_id,
+            last_integrity_value: None,
             stream,
             cipher,
-            opcode_table,
-            reverse_opcode_table,
+            registry,
+            version: None,
             global_request_channel,
             global_response_channel: rx_response_channel,
-            _instance_request_channel: None,
-            _instance_response_channel: None,
+            instance_request_channel: None,
+            instance_response_channel: None,
             write_timeout_dur: Duration::from_secs(15),
             read_timeout_dur: Duration::from_secs(15),
             peek_timeout_dur: Duration::from_secs(120),
@@ -149,13 +166,19 @@ impl<'a> GameSession<'a> {
     }
 
     /// Handles the writing / sending on the TCP stream.
+    ///
+    /// The connection leaves the local world it was handed off to once the session ends.
     pub async fn handle_connection(&mut self) -> Result<()> {
+        let result = self.process_streams().await;
+        self.leave_local_world().await;
+        result
+    }
+
+    async fn process_streams(&mut self) -> Result<()> {
         let mut header_buf = vec![0u8; 4];
         let mut peek_buf = vec![0u8; 4];
 
         loop {
-            // TODO Query instance response channel
-
             let rx = async {
                 let read = timeout(self.peek_timeout_dur, self.stream.peek(&mut peek_buf))
                     .await
@@ -168,7 +191,15 @@ impl<'a> GameSession<'a> {
                 Ok::<_, anyhow::Error>(ConnectionHandleEvent::GlobalTx(event))
             };
 
-            match select!(rx, global_tx).await? {
+            let local_tx = async {
+                let event = match &self.instance_response_channel {
+                    Some(channel) => channel.recv().await,
+                    None => future::pending().await,
+                };
+                Ok::<_, anyhow::Error>(ConnectionHandleEvent::LocalTx(event))
+            };
+
+            match select!(rx, global_tx, local_tx).await? {
                 ConnectionHandleEvent::Rx(read) => {
                     if read == 0 {
                         // Connection was closed
@@ -184,8 +215,6 @@ impl<'a> GameSession<'a> {
                         let packet_length = LittleEndian::read_u16(&header_buf[0..2]) as usize - 4;
                         let opcode = LittleEndian::read_u16(&header_buf[2..4]) as usize;
 
-                        // TODO handle the integrity bytes on some client packets (implement once need). Ignore the value, since it's broken anyhow.
-
                         let mut data_buf = vec![0u8; packet_length];
                         if packet_length != 0 {
                             timeout(self.read_timeout_dur, self.stream.read_exact(&mut data_buf))
@@ -203,8 +232,9 @@ impl<'a> GameSession<'a> {
                     }
                 }
                 ConnectionHandleEvent::GlobalTx(event) | ConnectionHandleEvent::LocalTx(event) => {
+                    // The connection ends once a world drops it or closes it's channel.
                     if let Err(e) = self.handle_event(event).await {
-                        self.handle_error(e)?;
+                        return self.handle_error(e);
                     }
                 }
             };
@@ -228,7 +258,30 @@ impl<'a> GameSession<'a> {
                     debug!("Received drop connection event");
                     bail!(AlmeticaError::ConnectionClosed);
                 }
-                match event.data()? {
+                if let Event::ResponseLocalWorldHandOff {
+                    local_world_id,
+                    user_id,
+                    visibility_range,
+                    request_channel,
+                    ..
+                } = &*event
+                {
+                    self.hand_off_to_local_world(
+                        *local_world_id,
+                        *user_id,
+                        *visibility_range,
+                        request_channel.clone(),
+                    )
+                    .await;
+                    return Ok(());
+                }
+                // Packets are written with the layout of the client version.
+                let layout = self
+                    .version
+                    .as_ref()
+                    .map(|version| version.layout)
+                    .unwrap_or_default();
+                match event.data_with_layout(layout)? {
                     Some(data) => match event.opcode() {
                         Some(opcode) => {
                             debug!("Sending packet {:?}", opcode);
@@ -251,9 +304,61 @@ impl<'a> GameSession<'a> {
         Ok(())
     }
 
+    /// Registers the connection, the selected user and it's settings in the given local world.
+    /// All events with a local target are send to the local world afterwards.
+    async fn hand_off_to_local_world(
+        &mut self,
+        local_world_id: u64,
+        user_id: i32,
+        visibility_range: u32,
+        request_channel: Sender<EcsEvent>,
+    ) {
+        debug!("Handing off connection to local world {}", local_world_id);
+
+        self.leave_local_world().await;
+
+        // Channel to receive response events from the local world ECS.
+        let (tx_response_channel, rx_response_channel) = channel(128);
+        request_channel
+            .send(Arc::new(Event::RequestRegisterLocalConnection {
+                connection_id: self.connection_id,
+                user_id,
+                visibility_range,
+                response_channel: tx_response_channel,
+            }))
+            .await;
+
+        self.instance_request_channel = Some(request_channel);
+        self.instance_response_channel = Some(rx_response_channel);
+    }
+
+    /// Unregisters the connection from the local world it is currently registered in.
+    async fn leave_local_world(&mut self) {
+        self.instance_response_channel = None;
+        if let Some(channel) = self.instance_request_channel.take() {
+            debug!("Leaving local world");
+            channel
+                .send(Arc::new(Event::RequestUnregisterLocalConnection {
+                    connection_id: self.connection_id,
+                }))
+                .await;
+        }
+    }
+
     /// Send packet to client.
     async fn send_packet(&mut self, opcode: Opcode, mut data: Vec<u8>) -> Result<()> {
-        match self.reverse_opcode_table.get(&opcode) {
+        let version = match &self.version {
+            Some(version) => version.clone(),
+            None => {
+                error!(
+                    "Can't send packet {:?} before the client version is known. Dropping packet.",
+                    opcode
+                );
+                return Ok(());
+            }
+        };
+
+        match version.reverse_opcode_table.get(&opcode) {
             Some(opcode_value) => {
                 let len = data.len() + 4;
                 if len > std::u16::MAX as usize {
@@ -282,22 +387,64 @@ impl<'a> GameSession<'a> {
     }
 
     /// Decodes a packet from the given `Vec<u8>` and sends it to game server logic.
-    async fn handle_packet(&mut self, opcode: usize, packet_data: Vec<u8>) -> Result<()> {
-        let opcode_type = self.opcode_table[opcode];
+    async fn handle_packet(&mut self, opcode: usize, mut packet_data: Vec<u8>) -> Result<()> {
+        let version = match &self.version {
+            Some(version) => version.clone(),
+            None => {
+                let version = self.registry.select_version(opcode as u16, &packet_data)?;
+                debug!("Selected client version {}", version.name);
+                self.version = Some(version.clone());
+                version
+            }
+        };
+
+        let opcode_type = version.opcode_table[opcode];
+        let mut data_offset = PACKET_HEADER_LENGTH;
+        if version.integrity_opcodes.contains(&opcode_type) {
+            match split_integrity_header(packet_data) {
+                Ok((integrity_value, data)) => {
+                    // The server sends 0 as the integrity IV and we don't know the hash function of
+                    // the client, so we can only record the value.
+                    trace!(
+                        "Integrity value of packet {:?}: {:#010x}",
+                        opcode_type,
+                        integrity_value
+                    );
+                    self.last_integrity_value = Some(integrity_value);
+                    packet_data = data;
+                    data_offset += INTEGRITY_HEADER_LENGTH;
+                }
+                Err(e) => {
+                    warn!("Dropping packet {:?}: {:?}", opcode_type, e);
+                    return Ok(());
+                }
+            }
+        }
+
         match opcode_type {
             Opcode::UNKNOWN => {
                 warn!("Unmapped and unhandled packet with opcode value {}", opcode);
             }
-            _ => match Event::new_from_packet(self.connection_id, opcode_type, packet_data) {
-                Ok(event) => {
+            _ => match Event::decode_packet(
+                self.connection_id,
+                opcode_type,
+                packet_data,
+                data_offset,
+                version.layout,
+            ) {
+                Ok((event, _)) => {
                     debug!("Received valid packet {:?}", opcode_type);
                     match event.target() {
                         EventTarget::Global => {
                             self.global_request_channel.send(Arc::new(event)).await;
                         }
-                        EventTarget::Local => {
-                            // TODO send to the local world
-                        }
+                        EventTarget::Local => match &self.instance_request_channel {
+                            Some(channel) => channel.send(Arc::new(event)).await,
+                            None => error!(
+                                "Can't send event {} with target Local from a connection that wasn't handed off to a local world",
+                                event
+                            ),
+                        },
                         EventTarget::Connection => {
                             error!(
                                 "Can't send event {} with target Connection from a connection",
@@ -321,8 +468,20 @@ impl<'a> GameSession<'a> {
     }
 }
 
+/// Splits the packet data into the value of the integrity header and the remaining data.
+/// The offsets inside the remaining data need to be rebased by `INTEGRITY_HEADER_LENGTH`.
+pub fn split_integrity_header(mut packet_data: Vec<u8>) -> Result<(u32, Vec<u8>)> {
+    ensure!(
+        packet_data.len() >= INTEGRITY_HEADER_LENGTH,
+        "Packet is too short to contain an integrity header"
+    );
+    let data = packet_data.split_off(INTEGRITY_HEADER_LENGTH);
+    Ok((LittleEndian::read_u32(&packet_data), data))
+}
+
 #[cfg(test)]
 mod tests {
+    use std::collections::{HashMap, HashSet};
     use std::net::SocketAddr;
     use std::time::{Duration, Instant};
 
@@ -334,9 +493,13 @@ mod tests {
     use shipyard::*;
 
     use crate::dataloader::*;
-    use crate::ecs::component::Connection;
+    use crate::ecs::component::{Connection, ConnectionID, IncomingEvent};
     use crate::ecs::event::Event::{RequestRegisterConnection, ResponseRegisterConnection};
+    use crate::ecs::resource::{ConnectionMapping, DeletionList, WorldId};
+    use crate::ecs::system::{cleaner_system, local_connection_manager_system};
+    use crate::protocol::client::GameClient;
     use crate::protocol::opcode::Opcode;
+    use crate::protocol::serde::PacketLayout;
     use crate::protocol::GameSession;
     use crate::Result;
 
@@ -344,6 +507,20 @@ mod tests {
     use shipyard::EntityId;
     use std::sync::Arc;
 
+    async fn get_registry() -> Result<ProtocolRegistry> {
+        let (opcode_table, reverse_opcode_table) = get_opcode_tables().await?;
+        let mut registry = ProtocolRegistry::default();
+        registry.register(ProtocolVersion {
+            name: "93.02".to_string(),
+            version: 366_222,
+            opcode_table,
+            reverse_opcode_table,
+            integrity_opcodes: HashSet::new(),
+            layout: PacketLayout::default(),
+        })?;
+        Ok(registry)
+    }
+
     async fn get_opcode_tables() -> Result<(Vec<Opcode>, HashMap<Opcode, u16>)> {
         let mut file = Vec::new();
         file.write_all(
@@ -369,6 +546,7 @@ mod tests {
                 entities.add_entity(
                     &mut connections,
                     Connection {
+                        account_id: None,
                         verified: false,
                         version_checked: false,
                         region: None,
@@ -383,20 +561,15 @@ mod tests {
     async fn spawn_dummy_server() -> Result<(SocketAddr, JoinHandle<()>, JoinHandle<()>)> {
         let srv = TcpListener::bind("127.0.0.1:0").await?;
         let addr = srv.local_addr()?;
-        let (opcode_mapping, reverse_opcode_mapping) = get_opcode_tables().await?;
+        let registry = get_registry().await?;
         let (tx_channel, rx_channel) = channel(1024);
 
         // TCP server
         let tcp_join = task::spawn(async move {
             let (mut socket, _) = srv.accept().await.unwrap();
-            let _session = GameSession::new(
-                &mut socket,
-                tx_channel,
-                Arc::new(opcode_mapping),
-                Arc::new(reverse_opcode_mapping),
-            )
-            .await
-            .unwrap();
+            let _session = GameSession::new(&mut socket, tx_channel, Arc::new(registry))
+                .await
+                .unwrap();
         });
 
         // World loop mock
@@ -423,6 +596,47 @@ mod tests {
         Ok((addr, tcp_join, world_join))
     }
 
+    #[test]
+    fn test_split_integrity_header() -> Result<()> {
+        let (value, data) = split_integrity_header(vec![0xef, 0xbe, 0xad, 0xde, 0x1, 0x2])?;
+        assert_eq!(value, 0xdead_beef);
+        assert_eq!(data, vec![0x1, 0x2]);
+
+        let (value, data) = split_integrity_header(vec![0x1, 0x0, 0x0, 0x0])?;
+        assert_eq!(value, 1);
+        assert!(data.is_empty());
+
+        assert!(split_integrity_header(vec![0x1, 0x2, 0x3]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_packet_with_integrity_header() -> Result<()> {
+        let connection_id = get_new_entity_with_connection_component();
+        let data = vec![
+            0x78, 0x56, 0x34, 0x12, 0xe, 0x0, 0x0, 0x0, 0x0, 0x0, 0x3c, 0x0, 0x46, 0x0, 0x4f, 0x0,
+            0x4e, 0x0, 0x54, 0x0, 0x3e, 0x0, 0x48, 0x0, 0x65, 0x0, 0x6c, 0x0, 0x6c, 0x0, 0x6f, 0x0,
+            0x3c, 0x0, 0x2f, 0x0, 0x46, 0x0, 0x4f, 0x0, 0x4e, 0x0, 0x54, 0x0, 0x3e, 0x0, 0x0, 0x0,
+        ];
+
+        let (value, data) = split_integrity_header(data)?;
+        assert_eq!(value, 0x1234_5678);
+
+        let event = Event::new_from_packet(
+            connection_id,
+            Opcode::C_CHAT,
+            data,
+            PACKET_HEADER_LENGTH + INTEGRITY_HEADER_LENGTH,
+        )?;
+        if let Event::RequestChat { packet, .. } = event {
+            assert_eq!(packet.message, "<FONT>Hello</FONT>");
+            assert_eq!(packet.channel, 0);
+        } else {
+            panic!("Packet wasn't decoded into a chat event");
+        }
+        Ok(())
+    }
+
     #[async_std::test]
     async fn test_gamesession_creation() -> Result<()> {
         let (addr, tcp_join, world_join) = spawn_dummy_server().await?;
@@ -484,4 +698,97 @@ mod tests {
         world_join.await;
         Ok(())
     }
+
+    #[async_std::test]
+    async fn test_local_world_cleanup_after_disconnect() -> Result<()> {
+        let srv = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = srv.local_addr()?;
+        let registry = Arc::new(get_registry().await?);
+        let version = registry.get(366_222).unwrap();
+        let (global_tx, global_rx) = channel(1024);
+        let (local_tx, local_rx) = channel(1024);
+
+        task::spawn(async move {
+            let (mut socket, _) = srv.accept().await.unwrap();
+            let mut session = GameSession::new(&mut socket, global_tx, registry)
+                .await
+                .unwrap();
+            session.handle_connection().await.unwrap();
+        });
+
+        // Global world mock that hands the connection off to the local world.
+        let connection_id = get_new_entity_with_connection_component();
+        task::spawn(async move {
+            // Keeps the response channel open until the connection ends.
+            let mut response_channel = None;
+            while let Some(event) = global_rx.recv().await {
+                if let RequestRegisterConnection {
+                    response_channel: channel,
+                } = &*event
+                {
+                    channel
+                        .send(Arc::new(ResponseRegisterConnection { connection_id }))
+                        .await;
+                    channel
+                        .send(Arc::new(Event::ResponseLocalWorldHandOff {
+                            connection_id,
+                            local_world_id: 1,
+                            user_id: 1,
+                            visibility_range: 2000,
+                            request_channel: local_tx.clone(),
+                        }))
+                        .await;
+                    response_channel = Some(channel.clone());
+                }
+            }
+            drop(response_channel);
+        });
+
+        // The local world processes the events the connection sends.
+        let world = World::new();
+        world.add_unique(WorldId(1));
+        world.add_unique(DeletionList(vec![]));
+        world.add_unique(ConnectionMapping(HashMap::new()));
+        let process_event = |event: EcsEvent| {
+            world.run(
+                |mut entities: EntitiesViewMut, mut events: ViewMut<IncomingEvent>| {
+                    entities.add_entity(&mut events, IncomingEvent(event));
+                },
+            );
+            world.run(local_connection_manager_system);
+            world.run(cleaner_system);
+        };
+
+        let client = GameClient::connect(addr, version).await?;
+
+        let event = timeout(Duration::from_secs(1), local_rx.recv())
+            .await?
+            .unwrap();
+        match &*event {
+            Event::RequestRegisterLocalConnection {
+                connection_id: id, ..
+            } => assert_eq!(*id, connection_id),
+            _ => panic!("Unexpected event {}", event),
+        }
+        process_event(event);
+        assert_eq!(world.borrow::<View<ConnectionID>>().iter().count(), 1);
+
+        // The client disconnects after the connection was handed off.
+        drop(client);
+
+        let event = timeout(Duration::from_secs(1), local_rx.recv())
+            .await?
+            .unwrap();
+        match &*event {
+            Event::RequestUnregisterLocalConnection {
+                connection_id: id, ..
+            } => assert_eq!(*id, connection_id),
+            _ => panic!("Unexpected event {}", event),
+        }
+        process_event(event);
+
+        assert!(world.borrow::<UniqueView<ConnectionMapping>>().0.is_empty());
+        assert_eq!(world.borrow::<View<ConnectionID>>().iter().count(), 0);
+        Ok(())
+    }
 }
@@ -0,0 +1,338 @@
+/// Module that implements a headless game client.
+///
+/// The client connects to a server, performs the key exchange of the client and sends / receives
+/// typed packets through the opcode tables of a client version. It's used to script end-to-end
+/// tests against a running server.
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use async_std::io::timeout;
+use async_std::net::{TcpStream, ToSocketAddrs};
+use async_std::prelude::*;
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+use rand::rngs::OsRng;
+use rand_core::RngCore;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tracing::{debug, trace};
+
+use crate::crypt::CryptSession;
+use crate::protocol::opcode::Opcode;
+use crate::protocol::packet::{CPong, Packet};
+use crate::protocol::serde::{from_vec_with_layout, to_vec_with_layout};
+use crate::protocol::version::ProtocolVersion;
+use crate::protocol::{INTEGRITY_HEADER_LENGTH, PACKET_HEADER_LENGTH};
+use crate::Result;
+
+/// A client session with a game server.
+pub struct GameClient {
+    stream: TcpStream,
+    cipher: CryptSession,
+    // Client version that is used to map the opcodes.
+    version: Arc<ProtocolVersion>,
+    timeout_dur: Duration,
+}
+
+impl GameClient {
+    /// Connects to the server and performs the key exchange.
+    pub async fn connect<A: ToSocketAddrs>(
+        addr: A,
+        version: Arc<ProtocolVersion>,
+    ) -> Result<GameClient> {
+        let mut stream = TcpStream::connect(addr)
+            .await
+            .context("Can't connect to server")?;
+        let cipher = GameClient::init_crypto(&mut stream).await?;
+        debug!("Connected to server with client version {}", version.name);
+
+        Ok(GameClient {
+            stream,
+            cipher,
+            version,
+            timeout_dur: Duration::from_secs(15),
+        })
+    }
+
+    /// Performs the client side of the key exchange with the server.
+    pub async fn init_crypto(stream: &mut TcpStream) -> Result<CryptSession> {
+        let timeout_dur = Duration::from_secs(5);
+
+        let mut magic_word_buffer = [0u8; 4];
+        let mut client_key_1 = vec![0; 128];
+        let mut client_key_2 = vec![0; 128];
+        let mut server_key_1 = vec![0; 128];
+        let mut server_key_2 = vec![0; 128];
+
+        timeout(timeout_dur, stream.read_exact(&mut magic_word_buffer))
+            .await
+            .context("Can't read magic word")?;
+        if LittleEndian::read_u32(&magic_word_buffer) != 1 {
+            bail!("No magic word received from the server");
+        }
+        debug!("Received magic word");
+
+        OsRng.fill_bytes(&mut client_key_1);
+        timeout(timeout_dur, stream.write_all(&client_key_1))
+            .await
+            .context("Can't write client key 1")?;
+        debug!("Send client key 1");
+
+        timeout(timeout_dur, stream.read_exact(&mut server_key_1))
+            .await
+            .context("Can't read server key 1")?;
+        debug!("Received server key 1");
+
+        OsRng.fill_bytes(&mut client_key_2);
+        timeout(timeout_dur, stream.write_all(&client_key_2))
+            .await
+            .context("Can't write client key 2")?;
+        debug!("Send client key 2");
+
+        timeout(timeout_dur, stream.read_exact(&mut server_key_2))
+            .await
+            .context("Can't read server key 2")?;
+        debug!("Received server key 2");
+
+        Ok(CryptSession::new(
+            [client_key_1, client_key_2],
+            [server_key_1, server_key_2],
+        ))
+    }
+
+    /// Sets the timeout for sending and receiving packets.
+    pub fn set_timeout(&mut self, timeout_dur: Duration) {
+        self.timeout_dur = timeout_dur;
+    }
+
+    /// Sends the packet to the server. Adds the integrity header if the client version needs it.
+    pub async fn send<P: Packet + Serialize>(&mut self, packet: &P) -> Result<()> {
+        let data = if self.version.integrity_opcodes.contains(&P::OPCODE) {
+            // The server only records the integrity value. The offsets inside the packet include
+            // the integrity header.
+            let mut data = vec![0; INTEGRITY_HEADER_LENGTH];
+            data.append(&mut to_vec_with_layout(
+                packet,
+                PACKET_HEADER_LENGTH + INTEGRITY_HEADER_LENGTH,
+                self.version.layout,
+            )?);
+            data
+        } else {
+            to_vec_with_layout(packet, PACKET_HEADER_LENGTH, self.version.layout)?
+        };
+        self.send_raw(P::OPCODE, data).await
+    }
+
+    /// Sends the packet data with the given opcode to the server.
+    pub async fn send_raw(&mut self, opcode: Opcode, mut data: Vec<u8>) -> Result<()> {
+        let opcode_value = match self.version.reverse_opcode_table.get(&opcode) {
+            Some(opcode_value) => *opcode_value,
+            None => bail!(
+                "Opcode {:?} is not mapped in client version {}",
+                opcode,
+                self.version.name
+            ),
+        };
+        let len = data.len() + 4;
+        if len > u16::MAX as usize {
+            bail!(
+                "Length of packet {:?} too big for u16 length ({})",
+                opcode,
+                len
+            );
+        }
+
+        let mut buffer = Vec::with_capacity(len);
+        WriteBytesExt::write_u16::<LittleEndian>(&mut buffer, len as u16)?;
+        WriteBytesExt::write_u16::<LittleEndian>(&mut buffer, opcode_value)?;
+        buffer.append(&mut data);
+
+        self.cipher.crypt_client_data(buffer.as_mut_slice());
+        timeout(self.timeout_dur, self.stream.write_all(&buffer))
+            .await
+            .context(format!("Can't send packet {:?}", opcode))?;
+        trace!("Send packet {:?}", opcode);
+        Ok(())
+    }
+
+    /// Receives the next packet of the server and returns it's opcode and data.
+    /// Pings of the server are answered automatically, so that the connection is kept alive.
+    pub async fn recv(&mut self) -> Result<(Opcode, Vec<u8>)> {
+        loop {
+            let mut header_buf = [0u8; 4];
+            timeout(self.timeout_dur, self.stream.read_exact(&mut header_buf))
+                .await
+                .context("Can't read packet header")?;
+            self.cipher.crypt_server_data(&mut header_buf);
+            let packet_length = LittleEndian::read_u16(&header_buf[0..2]) as usize;
+            let opcode_value = LittleEndian::read_u16(&header_buf[2..4]);
+            if packet_length < 4 {
+                bail!("Invalid packet length {}", packet_length);
+            }
+
+            let mut data = vec![0u8; packet_length - 4];
+            if !data.is_empty() {
+                timeout(self.timeout_dur, self.stream.read_exact(&mut data))
+                    .await
+                    .context("Can't read packet data")?;
+                self.cipher.crypt_server_data(&mut data);
+            }
+
+            let opcode = self.version.opcode_table[opcode_value as usize];
+            trace!("Received packet {:?} ({})", opcode, opcode_value);
+            if opcode == Opcode::S_PING {
+                self.send(&CPong {}).await?;
+                continue;
+            }
+            return Ok((opcode, data));
+        }
+    }
+
+    /// Receives the next packet of the given type. Packets of other types are skipped.
+    pub async fn recv_packet<P: Packet + DeserializeOwned>(&mut self) -> Result<P> {
+        loop {
+            let (opcode, data) = self.recv().await?;
+            if opcode == P::OPCODE {
+                let (packet, _) =
+                    from_vec_with_layout(data, PACKET_HEADER_LENGTH, self.version.layout)
+                        .context(format!("Can't decode packet {:?}", opcode))?;
+                return Ok(packet);
+            }
+            trace!("Skipping packet {:?}", opcode);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::net::SocketAddr;
+
+    use async_std::net::TcpListener;
+    use async_std::sync::channel;
+    use async_std::task::{self, JoinHandle};
+    use shipyard::*;
+
+    use crate::dataloader::{calculate_reverse_map, read_opcode_table};
+    use crate::ecs::event::Event;
+    use crate::protocol::packet::{CCheckVersion, CCheckVersionEntry, SCheckVersion, SPing};
+    use crate::protocol::serde::PacketLayout;
+    use crate::protocol::version::ProtocolRegistry;
+    use crate::protocol::GameSession;
+
+    use super::*;
+
+    fn get_version() -> Result<ProtocolVersion> {
+        let data = "
+        C_CHECK_VERSION: 1
+        S_CHECK_VERSION: 2
+        C_PONG: 3
+        S_PING: 4
+        ";
+        let opcode_table = read_opcode_table(&mut data.as_bytes())?;
+        let reverse_opcode_table = calculate_reverse_map(opcode_table.as_slice());
+        Ok(ProtocolVersion {
+            name: "93.02".to_string(),
+            version: 366_222,
+            opcode_table,
+            reverse_opcode_table,
+            integrity_opcodes: HashSet::new(),
+            layout: PacketLayout::default(),
+        })
+    }
+
+    /// Spawns a game session with a mocked world that answers the version check.
+    async fn spawn_server() -> Result<(SocketAddr, JoinHandle<()>)> {
+        let srv = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = srv.local_addr()?;
+        let mut registry = ProtocolRegistry::default();
+        registry.register(get_version()?)?;
+        let (tx_channel, rx_channel) = channel(1024);
+
+        task::spawn(async move {
+            let (mut socket, _) = srv.accept().await.unwrap();
+            let mut session = GameSession::new(&mut socket, tx_channel, Arc::new(registry))
+                .await
+                .unwrap();
+            // The connection is closed by the client or the mocked world.
+            session.handle_connection().await.ok();
+        });
+
+        let world_join = task::spawn(async move {
+            let connection_id = World::new().borrow::<EntitiesViewMut>().add_entity((), ());
+            let mut response_channel = None;
+            while let Some(event) = rx_channel.recv().await {
+                match &*event {
+                    Event::RequestRegisterConnection {
+                        response_channel: channel,
+                    } => {
+                        channel
+                            .send(Arc::new(Event::ResponseRegisterConnection {
+                                connection_id,
+                            }))
+                            .await;
+                        response_channel = Some(channel.clone());
+                    }
+                    Event::RequestCheckVersion { packet, .. } => {
+                        let channel = response_channel.as_ref().unwrap();
+                        channel
+                            .send(Arc::new(Event::ResponsePing {
+                                connection_id,
+                                packet: SPing {},
+                            }))
+                            .await;
+                        channel
+                            .send(Arc::new(Event::ResponseCheckVersion {
+                                connection_id,
+                                packet: SCheckVersion {
+                                    ok: packet.version[0].value == 366_222,
+                                },
+                            }))
+                            .await;
+                    }
+                    Event::RequestPong { .. } => break,
+                    _ => panic!("Unexpected event {}", event),
+                }
+            }
+        });
+
+        Ok((addr, world_join))
+    }
+
+    #[async_std::test]
+    async fn test_check_version() -> Result<()> {
+        let (addr, world_join) = spawn_server().await?;
+        let mut client = GameClient::connect(addr, Arc::new(get_version()?)).await?;
+        client.set_timeout(Duration::from_secs(1));
+
+        client
+            .send(&CCheckVersion {
+                version: vec![
+                    CCheckVersionEntry {
+                        index: 0,
+                        value: 366_222,
+                    },
+                    CCheckVersionEntry { index: 1, value: 0 },
+                ],
+            })
+            .await?;
+
+        // The ping that is send before the response is answered by the client.
+        let packet = client.recv_packet::<SCheckVersion>().await?;
+        assert!(packet.ok);
+
+        world_join.await;
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_unmapped_opcode() -> Result<()> {
+        let (addr, _) = spawn_server().await?;
+        let mut client = GameClient::connect(addr, Arc::new(get_version()?)).await?;
+        assert!(client
+            .send_raw(Opcode::C_LOGIN_ARBITER, Vec::new())
+            .await
+            .is_err());
+        Ok(())
+    }
+}
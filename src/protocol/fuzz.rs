@@ -0,0 +1,116 @@
+/// Module that provides the fuzz targets for the packet (de-)serialization.
+///
+/// The `fuzz` directory contains a cargo-fuzz target for every client packet that is registered
+/// as an event. The seed corpus of the targets is stored under `fuzz/corpus/<target>` and is also
+/// replayed by the unit tests, so that it can be run without a fuzzing toolchain.
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::protocol::packet::*;
+use crate::protocol::serde::{from_vec, to_vec};
+
+macro_rules! fuzz_targets {
+    ($($name:ident: $packet_type:ty,)*) => {
+        /// Names of all fuzz targets.
+        pub const TARGETS: &[&str] = &[$(stringify!($name),)*];
+
+        /// Runs the fuzz target with the given name on the data.
+        pub fn run_target(name: &str, data: &[u8]) {
+            match name {
+                $(stringify!($name) => round_trip::<$packet_type>(data),)*
+                _ => panic!("Unknown fuzz target {}", name),
+            }
+        }
+    };
+}
+
+fuzz_targets! {
+    c_can_create_user: CCanCreateUser,
+    c_cancel_delete_user: CCancelDeleteUser,
+    c_chat: CChat,
+    c_check_username: CCheckUserName,
+    c_check_version: CCheckVersion,
+    c_create_user: CCreateUser,
+    c_delete_user: CDeleteUser,
+    c_get_user_list: CGetUserList,
+    c_load_topo_fin: CLoadTopoFin,
+    c_login_arbiter: CLoginArbiter,
+    c_player_location: CPlayerLocation,
+    c_pong: CPong,
+    c_select_user: CSelectUser,
+    c_set_visible_range: CSetVisibleRange,
+    c_whisper: CWhisper,
+}
+
+/// Decodes the data as a packet of the given type. Malformed data needs to be rejected without
+/// panicking. Packets that could be decoded need to survive a round trip through the serializer.
+///
+/// The decoded packet is not compared with the input data, since the input can use a different
+/// layout for the offsets or contain trailing bytes.
+pub fn round_trip<T>(data: &[u8])
+where
+    T: DeserializeOwned + Serialize,
+{
+    let packet = match from_vec::<T>(data.to_vec()) {
+        Ok(packet) => packet,
+        Err(_) => return,
+    };
+
+    let encoded = to_vec(&packet).expect("Decoded packet could not be serialized");
+    let decoded =
+        from_vec::<T>(encoded.clone()).expect("Serialized packet could not be deserialized");
+    let re_encoded = to_vec(&decoded).expect("Deserialized packet could not be serialized");
+    assert_eq!(encoded, re_encoded, "Packet changed during the round trip");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use crate::Result;
+
+    use super::*;
+
+    fn corpus_dir() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("fuzz")
+            .join("corpus")
+    }
+
+    #[test]
+    fn test_seed_corpus() -> Result<()> {
+        for target in TARGETS {
+            let dir = corpus_dir().join(target);
+            let mut count = 0;
+            for entry in fs::read_dir(&dir)? {
+                let data = fs::read(entry?.path())?;
+                run_target(target, &data);
+                count += 1;
+            }
+            assert!(count > 0, "Seed corpus of target {} is empty", target);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_corpus_has_no_unknown_targets() -> Result<()> {
+        for entry in fs::read_dir(corpus_dir())? {
+            let name = entry?.file_name();
+            let name = name.to_string_lossy();
+            assert!(TARGETS.contains(&name.as_ref()), "Unknown target {}", name);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed_data() {
+        let data: Vec<u8> = (0..=255).collect();
+        for target in TARGETS {
+            for len in 0..data.len() {
+                run_target(target, &data[..len]);
+                run_target(target, &[0xff; 64][..len.min(64)]);
+            }
+        }
+    }
+}
@@ -1,10 +1,10 @@
 /// Module that defines the opcode used in the network protocol.
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use strum_macros::EnumString;
 
 /// Opcode enum
 #[allow(non_camel_case_types)]
-#[derive(Clone, Copy, Debug, Deserialize, Eq, EnumString, Hash, PartialEq)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, EnumString, Hash, PartialEq, Serialize)]
 pub enum Opcode {
     UNKNOWN,
     C_ACCEPT_CONTRACT,
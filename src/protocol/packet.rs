@@ -9,9 +9,33 @@
 /// datacenter files is also parsed with the signed variants. Unisgned should only be used when the
 /// field is not used inside the database (for example the integrity IV).
 ///
+/// Packets derive the `Packet` trait, which defines their opcode and the target of the event that
+/// carries them. The derive macro also adds a round trip property test for the packet:
+///
+/// ```ignore
+/// #[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+/// #[cfg_attr(test, derive(Arbitrary))]
+/// #[packet(opcode = C_CHAT, target = Local)]
+/// pub struct CChat { .. }
+/// ```
+///
+/// After that the packet only needs to be registered with it's event in `ecs::event`.
+///
+pub use almetica_derive::Packet;
 pub use client::*;
 pub use server::*;
 
+use crate::ecs::event::EventTarget;
+use crate::protocol::opcode::Opcode;
+
+/// A network packet with it's opcode. Implemented with `#[derive(Packet)]`.
+pub trait Packet {
+    /// Opcode of the packet.
+    const OPCODE: Opcode;
+    /// Target of the event that carries the packet.
+    const TARGET: EventTarget;
+}
+
 /// Used in unit tests for de- and serialization.
 #[allow(unused_macros)]
 #[macro_export]
@@ -53,5 +77,22 @@ macro_rules! print_packet_data_test {
     };
 }
 
+/// Strategies used by the property tests to generate packets.
+#[cfg(test)]
+pub(crate) mod strategy {
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    /// Strings without a null character, that can be encoded with UCS-2.
+    pub fn ucs2_string() -> impl Strategy<Value = String> {
+        "[^\\x00\\x{10000}-\\x{10FFFF}]{0,32}"
+    }
+
+    /// Small arrays, so that the offsets of the packet don't overflow.
+    pub fn array<T: Arbitrary>() -> impl Strategy<Value = Vec<T>> {
+        vec(any::<T>(), 0..4)
+    }
+}
+
 mod client;
 mod server;
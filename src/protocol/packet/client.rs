@@ -1,29 +1,62 @@
 /// Module for client network packages.
+#[cfg(test)]
+use proptest_derive::Arbitrary;
 use serde::{Deserialize, Serialize};
 
-use crate::model::{Class, Customization, Gender, Race, Region};
+use crate::model::{Angle, Class, Customization, Gender, Race, Region, Vec3};
+#[cfg(test)]
+use crate::protocol::packet::strategy::{array, ucs2_string};
+use crate::protocol::packet::Packet;
 
-#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = C_CAN_CREATE_USER, target = Global)]
 pub struct CCanCreateUser {}
 
-#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = C_CANCEL_DELETE_USER, target = Global)]
+pub struct CCancelDeleteUser {
+    pub db_id: i32,
+}
+
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = C_CHAT, target = Local)]
+pub struct CChat {
+    #[cfg_attr(test, proptest(strategy = "ucs2_string()"))]
+    pub message: String,
+    pub channel: u32,
+}
+
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = C_CHECK_VERSION, target = Global)]
 pub struct CCheckVersion {
+    #[cfg_attr(test, proptest(strategy = "array::<CCheckVersionEntry>()"))]
     pub version: Vec<CCheckVersionEntry>,
 }
 
 #[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+#[cfg_attr(test, derive(Arbitrary))]
 pub struct CCheckVersionEntry {
     pub index: i32,
     pub value: i32,
 }
 
-#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = C_CHECK_USERNAME, target = Global)]
 pub struct CCheckUserName {
+    #[cfg_attr(test, proptest(strategy = "ucs2_string()"))]
     pub name: String,
 }
 
-#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = C_CREATE_USER, target = Global)]
 pub struct CCreateUser {
+    #[cfg_attr(test, proptest(strategy = "ucs2_string()"))]
     pub name: String,
     #[serde(with = "serde_bytes")]
     pub details: Vec<u8>,
@@ -37,17 +70,36 @@ pub struct CCreateUser {
     pub appearance2: u32,
 }
 
-#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = C_DELETE_USER, target = Global)]
+pub struct CDeleteUser {
+    pub db_id: i32,
+}
+
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = C_GET_USER_LIST, target = Global)]
 pub struct CGetUserList {}
 
-#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = C_GET_USER_GUILD_LOGO, target = Global)]
 pub struct CGetUserGuildLogo {
     pub player_id: i32,
     pub guild_id: i32,
 }
 
-#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = C_LOAD_TOPO_FIN, target = Local)]
+pub struct CLoadTopoFin {}
+
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = C_LOGIN_ARBITER, target = Global)]
 pub struct CLoginArbiter {
+    #[cfg_attr(test, proptest(strategy = "ucs2_string()"))]
     pub master_account_name: String,
     #[serde(with = "serde_bytes")]
     pub ticket: Vec<u8>,
@@ -57,18 +109,54 @@ pub struct CLoginArbiter {
     pub patch_version: i32,
 }
 
-#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = C_PLAYER_LOCATION, target = Local)]
+pub struct CPlayerLocation {
+    pub location: Vec3,
+    pub rotation: Angle,
+    pub look_direction: i16,
+    pub destination: Vec3,
+    pub move_type: i32, // TODO enum: 0 = run, 2 = walk, 5 = jump, 7 = stop, 10 = fall ...
+    pub jump_distance: i16,
+    pub in_shuttle: bool,
+    pub time: u32, // Client time in ms
+}
+
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = C_PONG, target = Global)]
 pub struct CPong {}
 
-#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = C_SELECT_USER, target = Global)]
+pub struct CSelectUser {
+    pub db_id: i32,
+    pub unk: u8,
+}
+
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = C_SET_VISIBLE_RANGE, target = Global)]
 pub struct CSetVisibleRange {
     pub range: u32,
 }
 
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = C_WHISPER, target = Global)]
+pub struct CWhisper {
+    #[cfg_attr(test, proptest(strategy = "ucs2_string()"))]
+    pub target: String,
+    #[cfg_attr(test, proptest(strategy = "ucs2_string()"))]
+    pub message: String,
+}
+
 #[cfg(test)]
 #[macro_use]
 mod tests {
-    use crate::model::{Class, Customization, Gender, Race, Region};
+    use crate::model::{Class, Customization, Gender, Race, Region, Vec3};
     use crate::protocol::serde::{from_vec, to_vec, Result};
 
     use super::*;
@@ -79,6 +167,27 @@ mod tests {
         expected: CCanCreateUser {}
     );
 
+    packet_test!(
+        name: test_cancel_delete_user,
+        data: vec![0x2d, 0x8c, 0x1e, 0x0],
+        expected: CCancelDeleteUser {
+            db_id: 2_001_965,
+        }
+    );
+
+    packet_test!(
+        name: test_chat,
+        data: vec![
+            0xa, 0x0, 0x0, 0x0, 0x0, 0x0, 0x3c, 0x0, 0x46, 0x0, 0x4f, 0x0, 0x4e, 0x0, 0x54, 0x0,
+            0x3e, 0x0, 0x48, 0x0, 0x65, 0x0, 0x6c, 0x0, 0x6c, 0x0, 0x6f, 0x0, 0x3c, 0x0, 0x2f, 0x0,
+            0x46, 0x0, 0x4f, 0x0, 0x4e, 0x0, 0x54, 0x0, 0x3e, 0x0, 0x0, 0x0,
+        ],
+        expected: CChat {
+            message: "<FONT>Hello</FONT>".to_string(),
+            channel: 0,
+        }
+    );
+
     packet_test!(
         name: test_check_version,
         data: vec![
@@ -145,6 +254,14 @@ mod tests {
         }
     );
 
+    packet_test!(
+        name: test_delete_user,
+        data: vec![0x2d, 0x8c, 0x1e, 0x0],
+        expected: CDeleteUser {
+            db_id: 2_001_965,
+        }
+    );
+
     packet_test!(
         name: test_get_user_guild_logo,
         data: vec![0x1, 0x2f, 0x31, 0x1, 0x75, 0xe, 0x0, 0x0],
@@ -160,6 +277,12 @@ mod tests {
         expected: CGetUserList {}
     );
 
+    packet_test!(
+        name: test_load_topo_fin,
+        data: vec![],
+        expected: CLoadTopoFin {}
+    );
+
     packet_test!(
         name: test_login_arbiter,
         data: vec![
@@ -185,12 +308,48 @@ mod tests {
         }
     );
 
+    packet_test!(
+        name: test_player_location,
+        data: vec![
+            0x0, 0x9a, 0xb6, 0x47, 0x0, 0x4c, 0xac, 0xc7, 0x0, 0xcc, 0x8e, 0xc5, 0x0, 0xc0, 0x0, 0x0,
+            0x0, 0x9e, 0xb6, 0x47, 0x0, 0x4c, 0xac, 0xc7, 0x0, 0xcc, 0x8e, 0xc5, 0x7, 0x0, 0x0, 0x0,
+            0x0, 0x0, 0x0, 0x4e, 0x61, 0xbc, 0x0,
+        ],
+        expected: CPlayerLocation {
+            location: Vec3 {
+                x: 93492.0,
+                y: -88216.0,
+                z: -4569.5,
+            },
+            rotation: -16384,
+            look_direction: 0,
+            destination: Vec3 {
+                x: 93500.0,
+                y: -88216.0,
+                z: -4569.5,
+            },
+            move_type: 7,
+            jump_distance: 0,
+            in_shuttle: false,
+            time: 12_345_678,
+        }
+    );
+
     packet_test!(
         name: test_pong,
         data: vec![],
         expected: CPong {}
     );
 
+    packet_test!(
+        name: test_select_user,
+        data: vec![0x2d, 0x8c, 0x1e, 0x0, 0x0],
+        expected: CSelectUser {
+            db_id: 2_001_965,
+            unk: 0,
+        }
+    );
+
     packet_test!(
         name: test_set_visible_range,
         data: vec![0xd0, 0x7, 0x0, 0x0],
@@ -198,4 +357,17 @@ mod tests {
             range: 2000,
         }
     );
+
+    packet_test!(
+        name: test_whisper,
+        data: vec![
+            0x8, 0x0, 0x14, 0x0, 0x41, 0x0, 0x73, 0x0, 0x75, 0x0, 0x6e, 0x0, 0x61, 0x0, 0x0, 0x0,
+            0x3c, 0x0, 0x46, 0x0, 0x4f, 0x0, 0x4e, 0x0, 0x54, 0x0, 0x3e, 0x0, 0x48, 0x0, 0x69, 0x0,
+            0x3c, 0x0, 0x2f, 0x0, 0x46, 0x0, 0x4f, 0x0, 0x4e, 0x0, 0x54, 0x0, 0x3e, 0x0, 0x0, 0x0,
+        ],
+        expected: CWhisper {
+            target: "Asuna".to_string(),
+            message: "<FONT>Hi</FONT>".to_string(),
+        }
+    );
 }
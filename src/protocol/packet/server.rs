@@ -1,41 +1,98 @@
 /// Module for server network packages.
+#[cfg(test)]
+use proptest_derive::Arbitrary;
 use serde::{Deserialize, Serialize};
 
-use crate::model::{Class, Customization, Gender, Race, Region, Vec3, Vec3a};
+use crate::model::{Angle, Class, Customization, Gender, Race, Region, Vec3, Vec3a};
+#[cfg(test)]
+use crate::protocol::packet::strategy::{array, ucs2_string};
+use crate::protocol::packet::Packet;
 
-#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = S_ACCOUNT_PACKAGE_LIST, target = Connection)]
 pub struct SAccountPackageList {
+    #[cfg_attr(test, proptest(strategy = "array::<SAccountPackageListEntry>()"))]
     pub account_benefits: Vec<SAccountPackageListEntry>,
 }
 
 #[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+#[cfg_attr(test, derive(Arbitrary))]
 pub struct SAccountPackageListEntry {
     pub package_id: u32,
     pub expiration_date: i64,
 }
 
-#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = S_CAN_CREATE_USER, target = Connection)]
 pub struct SCanCreateUser {
     pub ok: bool,
 }
 
-#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = S_CANCEL_DELETE_USER, target = Connection)]
+pub struct SCancelDeleteUser {
+    pub ok: bool,
+}
+
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = S_CHAT, target = Connection)]
+pub struct SChat {
+    #[cfg_attr(test, proptest(strategy = "ucs2_string()"))]
+    pub name: String,
+    #[cfg_attr(test, proptest(strategy = "ucs2_string()"))]
+    pub message: String,
+    pub channel: u32,
+    pub author_id: u64,
+    pub unk1: bool,
+    pub gm: bool,
+    pub unk2: bool,
+}
+
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = S_CHECK_VERSION, target = Connection)]
 pub struct SCheckVersion {
     pub ok: bool,
 }
 
-#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = S_CHECK_USERNAME, target = Connection)]
 pub struct SCheckUserName {
     pub ok: bool,
 }
 
-#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = S_CREATE_USER, target = Connection)]
 pub struct SCreateUser {
     pub ok: bool,
 }
 
-#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = S_DELETE_USER, target = Connection)]
+pub struct SDeleteUser {
+    pub ok: bool,
+}
+
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = S_DESPAWN_USER, target = Connection)]
+pub struct SDespawnUser {
+    pub game_id: u64,
+    pub unk: u32,
+}
+
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = S_GET_USER_LIST, target = Global)]
 pub struct SGetUserList {
+    #[cfg_attr(test, proptest(strategy = "array::<SGetUserListCharacter>()"))]
     pub characters: Vec<SGetUserListCharacter>,
     pub veteran: bool,
     pub bonus_buf_sec: i32,
@@ -49,13 +106,20 @@ pub struct SGetUserList {
 }
 
 #[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+#[cfg_attr(test, derive(Arbitrary))]
 pub struct SGetUserListCharacter {
+    #[cfg_attr(
+        test,
+        proptest(strategy = "array::<SGetUserListCharacterCustomString>()")
+    )]
     pub custom_strings: Vec<SGetUserListCharacterCustomString>,
+    #[cfg_attr(test, proptest(strategy = "ucs2_string()"))]
     pub name: String,
     #[serde(with = "serde_bytes")]
     pub details: Vec<u8>,
     #[serde(with = "serde_bytes")]
     pub shape: Vec<u8>,
+    #[cfg_attr(test, proptest(strategy = "ucs2_string()"))]
     pub guild_name: String,
     pub db_id: i32,
     pub gender: Gender,
@@ -153,53 +217,159 @@ pub struct SGetUserListCharacter {
 }
 
 #[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+#[cfg_attr(test, derive(Arbitrary))]
 pub struct SGetUserListCharacterCustomString {
+    #[cfg_attr(test, proptest(strategy = "ucs2_string()"))]
     pub string: String,
     pub id: i32,
 }
 
-#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = S_GUILD_NAME, target = Connection)]
 pub struct SGuildName {
+    #[cfg_attr(test, proptest(strategy = "ucs2_string()"))]
     pub guild_name: String,
+    #[cfg_attr(test, proptest(strategy = "ucs2_string()"))]
     pub guild_rank: String,
+    #[cfg_attr(test, proptest(strategy = "ucs2_string()"))]
     pub guild_title: String,
+    #[cfg_attr(test, proptest(strategy = "ucs2_string()"))]
     pub guild_logo: String,
     pub game_id: u64,
 }
 
-#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = S_IMAGE_DATA, target = Connection)]
 pub struct SImageData {
+    #[cfg_attr(test, proptest(strategy = "ucs2_string()"))]
     pub name: String,
 
     #[serde(with = "serde_bytes")]
     pub data: Vec<u8>,
 }
 
-#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = S_ITEM_CUSTOM_STRING, target = Connection)]
 pub struct SItemCustomString {
+    #[cfg_attr(test, proptest(strategy = "array::<SItemCustomStringEntry>()"))]
     pub custom_strings: Vec<SItemCustomStringEntry>,
     pub game_id: u64,
 }
 
 #[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+#[cfg_attr(test, derive(Arbitrary))]
 pub struct SItemCustomStringEntry {
+    #[cfg_attr(test, proptest(strategy = "ucs2_string()"))]
     pub string: String,
     pub id: i32,
 }
 
-#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = S_LOADING_SCREEN_CONTROL_INFO, target = Connection)]
 pub struct SLoadingScreenControlInfo {
     pub custom_screen_enabled: bool,
 }
 
-#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = S_LOAD_TOPO, target = Connection)]
+pub struct SLoadTopo {
+    pub zone: i32,
+    pub location: Vec3,
+    pub quick: bool,
+}
+
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = S_LOGIN, target = Connection)]
+pub struct SLogin {
+    #[cfg_attr(test, proptest(strategy = "ucs2_string()"))]
+    pub name: String,
+    #[serde(with = "serde_bytes")]
+    pub details: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    pub shape: Vec<u8>,
+    pub template_id: i32,
+    pub game_id: u64,
+    pub server_id: i32,
+    pub player_id: i32,
+    pub relation: i32,
+    pub alive: bool,
+    pub status: i32,
+    pub walk_speed: i32,
+    pub run_speed: i32,
+    pub appearance: Customization,
+    pub visible: bool,
+    pub is_second_character: bool,
+    pub level: i16,
+    pub gather_enchant: i16,
+    pub gather_unk: i16,
+    pub gather_plant: i16,
+    pub gather_mining: i16,
+    pub gather_energy: i16,
+    pub gather_bug: i16,
+    pub exp: i64,
+    pub total_exp: i64,
+    pub rest_current: i64,
+    pub rest_max: i64,
+    pub exp_bonus_percent: f32,
+    pub drop_bonus: i32,
+    pub weapon: i32,
+    pub body: i32,
+    pub hand: i32,
+    pub feet: i32,
+    pub underwear: i32,
+    pub head: i32,
+    pub face: i32,
+    pub server_time: i64,
+    pub is_pk: bool,
+    pub title_id: i32,
+    pub weapon_model: i32,
+    pub body_model: i32,
+    pub hand_model: i32,
+    pub feet_model: i32,
+    pub weapon_dye: i32,
+    pub body_dye: i32,
+    pub hand_dye: i32,
+    pub feet_dye: i32,
+    pub underwear_dye: i32,
+    pub style_back_dye: i32,
+    pub style_head_dye: i32,
+    pub style_face_dye: i32,
+    pub weapon_enchant: i32,
+    pub world_event_target: bool,
+    pub infamy: i32,
+    pub show_face: bool,
+    pub style_head: i32,
+    pub style_face: i32,
+    pub style_back: i32,
+    pub style_weapon: i32,
+    pub style_body: i32,
+    pub style_footprint: i32,
+    pub style_body_dye: i32,
+    pub show_style: bool,
+    pub appearance2: i32,
+    pub scale: f32,
+    pub guild_logo_id: i32,
+}
+
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = S_LOGIN_ACCOUNT_INFO, target = Connection)]
 pub struct SLoginAccountInfo {
+    #[cfg_attr(test, proptest(strategy = "ucs2_string()"))]
     pub server_name: String,
     pub account_id: i64,
     pub integrity_iv: u32, // IV for the custom hash function of some client packets
 }
 
-#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = S_LOGIN_ARBITER, target = Connection)]
 pub struct SLoginArbiter {
     pub success: bool,
     pub login_queue: bool,
@@ -211,10 +381,14 @@ pub struct SLoginArbiter {
     pub unk3: u16, // 0
 }
 
-#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = S_PING, target = Connection)]
 pub struct SPing {}
 
-#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = S_REMAIN_PLAY_TIME, target = Connection)]
 pub struct SRemainPlayTime {
     // 1 = P2P (active subscription)
     // 2 = P2P (no active subscription),
@@ -225,6 +399,124 @@ pub struct SRemainPlayTime {
     pub minutes_left: u32,
 }
 
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = S_SPAWN_ME, target = Connection)]
+pub struct SSpawnMe {
+    pub game_id: u64,
+    pub location: Vec3,
+    pub rotation: Angle,
+    pub alive: bool,
+    pub unk: bool,
+}
+
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = S_SPAWN_USER, target = Connection)]
+pub struct SSpawnUser {
+    #[cfg_attr(test, proptest(strategy = "ucs2_string()"))]
+    pub name: String,
+    #[cfg_attr(test, proptest(strategy = "ucs2_string()"))]
+    pub guild_name: String,
+    #[cfg_attr(test, proptest(strategy = "ucs2_string()"))]
+    pub guild_rank: String,
+    #[serde(with = "serde_bytes")]
+    pub details: Vec<u8>,
+    #[cfg_attr(test, proptest(strategy = "ucs2_string()"))]
+    pub guild_title: String,
+    #[cfg_attr(test, proptest(strategy = "ucs2_string()"))]
+    pub guild_logo: String,
+    #[serde(with = "serde_bytes")]
+    pub shape: Vec<u8>,
+    pub server_id: i32,
+    pub player_id: i32,
+    pub game_id: u64,
+    pub location: Vec3,
+    pub rotation: Angle,
+    pub relation: i32,
+    pub template_id: i32,
+    pub visible: bool,
+    pub alive: bool,
+    pub appearance: Customization,
+    pub spawn_fx: bool,
+    pub mount: i32,
+    pub pose: i32,
+    pub title_id: i32,
+    pub level: i16,
+    pub gm: bool,
+    pub gm_invisible: bool,
+    pub weapon: i32,
+    pub body: i32,
+    pub hand: i32,
+    pub feet: i32,
+    pub underwear: i32,
+    pub head: i32,
+    pub face: i32,
+    pub weapon_model: i32,
+    pub body_model: i32,
+    pub hand_model: i32,
+    pub feet_model: i32,
+    pub weapon_dye: i32,
+    pub body_dye: i32,
+    pub hand_dye: i32,
+    pub feet_dye: i32,
+    pub underwear_dye: i32,
+    pub style_head: i32,
+    pub style_face: i32,
+    pub style_back: i32,
+    pub style_weapon: i32,
+    pub style_body: i32,
+    pub style_footprint: i32,
+    pub weapon_enchant: i32,
+    pub is_pk: bool,
+    pub infamy: i32,
+    pub show_face: bool,
+    pub show_style: bool,
+    pub appearance2: i32,
+    pub scale: f32,
+    pub guild_logo_id: i32,
+}
+
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = S_SYSTEM_MESSAGE, target = Connection)]
+pub struct SSystemMessage {
+    // Use protocol::system_message::SystemMessage to build the message.
+    #[cfg_attr(test, proptest(strategy = "ucs2_string()"))]
+    pub message: String,
+}
+
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = S_USER_LOCATION, target = Connection)]
+pub struct SUserLocation {
+    pub game_id: u64,
+    pub location: Vec3,
+    pub rotation: Angle,
+    pub look_direction: i16,
+    pub speed: i16,
+    pub destination: Vec3,
+    pub move_type: i32,
+    pub in_shuttle: bool,
+    pub time: u32,
+}
+
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Packet)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[packet(opcode = S_WHISPER, target = Connection)]
+pub struct SWhisper {
+    #[cfg_attr(test, proptest(strategy = "ucs2_string()"))]
+    pub author: String,
+    #[cfg_attr(test, proptest(strategy = "ucs2_string()"))]
+    pub recipient: String,
+    #[cfg_attr(test, proptest(strategy = "ucs2_string()"))]
+    pub message: String,
+    pub game_id: u64,
+    pub unk1: bool,
+    pub gm: bool,
+    pub unk2: bool,
+}
+
 #[cfg(test)]
 #[macro_use]
 mod tests {
@@ -256,6 +548,36 @@ mod tests {
         }
     );
 
+    packet_test!(
+        name: test_cancel_delete_user,
+        data: vec![
+            0x1,
+        ],
+        expected: SCancelDeleteUser {
+            ok: true,
+        }
+    );
+
+    packet_test!(
+        name: test_chat,
+        data: vec![
+            0x17, 0x0, 0x29, 0x0, 0x0, 0x0, 0x0, 0x0, 0x2d, 0x8c, 0x1e, 0x0, 0x0, 0x0, 0x0, 0x0,
+            0x0, 0x0, 0x0, 0x41, 0x0, 0x6c, 0x0, 0x6d, 0x0, 0x65, 0x0, 0x74, 0x0, 0x69, 0x0, 0x63,
+            0x0, 0x61, 0x0, 0x0, 0x0, 0x3c, 0x0, 0x46, 0x0, 0x4f, 0x0, 0x4e, 0x0, 0x54, 0x0, 0x3e,
+            0x0, 0x48, 0x0, 0x65, 0x0, 0x6c, 0x0, 0x6c, 0x0, 0x6f, 0x0, 0x3c, 0x0, 0x2f, 0x0, 0x46,
+            0x0, 0x4f, 0x0, 0x4e, 0x0, 0x54, 0x0, 0x3e, 0x0, 0x0, 0x0,
+        ],
+        expected: SChat {
+            name: "Almetica".to_string(),
+            message: "<FONT>Hello</FONT>".to_string(),
+            channel: 0,
+            author_id: 2_001_965,
+            unk1: false,
+            gm: false,
+            unk2: false,
+        }
+    );
+
     packet_test!(
         name: test_check_username,
         data: vec![
@@ -286,6 +608,27 @@ mod tests {
         }
     );
 
+    packet_test!(
+        name: test_delete_user,
+        data: vec![
+            0x1
+        ],
+        expected: SDeleteUser {
+            ok: true,
+        }
+    );
+
+    packet_test!(
+        name: test_despawn_user,
+        data: vec![
+            0x2d, 0x8c, 0x1e, 0x0, 0x0, 0x0, 0x0, 0x0, 0x1, 0x0, 0x0, 0x0,
+        ],
+        expected: SDespawnUser {
+            game_id: 2_001_965,
+            unk: 1,
+        }
+    );
+
     packet_test!(
         name: test_item_custom_string1,
         data: vec![
@@ -515,6 +858,116 @@ mod tests {
         }
     );
 
+    packet_test!(
+        name: test_load_topo,
+        data: vec![
+            0xd, 0x0, 0x0, 0x0, 0x0, 0x9a, 0xb6, 0x47, 0x0, 0x4c, 0xac, 0xc7, 0x0, 0xcc, 0x8e, 0xc5,
+            0x0,
+        ],
+        expected: SLoadTopo {
+            zone: 13,
+            location: Vec3 {
+                x: 93492.0,
+                y: -88216.0,
+                z: -4569.5,
+            },
+            quick: false,
+        }
+    );
+
+    packet_test!(
+        name: test_login,
+        data: vec![
+            0xff, 0x0, 0x11, 0x1, 0x4, 0x0, 0x15, 0x1, 0x4, 0x0, 0xd3, 0x29, 0x0, 0x0, 0x2d, 0x8c,
+            0x1e, 0x0, 0x0, 0x0, 0x0, 0x0, 0x1, 0x0, 0x0, 0x0, 0x2d, 0x8c, 0x1e, 0x0, 0x0, 0x0,
+            0x0, 0x0, 0x1, 0x0, 0x0, 0x0, 0x0, 0x32, 0x0, 0x0, 0x0, 0x96, 0x0, 0x0, 0x0, 0x1,
+            0x2, 0x3, 0x4, 0x5, 0x6, 0x7, 0x8, 0x1, 0x0, 0x1, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0,
+            0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x48,
+            0x3, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0,
+            0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x80, 0x3f, 0x0, 0x0, 0x0, 0x0, 0x0,
+            0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0,
+            0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0xf1, 0xe, 0x6b, 0x5e, 0x0,
+            0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0,
+            0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0,
+            0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0,
+            0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0,
+            0x0, 0x1, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0,
+            0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x1, 0x64,
+            0x0, 0x0, 0x0, 0x0, 0x0, 0x80, 0x3f, 0x0, 0x0, 0x0, 0x0, 0x41, 0x0, 0x6c, 0x0, 0x6d,
+            0x0, 0x65, 0x0, 0x74, 0x0, 0x69, 0x0, 0x63, 0x0, 0x61, 0x0, 0x0, 0x0, 0x1, 0x2, 0x3,
+            0x4, 0x5, 0x6, 0x7, 0x8,
+        ],
+        expected: SLogin {
+            name: "Almetica".to_string(),
+            details: vec![1, 2, 3, 4],
+            shape: vec![5, 6, 7, 8],
+            template_id: 10_707,
+            game_id: 2_001_965,
+            server_id: 1,
+            player_id: 2_001_965,
+            relation: 0,
+            alive: true,
+            status: 0,
+            walk_speed: 50,
+            run_speed: 150,
+            appearance: Customization {
+                data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+            },
+            visible: true,
+            is_second_character: false,
+            level: 1,
+            gather_enchant: 0,
+            gather_unk: 0,
+            gather_plant: 0,
+            gather_mining: 0,
+            gather_energy: 0,
+            gather_bug: 0,
+            exp: 0,
+            total_exp: 840,
+            rest_current: 0,
+            rest_max: 0,
+            exp_bonus_percent: 1.0,
+            drop_bonus: 0,
+            weapon: 0,
+            body: 0,
+            hand: 0,
+            feet: 0,
+            underwear: 0,
+            head: 0,
+            face: 0,
+            server_time: 1_584_074_481,
+            is_pk: false,
+            title_id: 0,
+            weapon_model: 0,
+            body_model: 0,
+            hand_model: 0,
+            feet_model: 0,
+            weapon_dye: 0,
+            body_dye: 0,
+            hand_dye: 0,
+            feet_dye: 0,
+            underwear_dye: 0,
+            style_back_dye: 0,
+            style_head_dye: 0,
+            style_face_dye: 0,
+            weapon_enchant: 0,
+            world_event_target: false,
+            infamy: 0,
+            show_face: true,
+            style_head: 0,
+            style_face: 0,
+            style_back: 0,
+            style_weapon: 0,
+            style_body: 0,
+            style_footprint: 0,
+            style_body_dye: 0,
+            show_style: true,
+            appearance2: 100,
+            scale: 1.0,
+            guild_logo_id: 0,
+        }
+    );
+
     packet_test!(
         name: test_login_arbiter,
         data: vec![
@@ -549,6 +1002,168 @@ mod tests {
         }
     );
 
+    packet_test!(
+        name: test_spawn_me,
+        data: vec![
+            0x2d, 0x8c, 0x1e, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x9a, 0xb6, 0x47, 0x0, 0x4c, 0xac, 0xc7,
+            0x0, 0xcc, 0x8e, 0xc5, 0x0, 0xc0, 0x1, 0x0,
+        ],
+        expected: SSpawnMe {
+            game_id: 2_001_965,
+            location: Vec3 {
+                x: 93492.0,
+                y: -88216.0,
+                z: -4569.5,
+            },
+            rotation: -16384,
+            alive: true,
+            unk: false,
+        }
+    );
+
+    packet_test!(
+        name: test_spawn_user,
+        data: vec![
+            0xc6, 0x0, 0xd8, 0x0, 0xda, 0x0, 0xdc, 0x0, 0x4, 0x0, 0xe0, 0x0, 0xe2, 0x0, 0xe4, 0x0,
+            0x4, 0x0, 0x1, 0x0, 0x0, 0x0, 0x2d, 0x8c, 0x1e, 0x0, 0x2d, 0x8c, 0x1e, 0x0, 0x0, 0x0,
+            0x0, 0x0, 0x0, 0x9a, 0xb6, 0x47, 0x0, 0x4c, 0xac, 0xc7, 0x0, 0xcc, 0x8e, 0xc5, 0x0, 0xc0,
+            0x0, 0x0, 0x0, 0x0, 0xd3, 0x29, 0x0, 0x0, 0x1, 0x1, 0x1, 0x2, 0x3, 0x4, 0x5, 0x6,
+            0x7, 0x8, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x1,
+            0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0,
+            0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0,
+            0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0,
+            0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0,
+            0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0,
+            0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0,
+            0x0, 0x0, 0x0, 0x0, 0x1, 0x1, 0x64, 0x0, 0x0, 0x0, 0x0, 0x0, 0x80, 0x3f, 0x0, 0x0,
+            0x0, 0x0, 0x41, 0x0, 0x6c, 0x0, 0x6d, 0x0, 0x65, 0x0, 0x74, 0x0, 0x69, 0x0, 0x63, 0x0,
+            0x61, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x1, 0x2, 0x3, 0x4, 0x0, 0x0, 0x0, 0x0,
+            0x5, 0x6, 0x7, 0x8,
+        ],
+        expected: SSpawnUser {
+            name: "Almetica".to_string(),
+            guild_name: "".to_string(),
+            guild_rank: "".to_string(),
+            details: vec![1, 2, 3, 4],
+            guild_title: "".to_string(),
+            guild_logo: "".to_string(),
+            shape: vec![5, 6, 7, 8],
+            server_id: 1,
+            player_id: 2_001_965,
+            game_id: 2_001_965,
+            location: Vec3 {
+                x: 93492.0,
+                y: -88216.0,
+                z: -4569.5,
+            },
+            rotation: -16384,
+            relation: 0,
+            template_id: 10_707,
+            visible: true,
+            alive: true,
+            appearance: Customization {
+                data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+            },
+            spawn_fx: false,
+            mount: 0,
+            pose: 0,
+            title_id: 0,
+            level: 1,
+            gm: false,
+            gm_invisible: false,
+            weapon: 0,
+            body: 0,
+            hand: 0,
+            feet: 0,
+            underwear: 0,
+            head: 0,
+            face: 0,
+            weapon_model: 0,
+            body_model: 0,
+            hand_model: 0,
+            feet_model: 0,
+            weapon_dye: 0,
+            body_dye: 0,
+            hand_dye: 0,
+            feet_dye: 0,
+            underwear_dye: 0,
+            style_head: 0,
+            style_face: 0,
+            style_back: 0,
+            style_weapon: 0,
+            style_body: 0,
+            style_footprint: 0,
+            weapon_enchant: 0,
+            is_pk: false,
+            infamy: 0,
+            show_face: true,
+            show_style: true,
+            appearance2: 100,
+            scale: 1.0,
+            guild_logo_id: 0,
+        }
+    );
+
+    packet_test!(
+        name: test_user_location,
+        data: vec![
+            0x2d, 0x8c, 0x1e, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x9a, 0xb6, 0x47, 0x0, 0x4c, 0xac, 0xc7,
+            0x0, 0xcc, 0x8e, 0xc5, 0x0, 0xc0, 0x0, 0x0, 0x96, 0x0, 0x0, 0x9e, 0xb6, 0x47, 0x0, 0x4c,
+            0xac, 0xc7, 0x0, 0xcc, 0x8e, 0xc5, 0x7, 0x0, 0x0, 0x0, 0x0, 0x4e, 0x61, 0xbc, 0x0,
+        ],
+        expected: SUserLocation {
+            game_id: 2_001_965,
+            location: Vec3 {
+                x: 93492.0,
+                y: -88216.0,
+                z: -4569.5,
+            },
+            rotation: -16384,
+            look_direction: 0,
+            speed: 150,
+            destination: Vec3 {
+                x: 93500.0,
+                y: -88216.0,
+                z: -4569.5,
+            },
+            move_type: 7,
+            in_shuttle: false,
+            time: 12_345_678,
+        }
+    );
+
+    packet_test!(
+        name: test_system_message,
+        data: vec![
+            0x6, 0x0, 0x40, 0x0, 0x32, 0x0, 0xb, 0x0, 0x55, 0x0, 0x73, 0x0, 0x65, 0x0, 0x72, 0x0,
+            0x4e, 0x0, 0x61, 0x0, 0x6d, 0x0, 0x65, 0x0, 0xb, 0x0, 0x41, 0x0, 0x73, 0x0, 0x75, 0x0,
+            0x6e, 0x0, 0x61, 0x0, 0x0, 0x0,
+        ],
+        expected: SSystemMessage {
+            message: "@2\u{b}UserName\u{b}Asuna".to_string(),
+        }
+    );
+
+    packet_test!(
+        name: test_whisper,
+        data: vec![
+            0x15, 0x0, 0x27, 0x0, 0x33, 0x0, 0x2d, 0x8c, 0x1e, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0,
+            0x0, 0x41, 0x0, 0x6c, 0x0, 0x6d, 0x0, 0x65, 0x0, 0x74, 0x0, 0x69, 0x0, 0x63, 0x0, 0x61,
+            0x0, 0x0, 0x0, 0x41, 0x0, 0x73, 0x0, 0x75, 0x0, 0x6e, 0x0, 0x61, 0x0, 0x0, 0x0, 0x3c,
+            0x0, 0x46, 0x0, 0x4f, 0x0, 0x4e, 0x0, 0x54, 0x0, 0x3e, 0x0, 0x48, 0x0, 0x69, 0x0, 0x3c,
+            0x0, 0x2f, 0x0, 0x46, 0x0, 0x4f, 0x0, 0x4e, 0x0, 0x54, 0x0, 0x3e, 0x0, 0x0, 0x0,
+        ],
+        expected: SWhisper {
+            author: "Almetica".to_string(),
+            recipient: "Asuna".to_string(),
+            message: "<FONT>Hi</FONT>".to_string(),
+            game_id: 2_001_965,
+            unk1: false,
+            gm: false,
+            unk2: false,
+        }
+    );
+
     packet_test!(
         name: test_login_account_info,
         data: vec![
@@ -3,6 +3,22 @@ mod de;
 mod error;
 mod ser;
 
-pub use de::{from_vec, Deserializer};
+pub use de::{from_vec, from_vec_with_layout, from_vec_with_offset, Deserializer};
 pub use error::{Error, Result};
-pub use ser::{to_vec, Serializer};
+pub use ser::{to_vec, to_vec_with_layout, to_vec_with_offset, Serializer};
+
+/// Name of the newtype struct that holds a skill id. Its length is defined by the packet layout.
+pub const SKILL_ID: &str = "SkillId";
+
+/// Layout differences of the packets between the client versions.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PacketLayout {
+    /// Length of a skill id in bytes (4 bytes before patch 74, 8 bytes since then).
+    pub skill_id_length: usize,
+}
+
+impl Default for PacketLayout {
+    fn default() -> Self {
+        PacketLayout { skill_id_length: 8 }
+    }
+}
@@ -6,16 +6,26 @@ use serde::de::IntoDeserializer;
 use serde::{self, Deserialize};
 
 use super::error::{Error, Result};
+use super::{PacketLayout, SKILL_ID};
+
+/// Maximal number of elements an array can contain.
+const MAX_ARRAY_LENGTH: usize = 1024;
+
+/// Maximal number of characters a string can contain.
+const MAX_STRING_LENGTH: usize = 4096;
 
 /// A Deserializer that reads bytes from a vector.
+/// All reads are bounds checked, so that malformed client data results in an error.
 #[derive(Clone, Debug)]
 pub struct Deserializer {
     data: Vec<u8>,
     pos: usize,
+    data_offset: usize,
+    layout: PacketLayout,
+    // End of the furthest data that was read.
+    consumed: usize,
 }
 
-// TODO we are currently too trustworthy with the client data and need to fet it more (we sometimes can get out of a slice boundary!)
-
 /// Parses the given `Vec<u8>`
 pub fn from_vec<'a, T>(v: Vec<u8>) -> Result<T>
 where
@@ -26,20 +36,93 @@ where
     Ok(t)
 }
 
+/// Parses the given `Vec<u8>` that starts `data_offset` bytes after the start of the packet.
+/// Returns the value and the number of bytes that were consumed, so that trailing data can be
+/// detected.
+pub fn from_vec_with_offset<'a, T>(v: Vec<u8>, data_offset: usize) -> Result<(T, usize)>
+where
+    T: Deserialize<'a>,
+{
+    from_vec_with_layout(v, data_offset, PacketLayout::default())
+}
+
+/// Parses the given `Vec<u8>` like `from_vec_with_offset` with the packet layout of a client version.
+pub fn from_vec_with_layout<'a, T>(
+    v: Vec<u8>,
+    data_offset: usize,
+    layout: PacketLayout,
+) -> Result<(T, usize)>
+where
+    T: Deserialize<'a>,
+{
+    let mut deserializer = Deserializer::with_layout(v, data_offset, layout);
+    let t = T::deserialize(&mut deserializer)?;
+    Ok((t, deserializer.consumed()))
+}
+
 impl<'de> Deserializer {
     /// Creates a new Deserializer with a given `Vec<u8>`.
     pub fn from_vec(r: Vec<u8>) -> Self {
-        Deserializer { data: r, pos: 0 }
+        // The array we have doesn't include the leading length / opcode u16, so 4 bytes
+        Deserializer::with_offset(r, 4)
+    }
+
+    /// Creates a new Deserializer with a given `Vec<u8>` that starts `data_offset` bytes after the
+    /// start of the packet. The offsets inside the packet are relative to the start of the packet.
+    pub fn with_offset(r: Vec<u8>, data_offset: usize) -> Self {
+        Deserializer::with_layout(r, data_offset, PacketLayout::default())
     }
 
-    fn abs_offset(&self, offset: usize) -> usize {
-        // The array we have doesn't include the leading opcode / length u16, so -4 bytes
-        if offset == 0 {
-            offset
-        } else {
-            offset - 4
+    /// Creates a new Deserializer like `with_offset` that reads the packet layout of a client version.
+    pub fn with_layout(r: Vec<u8>, data_offset: usize, layout: PacketLayout) -> Self {
+        Deserializer {
+            data: r,
+            pos: 0,
+            data_offset,
+            layout,
+            consumed: 0,
         }
     }
+
+    /// Returns the number of bytes from the start of the data up to the end of the furthest data
+    /// that was read.
+    pub fn consumed(&self) -> usize {
+        self.consumed
+    }
+
+    fn mark_consumed(&mut self, end: usize) {
+        self.consumed = self.consumed.max(end);
+    }
+
+    fn abs_offset(&self, offset: usize) -> Result<usize> {
+        match offset {
+            0 => Ok(offset),
+            _ if offset < self.data_offset => Err(Error::InvalidOffset(self.pos, offset)),
+            _ => Ok(offset - self.data_offset),
+        }
+    }
+
+    /// Returns the next `size` bytes and advances the position.
+    fn read(&mut self, size: usize) -> Result<&[u8]> {
+        let start = self.pos;
+        let end = start
+            .checked_add(size)
+            .filter(|end| *end <= self.data.len())
+            .ok_or(Error::UnexpectedEndOfData(start, size))?;
+        self.pos = end;
+        self.mark_consumed(end);
+        Ok(&self.data[start..end])
+    }
+
+    fn read_u16(&mut self) -> Result<u16> {
+        Ok(LittleEndian::read_u16(self.read(2)?))
+    }
+
+    /// Reads an offset and returns it as an absolute position inside the data.
+    fn read_offset(&mut self) -> Result<usize> {
+        let offset = self.read_u16()? as usize;
+        self.abs_offset(offset)
+    }
 }
 
 macro_rules! impl_nums {
@@ -49,8 +132,7 @@ macro_rules! impl_nums {
         where
             V: serde::de::Visitor<'de>,
         {
-            let d = LittleEndian::$reader_method(&self.data[self.pos..self.pos + $size]);
-            self.pos += $size;
+            let d = LittleEndian::$reader_method(self.read($size)?);
             visitor.$visitor_method(d)
         }
     };
@@ -86,8 +168,8 @@ impl<'de, 'a> serde::Deserializer<'de> for &'a mut Deserializer {
     where
         V: serde::de::Visitor<'de>,
     {
-        self.pos += 1;
-        visitor.visit_u8(self.data[self.pos - 1])
+        let d = self.read(1)?[0];
+        visitor.visit_u8(d)
     }
 
     #[inline]
@@ -95,8 +177,8 @@ impl<'de, 'a> serde::Deserializer<'de> for &'a mut Deserializer {
     where
         V: serde::de::Visitor<'de>,
     {
-        self.pos += 1;
-        visitor.visit_i8(self.data[self.pos - 1] as i8)
+        let d = self.read(1)?[0] as i8;
+        visitor.visit_i8(d)
     }
 
     impl_nums!(u16, deserialize_u16, visit_u16, read_u16, 2);
@@ -128,33 +210,45 @@ impl<'de, 'a> serde::Deserializer<'de> for &'a mut Deserializer {
     where
         V: serde::de::Visitor<'de>,
     {
-        let tmp_offset = LittleEndian::read_u16(&self.data[self.pos..self.pos + 2]) as usize;
-        let abs_pos = self.abs_offset(tmp_offset as usize);
-        self.pos += 2;
+        let abs_pos = self.read_offset()?;
 
         if abs_pos >= self.data.len() {
             return Err(Error::OffsetOutsideData(self.pos, abs_pos));
         }
 
-        for i in (abs_pos..self.data.len()).step_by(2) {
-            // Look for null terminator
-            if self.data[i] == 0 && self.data[i + 1] == 0 {
-                let mut aligned = vec![0u16; (i - abs_pos) / 2];
-                for (j, el) in aligned.iter_mut().enumerate() {
-                    *el = LittleEndian::read_u16(&self.data[abs_pos + j * 2..abs_pos + j * 2 + 2]);
-                }
+        // Look for null terminator
+        let terminator = self.data[abs_pos..]
+            .chunks_exact(2)
+            .take(MAX_STRING_LENGTH + 1)
+            .position(|c| c[0] == 0 && c[1] == 0);
+
+        match terminator {
+            Some(length) => {
+                // The string ends after the null terminator.
+                self.mark_consumed(abs_pos + (length + 1) * 2);
+                let mut aligned = vec![0u16; length];
+                LittleEndian::read_u16_into(
+                    &self.data[abs_pos..abs_pos + length * 2],
+                    &mut aligned,
+                );
+
                 let mut utf8 = vec![0u8; aligned.len() * 3];
-                let size = ucs2::decode(&aligned, &mut utf8).unwrap();
-                let s: &str;
+                let size = ucs2::decode(&aligned, &mut utf8)
+                    .map_err(|_| Error::InvalidCharEncoding(self.pos))?;
+                let s = str::from_utf8(&utf8[..size])
+                    .map_err(|_| Error::InvalidCharEncoding(self.pos))?;
 
-                unsafe {
-                    s = str::from_utf8_unchecked(&utf8[..size]);
+                visitor.visit_string(s.to_string())
+            }
+            None => {
+                let remaining = (self.data.len() - abs_pos) / 2;
+                if remaining > MAX_STRING_LENGTH {
+                    Err(Error::TooManyElements(self.pos, remaining))
+                } else {
+                    Err(Error::StringNotNullTerminated(self.pos))
                 }
-
-                return visitor.visit_string(s.to_string());
             }
         }
-        Err(Error::StringNotNullTerminated(self.pos))
     }
 
     fn deserialize_string<V>(self, visitor: V) -> Result<V::Value>
@@ -176,18 +270,15 @@ impl<'de, 'a> serde::Deserializer<'de> for &'a mut Deserializer {
     where
         V: serde::de::Visitor<'de>,
     {
-        let tmp_offset = LittleEndian::read_u16(&self.data[self.pos..self.pos + 2]) as usize;
-        let abs_offset = self.abs_offset(tmp_offset as usize);
-        self.pos += 2;
-
-        let len = LittleEndian::read_u16(&self.data[self.pos..self.pos + 2]) as usize;
-        self.pos += 2;
+        let abs_offset = self.read_offset()?;
+        let len = self.read_u16()? as usize;
 
-        if (abs_offset + len as usize) > self.data.len() {
+        if (abs_offset + len) > self.data.len() {
             return Err(Error::BytesTooBig(self.pos));
         };
 
-        let b = &self.data[abs_offset..abs_offset + len as usize];
+        self.mark_consumed(abs_offset + len);
+        let b = &self.data[abs_offset..abs_offset + len];
         visitor.visit_byte_buf(b.to_vec())
     }
 
@@ -293,22 +384,11 @@ impl<'de, 'a> serde::Deserializer<'de> for &'a mut Deserializer {
                     }
                     self.deserializer.pos = self.next_offset;
 
-                    let tmp_offset: usize = LittleEndian::read_u16(
-                        &self.deserializer.data[self.deserializer.pos..self.deserializer.pos + 2],
-                    ) as usize;
-                    let abs_offset: usize = self.deserializer.abs_offset(tmp_offset);
-                    self.deserializer.pos += 2;
-
+                    let abs_offset = self.deserializer.read_offset()?;
                     if abs_offset != self.next_offset {
                         return Err(Error::InvalidSeqEntry(abs_offset));
                     }
-
-                    let tmp_offset: usize = LittleEndian::read_u16(
-                        &self.deserializer.data[self.deserializer.pos..self.deserializer.pos + 2],
-                    ) as usize;
-                    let abs_offset: usize = self.deserializer.abs_offset(tmp_offset);
-                    self.next_offset = abs_offset;
-                    self.deserializer.pos += 2;
+                    self.next_offset = self.deserializer.read_offset()?;
 
                     let value =
                         serde::de::DeserializeSeed::deserialize(seed, &mut *self.deserializer)?;
@@ -325,11 +405,11 @@ impl<'de, 'a> serde::Deserializer<'de> for &'a mut Deserializer {
             }
         }
 
-        let count: usize = LittleEndian::read_u16(&self.data[self.pos..self.pos + 2]) as usize;
-        self.pos += 2;
-        let tmp_offset: usize = LittleEndian::read_u16(&self.data[self.pos..self.pos + 2]) as usize;
-        let next_offset: usize = self.abs_offset(tmp_offset);
-        self.pos += 2;
+        let count = self.read_u16()? as usize;
+        if count > MAX_ARRAY_LENGTH {
+            return Err(Error::TooManyElements(self.pos, count));
+        }
+        let next_offset = self.read_offset()?;
 
         let old_pos = self.pos;
         let data_len = self.data.len();
@@ -369,10 +449,17 @@ impl<'de, 'a> serde::Deserializer<'de> for &'a mut Deserializer {
         Err(Error::DeserializeIdentifierNotSupported(self.pos))
     }
 
-    fn deserialize_newtype_struct<V>(self, _name: &str, visitor: V) -> Result<V::Value>
+    fn deserialize_newtype_struct<V>(self, name: &str, visitor: V) -> Result<V::Value>
     where
         V: serde::de::Visitor<'de>,
     {
+        if name == SKILL_ID {
+            let skill_id = match self.layout.skill_id_length {
+                4 => u64::from(LittleEndian::read_u32(self.read(4)?)),
+                _ => LittleEndian::read_u64(self.read(8)?),
+            };
+            return visitor.visit_newtype_struct(skill_id.into_deserializer());
+        }
         visitor.visit_newtype_struct(self)
     }
 
@@ -463,4 +550,138 @@ mod tests {
         assert_eq!(str, expected);
         Ok(())
     }
+
+    #[test]
+    fn test_consumed_bytes() -> Result<()> {
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct SimpleStruct {
+            a: u8,
+            b: u16,
+        }
+
+        // The last byte is not part of the struct.
+        let (value, consumed) =
+            from_vec_with_offset::<SimpleStruct>(vec![0x12, 0x34, 0x12, 0xff], 4)?;
+        assert_eq!(value, SimpleStruct { a: 0x12, b: 0x1234 });
+        assert_eq!(consumed, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncated_primitive() {
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct SimpleStruct {
+            a: u8,
+            b: u32,
+        }
+
+        let result = from_vec::<SimpleStruct>(vec![0x1, 0x2, 0x3]);
+        assert!(matches!(result, Err(Error::UnexpectedEndOfData(1, 4))));
+
+        let result = from_vec::<SimpleStruct>(vec![]);
+        assert!(matches!(result, Err(Error::UnexpectedEndOfData(0, 1))));
+    }
+
+    #[test]
+    fn test_invalid_string_offset() {
+        let result = from_vec::<String>(vec![0x2, 0x0, 0x41, 0x0, 0x0, 0x0]);
+        assert!(matches!(result, Err(Error::InvalidOffset(..))));
+
+        let result = from_vec::<String>(vec![0xff, 0x0, 0x41, 0x0, 0x0, 0x0]);
+        assert!(matches!(result, Err(Error::OffsetOutsideData(..))));
+    }
+
+    #[test]
+    fn test_string_with_offset() -> Result<()> {
+        // The data starts after an additional 4 byte header.
+        let data = vec![0xa, 0x0, 0x41, 0x0, 0x0, 0x0];
+        assert_eq!(
+            from_vec_with_offset::<String>(data, 8)?,
+            ("A".to_string(), 6)
+        );
+
+        let result = from_vec_with_offset::<String>(vec![0x6, 0x0, 0x41, 0x0, 0x0, 0x0], 8);
+        assert!(matches!(result, Err(Error::InvalidOffset(..))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_skill_id_length() -> Result<()> {
+        #[derive(Deserialize, PartialEq, Debug)]
+        #[serde(rename = "SkillId")]
+        struct SkillId(u64);
+
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct SimpleStruct {
+            skill_id: SkillId,
+            a: u8,
+        }
+
+        let expected = SimpleStruct {
+            skill_id: SkillId(0x1234_5678),
+            a: 1,
+        };
+
+        let layout = PacketLayout { skill_id_length: 4 };
+        let data = vec![0x78, 0x56, 0x34, 0x12, 0x01];
+        let (value, consumed) = from_vec_with_layout::<SimpleStruct>(data, 4, layout)?;
+        assert_eq!(value, expected);
+        assert_eq!(consumed, 5);
+
+        let data = vec![0x78, 0x56, 0x34, 0x12, 0x00, 0x00, 0x00, 0x00, 0x01];
+        let (value, consumed) = from_vec_with_offset::<SimpleStruct>(data, 4)?;
+        assert_eq!(value, expected);
+        assert_eq!(consumed, 9);
+        Ok(())
+    }
+
+    #[test]
+    fn test_string_not_null_terminated() {
+        // Odd number of bytes after the last character
+        let result = from_vec::<String>(vec![0x6, 0x0, 0x41, 0x0, 0x0]);
+        assert!(matches!(result, Err(Error::StringNotNullTerminated(..))));
+    }
+
+    #[test]
+    fn test_string_too_long() {
+        let mut data = vec![0x6, 0x0];
+        data.extend(vec![0x41; (MAX_STRING_LENGTH + 1) * 2]);
+        let result = from_vec::<String>(data);
+        assert!(matches!(result, Err(Error::TooManyElements(..))));
+    }
+
+    #[test]
+    fn test_invalid_string_encoding() {
+        // Lone surrogate
+        let result = from_vec::<String>(vec![0x6, 0x0, 0x0, 0xd8, 0x0, 0x0]);
+        assert!(matches!(result, Err(Error::InvalidCharEncoding(..))));
+    }
+
+    #[test]
+    fn test_array_too_long() {
+        let result = from_vec::<Vec<u8>>(vec![0xff, 0xff, 0x8, 0x0]);
+        assert!(matches!(result, Err(Error::TooManyElements(..))));
+    }
+
+    #[test]
+    fn test_truncated_array_element() {
+        // The array header points to an element header that is cut off.
+        let result = from_vec::<Vec<u8>>(vec![0x1, 0x0, 0x8, 0x0, 0x8, 0x0]);
+        assert!(matches!(result, Err(Error::UnexpectedEndOfData(..))));
+    }
+
+    #[test]
+    fn test_invalid_bytes_offset() {
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct BytesStruct {
+            #[serde(with = "serde_bytes")]
+            data: Vec<u8>,
+        }
+
+        let result = from_vec::<BytesStruct>(vec![0x8, 0x0, 0xff, 0x0, 0x1]);
+        assert!(matches!(result, Err(Error::BytesTooBig(..))));
+
+        let result = from_vec::<BytesStruct>(vec![0x8, 0x0]);
+        assert!(matches!(result, Err(Error::UnexpectedEndOfData(..))));
+    }
 }
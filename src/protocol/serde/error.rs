@@ -50,12 +50,24 @@ pub enum Error {
     #[error("offset outside of data. Pos: {0} Offset: {1}")]
     OffsetOutsideData(usize, usize),
 
+    #[error("UnexpectedEndOfData. Pos: {0} Size: {1}")]
+    UnexpectedEndOfData(usize, usize),
+
+    #[error("InvalidOffset. Pos: {0} Offset: {1}")]
+    InvalidOffset(usize, usize),
+
+    #[error("TooManyElements. Pos: {0} Count: {1}")]
+    TooManyElements(usize, usize),
+
     #[error("NotImplemented.")]
     NotImplemented(),
 
     #[error("BytesTooBig. Pos: {0}")]
     BytesTooBig(usize),
 
+    #[error("SkillIdTooBig. Value: {0}")]
+    SkillIdTooBig(u64),
+
     #[error("serde error: {0}")]
     Serde(#[from] serde_yaml::Error),
 }
@@ -4,12 +4,13 @@ use std::collections::HashMap;
 use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
 use serde::{ser, Serialize};
 
-use super::{Error, Result};
+use super::{Error, PacketLayout, Result, SKILL_ID};
 
 #[derive(Debug, Clone)]
 pub struct Serializer {
     current_node: usize,
     nodes: HashMap<usize, DataNode>,
+    layout: PacketLayout,
 }
 
 #[derive(Debug, Clone)]
@@ -80,6 +81,24 @@ impl Serializer {
 
 /// Serializes the given structure into a `Vec<u8>` byte stream for the TERA network protocol.
 pub fn to_vec<T>(value: T) -> Result<Vec<u8>>
+where
+    T: Serialize,
+{
+    to_vec_with_offset(value, 4) // 4 bytes packet header
+}
+
+/// Serializes the given structure into a `Vec<u8>` byte stream that starts `data_offset` bytes
+/// after the start of the packet.
+pub fn to_vec_with_offset<T>(value: T, data_offset: usize) -> Result<Vec<u8>>
+where
+    T: Serialize,
+{
+    to_vec_with_layout(value, data_offset, PacketLayout::default())
+}
+
+/// Serializes the given structure like `to_vec_with_offset` with the packet layout of a client
+/// version.
+pub fn to_vec_with_layout<T>(value: T, data_offset: usize, layout: PacketLayout) -> Result<Vec<u8>>
 where
     T: Serialize,
 {
@@ -95,12 +114,13 @@ where
     let mut serializer = Serializer {
         current_node: 0,
         nodes: HashMap::new(),
+        layout,
     };
     serializer.nodes.insert(0, root_node);
     value.serialize(&mut serializer)?;
 
     // Recursively assemble the data
-    Ok(serializer.assemble_node(0, 4)) // 4 bytes packet header
+    Ok(serializer.assemble_node(0, data_offset))
 }
 
 macro_rules! impl_nums {
@@ -270,10 +290,25 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         Ok(())
     }
 
-    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<()>
+    fn serialize_newtype_struct<T>(self, name: &'static str, value: &T) -> Result<()>
     where
         T: ?Sized + Serialize,
     {
+        if name == SKILL_ID && self.layout.skill_id_length == 4 {
+            // Skill ids are u64 values. The older clients only use the lower 4 bytes.
+            let start = self.nodes.get(&self.current_node).unwrap().data.len();
+            value.serialize(&mut *self)?;
+            let data = &mut self.nodes.get_mut(&self.current_node).unwrap().data;
+            let skill_id = match data.get(start..) {
+                Some(value) if value.len() == 8 => LittleEndian::read_u64(value),
+                _ => return Err(Error::Custom("SkillId needs to hold an u64".to_string())),
+            };
+            if skill_id > u64::from(u32::MAX) {
+                return Err(Error::SkillIdTooBig(skill_id));
+            }
+            data.truncate(start + 4);
+            return Ok(());
+        }
         value.serialize(self)
     }
 
@@ -539,4 +574,46 @@ mod tests {
         assert_eq!(vec, expected);
         Ok(())
     }
+
+    #[test]
+    fn test_string_with_offset() -> Result<()> {
+        // The data starts after an additional 4 byte header.
+        let vec = to_vec_with_offset("A".to_string(), 8)?;
+        assert_eq!(vec, vec![0xa, 0x0, 0x41, 0x0, 0x0, 0x0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_skill_id_length() -> Result<()> {
+        #[derive(Serialize)]
+        #[serde(rename = "SkillId")]
+        struct SkillId(u64);
+
+        #[derive(Serialize)]
+        struct SimpleStruct {
+            skill_id: SkillId,
+            a: u8,
+        }
+
+        let data = SimpleStruct {
+            skill_id: SkillId(0x1234_5678),
+            a: 1,
+        };
+        let layout = PacketLayout { skill_id_length: 4 };
+        assert_eq!(
+            to_vec_with_layout(&data, 4, layout)?,
+            vec![0x78, 0x56, 0x34, 0x12, 0x01]
+        );
+        assert_eq!(
+            to_vec(&data)?,
+            vec![0x78, 0x56, 0x34, 0x12, 0x00, 0x00, 0x00, 0x00, 0x01]
+        );
+
+        let data = SimpleStruct {
+            skill_id: SkillId(0x1_0000_0000),
+            a: 1,
+        };
+        assert!(to_vec_with_layout(&data, 4, layout).is_err());
+        Ok(())
+    }
 }
@@ -0,0 +1,150 @@
+/// Module that builds the system messages (SMT_*) of the client.
+///
+/// The client knows all system messages by their ID, which is the index of the message inside the
+/// `messages.yaml` list. A message is send as a string in the format `@<id>\u{b}key\u{b}value...`,
+/// and the client replaces the placeholders of the localized message with the given values.
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::Context;
+
+use crate::protocol::packet::SSystemMessage;
+use crate::Result;
+
+/// Separates the ID, keys and values of a system message.
+const SEPARATOR: char = '\u{b}';
+
+/// Table of all system messages the client knows.
+#[derive(Clone, Debug, Default)]
+pub struct SystemMessageTable {
+    ids: Arc<HashMap<String, u32>>,
+}
+
+impl SystemMessageTable {
+    /// Creates the table from the names of the messages in the same order as the client.
+    pub fn new(names: Vec<String>) -> SystemMessageTable {
+        let mut ids = HashMap::with_capacity(names.len());
+        for (id, name) in names.into_iter().enumerate() {
+            ids.entry(name).or_insert(id as u32);
+        }
+        SystemMessageTable { ids: Arc::new(ids) }
+    }
+
+    /// Returns the number of messages inside the table.
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Returns true if the table contains no messages.
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Returns the ID of the message with the given name.
+    pub fn id(&self, name: &str) -> Option<u32> {
+        self.ids.get(name).copied()
+    }
+
+    /// Returns a builder for the message with the given name.
+    pub fn message(&self, name: &str) -> Result<SystemMessage> {
+        let id = self
+            .id(name)
+            .context(format!("Unknown system message {}", name))?;
+        Ok(SystemMessage::new(id))
+    }
+}
+
+/// Builder of a system message with it's parameters.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SystemMessage {
+    id: u32,
+    parameters: Vec<(String, String)>,
+}
+
+impl SystemMessage {
+    /// Creates a message without parameters.
+    pub fn new(id: u32) -> SystemMessage {
+        SystemMessage {
+            id,
+            parameters: Vec::new(),
+        }
+    }
+
+    /// Adds a parameter that replaces the placeholder with the given key.
+    pub fn with<T: ToString>(mut self, key: &str, value: T) -> SystemMessage {
+        self.parameters
+            .push((sanitize(key), sanitize(&value.to_string())));
+        self
+    }
+
+    /// Builds the message in the format of the client.
+    pub fn build(&self) -> String {
+        let mut message = format!("@{}", self.id);
+        for (key, value) in self.parameters.iter() {
+            message.push(SEPARATOR);
+            message.push_str(key);
+            message.push(SEPARATOR);
+            message.push_str(value);
+        }
+        message
+    }
+
+    /// Builds the S_SYSTEM_MESSAGE packet of the message.
+    pub fn packet(&self) -> SSystemMessage {
+        SSystemMessage {
+            message: self.build(),
+        }
+    }
+}
+
+/// The separator can't be part of a key or value.
+fn sanitize(value: &str) -> String {
+    value.replace(SEPARATOR, " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> SystemMessageTable {
+        SystemMessageTable::new(vec![
+            "SMT_UNDEFINED".to_string(),
+            "SMT_LOBBY_CANNOT_CONNECT".to_string(),
+            "SMT_GENERAL_NOT_IN_THE_WORLD".to_string(),
+            "SMT_UNDEFINED".to_string(),
+        ])
+    }
+
+    #[test]
+    fn test_table_lookup() {
+        let table = table();
+        assert_eq!(table.len(), 3);
+        assert_eq!(table.id("SMT_UNDEFINED"), Some(0));
+        assert_eq!(table.id("SMT_GENERAL_NOT_IN_THE_WORLD"), Some(2));
+        assert_eq!(table.id("SMT_DOES_NOT_EXIST"), None);
+        assert!(table.message("SMT_DOES_NOT_EXIST").is_err());
+    }
+
+    #[test]
+    fn test_build_message() -> Result<()> {
+        let table = table();
+        assert_eq!(table.message("SMT_LOBBY_CANNOT_CONNECT")?.build(), "@1");
+        assert_eq!(
+            table
+                .message("SMT_GENERAL_NOT_IN_THE_WORLD")?
+                .with("UserName", "Asuna")
+                .with("Count", 3)
+                .build(),
+            "@2\u{b}UserName\u{b}Asuna\u{b}Count\u{b}3"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_message_with_separator() {
+        assert_eq!(
+            SystemMessage::new(7).with("Name", "A\u{b}B").build(),
+            "@7\u{b}Name\u{b}A B"
+        );
+    }
+}
@@ -0,0 +1,297 @@
+/// Module that defines the client versions the network protocol supports.
+///
+/// Every version has it's own opcode table and integrity list, which are stored in a folder of the
+/// data path with the name of the version and cached inside the game data. The version is selected
+/// with the first value the client reports in C_CHECK_VERSION.
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use anyhow::{bail, ensure, Context};
+
+use crate::config::{DataConfiguration, VersionConfiguration};
+use crate::dataloader::{load_integrity_list, load_opcode_mapping};
+use crate::gamedata::{GameData, VersionTables};
+use crate::protocol::opcode::Opcode;
+use crate::protocol::packet::CCheckVersion;
+use crate::protocol::serde::{from_vec, PacketLayout};
+use crate::Result;
+
+/// A client version that is supported by the server.
+#[derive(Debug)]
+pub struct ProtocolVersion {
+    pub name: String,
+    pub version: i32,
+    pub opcode_table: Vec<Opcode>,
+    pub reverse_opcode_table: HashMap<Opcode, u16>,
+    pub integrity_opcodes: HashSet<Opcode>,
+    pub layout: PacketLayout,
+}
+
+impl ProtocolVersion {
+    /// Creates the version from it's opcode mapping and integrity list.
+    pub fn from_tables(
+        config: &VersionConfiguration,
+        tables: &VersionTables,
+    ) -> Result<ProtocolVersion> {
+        let mut opcode_table = vec![Opcode::UNKNOWN; std::u16::MAX as usize + 1];
+        for (opcode, value) in tables.opcodes.iter() {
+            opcode_table[*value as usize] = *opcode;
+        }
+        let reverse_opcode_table = tables.opcodes.clone();
+        let integrity_opcodes = tables.integrity.clone();
+
+        let mut layout = PacketLayout::default();
+        if let Some(skill_id_length) = config.skill_id_length {
+            ensure!(
+                skill_id_length == 4 || skill_id_length == 8,
+                "Skill id length of version {} needs to be 4 or 8 bytes",
+                config.name
+            );
+            layout.skill_id_length = skill_id_length;
+        }
+
+        Ok(ProtocolVersion {
+            name: config.name.clone(),
+            version: config.version,
+            opcode_table,
+            reverse_opcode_table,
+            integrity_opcodes,
+            layout,
+        })
+    }
+
+    /// Loads the configured version with the given name from it's folder inside the data folder.
+    /// Uses the first configured version if no name is given.
+    pub fn load(config: &DataConfiguration, name: Option<&str>) -> Result<ProtocolVersion> {
+        let version = match name {
+            Some(name) => config
+                .versions
+                .iter()
+                .find(|v| v.name == name)
+                .context(format!("Client version {} is not configured", name))?,
+            None => config
+                .versions
+                .first()
+                .context("No client version is configured")?,
+        };
+
+        let version_path = config.path.join(&version.name);
+        let (_, opcodes) = load_opcode_mapping(&version_path).context(format!(
+            "Can't read opcode mapping file of version {} in {}",
+            version.name,
+            version_path.display(),
+        ))?;
+        let integrity = load_integrity_list(&version_path).context(format!(
+            "Can't read integrity list file of version {} in {}",
+            version.name,
+            version_path.display(),
+        ))?;
+
+        ProtocolVersion::from_tables(version, &VersionTables { opcodes, integrity })
+    }
+}
+
+/// Registry of all supported client versions.
+#[derive(Debug, Default)]
+pub struct ProtocolRegistry {
+    versions: Vec<Arc<ProtocolVersion>>,
+}
+
+impl ProtocolRegistry {
+    /// Creates all versions that are defined in the data configuration.
+    pub fn from_game_data(
+        config: &DataConfiguration,
+        game_data: &GameData,
+    ) -> Result<ProtocolRegistry> {
+        let mut registry = ProtocolRegistry::default();
+        for version in config.versions.iter() {
+            let tables = game_data.versions.get(&version.name).context(format!(
+                "Game data contains no opcode tables for version {}",
+                version.name
+            ))?;
+            registry.register(ProtocolVersion::from_tables(version, tables)?)?;
+        }
+        ensure!(
+            !registry.versions.is_empty(),
+            "No client version configured"
+        );
+        Ok(registry)
+    }
+
+    /// Registers a version. Version values and names need to be unique.
+    pub fn register(&mut self, version: ProtocolVersion) -> Result<()> {
+        ensure!(
+            self.get(version.version).is_none(),
+            "Client version {} is registered twice",
+            version.version
+        );
+        ensure!(
+            self.get_by_name(&version.name).is_none(),
+            "Client version name {} is registered twice",
+            version.name
+        );
+        self.versions.push(Arc::new(version));
+        Ok(())
+    }
+
+    /// Returns all registered versions.
+    pub fn versions(&self) -> &[Arc<ProtocolVersion>] {
+        &self.versions
+    }
+
+    /// Returns the version with the given version value.
+    pub fn get(&self, version: i32) -> Option<Arc<ProtocolVersion>> {
+        self.versions.iter().find(|v| v.version == version).cloned()
+    }
+
+    /// Returns the version with the given name.
+    pub fn get_by_name(&self, name: &str) -> Option<Arc<ProtocolVersion>> {
+        self.versions.iter().find(|v| v.name == name).cloned()
+    }
+
+    /// Selects the version of a session with it's first packet, which needs to be C_CHECK_VERSION.
+    ///
+    /// If the reported version is not supported, the first version that knows the opcode of the
+    /// packet is returned, so that the session can answer the version check. The global world
+    /// rejects the unsupported version afterwards.
+    pub fn select_version(&self, opcode: u16, packet_data: &[u8]) -> Result<Arc<ProtocolVersion>> {
+        let candidate = match self
+            .versions
+            .iter()
+            .find(|v| v.opcode_table[opcode as usize] == Opcode::C_CHECK_VERSION)
+        {
+            Some(candidate) => candidate,
+            None => bail!(
+                "Expected C_CHECK_VERSION as the first packet, but got opcode value {}",
+                opcode
+            ),
+        };
+
+        let packet = from_vec::<CCheckVersion>(packet_data.to_vec())?;
+        let reported = packet
+            .version
+            .iter()
+            .find(|entry| entry.index == 0)
+            .map(|entry| entry.value);
+
+        match reported.and_then(|version| self.get(version)) {
+            Some(version) => Ok(version),
+            None => Ok(candidate.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::tests::get_configuration;
+    use crate::dataloader::calculate_reverse_map;
+    use crate::protocol::packet::CCheckVersionEntry;
+    use crate::protocol::serde::to_vec;
+
+    use super::*;
+
+    fn version(name: &str, version: i32, check_version_opcode: usize) -> ProtocolVersion {
+        let mut opcode_table = vec![Opcode::UNKNOWN; std::u16::MAX as usize + 1];
+        opcode_table[check_version_opcode] = Opcode::C_CHECK_VERSION;
+        let reverse_opcode_table = calculate_reverse_map(&opcode_table);
+
+        ProtocolVersion {
+            name: name.to_string(),
+            version,
+            opcode_table,
+            reverse_opcode_table,
+            integrity_opcodes: HashSet::new(),
+            layout: PacketLayout::default(),
+        }
+    }
+
+    fn check_version(value: i32) -> Vec<u8> {
+        to_vec(CCheckVersion {
+            version: vec![
+                CCheckVersionEntry { index: 0, value },
+                CCheckVersionEntry {
+                    index: 1,
+                    value: 1234,
+                },
+            ],
+        })
+        .unwrap()
+    }
+
+    fn registry() -> Result<ProtocolRegistry> {
+        let mut registry = ProtocolRegistry::default();
+        registry.register(version("92.04", 363_037, 100))?;
+        registry.register(version("93.02", 366_222, 200))?;
+        Ok(registry)
+    }
+
+    #[test]
+    fn test_register_duplicate_version() -> Result<()> {
+        let mut registry = registry()?;
+        assert!(registry.register(version("93.03", 366_222, 300)).is_err());
+        assert!(registry.register(version("93.02", 1, 300)).is_err());
+        assert_eq!(registry.versions().len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_version() -> Result<()> {
+        let registry = registry()?;
+
+        let version = registry.select_version(200, &check_version(366_222))?;
+        assert_eq!(version.name, "93.02");
+
+        let version = registry.select_version(100, &check_version(363_037))?;
+        assert_eq!(version.name, "92.04");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_registry_from_game_data() -> Result<()> {
+        let mut config = get_configuration().data;
+        config.versions[0].skill_id_length = Some(4);
+
+        let mut game_data = GameData::default();
+        assert!(ProtocolRegistry::from_game_data(&config, &game_data).is_err());
+
+        game_data.versions.insert(
+            "93.02".to_string(),
+            VersionTables {
+                opcodes: vec![(Opcode::C_CHECK_VERSION, 19900)].into_iter().collect(),
+                integrity: vec![Opcode::C_CHAT].into_iter().collect(),
+            },
+        );
+        let registry = ProtocolRegistry::from_game_data(&config, &game_data)?;
+
+        let version = registry.get(366_222).unwrap();
+        assert_eq!(version.opcode_table[19900], Opcode::C_CHECK_VERSION);
+        assert_eq!(
+            version.reverse_opcode_table[&Opcode::C_CHECK_VERSION],
+            19900
+        );
+        assert!(version.integrity_opcodes.contains(&Opcode::C_CHAT));
+        assert_eq!(version.layout.skill_id_length, 4);
+
+        config.versions[0].skill_id_length = Some(6);
+        assert!(ProtocolRegistry::from_game_data(&config, &game_data).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_unsupported_version() -> Result<()> {
+        let registry = registry()?;
+
+        // The first version that knows the opcode is used to answer the version check.
+        let version = registry.select_version(200, &check_version(1))?;
+        assert_eq!(version.name, "93.02");
+
+        assert!(registry
+            .select_version(300, &check_version(366_222))
+            .is_err());
+        assert!(registry.select_version(200, &[0x1, 0x2]).is_err());
+
+        Ok(())
+    }
+}
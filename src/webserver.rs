@@ -2,8 +2,11 @@
 pub mod request;
 pub mod response;
 
+use std::sync::Arc;
+
 use anyhow::ensure;
 use async_std::task;
+use futures_util::future::try_join;
 use http_types::StatusCode;
 use sqlx::PgPool;
 use tide::{Request, Response, Server};
@@ -11,6 +14,7 @@ use tracing::{error, info};
 
 use crate::config::Configuration;
 use crate::crypt::password_hash::verify_hash;
+use crate::metrics::Metrics;
 use crate::model::repository::{account, loginticket};
 use crate::model::PasswordHashAlgorithm;
 use crate::{AlmeticaError, Result};
@@ -21,8 +25,9 @@ struct WebServerState {
 }
 
 /// Main loop of the web server.
-pub async fn run(pool: PgPool, config: Configuration) -> Result<()> {
+pub async fn run(pool: PgPool, config: Configuration, metrics: Arc<Metrics>) -> Result<()> {
     let listen_string = format!("{}:{}", config.server.hostname, config.server.web_port);
+    let metrics_port = config.server.metrics_port;
 
     // FIXME: Add a body length limiting middleware once official implemented: https://github.com/http-rs/tide/issues/448
 
@@ -30,7 +35,20 @@ pub async fn run(pool: PgPool, config: Configuration) -> Result<()> {
     webserver.middleware(tide::middleware::RequestLogger::new());
     webserver.at("/server/*").get(server_list_endpoint);
     webserver.at("/auth").post(auth_endpoint);
-    webserver.listen(listen_string).await?;
+
+    match metrics_port {
+        Some(port) => {
+            // The metrics are only served on the loopback interface.
+            let mut metrics_server = Server::with_state(metrics);
+            metrics_server.at("/metrics").get(metrics_endpoint);
+            try_join(
+                webserver.listen(listen_string),
+                metrics_server.listen(format!("127.0.0.1:{}", port)),
+            )
+            .await?;
+        }
+        None => webserver.listen(listen_string).await?,
+    }
     Ok(())
 }
 
@@ -59,6 +77,17 @@ async fn server_list_endpoint(req: Request<WebServerState>) -> Response {
     Response::new(StatusCode::Ok).body_string(server_list_template)
 }
 
+/// Returns the current metrics of the multiverse.
+async fn metrics_endpoint(req: Request<Arc<Metrics>>) -> Response {
+    match Response::new(StatusCode::Ok).body_json(&req.state().snapshot()) {
+        Ok(resp) => resp,
+        Err(e) => {
+            error!("Couldn't serialize metrics: {:?}", e);
+            Response::new(StatusCode::InternalServerError)
+        }
+    }
+}
+
 /// Handles the client authentication.
 async fn auth_endpoint(mut req: Request<WebServerState>) -> Response {
     let login_request: request::Login = match req.body_form().await {